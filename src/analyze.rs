@@ -0,0 +1,330 @@
+// analyze: ANALYZE TABLE gathers table and column statistics with a distributed scan
+
+use std::{cmp::Ordering, fs::File};
+
+use ahash::RandomState;
+
+use crate::{
+    ast::AST,
+    compile_query,
+    diagnostic::SourceFile,
+    includes::*,
+    metadata::{ColumnStats, TableType},
+    qgm::ParserState,
+    resultset::ResultSet,
+    run_flow, sqlparser,
+};
+
+const HLL_BITS: u32 = 12; // 4096 HyperLogLog registers, for a standard error of about 1.6%
+const SAMPLE_SIZE: usize = 1024; // Values sampled per column and partition, for histograms
+const HISTOGRAM_BUCKETS: usize = 10;
+
+// Seeded so that every task hashes a value the same way
+fn hash_state() -> RandomState {
+    RandomState::with_seeds(0x6b6f6e61, 0x616e616c, 0x797a6520, 0x74626c73)
+}
+
+// Float64 values are ordered by their bits in Datum, so they need a numeric comparison
+pub fn compare(lhs: &Datum, rhs: &Datum) -> Ordering {
+    match (lhs, rhs) {
+        (Float64(lhs), Float64(rhs)) => f64::from(*lhs).total_cmp(&f64::from(*rhs)),
+        _ => lhs.cmp(rhs),
+    }
+}
+
+fn value_width(value: &Datum) -> usize {
+    match value {
+        Utf8(value) => value.len(),
+        Boolean(_) => 1,
+        Int32(_) | Date32(_) => 4,
+        Interval(..) | Decimal(..) => 16,
+        _ => 8,
+    }
+}
+
+/***************************************************************************************************/
+// Statistics of one column over one partition
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ColumnStatsBuilder {
+    registers: Vec<u8>, // HyperLogLog sketch of the distinct values
+    nvalues: usize,
+    null_count: usize,
+    width: usize, // Total bytes of all values
+    min: Option<Datum>,
+    max: Option<Datum>,
+    sample: Vec<Datum>, // Every `stride`-th value. The stride doubles whenever the sample fills up.
+    stride: usize,
+}
+
+impl ColumnStatsBuilder {
+    fn new() -> Self {
+        ColumnStatsBuilder { registers: vec![0; 1 << HLL_BITS], nvalues: 0, null_count: 0, width: 0, min: None, max: None, sample: vec![], stride: 1 }
+    }
+
+    fn add(&mut self, value: Option<Datum>, state: &RandomState) {
+        let value = match value {
+            Some(value) => value,
+            None => {
+                self.null_count += 1;
+                return;
+            }
+        };
+
+        // The leading bits pick a register, which keeps the longest run of leading zeros seen in the rest
+        let hash = state.hash_one(&value);
+        let register = (hash >> (64 - HLL_BITS)) as usize;
+        let rank = ((hash << HLL_BITS) | (1 << (HLL_BITS - 1))).leading_zeros() as u8 + 1;
+        self.registers[register] = self.registers[register].max(rank);
+
+        self.width += value_width(&value);
+        if self.min.as_ref().is_none_or(|min| compare(&value, min) == Ordering::Less) {
+            self.min = Some(value.clone());
+        }
+        if self.max.as_ref().is_none_or(|max| compare(&value, max) == Ordering::Greater) {
+            self.max = Some(value.clone());
+        }
+        if self.nvalues.is_multiple_of(self.stride) {
+            self.sample.push(value);
+            if self.sample.len() == 2 * SAMPLE_SIZE {
+                self.sample = self.sample.drain(..).step_by(2).collect();
+                self.stride *= 2;
+            }
+        }
+        self.nvalues += 1;
+    }
+
+    // Folds the builders of every partition into the column's stats. Also returns the column's total width.
+    fn finish(builders: Vec<ColumnStatsBuilder>) -> (ColumnStats, usize) {
+        let mut registers = vec![0u8; 1 << HLL_BITS];
+        let (mut nvalues, mut null_count, mut width) = (0, 0, 0);
+        let (mut min, mut max): (Option<Datum>, Option<Datum>) = (None, None);
+        let mut sample = vec![];
+        for builder in builders {
+            for (register, &rank) in registers.iter_mut().zip(builder.registers.iter()) {
+                *register = (*register).max(rank);
+            }
+            nvalues += builder.nvalues;
+            null_count += builder.null_count;
+            width += builder.width;
+            if let Some(value) = builder.min.filter(|value| min.as_ref().is_none_or(|min| compare(value, min) == Ordering::Less)) {
+                min = Some(value);
+            }
+            if let Some(value) = builder.max.filter(|value| max.as_ref().is_none_or(|max| compare(value, max) == Ordering::Greater)) {
+                max = Some(value);
+            }
+            let stride = builder.stride;
+            sample.extend(builder.sample.into_iter().map(|value| (value, stride)));
+        }
+
+        let ndv = Self::estimate_ndv(&registers).clamp(nvalues.min(1), nvalues);
+        let mut histogram = Self::equi_depth_histogram(sample, HISTOGRAM_BUCKETS.min(nvalues));
+        if let (Some(bound), Some(max)) = (histogram.last_mut(), max.as_ref()) {
+            *bound = max.clone();
+        }
+        let avg_width = if nvalues > 0 { width.div_ceil(nvalues) } else { 0 };
+        (ColumnStats { ndv, null_count, min, max, histogram, avg_width }, width)
+    }
+
+    fn estimate_ndv(registers: &[u8]) -> usize {
+        let m = registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let estimate = alpha * m * m / registers.iter().map(|&rank| 2f64.powi(-(rank as i32))).sum::<f64>();
+
+        // Small cardinalities are better estimated by counting empty registers
+        let nzeros = registers.iter().filter(|&&rank| rank == 0).count();
+        let estimate = if estimate <= 2.5 * m && nzeros > 0 { m * (m / nzeros as f64).ln() } else { estimate };
+        estimate.round() as usize
+    }
+
+    // Each sampled value stands for `weight` rows. Buckets end where the running weight crosses each 1/nbuckets share.
+    fn equi_depth_histogram(mut sample: Vec<(Datum, usize)>, nbuckets: usize) -> Vec<Datum> {
+        sample.sort_by(|(lhs, _), (rhs, _)| compare(lhs, rhs));
+        let total: usize = sample.iter().map(|(_, weight)| weight).sum();
+        let mut bounds = vec![];
+        let mut cumulative = 0;
+        for (value, weight) in sample {
+            cumulative += weight;
+            while bounds.len() < nbuckets && cumulative * nbuckets >= total * (bounds.len() + 1) {
+                bounds.push(value.clone());
+            }
+        }
+        bounds
+    }
+}
+
+// Statistics of one partition, written by its task and merged with the others once the scan is done
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PartitionStats {
+    nrows: usize,
+    columns: Vec<ColumnStatsBuilder>,
+}
+
+impl PartitionStats {
+    pub fn pathname(flow: &Flow, partition_id: PartitionId) -> String {
+        format!("{}/partition-{}.stats", flow.output_dir(), partition_id)
+    }
+
+    pub fn add_chunk(&mut self, chunk: &ChunkBox) -> Result<(), KonaError> {
+        if self.columns.is_empty() {
+            self.columns = chunk.arrays().iter().map(|_| ColumnStatsBuilder::new()).collect();
+        }
+        let state = hash_state();
+        self.nrows += chunk.len();
+        for (column, array) in self.columns.iter_mut().zip(chunk.arrays().iter()) {
+            for ix in 0..array.len() {
+                column.add(Datum::from_array(&**array, ix).map_err(KonaError::execution)?, &state);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, pathname: &str) -> Result<(), KonaError> {
+        let file = File::create(pathname).map_err(|err| KonaError::io(pathname, err))?;
+        bincode::serialize_into(file, self).map_err(|err| KonaError::io(pathname, std::io::Error::other(err)))
+    }
+
+    fn read_all(flow: &Flow) -> Result<Vec<PartitionStats>, KonaError> {
+        let output_dir = flow.output_dir();
+        let mut partitions = vec![];
+        // Partitions without any rows write nothing
+        if let Ok(entries) = fs::read_dir(&output_dir) {
+            for entry in entries {
+                let pathname = entry.map_err(|err| KonaError::io(&output_dir, err))?.path().display().to_string();
+                let file = File::open(&pathname).map_err(|err| KonaError::io(&pathname, err))?;
+                let partition = bincode::deserialize_from(file).map_err(|err| KonaError::Plan(f!("Cannot read {pathname}: {err}")))?;
+                partitions.push(partition);
+            }
+        }
+        Ok(partitions)
+    }
+}
+
+/***************************************************************************************************/
+// ANALYZE TABLE t [COLUMNS (c1, c2, ...)] scans the named columns, or all of them, and stores their stats in the catalog.
+// The table's row count is always refreshed; its average row size only when every column is scanned.
+pub fn analyze_table(env: &mut Env, schema: Option<String>, name: String, columns: Option<Vec<(String, bool)>>) -> Result<Option<ResultSet>, KonaError> {
+    let (schema, tbldesc) = env.metadata.get_tabledesc(schema.as_deref(), &name)?;
+    let name = name.to_uppercase();
+    if !matches!(tbldesc.get_type(), TableType::CSV | TableType::Parquet) {
+        return Err(KonaError::Bind(f!("{schema}.{name} is not a table and cannot be analyzed.")));
+    }
+
+    let colnames = match columns {
+        Some(columns) => columns
+            .iter()
+            .map(|(colname, quoted)| {
+                tbldesc.get_column(colname, *quoted).map(|(_, field)| field.name.clone()).ok_or_else(|| KonaError::Bind(f!("Column {colname} not found in {schema}.{name}.")))
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        None => tbldesc.fields().iter().map(|field| field.name.clone()).collect(),
+    };
+    if has_duplicates(&colnames) {
+        return Err(KonaError::Bind(f!("ANALYZE TABLE {name} names a column more than once: {colnames:?}")));
+    }
+
+    // Scan the table with an ordinary query over the columns
+    let select_list = colnames.iter().map(|colname| format!("\"{}\"", colname)).collect::<Vec<_>>().join(", ");
+    let sql = format!("SELECT {} FROM \"{}\".\"{}\"", select_list, schema, name);
+    let mut errors = vec![];
+    let mut qgm = match sqlparser::QueryParser::new().parse(&mut ParserState::default(), &mut errors, &sql) {
+        Ok(AST::QGM(qgm)) => qgm,
+        Ok(_) => unreachable!(),
+        Err(err) => return Err(KonaError::Plan(f!("Cannot scan {schema}.{name}: {}", SourceFile::new("<analyze>", &sql).describe_error(&err)))),
+    };
+    let mut flow = match compile_query(env, &mut qgm)? {
+        Some(flow) => flow,
+        None => return Ok(None),
+    };
+    flow.collect_stats = true;
+    run_flow(env, &flow)?;
+
+    let partitions = PartitionStats::read_all(&flow)?;
+    let nrows = partitions.iter().map(|partition| partition.nrows).sum::<usize>();
+    let mut builders = colnames.iter().map(|_| vec![]).collect::<Vec<_>>();
+    for partition in partitions {
+        for (builder, column) in builders.iter_mut().zip(partition.columns) {
+            builder.push(column);
+        }
+    }
+
+    let mut table_stats = tbldesc.get_stats().cloned().unwrap();
+    table_stats.nrows = nrows;
+    let mut width = 0;
+    for (colname, builder) in colnames.iter().zip(builders) {
+        let (column_stats, column_width) = ColumnStatsBuilder::finish(builder);
+        table_stats.columns.insert(colname.clone(), column_stats);
+        width += column_width;
+    }
+    if colnames.len() == tbldesc.fields().len() && nrows > 0 {
+        table_stats.avg_row_size = width.div_ceil(nrows).max(1);
+    }
+    env.metadata.set_table_stats(&schema, &name, table_stats)?;
+    Ok(Some(ResultSet::status(f!("Analyzed table {schema}.{name}: {nrows} rows"))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    fn build(partitions: &[Vec<Option<Datum>>]) -> ColumnStats {
+        let state = hash_state();
+        let builders = partitions
+            .iter()
+            .map(|values| {
+                let mut builder = ColumnStatsBuilder::new();
+                values.iter().for_each(|value| builder.add(value.clone(), &state));
+                builder
+            })
+            .collect();
+        ColumnStatsBuilder::finish(builders).0
+    }
+
+    #[test]
+    fn sketches_estimate_distinct_values() {
+        // Partitions overlap, so distinct values are only counted once when their sketches are merged
+        let partitions = (0..4).map(|ix| (ix * 20_000..ix * 20_000 + 30_000).map(|n| Some(Int64(n))).collect()).collect::<Vec<_>>();
+        let stats = build(&partitions);
+        assert!(stats.ndv.abs_diff(90_000) < 90_000 * 5 / 100, "ndv {}", stats.ndv);
+        assert_eq!((stats.min, stats.max), (Some(Int64(0)), Some(Int64(89_999))));
+
+        // Small cardinalities are counted from the empty registers and are close to exact
+        let stats = build(&[(0..1000).map(|n| Some(Int64(n % 37))).chain([None, None]).collect()]);
+        assert!(stats.ndv.abs_diff(37) <= 1, "ndv {}", stats.ndv);
+        assert_eq!((stats.null_count, stats.avg_width), (2, 8));
+    }
+
+    #[test]
+    fn histograms_have_equal_depth_buckets() {
+        // Far more values than the sample holds, so the sample is thinned out
+        let stats = build(&[(1..=50_000).map(|n| Some(Int64(n))).collect(), (50_001..=100_000).map(|n| Some(Int64(n))).collect()]);
+        assert_eq!(stats.histogram.len(), HISTOGRAM_BUCKETS);
+        for (ix, bound) in stats.histogram.iter().enumerate() {
+            let expected = (ix as i64 + 1) * 10_000;
+            assert!(matches!(bound, Int64(n) if n.abs_diff(expected) <= 500), "bucket {}: {:?}", ix, bound);
+        }
+        assert_eq!(stats.histogram.last(), Some(&Int64(100_000)));
+
+        // Skewed values repeat a bound
+        let stats = build(&[(0..100).map(|n| Some(Utf8(if n < 80 { "a" } else { "b" }.into()))).collect()]);
+        assert_eq!(stats.histogram.iter().filter(|bound| **bound == Utf8("a".into())).count(), 8);
+    }
+
+    #[test]
+    fn analyze_table_stores_column_stats() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT, N INT", &["1,5", "2,", "3,5", "4,7"]);
+        let status = query_value(&mut session, "ANALYZE TABLE T COLUMNS (N)").unwrap();
+        assert_eq!(status, Utf8("Analyzed table PUBLIC.T: 4 rows".into()));
+        let rows = query(&mut session, "DESCRIBE TABLE T").unwrap();
+        assert!(rows.contains(&vec![Utf8("NROWS".into()), Utf8("4".into())]), "{:?}", rows);
+        assert!(rows.contains(&vec![Utf8("STATS N".into()), Utf8("ndv 2, nulls 1, width 8, min 5, max 7, histogram [5, 5, 7]".into())]), "{:?}", rows);
+        // Only the named columns are analyzed
+        assert!(!rows.iter().any(|row| row[0] == Utf8("STATS ID".into())));
+
+        for (sql, message) in [("ANALYZE TABLE T COLUMNS (NOPE)", "Column NOPE not found"), ("ANALYZE TABLE T COLUMNS (ID, ID)", "names a column more than once")] {
+            let err = query(&mut session, sql).unwrap_err();
+            assert!(matches!(&err, KonaError::Bind(msg) if msg.contains(message)), "{}: {}", sql, err);
+        }
+    }
+}
@@ -1,11 +1,37 @@
 // ast: abstract syntax tree definitions
 
+use arrow2::datatypes::Field;
+
 use crate::{datum::Datum, qgm::QGM};
 
 #[derive(Debug)]
 pub enum AST {
-    CatalogTable { name: String, options: Vec<(String, Datum)> },
-    DescribeTable { name: String },
+    CreateSchema { name: String },
+    CreateTableAs { schema: Option<String>, name: String, options: Vec<(String, Datum)>, qgm: QGM },
+    InsertInto { schema: Option<String>, name: String, qgm: QGM },
+    CreateView { schema: Option<String>, name: String, columns: Option<Vec<String>>, sql: String, qgm: QGM, or_replace: bool },
+    CatalogTable { schema: Option<String>, name: String, columns: Option<Vec<Field>>, options: Vec<(String, Datum)>, or_replace: bool },
+    AlterTable { schema: Option<String>, name: String, options: Vec<(String, Datum)> },
+    DropTable { schema: Option<String>, name: String, if_exists: bool, is_view: bool },
+    ShowTables,
+    AnalyzeTable { schema: Option<String>, name: String, columns: Option<Vec<(String, bool)>> },
+    ShowColumns { schema: Option<String>, name: String },
+    DescribeTable { schema: Option<String>, name: String },
+    DescribeView { schema: Option<String>, name: String },
     QGM(QGM),
+    Explain { mode: ExplainMode, qgm: QGM },
     SetOption { name: String, value: Datum },
+    ShowOption { name: Option<String> },  // None shows all settings
+    ResetOption { name: Option<String> }, // None resets all settings
+    Prepare { name: String, sql: String, qgm: QGM },
+    Execute { name: String, values: Vec<Datum> },
+    Deallocate { name: Option<String> }, // None deallocates all prepared statements
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExplainMode {
+    All, // Logical and physical plans
+    Logical,
+    Physical,
+    Analyze, // Physical plan, run and annotated with runtime metrics
 }
@@ -0,0 +1,166 @@
+// catalog: tables, views and schemas persisted across jobs
+
+use std::{
+    fs::File,
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    includes::*,
+    metadata::{TableStats, TableType},
+};
+
+// Bumped whenever the entry layout changes incompatibly
+pub const CATALOG_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub version: u32,
+    pub name: String,
+    pub object: CatalogObject,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum CatalogObject {
+    Table { typ: TableType, path: String, header: Option<bool>, separator: Option<char>, partitions: usize, stats: TableStats, columns: Vec<Field> },
+    View { sql: String, columns: Vec<Field> },
+}
+
+// One directory per schema and one JSON file per table or view: <dir>/<SCHEMA>/<NAME>.json. Jobs that catalog different
+// tables never rewrite each other's files, and a file is only ever replaced by renaming a complete copy over it.
+#[derive(Debug)]
+pub struct Catalog {
+    dir: PathBuf,
+}
+
+impl Catalog {
+    pub fn open(dir: &str) -> Result<Self, KonaError> {
+        fs::create_dir_all(dir).map_err(|err| KonaError::io(f!("Cannot open catalog {dir}"), err))?;
+        Ok(Catalog { dir: PathBuf::from(dir) })
+    }
+
+    fn entry_path(&self, schema: &str, name: &str) -> PathBuf {
+        self.dir.join(schema).join(f!("{name}.json"))
+    }
+
+    // Returns every schema along with the entries cataloged in it
+    pub fn load(&self) -> Result<Vec<(String, Vec<CatalogEntry>)>, KonaError> {
+        let mut schemas = vec![];
+        for schema_dir in Self::read_dir(&self.dir)? {
+            if !schema_dir.is_dir() {
+                continue;
+            }
+            let schema = schema_dir.file_name().unwrap().to_string_lossy().to_string();
+            let mut entries = vec![];
+            // Temporary files left behind by an interrupted write don't end in .json
+            for path in Self::read_dir(&schema_dir)?.into_iter().filter(|path| path.extension().is_some_and(|ext| ext == "json")) {
+                entries.push(Self::read_entry(&path)?);
+            }
+            schemas.push((schema, entries));
+        }
+        Ok(schemas)
+    }
+
+    fn read_dir(dir: &Path) -> Result<Vec<PathBuf>, KonaError> {
+        let context = || f!("Cannot read catalog directory {}", dir.display());
+        let entries = fs::read_dir(dir).map_err(|err| KonaError::io(context(), err))?;
+        let mut paths = entries.map(|entry| entry.map(|entry| entry.path())).collect::<Result<Vec<_>, _>>().map_err(|err| KonaError::io(context(), err))?;
+        paths.sort();
+        Ok(paths)
+    }
+
+    // Entries that can't be understood are reported as InvalidData
+    fn read_entry(path: &Path) -> Result<CatalogEntry, KonaError> {
+        let contents = fs::read_to_string(path).map_err(|err| KonaError::io(f!("Cannot read catalog entry {}", path.display()), err))?;
+        let entry: CatalogEntry =
+            serde_json::from_str(&contents).map_err(|err| KonaError::io(f!("Invalid catalog entry {}", path.display()), io::Error::new(ErrorKind::InvalidData, err)))?;
+        if entry.version > CATALOG_VERSION {
+            let message = f!("version {} is newer than the supported version {CATALOG_VERSION}", entry.version);
+            return Err(KonaError::io(f!("Invalid catalog entry {}", path.display()), io::Error::new(ErrorKind::InvalidData, message)));
+        }
+        Ok(entry)
+    }
+
+    pub fn create_schema(&self, schema: &str) -> Result<(), KonaError> {
+        let dir = self.dir.join(schema);
+        fs::create_dir_all(&dir).map_err(|err| KonaError::io(f!("Cannot create catalog directory {}", dir.display()), err))
+    }
+
+    pub fn save(&self, schema: &str, entry: &CatalogEntry) -> Result<(), KonaError> {
+        self.create_schema(schema)?;
+        let path = self.entry_path(schema, &entry.name);
+        let contents = serde_json::to_string_pretty(entry).map_err(|err| KonaError::io(f!("Cannot write catalog entry {}", path.display()), err.into()))?;
+
+        // Write a private copy first so that readers only ever see a complete entry
+        let tmp_path = path.with_extension(f!("json.{}.tmp", std::process::id()));
+        let write_tmp = || -> std::io::Result<()> {
+            let mut file = File::create(&tmp_path)?;
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        };
+        write_tmp().and_then(|_| fs::rename(&tmp_path, &path)).map_err(|err| {
+            let _ = fs::remove_file(&tmp_path);
+            KonaError::io(f!("Cannot write catalog entry {}", path.display()), err)
+        })
+    }
+
+    pub fn remove(&self, schema: &str, name: &str) -> Result<(), KonaError> {
+        let path = self.entry_path(schema, name);
+        match fs::remove_file(&path) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(KonaError::io(f!("Cannot remove catalog entry {}", path.display()), err)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metadata::Metadata, testing::*};
+
+    #[test]
+    fn entries_outlive_the_session_that_made_them() {
+        let dir = temp_dir("CATALOG");
+        let path = f!("{}/T.csv", temp_dir("T"));
+        fs::write(&path, "1,a\n2,b\n").unwrap();
+
+        let mut session = session_with_catalog(Some(&dir));
+        execute(&mut session, "CREATE SCHEMA S").unwrap();
+        execute(&mut session, &f!("CATALOG TABLE S.T (ID INT, NAME STRING) WITH (TYPE = 'CSV', PATH = '{path}', HEADER = 'N')")).unwrap();
+        execute(&mut session, "CREATE VIEW S.V AS SELECT NAME FROM S.T WHERE ID = 2").unwrap();
+        execute(&mut session, "CATALOG TABLE S.GONE (ID INT) WITH (TYPE = 'CSV', PATH = 'gone.csv', HEADER = 'N')").unwrap();
+        execute(&mut session, "DROP TABLE S.GONE").unwrap();
+        drop(session);
+
+        let mut session = session_with_catalog(Some(&dir));
+        assert_eq!(query(&mut session, "SELECT ID FROM S.T WHERE NAME = 'a'").unwrap(), vec![vec![Int64(1)]]);
+        assert_eq!(query(&mut session, "SELECT NAME FROM S.V").unwrap(), vec![vec![Utf8("b".into())]]);
+        assert!(matches!(query(&mut session, "SELECT ID FROM S.GONE"), Err(KonaError::Bind(_))));
+        assert!(Path::new(&dir).join("S").join("V.json").exists());
+    }
+
+    #[test]
+    fn newer_or_damaged_entries_are_rejected() {
+        let dir = temp_dir("CATALOG");
+        let catalog = Catalog::open(&dir).unwrap();
+        catalog
+            .save(
+                "PUBLIC",
+                &CatalogEntry { version: CATALOG_VERSION, name: String::from("V"), object: CatalogObject::View { sql: String::from("SELECT 1"), columns: vec![] } },
+            )
+            .unwrap();
+        // Interrupted writes leave temporary files behind, which are ignored
+        fs::write(Path::new(&dir).join("PUBLIC").join("W.json.1.tmp"), "{").unwrap();
+        assert_eq!(catalog.load().unwrap()[0].1.len(), 1);
+
+        let entry_path = catalog.entry_path("PUBLIC", "V");
+        let contents = fs::read_to_string(&entry_path).unwrap();
+        fs::write(&entry_path, contents.replace(&f!("\"version\": {CATALOG_VERSION}"), "\"version\": 99")).unwrap();
+        let err = Metadata::open(&dir).err().unwrap();
+        assert!(matches!(&err, KonaError::Io { source, .. } if source.kind() == ErrorKind::InvalidData && source.to_string().contains("version 99")), "{}", err);
+
+        fs::write(&entry_path, "{").unwrap();
+        assert!(matches!(Metadata::open(&dir), Err(KonaError::Io { source, .. }) if source.kind() == ErrorKind::InvalidData));
+    }
+}
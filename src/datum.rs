@@ -1,10 +1,15 @@
 // row: Representation of N-tuples
 
-use std::{collections::HashMap, fmt};
+use std::{collections::HashMap, convert::TryFrom, fmt};
 
+use arrow2::{array::Utf8Array, types::months_days_ns};
 use lazy_static::lazy_static;
 
-use crate::includes::*;
+use crate::{
+    decimal,
+    includes::*,
+    temporal::{self, is_temporal},
+};
 
 #[allow(dead_code)]
 pub struct DataTypeDesc<'a> {
@@ -36,7 +41,8 @@ lazy_static! {
             (DataType::Float64, DataTypeDesc::new("FLOAT64", true, 11)),
             (DataType::Date32, DataTypeDesc::new("DATE32", true, 12)),
             (DataType::Date64, DataTypeDesc::new("DATE64", true, 13)),
-            (DataType::Decimal(15, 2), DataTypeDesc::new("DECIMAL(15,2)", true, 13)),
+            (DataType::Decimal(decimal::DEFAULT_PRECISION, 0), DataTypeDesc::new("DECIMAL", true, 13)),
+            (temporal::timestamp_datatype(None), DataTypeDesc::new("TIMESTAMP", false, 14)),
         ];
         for (typ, metadata) in type_metadata.into_iter() {
             map.insert(typ, metadata);
@@ -53,22 +59,48 @@ lazy_static! {
     };
 }
 
-pub fn is_numeric(dt: &DataType) -> bool {
-    let metadata = DATATYPE_PROPS.get(dt);
-    if let Some(metadata) = metadata {
-        metadata.is_numeric
+// All DECIMAL(p,s) types share one entry
+fn get_datatype_desc(dt: &DataType) -> Option<&'static DataTypeDesc<'static>> {
+    if decimal::is_decimal(dt) {
+        DATATYPE_PROPS.get(&DataType::Decimal(decimal::DEFAULT_PRECISION, 0))
     } else {
-        panic!("is_numeric() passed invalid datatype")
+        DATATYPE_PROPS.get(dt)
+    }
+}
+
+pub fn is_numeric(dt: &DataType) -> bool {
+    let metadata = get_datatype_desc(dt);
+    metadata.is_some_and(|metadata| metadata.is_numeric)
+}
+
+// Accepts the internal type names (INT64, FLOAT64, DATE32, ...) as well as their common SQL spellings
+pub fn parse_datatype(name: &str) -> Result<DataType, String> {
+    if let Some(datatype) = decimal::parse_datatype(name) {
+        return datatype;
+    }
+    let name = name.trim().to_uppercase();
+    let datatype = match name.as_str() {
+        "STRING" | "VARCHAR" | "CHAR" | "CHARACTER" | "TEXT" | "UTF8" => DataType::Utf8,
+        "BOOLEAN" | "BOOL" => DataType::Boolean,
+        "INT" | "INTEGER" | "BIGINT" => DataType::Int64,
+        "DOUBLE" | "DOUBLE PRECISION" | "FLOAT" | "REAL" => DataType::Float64,
+        "DATE" => DataType::Date32,
+        "TIMESTAMPTZ" => temporal::timestamp_datatype(Some(String::from("+00:00"))),
+        _ => STR_TO_DATATYPE.get(&name).cloned().ok_or_else(|| f!("Invalid datatype {name}"))?,
+    };
+    // Hash joins and aggregation have no kernels for narrower or unsigned integers and other float widths yet
+    match datatype {
+        DataType::Boolean | DataType::Int32 | DataType::Int64 | DataType::Float64 | DataType::Utf8 | DataType::Date32 | DataType::Timestamp(..) => Ok(datatype),
+        _ => Err(f!("Datatype {name} is not supported, use INT, BIGINT or DOUBLE instead")),
     }
 }
 
-pub fn to_datatype(name: &str) -> Option<&DataType> {
-    let name = name.to_uppercase();
-    STR_TO_DATATYPE.get(&name)
+pub fn to_datatype(name: &str) -> Option<DataType> {
+    parse_datatype(name).ok()
 }
 
 pub fn get_rank(dt: &DataType) -> usize {
-    let metadata = DATATYPE_PROPS.get(dt);
+    let metadata = get_datatype_desc(dt);
     if let Some(metadata) = metadata {
         metadata.rank
     } else {
@@ -86,15 +118,87 @@ pub enum CastResult {
 }
 
 pub fn check_castability(from: &DataType, to: &DataType) -> CastResult {
-    if *from == *to {
+    if common_supertype(from, to).as_ref() == Some(to) {
         CastResult::Implicit
-    } else if is_numeric(from) && is_numeric(to) {
+    } else if (is_numeric(from) && is_numeric(to)) || *from == DataType::Utf8 || *to == DataType::Utf8 || (is_temporal(from) && is_temporal(to)) {
         CastResult::Explicit
     } else {
         CastResult::CannotCast
     }
 }
 
+fn integer_width(dt: &DataType) -> Option<(usize, bool)> {
+    // (bytes needed to hold every value as a signed integer, is_unsigned)
+    let width = match dt {
+        DataType::Int8 => (1, false),
+        DataType::Int16 => (2, false),
+        DataType::Int32 => (4, false),
+        DataType::Int64 => (8, false),
+        DataType::UInt8 => (2, true),
+        DataType::UInt16 => (4, true),
+        DataType::UInt32 | DataType::UInt64 => (8, true),
+        _ => return None,
+    };
+    Some(width)
+}
+
+// Implicit coercion lattice. Two types meet at the later type of the chain they share:
+//    INT8 -> INT16 -> INT32 -> INT64 -> DECIMAL -> FLOAT64      (unsigned ints join at the next wider signed type)
+//    FLOAT32 -> FLOAT64
+//    DATE32 -> TIMESTAMP
+//    NULL -> any type
+// String literals additionally coerce to dates and timestamps; that happens where the literal is folded.
+pub fn common_supertype(lhs: &DataType, rhs: &DataType) -> Option<DataType> {
+    let is_float = |dt: &DataType| matches!(dt, DataType::Float32 | DataType::Float64);
+
+    let datatype = match (lhs, rhs) {
+        _ if lhs == rhs => lhs.clone(),
+        (DataType::Null, other) | (other, DataType::Null) => other.clone(),
+        (DataType::Timestamp(..), DataType::Date32) => lhs.clone(),
+        (DataType::Date32, DataType::Timestamp(..)) => rhs.clone(),
+        _ if is_float(lhs) && (is_float(rhs) || decimal::precision_scale(rhs).is_some()) => DataType::Float64,
+        _ if is_float(rhs) && decimal::precision_scale(lhs).is_some() => DataType::Float64,
+        (DataType::Decimal(..), _) | (_, DataType::Decimal(..)) => {
+            let (lhs, rhs) = (decimal::precision_scale(lhs)?, decimal::precision_scale(rhs)?);
+            decimal::common_datatype(lhs, rhs)
+        }
+        _ => {
+            let ((lhs_width, lhs_unsigned), (rhs_width, rhs_unsigned)) = (integer_width(lhs)?, integer_width(rhs)?);
+            if lhs_unsigned && rhs_unsigned {
+                if lhs_width >= rhs_width {
+                    lhs.clone()
+                } else {
+                    rhs.clone()
+                }
+            } else {
+                match lhs_width.max(rhs_width) {
+                    1 => DataType::Int8,
+                    2 => DataType::Int16,
+                    4 => DataType::Int32,
+                    _ => DataType::Int64,
+                }
+            }
+        }
+    };
+    Some(datatype)
+}
+
+// Convert a literal to `to_datatype`, failing if the value doesn't fit
+pub fn cast_literal(datum: &Datum, to_datatype: &DataType) -> Result<Datum, String> {
+    let datum = match (datum, to_datatype) {
+        _ if datum.datatype() == *to_datatype => datum.clone(),
+        (Null, _) => Null,
+        (Int32(value), DataType::Int64) => Int64(*value as i64),
+        (Int64(value), DataType::Int32) => Int32(i32::try_from(*value).map_err(|_| f!("{} does not fit in {:?}", datum, to_datatype))?),
+        (Int32(value), DataType::Float64) => Float64(F64::from(*value as f64)),
+        (Int64(value), DataType::Float64) => Float64(F64::from(*value as f64)),
+        (Decimal(..), _) | (_, DataType::Decimal(..)) => decimal::cast_datum(datum, to_datatype)?,
+        (_, DataType::Date32 | DataType::Timestamp(..)) => temporal::coerce_temporal_literal(datum, to_datatype)?,
+        _ => return Err(f!("Cannot convert {} to {:?}", datum, to_datatype)),
+    };
+    Ok(datum)
+}
+
 #[derive(Clone, Copy, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub struct F64(u64);
 impl From<f64> for F64 {
@@ -132,18 +236,92 @@ pub enum Datum {
     Utf8(String),
     Date32(i32),
     Float64(F64),
+    Timestamp(i64, Option<String>), // microseconds since epoch (UTC), timezone
+    Interval(i32, i32, i64),        // months, days, nanoseconds
+    Decimal(i128, usize, usize),    // value scaled by 10^scale, precision, scale
 }
 
 impl Datum {
     #[inline]
     pub fn try_as_i64(&self) -> Option<i64> {
-        if let Int64(val) = self {
+        match *self {
+            Int64(val) => Some(val),
+            Timestamp(val, _) => Some(val),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    pub fn try_as_i128(&self) -> Option<i128> {
+        if let Decimal(val, ..) = self {
             Some(*val)
         } else {
             None
         }
     }
 
+    #[inline]
+    pub fn try_as_interval(&self) -> Option<months_days_ns> {
+        if let Interval(months, days, nanos) = self {
+            Some(months_days_ns::new(*months, *days, *nanos))
+        } else {
+            None
+        }
+    }
+
+    pub fn timezone(&self) -> Option<&String> {
+        if let Timestamp(_, tz) = self {
+            tz.as_ref()
+        } else {
+            None
+        }
+    }
+
+    // Broadcast the value into an array of `len` elements
+    pub fn to_array(&self, len: usize) -> Box<dyn Array> {
+        match self {
+            Null => arrow2::array::new_null_array(DataType::Null, len),
+            Boolean(b) => Box::new(BooleanArray::from_slice(vec![*b; len])),
+            Int32(i) => Box::new(PrimitiveArray::from_vec(vec![*i; len])),
+            Int64(i) => Box::new(PrimitiveArray::from_vec(vec![*i; len])),
+            Utf8(s) => Box::new(Utf8Array::<i32>::from_iter_values(std::iter::repeat_n(s, len))),
+            Date32(d) => Box::new(PrimitiveArray::from_vec(vec![*d; len]).to(DataType::Date32)),
+            Float64(f) => Box::new(PrimitiveArray::from_vec(vec![f64::from(*f); len])),
+            Timestamp(ts, tz) => Box::new(PrimitiveArray::from_vec(vec![*ts; len]).to(temporal::timestamp_datatype(tz.clone()))),
+            Interval(..) => Box::new(PrimitiveArray::from_vec(vec![self.try_as_interval().unwrap(); len])),
+            Decimal(value, precision, scale) => decimal::literal_to_array(*value, *precision, *scale, len),
+        }
+    }
+
+    // The value at position `ix` of an array, or None if it's null
+    pub fn from_array(array: &dyn Array, ix: usize) -> Result<Option<Datum>, String> {
+        fn value<T: arrow2::types::NativeType>(array: &dyn Array, ix: usize) -> T {
+            array.as_any().downcast_ref::<PrimitiveArray<T>>().unwrap().value(ix)
+        }
+
+        if array.is_null(ix) {
+            return Ok(None);
+        }
+        let datum = match array.data_type() {
+            DataType::Boolean => Boolean(array.as_any().downcast_ref::<BooleanArray>().unwrap().value(ix)),
+            DataType::Int8 => Int32(value::<i8>(array, ix) as i32),
+            DataType::Int16 => Int32(value::<i16>(array, ix) as i32),
+            DataType::Int32 => Int32(value::<i32>(array, ix)),
+            DataType::Int64 => Int64(value::<i64>(array, ix)),
+            DataType::Float64 => Float64(F64::from(value::<f64>(array, ix))),
+            DataType::Utf8 => Utf8(array.as_any().downcast_ref::<Utf8Array<i32>>().unwrap().value(ix).to_string()),
+            DataType::Date32 => Date32(value::<i32>(array, ix)),
+            DataType::Timestamp(_, tz) => Timestamp(value::<i64>(array, ix), tz.clone()),
+            DataType::Interval(_) => {
+                let interval = value::<months_days_ns>(array, ix);
+                Interval(interval.months(), interval.days(), interval.ns())
+            }
+            DataType::Decimal(precision, scale) => Decimal(value::<i128>(array, ix), *precision, *scale),
+            typ => return Err(f!("Values of type {typ:?} are not supported")),
+        };
+        Ok(Some(datum))
+    }
+
     pub fn add_i64(&mut self, other: i64) {
         if let Int64(val) = self {
             *val += other
@@ -195,9 +373,12 @@ impl Datum {
             Boolean(_) => DataType::Boolean,
             Int32(_) => DataType::Int32,
             Int64(_) => DataType::Int64,
-            Utf8(_) => DataType::UInt64,
+            Utf8(_) => DataType::Utf8,
             Date32(_) => DataType::Date32,
             Float64(_) => DataType::Float64,
+            Timestamp(_, tz) => temporal::timestamp_datatype(tz.clone()),
+            Interval(..) => temporal::interval_datatype(),
+            Decimal(_, precision, scale) => DataType::Decimal(*precision, *scale),
         }
     }
 }
@@ -210,8 +391,66 @@ impl fmt::Display for Datum {
             Int32(il) => write!(f, "{}", il),
             Int64(il) => write!(f, "{}", il),
             Utf8(sl) => write!(f, "\"{}\"", sl),
-            Date32(d) => write!(f, "\"{}\"", d),
-            Float64(value) => write!(f, "{:?}", Into::<F64>::into(*value)),
+            Date32(d) => write!(f, "DATE '{}'", temporal::format_date(*d)),
+            Float64(value) => write!(f, "{:?}", f64::from(*value)),
+            Timestamp(ts, tz) => write!(f, "TIMESTAMP '{}'", temporal::format_timestamp(*ts, tz.as_ref())),
+            Interval(..) => write!(f, "INTERVAL '{}'", temporal::format_interval(&self.try_as_interval().unwrap())),
+            Decimal(value, _, scale) => write!(f, "DECIMAL '{}'", decimal::format_decimal(*value, *scale)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn coercion_lattice() {
+        assert_eq!(common_supertype(&DataType::Int32, &DataType::Int64), Some(DataType::Int64));
+        assert_eq!(common_supertype(&DataType::Int8, &DataType::UInt8), Some(DataType::Int16));
+        assert_eq!(common_supertype(&DataType::UInt16, &DataType::UInt32), Some(DataType::UInt32));
+        assert_eq!(common_supertype(&DataType::Int64, &DataType::Decimal(10, 2)), Some(DataType::Decimal(21, 2)));
+        assert_eq!(common_supertype(&DataType::Decimal(10, 2), &DataType::Float32), Some(DataType::Float64));
+        assert_eq!(common_supertype(&DataType::Null, &DataType::Utf8), Some(DataType::Utf8));
+        assert_eq!(common_supertype(&DataType::Date32, &temporal::timestamp_datatype(None)), Some(temporal::timestamp_datatype(None)));
+        assert_eq!(common_supertype(&DataType::Utf8, &DataType::Int64), None);
+        assert_eq!(common_supertype(&DataType::Date32, &DataType::Int64), None);
+
+        assert!(matches!(check_castability(&DataType::Int32, &DataType::Float64), CastResult::Implicit));
+        assert!(matches!(check_castability(&DataType::Float64, &DataType::Int32), CastResult::Explicit));
+        assert!(matches!(check_castability(&DataType::Utf8, &DataType::Date32), CastResult::Explicit));
+        assert!(matches!(check_castability(&DataType::Boolean, &DataType::Date32), CastResult::CannotCast));
+    }
+
+    #[test]
+    fn literal_conversions() {
+        assert_eq!(cast_literal(&Int32(7), &DataType::Int64), Ok(Int64(7)));
+        assert_eq!(cast_literal(&Int64(7), &DataType::Int32), Ok(Int32(7)));
+        assert!(cast_literal(&Int64(i64::MAX), &DataType::Int32).is_err());
+        assert_eq!(cast_literal(&Int64(3), &DataType::Float64), Ok(Float64(F64::from(3.0))));
+        assert_eq!(cast_literal(&Null, &DataType::Utf8), Ok(Null));
+        assert_eq!(cast_literal(&Utf8("2020-01-02".into()), &DataType::Date32), Ok(Date32(temporal::parse_date("2020-01-02").unwrap())));
+        assert!(cast_literal(&Utf8("x".into()), &DataType::Int64).is_err());
+    }
+
+    #[test]
+    fn mixed_type_expressions() {
+        let mut session = session();
+        create_table(&mut session, "T", "I INT, F DOUBLE, S STRING, D DATE", &["1,1.5,x,2020-01-02", "2,2.5,y,2020-01-03"]);
+
+        let rows = query(&mut session, "SELECT I + F, I * 2.5 FROM T WHERE F > 2").unwrap();
+        assert_eq!(rows, vec![vec![Float64(F64::from(4.5)), Float64(F64::from(5.0))]]);
+        let rows = query(&mut session, "SELECT S FROM T WHERE D >= '2020-01-03' AND I = 2.0").unwrap();
+        assert_eq!(rows, vec![vec![Utf8("y".into())]]);
+
+        // NULL operands yield NULL, and comparisons with NULL match nothing
+        let rows = query(&mut session, "SELECT S, I + NULL FROM T WHERE I = 1").unwrap();
+        assert_eq!(rows, vec![vec![Utf8("x".into()), Null]]);
+        assert_eq!(query(&mut session, "SELECT S FROM T WHERE I = NULL").unwrap(), Vec::<Vec<Datum>>::new());
+
+        for sql in ["SELECT S + I FROM T", "SELECT S FROM T WHERE D = I"] {
+            assert!(matches!(query(&mut session, sql), Err(KonaError::Bind(_))), "{}", sql);
         }
     }
 }
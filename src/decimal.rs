@@ -0,0 +1,398 @@
+// decimal: Fixed-point DECIMAL(p,s) values and arithmetic
+
+// A DECIMAL(p,s) value is an i128 holding the number scaled by 10^s. All arithmetic below works on those
+// integers directly so that money amounts never round-trip through f64.
+
+use std::{cmp, convert::TryFrom};
+
+use arrow2::{array::Utf8Array, compute::cast};
+
+use crate::{datum::F64, expr::ArithOp, includes::*};
+
+pub const MAX_PRECISION: usize = 38;
+pub const DEFAULT_PRECISION: usize = 18;
+
+// Division keeps at least this many fractional digits, as does any result whose precision had to be capped
+pub const MIN_ADJUSTED_SCALE: usize = 6;
+
+pub fn is_decimal(dt: &DataType) -> bool {
+    matches!(dt, DataType::Decimal(..))
+}
+
+// Precision and scale of a type that can take part in decimal arithmetic. Integers behave as DECIMAL(p,0).
+pub fn precision_scale(dt: &DataType) -> Option<(usize, usize)> {
+    match dt {
+        DataType::Decimal(p, s) => Some((*p, *s)),
+        DataType::Int32 => Some((10, 0)),
+        DataType::Int64 => Some((19, 0)),
+        _ => None,
+    }
+}
+
+pub fn decimal_datatype(precision: usize, scale: usize) -> Result<DataType, String> {
+    if precision == 0 || precision > MAX_PRECISION {
+        Err(f!("DECIMAL precision must be between 1 and {MAX_PRECISION}, found {precision}"))
+    } else if scale > precision {
+        Err(f!("DECIMAL scale {scale} exceeds precision {precision}"))
+    } else {
+        Ok(DataType::Decimal(precision, scale))
+    }
+}
+
+// Parse a type name of the form DECIMAL, DECIMAL(p) or DECIMAL(p,s). NUMERIC is accepted as a synonym.
+pub fn parse_datatype(name: &str) -> Option<Result<DataType, String>> {
+    let name = name.to_uppercase().replace(' ', "");
+    let args = name.strip_prefix("DECIMAL").or_else(|| name.strip_prefix("NUMERIC"))?;
+    if args.is_empty() {
+        return Some(decimal_datatype(DEFAULT_PRECISION, 0));
+    }
+    let args = args.strip_prefix('(').and_then(|args| args.strip_suffix(')'))?;
+    let args = args.split(',').map(|arg| arg.parse::<usize>()).collect::<Result<Vec<_>, _>>();
+    let datatype = match args.as_deref() {
+        Ok([precision]) => decimal_datatype(*precision, 0),
+        Ok([precision, scale]) => decimal_datatype(*precision, *scale),
+        _ => Err(f!("Invalid datatype {name}")),
+    };
+    Some(datatype)
+}
+
+// Result type of `lhs <op> rhs`, following SQL Server:
+//    +, -    scale = max(s1, s2)              precision = max(p1 - s1, p2 - s2) + scale + 1
+//    *       scale = s1 + s2                  precision = p1 + p2 + 1
+//    /       scale = max(6, s1 + p2 + 1)      precision = p1 - s1 + s2 + scale
+// When the precision exceeds 38, integral digits are kept and the scale is reduced, but not below 6.
+pub fn result_datatype(op: ArithOp, lhs: (usize, usize), rhs: (usize, usize)) -> DataType {
+    let ((p1, s1), (p2, s2)) = (lhs, rhs);
+    let (precision, scale) = match op {
+        ArithOp::Add | ArithOp::Sub => {
+            let scale = cmp::max(s1, s2);
+            (cmp::max(p1 - s1, p2 - s2) + scale + 1, scale)
+        }
+        ArithOp::Mul => (p1 + p2 + 1, s1 + s2),
+        ArithOp::Div => {
+            let scale = cmp::max(MIN_ADJUSTED_SCALE, s1 + p2 + 1);
+            (p1 - s1 + s2 + scale, scale)
+        }
+    };
+    if precision > MAX_PRECISION {
+        let integral_digits = precision - scale;
+        let min_scale = cmp::min(scale, MIN_ADJUSTED_SCALE);
+        let scale = cmp::max(min_scale, MAX_PRECISION.saturating_sub(integral_digits));
+        DataType::Decimal(MAX_PRECISION, scale)
+    } else {
+        DataType::Decimal(precision, scale)
+    }
+}
+
+// Smallest type that holds both operands of a comparison without losing digits
+pub fn common_datatype(lhs: (usize, usize), rhs: (usize, usize)) -> DataType {
+    let ((p1, s1), (p2, s2)) = (lhs, rhs);
+    let scale = cmp::max(s1, s2);
+    let precision = cmp::min(cmp::max(p1 - s1, p2 - s2) + scale, MAX_PRECISION);
+    DataType::Decimal(precision, cmp::min(scale, precision))
+}
+
+/***************************************************************************************************/
+// Scalar values
+
+fn pow10(exp: usize) -> Option<i128> {
+    10_i128.checked_pow(exp as u32)
+}
+
+pub fn fits_precision(value: i128, precision: usize) -> bool {
+    pow10(precision).is_none_or(|limit| value.unsigned_abs() < limit as u128)
+}
+
+// Integer division rounding half away from zero
+fn div_round(num: i128, den: i128) -> Option<i128> {
+    let (quotient, remainder) = (num.checked_div(den)?, num.checked_rem(den)?);
+    if remainder.unsigned_abs() >= den.unsigned_abs() - remainder.unsigned_abs() {
+        let sign = if (num < 0) == (den < 0) { 1 } else { -1 };
+        quotient.checked_add(sign)
+    } else {
+        Some(quotient)
+    }
+}
+
+// Change the scale of `value`, rounding when fractional digits are dropped
+pub fn rescale(value: i128, from_scale: usize, to_scale: usize) -> Option<i128> {
+    if to_scale >= from_scale {
+        value.checked_mul(pow10(to_scale - from_scale)?)
+    } else {
+        div_round(value, pow10(from_scale - to_scale)?)
+    }
+}
+
+// Parse "123.45" into (12345, precision = 5, scale = 2)
+pub fn parse_decimal(s: &str) -> Result<(i128, usize, usize), String> {
+    let err = || f!("Invalid DECIMAL value '{s}'");
+    let trimmed = s.trim();
+    let (negative, digits) = match trimmed.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let (integral, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if integral.is_empty() && fraction.is_empty() || !integral.chars().chain(fraction.chars()).all(|ch| ch.is_ascii_digit()) {
+        return Err(err());
+    }
+
+    let integral = integral.trim_start_matches('0');
+    let scale = fraction.len();
+    let precision = cmp::max(integral.len() + scale, 1);
+    if precision > MAX_PRECISION {
+        return Err(f!("DECIMAL value '{s}' exceeds the maximum precision of {MAX_PRECISION}"));
+    }
+    let value = f!("{integral}{fraction}");
+    let value = if value.is_empty() { 0 } else { value.parse::<i128>().map_err(|_| err())? };
+    Ok((if negative { -value } else { value }, precision, scale))
+}
+
+// Parse a string into a DECIMAL(p,s) value, rounding any extra fractional digits
+pub fn parse_decimal_as(s: &str, precision: usize, scale: usize) -> Result<i128, String> {
+    let (value, _, from_scale) = parse_decimal(s)?;
+    rescale(value, from_scale, scale).filter(|&value| fits_precision(value, precision)).ok_or_else(|| f!("'{s}' does not fit in DECIMAL({precision},{scale})"))
+}
+
+pub fn format_decimal(value: i128, scale: usize) -> String {
+    let digits = value.unsigned_abs().to_string();
+    let sign = if value < 0 { "-" } else { "" };
+    if scale == 0 {
+        f!("{sign}{digits}")
+    } else {
+        let digits = f!("{:0>width$}", digits, width = scale + 1);
+        let (integral, fraction) = digits.split_at(digits.len() - scale);
+        f!("{sign}{integral}.{fraction}")
+    }
+}
+
+// Exact decimal form of a numeric literal, with the smallest precision and scale that hold it
+pub fn to_decimal_datum(datum: &Datum) -> Option<Datum> {
+    let text = match datum {
+        Int32(value) => value.to_string(),
+        Int64(value) => value.to_string(),
+        Float64(value) => f64::from(*value).to_string(),
+        Decimal(..) => return Some(datum.clone()),
+        _ => return None,
+    };
+    let (value, precision, scale) = parse_decimal(&text).ok()?;
+    Some(Decimal(value, precision, scale))
+}
+
+// Literal conversions to and from DECIMAL, used by CAST and by operand coercion. Floats convert via their
+// shortest textual form so that 0.1 becomes exactly 0.1.
+pub fn cast_datum(datum: &Datum, to_datatype: &DataType) -> Result<Datum, String> {
+    let overflow = || f!("{} does not fit in {:?}", datum, to_datatype);
+    let datum = match (datum, to_datatype) {
+        (Int32(_) | Int64(_) | Float64(_) | Utf8(_), DataType::Decimal(p, s)) => {
+            let text = match datum {
+                Float64(value) => f64::from(*value).to_string(),
+                Utf8(s) => s.clone(),
+                _ => datum.to_string(),
+            };
+            Decimal(parse_decimal_as(&text, *p, *s)?, *p, *s)
+        }
+        (Decimal(value, _, from_scale), DataType::Decimal(p, s)) => {
+            let value = rescale(*value, *from_scale, *s).filter(|&value| fits_precision(value, *p)).ok_or_else(overflow)?;
+            Decimal(value, *p, *s)
+        }
+        (Decimal(value, _, scale), DataType::Int64) => {
+            // Truncates, like SQL Server
+            let value = value / pow10(*scale).ok_or_else(overflow)?;
+            Int64(i64::try_from(value).map_err(|_| overflow())?)
+        }
+        (Decimal(value, _, scale), DataType::Float64) => {
+            let value = format_decimal(*value, *scale).parse::<f64>().map_err(stringify)?;
+            Float64(F64::from(value))
+        }
+        (Decimal(value, _, scale), DataType::Utf8) => Utf8(format_decimal(*value, *scale)),
+        _ => return Err(f!("Cannot convert {} to {:?}", datum, to_datatype)),
+    };
+    Ok(datum)
+}
+
+/***************************************************************************************************/
+// Array kernels, used by PCode
+
+pub fn literal_to_array(value: i128, precision: usize, scale: usize, len: usize) -> Box<dyn Array> {
+    Box::new(PrimitiveArray::<i128>::from_vec(vec![value; len]).to(DataType::Decimal(precision, scale)))
+}
+
+// View an integer or decimal array as i128 values
+fn to_i128(array: &dyn Array) -> Result<PrimitiveArray<i128>, String> {
+    let array = match array.data_type() {
+        DataType::Decimal(..) => array.as_any().downcast_ref::<PrimitiveArray<i128>>().unwrap().clone(),
+        DataType::Int32 => {
+            let array = array.as_any().downcast_ref::<PrimitiveArray<i32>>().unwrap();
+            PrimitiveArray::<i128>::from_trusted_len_iter(array.iter().map(|value| value.map(|&value| value as i128)))
+        }
+        DataType::Int64 => {
+            let array = array.as_any().downcast_ref::<PrimitiveArray<i64>>().unwrap();
+            PrimitiveArray::<i128>::from_trusted_len_iter(array.iter().map(|value| value.map(|&value| value as i128)))
+        }
+        typ => return Err(f!("Expected a decimal or integer, found {:?}", typ)),
+    };
+    Ok(array)
+}
+
+fn apply(op: ArithOp, (lhs, lhs_scale): (i128, usize), (rhs, rhs_scale): (i128, usize), (precision, scale): (usize, usize)) -> Result<i128, String> {
+    let overflow = || f!("Decimal overflow computing {} {} {}", format_decimal(lhs, lhs_scale), op, format_decimal(rhs, rhs_scale));
+    let value = match op {
+        ArithOp::Add | ArithOp::Sub => {
+            let lhs = rescale(lhs, lhs_scale, scale).ok_or_else(overflow)?;
+            let rhs = rescale(rhs, rhs_scale, scale).ok_or_else(overflow)?;
+            if op == ArithOp::Add {
+                lhs.checked_add(rhs)
+            } else {
+                lhs.checked_sub(rhs)
+            }
+        }
+        ArithOp::Mul => lhs.checked_mul(rhs).and_then(|product| rescale(product, lhs_scale + rhs_scale, scale)),
+        ArithOp::Div => {
+            if rhs == 0 {
+                return Err(String::from("Division by zero"));
+            }
+            // lhs / rhs carries scale (lhs_scale - rhs_scale), so shift the dividend (or divisor) to land on `scale`
+            if scale + rhs_scale >= lhs_scale {
+                pow10(scale + rhs_scale - lhs_scale).and_then(|factor| lhs.checked_mul(factor)).and_then(|lhs| div_round(lhs, rhs))
+            } else {
+                pow10(lhs_scale - scale - rhs_scale).and_then(|factor| rhs.checked_mul(factor)).and_then(|rhs| div_round(lhs, rhs))
+            }
+        }
+    };
+    value.filter(|&value| fits_precision(value, precision)).ok_or_else(overflow)
+}
+
+// Element-wise arithmetic between two decimal (or integer) arrays. The result type follows `result_datatype()`.
+pub fn arith(lhs: &dyn Array, op: ArithOp, rhs: &dyn Array) -> Result<Box<dyn Array>, String> {
+    let (lhs_datatype, rhs_datatype) = (lhs.data_type(), rhs.data_type());
+    let (_, lhs_scale) = precision_scale(lhs_datatype).ok_or_else(|| f!("Expected a decimal, found {:?}", lhs_datatype))?;
+    let (_, rhs_scale) = precision_scale(rhs_datatype).ok_or_else(|| f!("Expected a decimal, found {:?}", rhs_datatype))?;
+    let to_datatype = result_datatype(op, precision_scale(lhs_datatype).unwrap(), precision_scale(rhs_datatype).unwrap());
+    let to = precision_scale(&to_datatype).unwrap();
+
+    let (lhs, rhs) = (to_i128(lhs)?, to_i128(rhs)?);
+    let values = lhs
+        .iter()
+        .zip(rhs.iter())
+        .map(|(lhs, rhs)| match (lhs, rhs) {
+            (Some(&lhs), Some(&rhs)) => apply(op, (lhs, lhs_scale), (rhs, rhs_scale), to).map(Some),
+            _ => Ok(None),
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    Ok(Box::new(PrimitiveArray::<i128>::from(values).to(to_datatype)))
+}
+
+// CAST of an array to or from DECIMAL. Values that don't convert become NULL, like Arrow's own casts.
+pub fn cast_array(array: &dyn Array, to_datatype: &DataType) -> Result<Box<dyn Array>, String> {
+    let array: Box<dyn Array> = match (array.data_type(), to_datatype) {
+        (DataType::Utf8, DataType::Decimal(p, s)) => {
+            let array = array.as_any().downcast_ref::<Utf8Array<i32>>().unwrap();
+            let values = array.iter().map(|value| value.and_then(|value| parse_decimal_as(value, *p, *s).ok()));
+            Box::new(PrimitiveArray::<i128>::from_trusted_len_iter(values).to(to_datatype.clone()))
+        }
+        (DataType::Decimal(_, scale), DataType::Utf8) => {
+            let array = array.as_any().downcast_ref::<PrimitiveArray<i128>>().unwrap();
+            let values = array.iter().map(|value| value.map(|&value| format_decimal(value, *scale)));
+            Box::new(Utf8Array::<i32>::from_trusted_len_iter(values))
+        }
+        _ => cast::cast(array, to_datatype, Default::default()).map_err(stringify)?,
+    };
+    Ok(array)
+}
+
+// Arrow's CSV writer has no decimal support, so decimals are written out as their text
+pub fn format_chunk(chunk: &ChunkBox) -> ChunkBox {
+    let arrays = chunk
+        .arrays()
+        .iter()
+        .map(|array| match array.data_type() {
+            DataType::Decimal(..) => cast_array(&**array, &DataType::Utf8).unwrap(),
+            _ => array.clone(),
+        })
+        .collect();
+    Chunk::new(arrays)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn result_precision_and_scale() {
+        assert_eq!(result_datatype(ArithOp::Add, (10, 2), (5, 3)), DataType::Decimal(12, 3));
+        assert_eq!(result_datatype(ArithOp::Sub, (10, 2), (19, 0)), DataType::Decimal(22, 2));
+        assert_eq!(result_datatype(ArithOp::Mul, (10, 2), (5, 3)), DataType::Decimal(16, 5));
+        assert_eq!(result_datatype(ArithOp::Div, (10, 2), (5, 3)), DataType::Decimal(19, 8));
+        // Capped at 38 digits: integral digits are kept, the scale shrinks but not below 6
+        assert_eq!(result_datatype(ArithOp::Mul, (38, 10), (38, 10)), DataType::Decimal(38, 6));
+        assert_eq!(result_datatype(ArithOp::Add, (38, 2), (38, 2)), DataType::Decimal(38, 2));
+        assert_eq!(common_datatype((10, 2), (5, 4)), DataType::Decimal(12, 4));
+    }
+
+    #[test]
+    fn type_names() {
+        assert_eq!(parse_datatype("decimal").unwrap(), Ok(DataType::Decimal(18, 0)));
+        assert_eq!(parse_datatype("NUMERIC(12, 3)").unwrap(), Ok(DataType::Decimal(12, 3)));
+        assert!(parse_datatype("DECIMAL(39,2)").unwrap().is_err());
+        assert!(parse_datatype("DECIMAL(2,3)").unwrap().is_err());
+        assert!(parse_datatype("DOUBLE").is_none());
+    }
+
+    #[test]
+    fn parse_and_format() {
+        assert_eq!(parse_decimal("123.45"), Ok((12345, 5, 2)));
+        assert_eq!(parse_decimal("-0.05"), Ok((-5, 2, 2)));
+        assert_eq!(parse_decimal("+007"), Ok((7, 1, 0)));
+        assert!(parse_decimal("1.2.3").is_err());
+        assert!(parse_decimal(".").is_err());
+        assert!(parse_decimal(&"9".repeat(39)).is_err());
+
+        assert_eq!(parse_decimal_as("1.005", 5, 2), Ok(101));
+        assert_eq!(parse_decimal_as("-1.005", 5, 2), Ok(-101));
+        assert!(parse_decimal_as("1000", 5, 2).is_err());
+
+        assert_eq!(format_decimal(12345, 2), "123.45");
+        assert_eq!(format_decimal(-5, 2), "-0.05");
+        assert_eq!(format_decimal(7, 0), "7");
+    }
+
+    #[test]
+    fn array_arithmetic() {
+        let lhs = literal_to_array(1050, 10, 2, 1);
+        let rhs = literal_to_array(3, 5, 0, 1);
+        let value = |array: Box<dyn Array>| (array.data_type().clone(), Datum::from_array(&*array, 0).unwrap().unwrap());
+        assert_eq!(value(arith(&*lhs, ArithOp::Add, &*rhs).unwrap()), (DataType::Decimal(11, 2), Decimal(1350, 11, 2)));
+        assert_eq!(value(arith(&*lhs, ArithOp::Mul, &*rhs).unwrap()), (DataType::Decimal(16, 2), Decimal(3150, 16, 2)));
+        // 10.50 / 3 = 3.50000000
+        assert_eq!(value(arith(&*lhs, ArithOp::Div, &*rhs).unwrap()), (DataType::Decimal(16, 8), Decimal(350000000, 16, 8)));
+
+        let zero = literal_to_array(0, 5, 0, 1);
+        assert_eq!(arith(&*lhs, ArithOp::Div, &*zero).unwrap_err(), "Division by zero");
+        let big = literal_to_array(10_i128.pow(37), 38, 0, 1);
+        assert!(arith(&*big, ArithOp::Mul, &*big).is_err());
+    }
+
+    #[test]
+    fn literal_casts() {
+        assert_eq!(cast_datum(&Float64(F64::from(0.1)), &DataType::Decimal(5, 2)), Ok(Decimal(10, 5, 2)));
+        assert_eq!(cast_datum(&Decimal(-1999, 5, 2), &DataType::Int64), Ok(Int64(-19)));
+        assert_eq!(cast_datum(&Decimal(1999, 5, 2), &DataType::Decimal(3, 1)), Ok(Decimal(200, 3, 1)));
+        assert!(cast_datum(&Decimal(1999, 5, 2), &DataType::Decimal(3, 2)).is_err());
+    }
+
+    #[test]
+    fn sum_avg_and_arithmetic_in_queries() {
+        let mut session = session();
+        create_table(&mut session, "PRICES", "CAT STRING, AMOUNT DECIMAL(10,2)", &["a,1.10", "a,2.25", "b,0.05"]);
+        // AVG is SUM / COUNT, so it stays exact
+        let sql = "SELECT CAT, SUM(AMOUNT), AVG(AMOUNT) FROM PRICES GROUP BY CAT";
+        let datatypes = session.sql(sql).unwrap()[0].arrays().iter().map(|array| array.data_type().clone()).collect::<Vec<_>>();
+        assert_eq!(datatypes, vec![DataType::Utf8, DataType::Decimal(38, 2), DataType::Decimal(38, 6)]);
+        let rows = query(&mut session, sql).unwrap();
+        assert_eq!(rows, vec![vec![Utf8("a".into()), Decimal(335, 38, 2), Decimal(1675000, 38, 6)], vec![Utf8("b".into()), Decimal(5, 38, 2), Decimal(50000, 38, 6)]]);
+
+        let rows = query(&mut session, "SELECT AMOUNT * 2, AMOUNT + 0.005 FROM PRICES WHERE AMOUNT > 2").unwrap();
+        assert_eq!(rows, vec![vec![Decimal(450, 12, 2), Decimal(2255, 12, 3)]]);
+    }
+}
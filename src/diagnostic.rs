@@ -0,0 +1,136 @@
+// diagnostic: render parser errors against the job source
+
+use lalrpop_util::{lexer::Token, ErrorRecovery, ParseError};
+
+// Errors raised by grammar actions, e.g. an invalid DATE literal, carry the span of the text they are about
+pub type UserError = (usize, String, usize);
+
+pub type SqlParseError<'input> = ParseError<usize, Token<'input>, UserError>;
+
+pub fn user_error<'input>(start: usize, message: impl Into<String>, end: usize) -> SqlParseError<'input> {
+    ParseError::User { error: (start, message.into(), end) }
+}
+
+pub struct SourceFile<'a> {
+    pathname: &'a str,
+    contents: &'a str,
+}
+
+impl<'a> SourceFile<'a> {
+    pub fn new(pathname: &'a str, contents: &'a str) -> Self {
+        SourceFile { pathname, contents }
+    }
+
+    // A summary line, then every recovered error followed by the fatal one, if any
+    pub fn describe_errors(&self, recovered: &[ErrorRecovery<usize, Token<'_>, UserError>], fatal: Option<&SqlParseError<'_>>) -> String {
+        let diagnostics: Vec<String> = recovered.iter().map(|recovery| &recovery.error).chain(fatal).map(|error| self.describe_error(error)).collect();
+        let nerrors = diagnostics.len();
+        let summary = if nerrors == 1 { f!("1 syntax error in {}", self.pathname) } else { f!("{nerrors} syntax errors in {}", self.pathname) };
+        f!("{summary}\n{}", diagnostics.join("\n"))
+    }
+
+    pub fn describe_error(&self, error: &SqlParseError<'_>) -> String {
+        match error {
+            ParseError::InvalidToken { location } => self.render("invalid token", *location, *location + 1),
+            ParseError::UnrecognizedEof { location, expected } => self.render(&f!("unexpected end of input{}", Self::describe_expected(expected)), *location, *location),
+            ParseError::UnrecognizedToken { token: (start, token, end), expected } => {
+                self.render(&format!("unexpected token `{token}`{}", Self::describe_expected(expected)), *start, *end)
+            }
+            ParseError::ExtraToken { token: (start, token, end) } => self.render(&format!("extra token `{token}`"), *start, *end),
+            ParseError::User { error: (start, message, end) } => self.render(message, *start, *end),
+        }
+    }
+
+    // Point a caret (or a run of them) at the source span [start, end)
+    fn render(&self, message: &str, start: usize, end: usize) -> String {
+        let start = start.min(self.contents.len());
+        let line_start = self.contents[..start].rfind('\n').map_or(0, |ix| ix + 1);
+        let line_end = self.contents[start..].find('\n').map_or(self.contents.len(), |ix| start + ix);
+        let line_no = self.contents[..start].matches('\n').count() + 1;
+        let source_line = self.contents[line_start..line_end].trim_end_matches('\r');
+
+        let column = self.contents[line_start..start].chars().count() + 1;
+        let width = self.contents[start..end.clamp(start, line_end)].chars().count().max(1);
+        let margin = " ".repeat(line_no.to_string().len());
+
+        f!(
+            "error: {message}\n{margin}--> {}:{line_no}:{column}\n{margin} |\n{line_no} | {source_line}\n{margin} | {}{}",
+            self.pathname,
+            " ".repeat(column - 1),
+            "^".repeat(width)
+        )
+    }
+
+    fn describe_expected(expected: &[String]) -> String {
+        let mut names: Vec<String> = expected.iter().map(|token| Self::token_name(token)).collect();
+        names.dedup();
+        match names.len() {
+            0 => String::new(),
+            1 => f!(", expected {}", names[0]),
+            _ => f!(", expected one of {}", names.join(", ")),
+        }
+    }
+
+    // LALRPOP spells terminals as they appear in the grammar: keywords and punctuation quoted, the rest as raw regexes
+    fn token_name(token: &str) -> String {
+        let regex = token.strip_prefix("r#\"").and_then(|token| token.strip_suffix("\"#"));
+        match regex {
+            Some(r"[a-zA-Z][a-zA-Z0-9_-]*") => "identifier".to_string(),
+            Some(r#"\"[^\"]*\""#) => "quoted identifier".to_string(),
+            Some(r"'[^']*'") => "string".to_string(),
+            Some(r"[-+]?[0-9]+") => "integer".to_string(),
+            Some(r"[-+]?\\d*\\.\\d*") => "number".to_string(),
+            Some(r"\\$[0-9]+") => "parameter".to_string(),
+            _ => format!("`{}`", token.trim_matches('"')),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{includes::*, parse_script};
+
+    fn parse_error(sql: &str) -> String {
+        let err = parse_script("q.sql", sql).unwrap_err();
+        assert!(matches!(err, KonaError::Parse(_)), "{}", err);
+        err.to_string()
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_token() {
+        let message = parse_error("SELECT A FROM T WHERE A =");
+        assert!(message.starts_with("Parse error [42601]: 1 syntax error in q.sql\nerror: unexpected end of input, expected one of"), "{}", message);
+        assert!(message.contains("integer, number, identifier, parameter\n --> q.sql:1:26\n"), "{}", message);
+
+        let message = parse_error("SELECT A FROM T;\nSELECT A FROM WHERE;\nSELECT FROM T");
+        assert!(message.starts_with("Parse error [42601]: 2 syntax errors in q.sql\n"), "{}", message);
+        assert!(message.contains(" --> q.sql:2:15\n  |\n2 | SELECT A FROM WHERE;\n  |               ^^^^^\n"), "{}", message);
+        assert!(message.contains(" --> q.sql:3:8\n"), "{}", message);
+        assert!(!message.contains("error: error"), "{}", message);
+    }
+
+    #[test]
+    fn literal_errors_point_at_the_literal() {
+        let message = parse_error("SELECT A FROM T\nWHERE D > DATE '2020-13-01'");
+        assert!(
+            message.ends_with("error: Invalid DATE literal '2020-13-01'\n --> q.sql:2:11\n  |\n2 | WHERE D > DATE '2020-13-01'\n  |           ^^^^^^^^^^^^^^^^^"),
+            "{}",
+            message
+        );
+
+        let message = parse_error("SELECT A + INTERVAL '99999999999' YEAR FROM T");
+        assert!(message.contains("error: Interval out of range: '99999999999'\n --> q.sql:1:12\n"), "{}", message);
+        assert!(message.ends_with(&f!("|            {}", "^".repeat(27))), "{}", message);
+
+        for (sql, expected) in [
+            ("SELECT $0 FROM T", "Invalid parameter marker $0\n --> q.sql:1:8"),
+            ("SELECT CAST(A AS BLOB) FROM T", "Invalid datatype BLOB\n --> q.sql:1:18"),
+            ("SELECT EXTRACT(CENTURY FROM D) FROM T", "Invalid date/time field: CENTURY\n --> q.sql:1:16"),
+            ("SELECT DECIMAL '1.2.3' FROM T", "Invalid DECIMAL value '1.2.3'\n --> q.sql:1:8"),
+            ("CATALOG TABLE T (A INT16) WITH (TYPE = 'CSV', PATH = 'x.csv')", "Datatype INT16 is not supported, use INT, BIGINT or DOUBLE instead\n --> q.sql:1:20"),
+        ] {
+            let message = parse_error(sql);
+            assert!(message.contains(expected), "{}", message);
+        }
+    }
+}
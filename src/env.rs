@@ -1,13 +1,19 @@
 // env
 
-use crate::{includes::*, logging, metadata::Metadata, scheduler::Scheduler};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
-#[derive(Debug, Default)]
-pub struct EnvSettings {
-    pub parallel_degree: Option<usize>,
-    pub parse_only: Option<bool>,
-    pub trace: Option<String>,
-}
+use crate::{
+    includes::*,
+    logging,
+    metadata::Metadata,
+    prepare::PreparedStatement,
+    resultset::ResultSet,
+    scheduler::Scheduler,
+    settings::{EnvSettings, SettingDef, SETTINGS},
+};
 
 pub struct Env {
     pub id: usize,
@@ -16,55 +22,175 @@ pub struct Env {
     pub input_pathname: String,
     pub output_dir: String,
     pub settings: EnvSettings,
+    pub prepared_statements: HashMap<String, PreparedStatement>,
 }
 
 impl Env {
-    pub fn new(id: usize, nthreads: usize, input_pathname: String, output_dir: String) -> Self {
+    // Without a catalog directory, tables and views only last as long as the Env
+    pub fn new(id: usize, nthreads: usize, input_pathname: String, output_dir: String, catalog_dir: Option<&str>) -> Result<Self, KonaError> {
+        let metadata = match catalog_dir {
+            Some(catalog_dir) => Metadata::open(catalog_dir)?,
+            None => Metadata::default(),
+        };
         let scheduler = Scheduler::new(nthreads);
-        let metadata = Metadata::default();
         let options = EnvSettings::default();
 
-        Env { id, scheduler, metadata, input_pathname, output_dir, settings: options }
+        Ok(Env { id, scheduler, metadata, input_pathname, output_dir, settings: options, prepared_statements: HashMap::new() })
     }
 
-    pub fn set_option(&mut self, name: String, value: Datum) -> Result<(), String> {
+    pub fn set_option(&mut self, name: String, value: Datum) -> Result<(), KonaError> {
         debug!("SET {} = {}", &name, &value);
-        let name = name.to_uppercase();
-        match name.as_str() {
-            "PARALLEL_DEGREE" => self.settings.parallel_degree = Some(Self::get_int_option(name.as_str(), &value)? as usize),
-            "PARSE_ONLY" => self.settings.parse_only = Some(self.get_boolean_option(name.as_str(), &value)?),
-            "TRACE" => {
-                self.settings.trace = Some(Self::get_string_option(name.as_str(), &value)?.to_string());
-                logging::init(&self.settings.trace.as_ref().unwrap());
-            }
-            _ => return Err(f!("Invalid option specified: {name}.")),
+        let def = SettingDef::lookup(&name).map_err(KonaError::Bind)?;
+        let value = def.coerce(&value).map_err(KonaError::Bind)?;
+        self.apply_option(def, &value)?;
+        self.settings.set(def, value);
+        Ok(())
+    }
+
+    // RESET ALL leaves TRACE alone since tracing can only be started once
+    pub fn reset_option(&mut self, name: Option<String>) -> Result<(), KonaError> {
+        let defs = match name {
+            Some(name) => vec![SettingDef::lookup(&name).map_err(KonaError::Bind)?],
+            None => SETTINGS.iter().filter(|def| def.name != "TRACE").collect(),
         };
+        for def in defs {
+            if def.name == "TRACE" {
+                return Err(KonaError::Bind(String::from("Option TRACE cannot be reset.")));
+            }
+            self.settings.reset(def);
+            let value = self.settings.get(def);
+            self.apply_option(def, &value)?;
+        }
+        Ok(())
+    }
+
+    // Startup values from the command line, the environment or a config file. They become the defaults RESET returns to.
+    pub fn set_startup_option(&mut self, name: &str, text: &str) -> Result<(), KonaError> {
+        let def = SettingDef::lookup(name).map_err(KonaError::Bind)?;
+        let value = def.parse(text).map_err(KonaError::Bind)?;
+        self.apply_option(def, &value)?;
+        self.settings.set_default(def, value);
         Ok(())
     }
 
-    pub fn get_boolean_option(&self, name: &str, value: &Datum) -> Result<bool, String> {
-        if let Utf8(s) = value {
-            let s = s.to_uppercase();
-            return match s.as_str() {
-                "TRUE" | "T" | "YES" | "Y" => Ok(true),
-                _ => Ok(false),
-            };
+    // A config file holds one `NAME = value` per line. Blank lines and lines starting with # are skipped.
+    pub fn load_config(&mut self, pathname: &str) -> Result<(), KonaError> {
+        let contents = fs::read_to_string(pathname).map_err(|err| KonaError::io(f!("Cannot read config file {pathname}"), err))?;
+        for (lineno, line) in contents.lines().enumerate().map(|(ix, line)| (ix + 1, line.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, value) = line.split_once('=').ok_or_else(|| KonaError::Bind(f!("{pathname}:{lineno}: Expected NAME = value")))?;
+            let value = value.trim().trim_matches('"');
+            self.set_startup_option(name.trim(), value).map_err(|err| match err {
+                KonaError::Bind(message) => KonaError::Bind(f!("{pathname}:{lineno}: {message}")),
+                err => err,
+            })?;
         }
-        Err(f!("Option {name} needs to be a string. It holds {value} instead."))
+        Ok(())
     }
 
-    pub fn get_int_option(name: &str, value: &Datum) -> Result<i64, String> {
-        if let Int64(ival) = value {
-            return Ok(*ival);
+    // Relative PATHs of tables are taken from DATA_DIR or, without one, from the directory of the job file. They are
+    // cataloged as absolute paths so that persisted tables don't depend on where later jobs run from.
+    pub fn resolve_path(&self, path: &str) -> String {
+        if Path::new(path).is_absolute() {
+            return path.to_string();
         }
-        Err(f!("Option {name} needs to be an integer. It holds {value} instead."))
+        let base = match self.settings.data_dir() {
+            Some(data_dir) => PathBuf::from(data_dir),
+            None => Path::new(&self.input_pathname).parent().map(Path::to_path_buf).unwrap_or_default(),
+        };
+        let path = std::env::current_dir().unwrap_or_default().join(base).join(path);
+        fs::canonicalize(&path).unwrap_or(path).display().to_string()
+    }
+
+    pub fn resolve_path_options(&self, options: Vec<(String, Datum)>) -> Vec<(String, Datum)> {
+        options
+            .into_iter()
+            .map(|(name, value)| match value {
+                Utf8(path) if name.eq_ignore_ascii_case("PATH") => (name, Utf8(self.resolve_path(&path))),
+                value => (name, value),
+            })
+            .collect()
+    }
+
+    // Plans are written next to the flows that run them, in a directory of this Env's own
+    pub fn init_output_dir(&mut self) -> Result<(), KonaError> {
+        self.output_dir = f!("{}/plans-{}", self.settings.temp_dir(), self.id);
+        fs::create_dir_all(&self.output_dir).map_err(|err| KonaError::io(f!("Cannot create {}", self.output_dir), err))
     }
 
-    pub fn get_string_option<'a>(name: &str, value: &'a Datum) -> Result<&'a str, String> {
-        if let Utf8(s) = value {
-            Ok(s)
-        } else {
-            Err(f!("Option {name} needs to be a string. It holds {value} instead."))
+    pub fn show_options(&self, name: Option<String>) -> Result<ResultSet, KonaError> {
+        self.settings.show(name.as_deref()).map_err(KonaError::Bind)
+    }
+
+    // Settings that take effect outside EnvSettings
+    fn apply_option(&mut self, def: &SettingDef, value: &Datum) -> Result<(), KonaError> {
+        match (def.name, value) {
+            ("TRACE", Utf8(filter)) => logging::init(filter),
+            ("SCHEMA", Utf8(path)) => self.metadata.set_search_path(path)?,
+            _ => {}
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    fn env(input_pathname: &str) -> Env {
+        Env::new(0, 1, input_pathname.to_string(), String::new(), None).unwrap()
+    }
+
+    #[test]
+    fn load_config_sets_startup_defaults() {
+        let dir = temp_dir("CONFIG");
+        let pathname = f!("{dir}/kona.conf");
+        fs::write(&pathname, "# Settings\n\nPARALLEL_DEGREE = 4\n  data_dir = \"/data\"  \n").unwrap();
+        let mut env = env("");
+        env.load_config(&pathname).unwrap();
+        assert_eq!(env.settings.parallel_degree(), 4);
+        assert_eq!(env.settings.data_dir().as_deref(), Some("/data"));
+
+        // Config values become the defaults that RESET returns to
+        env.set_option(String::from("PARALLEL_DEGREE"), Int64(2)).unwrap();
+        env.reset_option(Some(String::from("PARALLEL_DEGREE"))).unwrap();
+        assert_eq!(env.settings.parallel_degree(), 4);
+    }
+
+    #[test]
+    fn load_config_reports_the_bad_line() {
+        let dir = temp_dir("CONFIG");
+        let pathname = f!("{dir}/kona.conf");
+        for (contents, message) in [
+            ("PARALLEL_DEGREE = 2\nPARALLEL_DEGREE 4\n", f!("{pathname}:2: Expected NAME = value")),
+            ("# Settings\nNOPE = 1\n", f!("{pathname}:2: ")),
+            ("PARALLEL_DEGREE = 0\n", f!("{pathname}:1: ")),
+        ] {
+            fs::write(&pathname, contents).unwrap();
+            let err = env("").load_config(&pathname).unwrap_err();
+            assert!(matches!(&err, KonaError::Bind(msg) if msg.starts_with(&message)), "{}: {}", contents, err);
+        }
+        let err = env("").load_config(&f!("{dir}/missing.conf")).unwrap_err();
+        assert!(matches!(&err, KonaError::Io { .. }), "{}", err);
+    }
+
+    #[test]
+    fn resolve_path_against_data_dir_or_job_file() {
+        let dir = temp_dir("RESOLVE");
+        let mut env = env(&f!("{dir}/jobs/job.sql"));
+        assert_eq!(env.resolve_path("/abs/t.csv"), "/abs/t.csv");
+        assert_eq!(env.resolve_path("t.csv"), f!("{dir}/jobs/t.csv"));
+
+        env.set_startup_option("DATA_DIR", &f!("{dir}/data")).unwrap();
+        assert_eq!(env.resolve_path("t.csv"), f!("{dir}/data/t.csv"));
+        assert_eq!(env.resolve_path("sub/t.csv"), f!("{dir}/data/sub/t.csv"));
+
+        // Only PATH options name files
+        let options = vec![(String::from("PATH"), Utf8("t.csv".into())), (String::from("DELIMITER"), Utf8("t.csv".into()))];
+        let options = env.resolve_path_options(options);
+        assert_eq!(options, vec![(String::from("PATH"), Utf8(f!("{dir}/data/t.csv"))), (String::from("DELIMITER"), Utf8("t.csv".into()))]);
     }
 }
@@ -0,0 +1,106 @@
+// error: engine-wide error type
+
+use std::fmt;
+
+use crate::includes::*;
+
+/// Errors raised while running a job, classified by the phase that raised them.
+#[derive(Debug)]
+pub enum KonaError {
+    Parse(String),
+    Bind(String),
+    Plan(String),
+    Io { context: String, source: std::io::Error },
+    Arrow { context: String, source: arrow2::error::Error },
+    Execution { stage_id: StageId, partition_id: PartitionId, message: String },
+}
+
+impl KonaError {
+    pub fn io(context: impl Into<String>, source: std::io::Error) -> Self {
+        KonaError::Io { context: context.into(), source }
+    }
+
+    pub fn arrow(context: impl Into<String>, source: arrow2::error::Error) -> Self {
+        KonaError::Arrow { context: context.into(), source }
+    }
+
+    /// An error raised by an operator of a task. The scheduler fills in the task's stage and partition.
+    pub fn execution(message: impl Into<String>) -> Self {
+        KonaError::Execution { stage_id: 0, partition_id: 0, message: message.into() }
+    }
+
+    /// SQLSTATE-style code, stable across releases so that callers can match on it.
+    pub fn code(&self) -> &'static str {
+        match self {
+            KonaError::Parse(_) => "42601",
+            KonaError::Bind(_) => "42000",
+            KonaError::Plan(_) => "0A000",
+            KonaError::Io { .. } => "58030",
+            KonaError::Arrow { .. } => "22000",
+            KonaError::Execution { .. } => "XX000",
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            KonaError::Parse(_) => "Parse",
+            KonaError::Bind(_) => "Bind",
+            KonaError::Plan(_) => "Plan",
+            KonaError::Io { .. } => "I/O",
+            KonaError::Arrow { .. } => "Arrow",
+            KonaError::Execution { .. } => "Execution",
+        }
+    }
+}
+
+impl fmt::Display for KonaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} error [{}]: ", self.kind(), self.code())?;
+        match self {
+            KonaError::Parse(message) | KonaError::Bind(message) | KonaError::Plan(message) => write!(f, "{message}"),
+            KonaError::Io { context, source } => write!(f, "{context}: {source}"),
+            KonaError::Arrow { context, source } => write!(f, "{context}: {source}"),
+            KonaError::Execution { stage_id, partition_id, message } => write!(f, "stage {stage_id}, partition {partition_id}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for KonaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KonaError::Io { source, .. } => Some(source),
+            KonaError::Arrow { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn errors_are_classified_by_phase() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT, S STRING", &["1,x"]);
+
+        assert!(matches!(query(&mut session, "SELEC ID FROM T"), Err(KonaError::Parse(_))));
+        assert!(matches!(query(&mut session, "SELECT NOPE FROM T"), Err(KonaError::Bind(_))));
+        assert!(matches!(query(&mut session, "SELECT /*+ BROADCAST(NOPE) */ A.ID FROM T A, T B WHERE A.ID = B.ID"), Err(KonaError::Plan(_))));
+        assert!(matches!(query(&mut session, "SELECT ID, CAST(S AS INT) FROM T"), Err(KonaError::Execution { .. })));
+    }
+
+    #[test]
+    fn missing_input_files_are_io_errors() {
+        let mut session = session();
+        let path = f!("{}/T.csv", temp_dir("T"));
+        fs::write(&path, "1\n").unwrap();
+        execute(&mut session, &f!("CATALOG TABLE T (ID INT) WITH (TYPE = 'CSV', PATH = '{path}', HEADER = 'N')")).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let err = query(&mut session, "SELECT ID FROM T").unwrap_err();
+        assert!(matches!(&err, KonaError::Io { source, .. } if source.kind() == std::io::ErrorKind::NotFound), "{}", err);
+        assert_eq!(err.code(), "58030");
+    }
+}
@@ -0,0 +1,310 @@
+// explain: EXPLAIN shows a query's logical and physical plans as indented text. EXPLAIN ANALYZE also runs the query and
+// annotates each operator with its runtime metrics.
+
+use std::time::Duration;
+
+use crate::{
+    ast::ExplainMode,
+    bitset::Bitset,
+    graph::{ExprKey, LOPKey, POPKey},
+    includes::*,
+    lop::{LOPGraph, LOP},
+    metadata::PartType,
+    metrics::{FlowMetrics, OpMetrics},
+    pcode::PCode,
+    pop::POP,
+    qgm::Ordering,
+    resultset::ResultSet,
+    run_flow,
+    stage::{Stage, StageGraph},
+    QGM,
+};
+
+const INDENT: &str = "    ";
+
+pub fn explain(env: &mut Env, mode: ExplainMode, mut qgm: QGM) -> Result<ResultSet, KonaError> {
+    if mode == ExplainMode::Analyze && qgm.has_parameters() {
+        return Err(KonaError::Bind(String::from("EXPLAIN ANALYZE cannot run a query with parameter markers.")));
+    }
+    qgm.resolve(env)?;
+    let (lop_graph, lop_key) = qgm.build_logical_plan(env)?;
+
+    let mut lines = vec![];
+    if matches!(mode, ExplainMode::All | ExplainMode::Logical) {
+        lines.push(String::from("Logical plan:"));
+        qgm.explain_lop(&lop_graph, lop_key, 1, &mut lines);
+    }
+    if mode != ExplainMode::Logical {
+        // Compiling a flow doesn't run it
+        let flow = POP::compile_flow(env, &mut qgm, &lop_graph, lop_key)?;
+        let metrics = if mode == ExplainMode::Analyze { Some(run_flow(env, &flow)?) } else { None };
+        lines.push(String::from("Physical plan:"));
+        flow.stage_graph.explain(metrics.as_ref(), &mut lines);
+        if let Some(metrics) = metrics.as_ref() {
+            lines.push(f!("Execution time: {}", describe_duration(metrics.elapsed)));
+        }
+    }
+    Ok(ResultSet::new(&["PLAN"], lines.into_iter().map(|line| vec![line]).collect()))
+}
+
+fn describe_exprs(qgm: &QGM, exprs: &[ExprKey], separator: &str) -> String {
+    exprs.iter().map(|expr_key| expr_key.describe(&qgm.expr_graph, false)).collect::<Vec<_>>().join(separator)
+}
+
+fn describe_quncols(qgm: &QGM, quncols: &Bitset<QunCol>) -> Vec<String> {
+    quncols.elements().iter().map(|&quncol| f!("{} ({}.{})", qgm.metadata.get_fieldname(quncol), quncol.0, quncol.1)).collect()
+}
+
+fn describe_pcodes(pcodes: &[PCode], separator: &str) -> String {
+    pcodes.iter().map(|pcode| pcode.describe()).collect::<Vec<_>>().join(separator)
+}
+
+fn describe_sort_keys<'a>(keys: impl Iterator<Item = (String, &'a Ordering)>) -> String {
+    keys.map(|(key, ordering)| if matches!(ordering, Ordering::Desc) { f!("{key} DESC") } else { key }).collect::<Vec<_>>().join(", ")
+}
+
+fn describe_duration(duration: Duration) -> String {
+    f!("{:.3} ms", duration.as_secs_f64() * 1000.0)
+}
+
+// Largest over average value across partitions, 1.0 when evenly spread
+fn skew(values: &[usize]) -> f64 {
+    let total = values.iter().sum::<usize>();
+    if total == 0 {
+        1.0
+    } else {
+        *values.iter().max().unwrap() as f64 * values.len() as f64 / total as f64
+    }
+}
+
+fn explain_header(indent: &str, label: &str, id: String, extrastr: &str) -> String {
+    if extrastr.is_empty() {
+        f!("{indent}{label}-{id}")
+    } else {
+        f!("{indent}{label}-{id}: {extrastr}")
+    }
+}
+
+impl QGM {
+    // One line per operator followed by its properties, with inputs indented below it
+    fn explain_lop(&self, lop_graph: &LOPGraph, lop_key: LOPKey, depth: usize, lines: &mut Vec<String>) {
+        let (lop, props, children) = lop_graph.get3(lop_key);
+        let indent = INDENT.repeat(depth);
+
+        let (label, extrastr) = match lop {
+            LOP::TableScan { input_projection } => ("TableScan", f!("input = {}", describe_quncols(self, input_projection).join(", "))),
+            LOP::HashJoin { lhs_join_keys, rhs_join_keys } => {
+                ("HashJoin", f!("{} = {}", describe_exprs(self, lhs_join_keys, ", "), describe_exprs(self, rhs_join_keys, ", ")))
+            }
+            LOP::Repartition { cpartitions } => match &props.partdesc.part_type {
+                PartType::BROADCAST => ("Repartition", f!("c = {cpartitions}, broadcast")),
+                PartType::HASHEXPR(keys) => ("Repartition", f!("c = {cpartitions}, keys = {}", describe_exprs(self, keys, ", "))),
+                PartType::RAW => ("Repartition", f!("c = {cpartitions}")),
+            },
+            LOP::Aggregation { key_len } => ("Aggregation", f!("key_len = {key_len}")),
+            LOP::DerivedTable => ("DerivedTable", String::new()),
+            LOP::Sort { sort_keys } => {
+                ("Sort", f!("keys = {}", describe_sort_keys(sort_keys.iter().map(|(expr_key, ordering)| (expr_key.describe(&self.expr_graph, false), ordering)))))
+            }
+        };
+        lines.push(explain_header(&indent, label, lop_key.id(), &extrastr));
+
+        let partdesc = props.partdesc.describe(&self.expr_graph, false);
+        lines.push(f!("{indent}{INDENT}quns = {:?}, {partdesc}, {}", props.quns.elements(), props.estimate.describe()));
+        let mut cols = describe_quncols(self, &props.cols);
+        if let Some(virtcols) = props.virtcols.as_ref() {
+            cols.extend(virtcols.iter().map(|expr_key| expr_key.describe(&self.expr_graph, false)));
+        }
+        lines.push(f!("{indent}{INDENT}cols = {}", cols.join(", ")));
+        if !props.preds.is_empty() {
+            lines.push(f!("{indent}{INDENT}preds = {}", describe_exprs(self, &props.preds.elements(), " AND ")));
+        }
+
+        for &child_key in children.into_iter().flatten() {
+            self.explain_lop(lop_graph, child_key, depth + 1, lines);
+        }
+    }
+}
+
+impl StageGraph {
+    // Stages are listed in the order they were created, each naming the stage its output flows into
+    fn explain(&self, metrics: Option<&FlowMetrics>, lines: &mut Vec<String>) {
+        for stage in self.stages.iter() {
+            let parent = match (stage.parent_stage_id, stage.parent_pop_key) {
+                (Some(parent_stage_id), Some(parent_pop_key)) => f!(", read by RepartitionRead-{} in stage {parent_stage_id}", parent_pop_key.id()),
+                _ => String::new(),
+            };
+            lines.push(f!("{INDENT}Stage {}: {} partitions{parent}", stage.stage_id, stage.npartitions));
+            if let Some(metrics) = metrics {
+                // A task's time is the time spent in its root operator
+                let elapsed = metrics.partitions(stage.stage_id, stage.root_pop_key.unwrap()).iter().map(|partition| partition.elapsed).collect::<Vec<_>>();
+                let max = elapsed.iter().max().cloned().unwrap_or_default();
+                let avg = elapsed.iter().sum::<Duration>() / elapsed.len().max(1) as u32;
+                lines.push(f!("{INDENT}{INDENT}task time: max = {}, avg = {}", describe_duration(max), describe_duration(avg)));
+            }
+            stage.explain_pop(stage.root_pop_key.unwrap(), 2, metrics, lines);
+        }
+    }
+}
+
+impl Stage {
+    fn explain_pop(&self, pop_key: POPKey, depth: usize, metrics: Option<&FlowMetrics>, lines: &mut Vec<String>) {
+        let (pop, props, children) = self.pop_graph.get3(pop_key);
+        let indent = INDENT.repeat(depth);
+
+        let (label, extrastr) = match pop {
+            POP::CSV(csv) => ("CSV", f!("file = {}, input = {:?}", csv.pathname, csv.input_projection)),
+            POP::Parquet(pq) => ("Parquet", f!("file = {}, input = {:?}", pq.pathname, pq.input_projection)),
+            POP::HashMatch(hm) => ("HashMatch", f!("keys = {}", hm.keycols.iter().map(|keycols| format!("{:?}", keycols)).collect::<Vec<_>>().join(" = "))),
+            POP::HashAgg(ha) => {
+                let aggs = ha.aggs.iter().map(|(agg, _)| f!("{:?}(#{})", agg.agg_type, agg.input_colid)).collect::<Vec<_>>().join(", ");
+                ("HashAgg", f!("keys = {:?}, aggs = {aggs}", ha.keycols[0]))
+            }
+            POP::Project(prj) => ("Project", f!("qun = {}", prj.qunid)),
+            POP::Sort(sort) => ("Sort", f!("keys = {}", describe_sort_keys(sort.sort_keys.iter().map(|(pcode, ordering)| (pcode.describe(), ordering))))),
+            POP::RepartitionWrite(rpw) if rpw.repart_key().is_empty() => ("RepartitionWrite", f!("c = {}, broadcast", rpw.cpartitions())),
+            POP::RepartitionWrite(rpw) => ("RepartitionWrite", f!("c = {}, keys = {}", rpw.cpartitions(), describe_pcodes(rpw.repart_key(), ", "))),
+            POP::RepartitionRead(rpr) => ("RepartitionRead", f!("from stage {}", rpr.stage_link().0)),
+        };
+        lines.push(explain_header(&indent, label, pop_key.id(), &extrastr));
+
+        let cols = props.cols.as_ref().map_or(String::from("[]"), |cols| f!("{cols:?}"));
+        lines.push(f!("{indent}{INDENT}p = {}, cols = {cols}", props.npartitions));
+        if let Some(virtcols) = props.virtcols.as_ref() {
+            lines.push(f!("{indent}{INDENT}virtcols = {}", describe_pcodes(virtcols, ", ")));
+        }
+        if let Some(predicates) = props.predicates.as_ref() {
+            lines.push(f!("{indent}{INDENT}preds = {}", describe_pcodes(predicates, " AND ")));
+        }
+        if let Some(metrics) = metrics {
+            self.explain_metrics(pop_key, metrics, &f!("{indent}{INDENT}"), lines);
+        }
+
+        for &child_key in children.into_iter().flatten() {
+            self.explain_pop(child_key, depth + 1, metrics, lines);
+        }
+    }
+
+    // An operator's metrics in each partition. Only scans count their inputs: other operators read what their
+    // children return, or what a child stage wrote.
+    fn pop_metrics(&self, pop_key: POPKey, metrics: &FlowMetrics) -> Vec<OpMetrics> {
+        let (pop, _, children) = self.pop_graph.get3(pop_key);
+        let mut partitions = metrics.partitions(self.stage_id, pop_key);
+        if let Some(children) = children {
+            for &child_key in children.iter() {
+                for (partition, child) in partitions.iter_mut().zip(metrics.partitions(self.stage_id, child_key)) {
+                    partition.rows_in += child.rows_out;
+                    partition.chunks_in += child.chunks_out;
+                }
+            }
+        } else if matches!(pop, POP::RepartitionRead(_)) {
+            for partition in partitions.iter_mut() {
+                partition.rows_in = partition.rows_out;
+                partition.chunks_in = partition.chunks_out;
+            }
+        }
+        partitions
+    }
+
+    fn explain_metrics(&self, pop_key: POPKey, metrics: &FlowMetrics, indent: &str, lines: &mut Vec<String>) {
+        let partitions = self.pop_metrics(pop_key, metrics);
+        let mut total = OpMetrics::default();
+        partitions.iter().for_each(|partition| total.add(partition));
+
+        // Time spent in this operator alone
+        let (_, _, children) = self.pop_graph.get3(pop_key);
+        let children_elapsed = children.into_iter().flatten().flat_map(|&child_key| metrics.partitions(self.stage_id, child_key)).map(|child| child.elapsed).sum::<Duration>();
+        let self_elapsed = total.elapsed.saturating_sub(children_elapsed);
+
+        lines.push(f!(
+            "{indent}rows in = {}, chunks in = {}, rows out = {}, chunks out = {}, bytes out = {}",
+            total.rows_in,
+            total.chunks_in,
+            total.rows_out,
+            total.chunks_out,
+            total.bytes_out
+        ));
+        let mut timing = f!("{indent}time = {}, self time = {}", describe_duration(total.elapsed), describe_duration(self_elapsed));
+        if let Some(entries) = total.hash_table_entries {
+            timing = f!("{timing}, hash table entries = {entries}");
+        }
+        lines.push(timing);
+        if partitions.len() > 1 {
+            let rows_in = partitions.iter().map(|partition| partition.rows_in).collect::<Vec<_>>();
+            let (min, max) = (rows_in.iter().min().unwrap(), rows_in.iter().max().unwrap());
+            lines.push(f!("{indent}rows in per partition: min = {min}, max = {max}, skew = {:.2}", skew(&rows_in)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    // The lines of an EXPLAIN, with scanned files shown by their names alone
+    fn explain_lines(session: &mut TestSession, sql: &str) -> Vec<String> {
+        let rows = query_in_order(session, sql).unwrap();
+        rows.iter()
+            .map(|row| {
+                let line = if let Utf8(line) = &row[0] { line } else { panic!("{:?}", row) };
+                match (line.find("file = "), line.find(", input")) {
+                    (Some(start), Some(end)) => {
+                        let pathname = &line[start + "file = ".len()..end];
+                        line.replace(pathname, pathname.rsplit('/').next().unwrap())
+                    }
+                    _ => line.clone(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn explain_logical_shows_operators_and_predicates() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT, CAT STRING, N DOUBLE", &["1,a,1.5", "2,b,2.5"]);
+        let lines = explain_lines(&mut session, "EXPLAIN LOGICAL SELECT CAT, SUM(N) FROM T WHERE N > 1.0 AND ID < 5 GROUP BY CAT");
+        assert_eq!(
+            lines,
+            [
+                "Logical plan:",
+                "    Aggregation-2v1: key_len = 1",
+                "        quns = [3], p = 1 (CAT), rows = 11, width = 24",
+                "        cols = $3.0, SUM($3.1)",
+                "        TableScan-1v1: input = CAT (1.1), N (1.2), ID (1.0)",
+                "            quns = [1], p = 1 (RAW), rows = 111, width = 24",
+                "            cols = CAT, N",
+                "            preds = N > 1.0 AND ID < 5",
+            ]
+        );
+    }
+
+    #[test]
+    fn explain_physical_shows_stages_and_their_links() {
+        let mut session = session();
+        create_table(&mut session, "A", "ID INT, X DOUBLE", &["1,0.5"]);
+        create_table(&mut session, "B", "ID INT, Y INT", &["1,100"]);
+        let lines = explain_lines(&mut session, "EXPLAIN PHYSICAL SELECT /*+ BROADCAST(B) */ A.X, B.Y FROM A, B WHERE A.ID = B.ID AND A.X < 2.5");
+        let physical = lines.iter().take_while(|line| *line != "Logical plan:").collect::<Vec<_>>();
+        assert_eq!(
+            physical,
+            [
+                "Physical plan:",
+                "    Stage 0: 1 partitions",
+                "        HashMatch-3v1: keys = [1] = [1]",
+                "            p = 1, cols = []",
+                "            virtcols = #0, #2",
+                "            CSV-1v1: file = A.csv, input = [1, 0]",
+                "                p = 1, cols = [0, 1]",
+                "                preds = #0 < 2.5",
+                "            RepartitionRead-2v1: from stage 1",
+                "                p = 1, cols = [0, 1]",
+                "    Stage 1: 1 partitions, read by RepartitionRead-2v1 in stage 0",
+                "        RepartitionWrite-2v1: c = 1, broadcast",
+                "            p = 1, cols = [0, 1]",
+                "            CSV-1v1: file = B.csv, input = [1, 0]",
+                "                p = 1, cols = [0, 1]",
+            ]
+        );
+    }
+}
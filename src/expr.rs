@@ -9,6 +9,7 @@ use Expr::*;
 use crate::{
     graph::{ExprKey, Graph, QueryBlockKey},
     includes::*,
+    temporal::DateField,
 };
 
 pub type ExprGraph = Graph<ExprKey, Expr, ExprProp>;
@@ -112,12 +113,13 @@ impl std::default::Default for ExprProp {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Hash)]
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
 pub enum Expr {
     CID(QunId, ColId),
-    Column { prefix: Option<String>, colname: String, qunid: QunId, colid: ColId },
+    Column { schema: Option<String>, prefix: Option<String>, colname: String, quoted: bool, qunid: QunId, colid: ColId },
     Star { prefix: Option<String> },
     Literal(Datum),
+    Parameter(usize), // Parameter marker of a prepared statement, 0-based: `?` or `$1`
     NegatedExpr,
     BinaryExpr(ArithOp),
     RelExpr(RelOp),
@@ -130,6 +132,9 @@ pub enum Expr {
     AggFunction(AggType, bool),
     ScalarFunction(String),
     Cast,
+    TryCast,
+    Extract(DateField),
+    DateTrunc(DateField),
 }
 
 impl Expr {
@@ -138,8 +143,10 @@ impl Expr {
             CID(qunid, colid) => {
                 format!("${}.{}", *qunid, *colid)
             }
-            Column { prefix, colname, qunid, colid } => {
-                if let Some(prefix) = prefix {
+            Column { schema, prefix, colname, qunid, colid, .. } => {
+                if let (Some(schema), Some(prefix)) = (schema, prefix) {
+                    format!("{}.{}.{} (${}.{})", schema, prefix, colname, *qunid, *colid)
+                } else if let Some(prefix) = prefix {
                     format!("{}.{} (${}.{})", prefix, colname, *qunid, *colid)
                 } else {
                     format!("{} (${}.{})", colname, *qunid, *colid)
@@ -147,6 +154,7 @@ impl Expr {
             }
             Star { .. } => String::from("*"),
             Literal(v) => format!("{}", v).replace('"', r#"\""#),
+            Parameter(ix) => format!("${}", ix + 1),
             BinaryExpr(op) => format!("{}", op),
             NegatedExpr => "-".to_string(),
             RelExpr(op) => format!("{}", op),
@@ -161,6 +169,9 @@ impl Expr {
             }
             ScalarFunction(name) => format!("{}()", name),
             Cast => String::from("CAST"),
+            TryCast => String::from("TRY_CAST"),
+            Extract(field) => format!("EXTRACT({})", field),
+            DateTrunc(field) => format!("DATE_TRUNC({})", field),
         }
     }
 
@@ -193,11 +204,16 @@ impl Expr {
             (BinaryExpr(c1), BinaryExpr(c2)) => *c1 == *c2,
             (RelExpr(c1), RelExpr(c2)) => *c1 == *c2,
             (LogExpr(c1), LogExpr(c2)) => *c1 == *c2,
-            (Column { prefix: p1, colname: n1, qunid: _, colid: _ }, Column { prefix: p2, colname: n2, qunid: _, colid: _ }) => p1 == p2 && n1 == n2,
+            (Column { schema: s1, prefix: p1, colname: n1, quoted: q1, .. }, Column { schema: s2, prefix: p2, colname: n2, quoted: q2, .. }) => {
+                s1 == s2 && p1 == p2 && n1 == n2 && q1 == q2
+            }
             (Literal(c1), Literal(c2)) => *c1 == *c2,
+            (Parameter(ix1), Parameter(ix2)) => ix1 == ix2,
             (NegatedExpr, NegatedExpr) => true,
             (BetweenExpr, BetweenExpr) => true,
             (InListExpr, InListExpr) => true,
+            (Extract(f1), Extract(f2)) => *f1 == *f2,
+            (DateTrunc(f1), DateTrunc(f2)) => *f1 == *f2,
             _ => false,
         }
     }
@@ -254,6 +270,14 @@ impl ExprKey {
         }
     }
 
+    // Copy the expression tree rooted at this node so that the copy can be rewritten independently
+    pub fn deep_copy(&self, expr_graph: &mut ExprGraph) -> ExprKey {
+        let (expr, props, children) = expr_graph.get3(*self);
+        let (expr, props, children) = (expr.clone(), props.clone(), children.cloned());
+        let children = children.map(|children| children.iter().map(|child_key| child_key.deep_copy(expr_graph)).collect());
+        expr_graph.add_node_with_props(expr, props, children)
+    }
+
     pub fn is_column(&self, graph: &ExprGraph) -> bool {
         let expr = &graph.get(*self).value;
         matches!(expr, Column { .. })
@@ -265,8 +289,10 @@ impl ExprKey {
             CID(qunid, colid) => {
                 format!("${}.{}", *qunid, *colid)
             }
-            Column { prefix, colname, .. } => {
-                if let Some(prefix) = prefix {
+            Column { schema, prefix, colname, .. } => {
+                if let (Some(schema), Some(prefix)) = (schema, prefix) {
+                    format!("{}.{}.{}", schema, prefix, colname)
+                } else if let Some(prefix) = prefix {
                     format!("{}.{}", prefix, colname)
                 } else {
                     colname.to_string()
@@ -274,6 +300,7 @@ impl ExprKey {
             }
             Star { .. } => String::from("*"),
             Literal(v) => format!("{}", v).replace('"', r#"\""#),
+            Parameter(ix) => format!("${}", ix + 1),
             BinaryExpr(op) => {
                 let (lhs_key, rhs_key) = (children.unwrap()[0], children.unwrap()[1]);
                 format!("{} {} {}", lhs_key.describe(expr_graph, false), op, rhs_key.describe(expr_graph, false),)
@@ -286,6 +313,10 @@ impl ExprKey {
                 let (lhs_key, rhs_key) = (children.unwrap()[0], children.unwrap()[1]);
                 format!("{} {} {}", lhs_key.describe(expr_graph, false), op, rhs_key.describe(expr_graph, false),)
             }
+            LogExpr(LogOp::Not) => {
+                let child_key = children.unwrap()[0];
+                format!("NOT ({})", child_key.describe(expr_graph, false))
+            }
             LogExpr(op) => {
                 let (lhs_key, rhs_key) = (children.unwrap()[0], children.unwrap()[1]);
                 format!("{} {} {}", lhs_key.describe(expr_graph, false), op, rhs_key.describe(expr_graph, false),)
@@ -306,6 +337,18 @@ impl ExprKey {
                 let child_key = children.unwrap()[0];
                 format!("({}) AS {:?}", child_key.describe(expr_graph, false), props.data_type())
             }
+            TryCast => {
+                let child_key = children.unwrap()[0];
+                format!("TRY_CAST({} AS {:?})", child_key.describe(expr_graph, false), props.data_type())
+            }
+            Extract(field) => {
+                let child_key = children.unwrap()[0];
+                format!("EXTRACT({} FROM {})", field, child_key.describe(expr_graph, false))
+            }
+            DateTrunc(field) => {
+                let child_key = children.unwrap()[0];
+                format!("DATE_TRUNC('{}', {})", field, child_key.describe(expr_graph, false))
+            }
         };
         if do_escape {
             do_escape_fn(&retval)
@@ -1,6 +1,10 @@
 // flow
 
-use crate::{includes::*, stage::StageGraph};
+use crate::{
+    includes::*,
+    sink::{OutputFormat, TableSink},
+    stage::StageGraph,
+};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Flow {
@@ -10,4 +14,36 @@ pub struct Flow {
     pub stage_graph: StageGraph,
 
     pub schema: Schema,
+
+    pub sink: Option<TableSink>, // Set for CREATE TABLE AS SELECT and INSERT INTO ... SELECT
+
+    pub collect_stats: bool, // Set for ANALYZE TABLE: top-level tasks gather statistics instead of writing rows
+
+    pub return_chunks: bool, // Set for Session::sql: top-level tasks send their chunks to the driver instead of writing them
+
+    pub settings: FlowSettings,
+}
+
+// Session settings needed by tasks, copied into the flow when it is compiled
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FlowSettings {
+    pub chunk_size: usize,
+    pub nsplits: usize,
+    pub temp_dir: String,
+    pub memory_limit: usize, // 0 means no limit
+    pub output_format: OutputFormat,
+}
+
+impl Flow {
+    pub fn dir(&self) -> String {
+        format!("{}/flow-{}", self.settings.temp_dir, self.id)
+    }
+
+    pub fn partition_dir(&self, stage_link: StageLink, pid: PartitionId) -> String {
+        format!("{}/pipeline-{}-{}/consumer-{}", self.dir(), stage_link.0, stage_link.1, pid)
+    }
+
+    pub fn output_dir(&self) -> String {
+        format!("{}/output", self.dir())
+    }
 }
@@ -200,7 +200,7 @@ pub fn key_to_id(keystr: &str) -> String {
     //let re3 = Regex::new(r"v1$").unwrap();
 
     let id = keystr;
-    let id = re1.replace_all(&id, "");
+    let id = re1.replace_all(id, "");
     let id = re2.replace_all(&id, "");
     //let id = re3.replace_all(&id, "");
     id.to_string()
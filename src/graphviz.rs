@@ -1,5 +1,21 @@
 // graphviz
 
+use std::process::Command;
+
+use crate::includes::*;
+
 pub fn htmlify(s: String) -> String {
     s.replace('&', "&amp;").replace('>', "&gt;").replace('<', "&lt;")
 }
+
+// Plans are always written as .dot files. They are rendered to .jpg only when SET GRAPHVIZ names the dot executable.
+pub fn render(env: &Env, pathname: &str) -> Result<(), String> {
+    if let Some(dot) = env.settings.graphviz() {
+        // dot -Tjpg -oex.jpg exampl1.dot
+        let status = Command::new(&dot).arg("-Tjpg").arg(f!("-o{pathname}.jpg")).arg(pathname).status().map_err(|err| f!("Cannot run {dot}: {err}"))?;
+        if !status.success() {
+            return Err(f!("{dot} failed to render {pathname}: {status}"));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+// hints: Optimizer hints written as a `/*+ ... */` comment after SELECT, e.g.
+//     SELECT /*+ LEADING(d t) BROADCAST(d) PARALLEL(4) */ ...
+// They apply to the query block they appear in.
+
+use crate::{includes::*, qgm::QueryBlock};
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Hints {
+    pub broadcast: Vec<String>,  // BROADCAST(t ...): replicate these tables to every partition of the other join leg
+    pub leading: Vec<String>,    // LEADING(a b c): join these tables first, in this order
+    pub parallel: Option<usize>, // PARALLEL(n): partition count for joins and aggregations, overrides PARALLEL_DEGREE
+    pub no_repartition: bool,    // NO_REPARTITION: join co-located legs as they are
+}
+
+impl Hints {
+    // Table names are uppercased like any other identifier
+    pub fn parse(text: &str) -> Result<Hints, String> {
+        let body = text.trim_start_matches("/*+").trim_end_matches('/').trim_end_matches('*');
+        let mut hints = Hints::default();
+
+        let mut rest = body.trim_start();
+        while !rest.is_empty() {
+            let name_len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            let (name, tail) = rest.split_at(name_len);
+            if name.is_empty() {
+                return Err(f!("Invalid hint syntax at '{rest}'"));
+            }
+
+            let tail = tail.trim_start();
+            let (args, tail) = if let Some(tail) = tail.strip_prefix('(') {
+                let end = tail.find(')').ok_or_else(|| f!("Missing ')' in hint {name}"))?;
+                let args = tail[..end].split(|c: char| c == ',' || c.is_whitespace()).filter(|arg| !arg.is_empty()).map(str::to_uppercase).collect::<Vec<_>>();
+                (Some(args), &tail[end + 1..])
+            } else {
+                (None, tail)
+            };
+            hints.add(name, args)?;
+            rest = tail.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+        }
+        Ok(hints)
+    }
+
+    fn add(&mut self, name: &str, args: Option<Vec<String>>) -> Result<(), String> {
+        let name = name.to_uppercase();
+        match (name.as_str(), args) {
+            ("BROADCAST", Some(args)) if !args.is_empty() => self.broadcast.extend(args),
+            ("LEADING", Some(args)) if !args.is_empty() => self.leading = args,
+            ("PARALLEL", Some(args)) if args.len() == 1 => {
+                let degree = args[0].parse::<usize>().ok().filter(|&degree| degree > 0).ok_or_else(|| f!("Invalid PARALLEL degree: {}", args[0]))?;
+                self.parallel = Some(degree)
+            }
+            ("NO_REPARTITION", None) => self.no_repartition = true,
+            ("BROADCAST" | "LEADING" | "PARALLEL" | "NO_REPARTITION", _) => return Err(f!("Invalid arguments to hint {name}")),
+            _ => return Err(f!("Unknown hint {name}")),
+        }
+        Ok(())
+    }
+}
+
+// Hints of a query block, with table names resolved to its quantifiers
+pub struct JoinHints {
+    pub broadcast: Vec<QunId>,
+    pub leading: Vec<QunId>,
+    pub parallel: usize,
+    pub no_repartition: bool,
+    pub broadcast_threshold: usize, // BROADCAST_THRESHOLD setting, for legs without a BROADCAST hint
+}
+
+impl QueryBlock {
+    pub fn parallel_degree(&self, env: &Env) -> usize {
+        self.hints.parallel.unwrap_or_else(|| env.settings.parallel_degree())
+    }
+
+    pub fn join_hints(&self, env: &Env) -> Result<JoinHints, KonaError> {
+        let hints = &self.hints;
+        Ok(JoinHints {
+            broadcast: self.hinted_quns("BROADCAST", &hints.broadcast)?,
+            leading: self.hinted_quns("LEADING", &hints.leading)?,
+            parallel: self.parallel_degree(env),
+            no_repartition: hints.no_repartition,
+            broadcast_threshold: env.settings.broadcast_threshold(),
+        })
+    }
+
+    fn hinted_quns(&self, hint: &str, names: &[String]) -> Result<Vec<QunId>, KonaError> {
+        names
+            .iter()
+            .map(|name| {
+                self.quns.iter().find(|qun| qun.matches_name_or_alias(name)).map(|qun| qun.id).ok_or_else(|| KonaError::Plan(f!("{hint} hint refers to unknown table {name}")))
+            })
+            .collect()
+    }
+}
@@ -1,6 +1,7 @@
 // includes.rs
 pub use std::{any::Any, collections::HashSet, fs, mem::replace};
 
+pub use crate::graph::KeyId;
 pub use arrow2::{
     array::{Array, BooleanArray, PrimitiveArray},
     chunk::Chunk,
@@ -10,18 +11,15 @@ pub use arrow2::{
 };
 pub use serde::{Deserialize, Serialize};
 pub use tracing::{debug, error, event, info, Level};
-pub use crate::graph::KeyId;
 
 pub use crate::{
     datum::{Datum, Datum::*},
     env::Env,
+    error::KonaError,
     stage::StageLink,
     *,
 };
 
-pub const TOPDIR: &str = "/Users/adarshrp/Projects/kona";
-pub const TEMPDIR: &str = "/Users/adarshrp/Projects/kona/tmp";
-
 pub type ColId = usize;
 pub type QunId = usize;
 pub type QBId = usize;
@@ -65,14 +63,17 @@ macro_rules! fprint {
 }
 pub(crate) use fprint;
 
-pub fn list_files(dirname: &String) -> Result<Vec<String>, String> {
-    let dir = fs::read_dir(dirname).map_err(|err| stringify1(err, dirname))?;
+pub fn list_files(dirname: &String) -> Result<Vec<String>, KonaError> {
+    let dir = fs::read_dir(dirname).map_err(|err| KonaError::io(dirname, err))?;
     let mut pathnames = vec![];
     for entry in dir {
-        let entry = entry.map_err(stringify)?;
+        let entry = entry.map_err(|err| KonaError::io(dirname, err))?;
         let path = entry.path();
         if !path.is_dir() {
-            let pathstr = path.into_os_string().into_string().map_err(stringify)?;
+            let pathstr = path
+                .into_os_string()
+                .into_string()
+                .map_err(|path| KonaError::io(dirname, std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?} is not valid UTF-8", path))))?;
             pathnames.push(pathstr)
         }
     }
@@ -81,8 +82,6 @@ pub fn list_files(dirname: &String) -> Result<Vec<String>, String> {
 
 pub type ChunkBox = Chunk<Box<dyn Array>>;
 
-pub const CHUNK_SIZE: usize = 1024;
-
 #[allow(unused_macros)]
 macro_rules! function_name {
     () => {{
@@ -98,14 +97,6 @@ macro_rules! function_name {
 #[allow(unused_imports)]
 pub(crate) use function_name;
 
-pub fn get_partition_dir(flow_id: usize, stage_link: StageLink, pid: PartitionId) -> String {
-    format!("{}/flow-{}/pipeline-{}-{}/consumer-{}", TEMPDIR, flow_id, stage_link.0, stage_link.1, pid)
-}
-
-pub fn get_output_dir(flow_id: usize) -> String {
-    format!("{}/flow-{}/output", TEMPDIR, flow_id)
-}
-
 pub fn has_duplicates<T: Eq + std::hash::Hash>(vec: &[T]) -> bool {
     // Create a HashSet from the vector to remove duplicates
     let set: HashSet<&T> = vec.iter().collect();
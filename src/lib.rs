@@ -0,0 +1,348 @@
+// lib: The engine. The kona binary and programs that embed it through a Session run statements with `run_script`.
+
+#![allow(clippy::too_many_arguments)]
+#![deny(elided_lifetimes_in_paths)]
+
+use ast::AST;
+use flow::Flow;
+use pop::POP;
+use qgm::QGM;
+
+use crate::{
+    diagnostic::SourceFile,
+    includes::*,
+    metrics::FlowMetrics,
+    qgm::ParserState,
+    resultset::ResultSet,
+    scheduler::FlowRun,
+    sink::{OutputFormat, SinkFormat, TableSink},
+};
+
+#[macro_use]
+extern crate lalrpop_util;
+
+#[macro_use]
+extern crate fstrings;
+
+extern crate lazy_static;
+
+extern crate tracing;
+
+lalrpop_mod!(#[allow(clippy::ptr_arg, clippy::empty_line_after_outer_attr)] pub sqlparser); // synthesized by LALRPOP
+
+#[macro_use]
+extern crate derivative;
+
+pub mod analyze;
+pub mod bitset;
+pub mod catalog;
+pub mod diagnostic;
+pub mod env;
+pub mod error;
+pub mod explain;
+pub mod graph;
+pub mod graphviz;
+pub mod includes;
+pub mod logging;
+pub mod metadata;
+pub mod repl;
+pub mod settings;
+
+pub mod ast;
+pub mod expr;
+pub mod hints;
+pub mod qgm;
+pub mod qgmiter;
+
+pub mod lop;
+pub mod lop_estimate;
+pub mod lop_repartition;
+pub mod prepare;
+pub mod qst;
+pub mod resultset;
+
+pub mod flow;
+pub mod metrics;
+pub mod pcode;
+pub mod pop;
+pub mod pop_compile;
+pub mod pop_csv;
+pub mod pop_hash;
+pub mod pop_hashagg;
+pub mod pop_hashmatch;
+pub mod pop_parquet;
+pub mod pop_project;
+pub mod pop_repartition;
+pub mod pop_run;
+pub mod pop_sort;
+
+pub mod datum;
+pub mod decimal;
+pub mod scheduler;
+pub mod session;
+pub mod sink;
+pub mod stage;
+pub mod task;
+pub mod temporal;
+#[cfg(test)]
+mod testing;
+
+pub mod print;
+
+pub use tracing::{debug, event, info, Level};
+
+pub use session::{QueryResult, Session};
+
+/***************************************************************************************************/
+pub fn run_flow(env: &mut Env, flow: &Flow) -> Result<FlowMetrics, KonaError> {
+    // Clear output directories
+    let dirname = format!("{}/flow", env.settings.temp_dir());
+    //std::fs::remove_dir_all(&dirname).map_err(|err| format!("Cannot remove temporary directory: {}", dirname))?;
+    std::fs::remove_dir_all(dirname).unwrap_or_default();
+
+    // Run the flow
+    FlowRun::start(env, flow)?.finish(env, flow)
+}
+
+pub fn enable_tracing(env: &mut Env, astlist: &mut Vec<AST>, run_trace: bool) -> Result<(), KonaError> {
+    let mut ix_trace = None;
+    for (ix, ast) in astlist.iter().enumerate() {
+        if let AST::SetOption { name, value } = ast {
+            if name.to_uppercase() == "TRACE" {
+                if ix_trace.is_some() {
+                    return Err(KonaError::Bind("Multiple SET TRACE statements found.".to_owned()));
+                }
+                if run_trace {
+                    env.set_option(name.clone(), value.clone())?;
+                }
+                ix_trace = Some(ix);
+            }
+        }
+    }
+
+    if let Some(ix_trace) = ix_trace {
+        astlist.remove(ix_trace);
+    } else if run_trace && !logging::is_started() {
+        // Default trace setting
+        logging::init("info");
+    }
+    Ok(())
+}
+
+// Resolve, plan and compile a query. PARSE_ONLY jobs stop after the logical plan and get no flow.
+fn compile_query(env: &mut Env, qgm: &mut QGM) -> Result<Option<Flow>, KonaError> {
+    let qgm_raw_pathname = format!("{}/{}", env.output_dir, "qgm_raw.dot");
+    let qgm_resolved_pathname = format!("{}/{}", env.output_dir, "qgm_resolved.dot");
+
+    // Resolve QGM
+    qgm.write_qgm_to_graphviz(&qgm_raw_pathname).map_err(KonaError::Plan)?;
+    graphviz::render(env, &qgm_raw_pathname).map_err(KonaError::Plan)?;
+    if qgm.has_parameters() {
+        return Err(KonaError::Bind(String::from("Parameter markers are only allowed in PREPARE statements.")));
+    }
+    qgm.resolve(env)?;
+    qgm.write_qgm_to_graphviz(&qgm_resolved_pathname).map_err(KonaError::Plan)?;
+    graphviz::render(env, &qgm_resolved_pathname).map_err(KonaError::Plan)?;
+
+    // Build LOPs
+    let (lop_graph, lop_key) = qgm.build_logical_plan(env)?;
+
+    if env.settings.parse_only() {
+        return Ok(None);
+    }
+
+    // Build POPs
+    let flow = POP::compile_flow(env, qgm, &lop_graph, lop_key)?;
+    Ok(Some(flow))
+}
+
+// Run a flow whose output goes to a table, and fold its partitions into the table's file
+fn run_sink_flow(env: &mut Env, flow: &Flow) -> Result<usize, KonaError> {
+    run_flow(env, flow)?;
+    flow.sink.as_ref().unwrap().commit(flow)
+}
+
+// Parse the statements of a job. `pathname` names their source in diagnostics.
+pub fn parse_script(pathname: &str, contents: &str) -> Result<Vec<AST>, KonaError> {
+    let mut parser_state = ParserState::default();
+
+    // Report every syntax error in the job, not just the first one
+    let mut errors = vec![];
+    let parse_result = sqlparser::JobParser::new().parse(&mut parser_state, &mut errors, contents);
+    match parse_result {
+        Ok(astlist) if errors.is_empty() => Ok(astlist),
+        Ok(_) => Err(KonaError::Parse(SourceFile::new(pathname, contents).describe_errors(&errors, None))),
+        Err(error) => Err(KonaError::Parse(SourceFile::new(pathname, contents).describe_errors(&errors, Some(&error)))),
+    }
+}
+
+// Parse and run statements, printing what they return
+pub fn run_script(env: &mut Env, pathname: &str, contents: &str, run_trace: bool) -> Result<(), KonaError> {
+    let mut astlist = parse_script(pathname, contents)?;
+
+    // Run any SET TRACE statement right away, if required. Additionally, ensure only one such statement exists in the job.
+    enable_tracing(env, &mut astlist, run_trace)?;
+
+    for ast in astlist.into_iter() {
+        if let Some(resultset) = run_statement(env, ast)? {
+            println!("{}\n", resultset);
+        }
+    }
+    Ok(())
+}
+
+// Run one statement. Catalog statements and EXPLAIN return their results, and statements that write tables or views a
+// one-row status; queries write theirs to the flow's output directory and print them in OUTPUT_FORMAT.
+pub fn run_statement(env: &mut Env, ast: AST) -> Result<Option<ResultSet>, KonaError> {
+    match ast {
+        AST::CreateSchema { name } => {
+            env.metadata.create_schema(name)?;
+        }
+        AST::CatalogTable { schema, name, columns, options, or_replace } => {
+            let options = env.resolve_path_options(options);
+            env.metadata.catalog_table(schema, name, columns, options, or_replace)?;
+        }
+        AST::AlterTable { schema, name, options } => {
+            let options = env.resolve_path_options(options);
+            env.metadata.alter_table(schema, name, options)?;
+        }
+        AST::DropTable { schema, name, if_exists, is_view } => {
+            env.metadata.drop_table(schema, name, if_exists, is_view)?;
+        }
+        AST::AnalyzeTable { schema, name, columns } => {
+            return analyze::analyze_table(env, schema, name, columns);
+        }
+        AST::ShowTables => return Ok(Some(env.metadata.show_tables())),
+        AST::ShowColumns { schema, name } => return env.metadata.show_columns(schema, name).map(Some),
+        AST::DescribeTable { schema, name } => return env.metadata.describe_table(schema, name).map(Some),
+        AST::DescribeView { schema, name } => return env.metadata.describe_view(schema, name).map(Some),
+        AST::CreateView { schema, name, columns, sql, mut qgm, or_replace } => {
+            // Resolving the query checks it and gives the view its column types
+            qgm.resolve(env)?;
+            let fields = qgm.main_qblock().get_projection(&qgm.expr_graph).fields().clone();
+            let names = columns.unwrap_or_else(|| qgm.output_column_names());
+            if names.len() != fields.len() {
+                return Err(KonaError::Bind(f!("CREATE VIEW {name} names {} columns but its query returns {}", names.len(), fields.len())));
+            }
+            if has_duplicates(&names) {
+                return Err(KonaError::Bind(f!("CREATE VIEW {name} needs a distinct name for every column: {names:?}")));
+            }
+            // Expressions don't track whether they can be NULL, so view columns may always be
+            let columns = names.into_iter().zip(fields.iter()).map(|(colname, field)| Field::new(colname, field.data_type.clone(), true)).collect();
+            env.metadata.create_view(schema, name.clone(), sql, columns, or_replace)?;
+            return Ok(Some(ResultSet::status(f!("Created view {name}"))));
+        }
+        AST::SetOption { name, value } => {
+            env.set_option(name, value)?;
+        }
+        AST::ShowOption { name } => return env.show_options(name).map(Some),
+        AST::ResetOption { name } => {
+            env.reset_option(name)?;
+        }
+        AST::QGM(mut qgm) => {
+            if let Some(flow) = compile_query(env, &mut qgm)? {
+                run_flow(env, &flow)?;
+
+                display_output(&flow)?;
+            }
+        }
+        AST::Explain { mode, qgm } => return explain::explain(env, mode, qgm).map(Some),
+        AST::Prepare { name, sql, qgm } => {
+            return prepare::prepare(env, name, sql, qgm).map(Some);
+        }
+        AST::Execute { name, values } => {
+            if let Some(flow) = prepare::execute(env, name, values)? {
+                run_flow(env, &flow)?;
+
+                display_output(&flow)?;
+            }
+        }
+        AST::Deallocate { name } => {
+            prepare::deallocate(env, name)?;
+        }
+        AST::CreateTableAs { schema, name, options, mut qgm } => {
+            let options = env.resolve_path_options(options);
+            let (format, pathname) = env.metadata.new_table_sink(schema.clone(), &name, &options)?;
+            if let Some(mut flow) = compile_query(env, &mut qgm)? {
+                let names = qgm.output_column_names();
+                if names.len() != flow.schema.fields.len() || has_duplicates(&names) {
+                    return Err(KonaError::Bind(f!("CREATE TABLE {name} needs a distinct name for every column: {names:?}")));
+                }
+                let fields: Vec<Field> = names.into_iter().zip(flow.schema.fields.iter()).map(|(colname, field)| Field::new(colname, field.data_type.clone(), true)).collect();
+
+                // CSV files don't carry their types so the table keeps the query's; Parquet tables read theirs back from the file
+                let columns = if matches!(format, SinkFormat::CSV { .. }) { Some(fields.clone()) } else { None };
+
+                flow.sink = Some(TableSink::new(format, pathname, Schema::from(fields), false));
+                let nrows = run_sink_flow(env, &flow)?;
+                env.metadata.catalog_table(schema, name.clone(), columns, options, false)?;
+                return Ok(Some(ResultSet::status(f!("Created table {name} with {nrows} rows"))));
+            }
+        }
+        AST::InsertInto { schema, name, mut qgm } => {
+            let (format, pathname, fields) = env.metadata.table_sink(schema.as_deref(), &name)?;
+            if let Some(mut flow) = compile_query(env, &mut qgm)? {
+                let types = flow.schema.fields.iter().map(|field| &field.data_type);
+                if fields.len() != flow.schema.fields.len() || fields.iter().zip(types).any(|(field, data_type)| field.data_type != *data_type) {
+                    let expected = fields.iter().map(|field| &field.data_type).collect::<Vec<_>>();
+                    let found = flow.schema.fields.iter().map(|field| &field.data_type).collect::<Vec<_>>();
+                    return Err(KonaError::Bind(f!("INSERT INTO {name} expects columns of type {expected:?} but the query returns {found:?}")));
+                }
+
+                flow.sink = Some(TableSink::new(format, pathname, Schema::from(fields), true));
+                let nrows = run_sink_flow(env, &flow)?;
+                return Ok(Some(ResultSet::status(f!("Inserted {nrows} rows into {name}"))));
+            }
+        }
+    }
+    Ok(None)
+}
+fn display_output(flow: &Flow) -> Result<(), KonaError> {
+    let output_dir = flow.output_dir();
+    let mut files = list_files(&output_dir)?;
+    files.sort();
+    match flow.settings.output_format {
+        OutputFormat::CSV { .. } | OutputFormat::JSON => display_output_dir(flow),
+        OutputFormat::Parquet { .. } | OutputFormat::IPC { .. } => {
+            println!("---------- output ----------");
+            for file in files.iter() {
+                println!("{}", file);
+            }
+            println!("----------------------------");
+            println!();
+        }
+        OutputFormat::Table { max_rows } => {
+            let mut chunks = vec![];
+            for file in files.iter() {
+                chunks.extend(sink::read_ipc(file)?);
+            }
+            println!("{}\n", pop::chunks_to_tabularstring(&flow.schema, &chunks, max_rows));
+        }
+    }
+    Ok(())
+}
+
+fn display_output_dir(flow: &Flow) {
+    println!("---------- output ----------");
+    let output_dir = flow.output_dir();
+    let mut files = list_files(&output_dir).unwrap();
+    files.sort();
+    for file_path in files.iter() {
+        let contents = fs::read_to_string(file_path).expect("Should have been able to read the file");
+        let lines = contents.split('\n').collect::<Vec<_>>();
+        for line in lines.iter().take(10) {
+            println!("{}", line);
+        }
+        if lines.len() > 10 {
+            println!("[{} lines not shown]", lines.len());
+        }
+    }
+    println!("----------------------------");
+    println!();
+}
+
+/*
+fn test(from: PrimitiveArray<i128>) {
+    cast::integer_to_decimal(&from, 5, 2);
+}
+*/
@@ -1,8 +1,11 @@
 // logging
 
-use std::env;
+use std::{env, sync::OnceLock};
 
-use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+use tracing_subscriber::{fmt, prelude::*, reload, EnvFilter, Registry};
+
+// Tracing starts once per process. Later calls swap in a new filter, so the shell can SET TRACE more than once.
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
 
 pub fn init(trace_cmd: &str) {
     env::set_var("RUST_LOG", "debug,[{tag}]=debug");
@@ -13,9 +16,18 @@ pub fn init(trace_cmd: &str) {
     //let env_filter = EnvFilter::from_default_env();
     let env_filter = EnvFilter::builder().parse_lossy(trace_cmd);
 
+    if let Some(handle) = FILTER.get() {
+        handle.reload(env_filter).unwrap();
+        return;
+    }
+    let (env_filter, handle) = reload::Layer::new(env_filter);
     tracing_subscriber::registry()
-        .with(fmt::layer()) //.pretty())
         .with(env_filter)
+        .with(fmt::layer()) //.pretty())
         .init();
+    FILTER.set(handle).unwrap();
 }
 
+pub fn is_started() -> bool {
+    FILTER.get().is_some()
+}
@@ -10,8 +10,9 @@ use crate::{
     expr::{Expr::*, ExprGraph, *},
     graph::{ExprKey, Graph, LOPKey, QueryBlockKey},
     includes::*,
+    lop_estimate::Estimate,
     metadata::{PartDesc, PartType},
-    qgm::{QueryBlock, QueryBlockType},
+    qgm::{Ordering, QueryBlock, QueryBlockType},
     QGM,
 };
 
@@ -69,6 +70,8 @@ pub enum LOP {
     HashJoin { lhs_join_keys: Vec<ExprKey>, rhs_join_keys: Vec<ExprKey> },
     Repartition { cpartitions: usize },
     Aggregation { key_len: usize },
+    DerivedTable,
+    Sort { sort_keys: Vec<(ExprKey, Ordering)> },
 }
 
 /***************************************************************************************************/
@@ -81,11 +84,12 @@ pub struct LOPProps {
     pub virtcols: Option<Vec<VirtCol>>,
     pub preds: Bitset<ExprKey>,
     pub partdesc: PartDesc,
+    pub estimate: Estimate,
 }
 
 impl LOPProps {
     fn new(quns: Bitset<QunId>, cols: Bitset<QunCol>, virtcols: Option<Vec<VirtCol>>, preds: Bitset<ExprKey>, partdesc: PartDesc) -> Self {
-        LOPProps { quns, cols, preds, partdesc, virtcols }
+        LOPProps { quns, cols, preds, partdesc, virtcols, estimate: Estimate::default() }
     }
 }
 
@@ -199,23 +203,23 @@ impl ExprEqClass {
 }
 
 impl QGM {
-    pub fn build_logical_plan(self: &mut QGM, env: &Env) -> Result<(LOPGraph, LOPKey), String> {
+    pub fn build_logical_plan(self: &mut QGM, env: &Env) -> Result<(LOPGraph, LOPKey), KonaError> {
         // Construct bitmaps
         let aps_context = APSContext::new(self);
         let mut lop_graph: LOPGraph = Graph::default();
 
-        let lop_key = self.build_qblock_logical_plan(env, self.main_qblock_key, &aps_context, &mut lop_graph, None);
-        if let Ok(lop_key) = lop_key {
-            // Perform any rewrites
-            let lop_key = self.qrw_add_repartitioning_keys_to_projections(&mut lop_graph, lop_key);
-            let lop_key = self.qrw_pushdown_join_keys(&mut lop_graph, lop_key);
+        let lop_key = self.build_qblock_logical_plan(env, self.main_qblock_key, &aps_context, &mut lop_graph, None)?;
 
-            let plan_pathname = format!("{}/{}", env.output_dir, "lop.dot");
-            self.write_logical_plan_to_graphviz(&lop_graph, lop_key, &plan_pathname)?;
-            Ok((lop_graph, lop_key))
-        } else {
-            Err("Something bad happened lol".to_string())
-        }
+        // Perform any rewrites
+        let lop_key = self.qrw_add_repartitioning_keys_to_projections(&mut lop_graph, lop_key);
+        let lop_key = self.qrw_pushdown_join_keys(&mut lop_graph, lop_key);
+
+        self.estimate_logical_plan(&mut lop_graph, lop_key);
+
+        let plan_pathname = format!("{}/{}", env.output_dir, "lop.dot");
+        self.write_logical_plan_to_graphviz(&lop_graph, lop_key, &plan_pathname).map_err(KonaError::Plan)?;
+        graphviz::render(env, &plan_pathname).map_err(KonaError::Plan)?;
+        Ok((lop_graph, lop_key))
     }
 
     fn append_virt_cols(lop_graph: &mut LOPGraph, lop_key: LOPKey, newcols: Option<&Vec<VirtCol>>) {
@@ -255,25 +259,22 @@ impl QGM {
         let mut iter = lop_graph.iter(root_lop_key);
         while let Some(lop_key) = iter.next(lop_graph) {
             let lop = lop_graph.get(lop_key);
-            match &lop.value {
-                LOP::HashJoin { lhs_join_keys, rhs_join_keys } => {
-                    // Only push down projections that are NOT column references. Singleton columns are already a part of the projection.
-                    let lhs_has_columns_only = lhs_join_keys.iter().all(|e| e.is_column(&self.expr_graph));
-                    let rhs_has_columns_only = rhs_join_keys.iter().all(|e| e.is_column(&self.expr_graph));
-                    if !(lhs_has_columns_only && rhs_has_columns_only) {
-                        let children = lop.children.clone();
-                        let (lhs_join_keys, rhs_join_keys) = (lhs_join_keys.clone(), rhs_join_keys.clone());
-                        let key_exprs = [lhs_join_keys, rhs_join_keys];
-                        for ix in [0, 1] {
-                            let child_lop_key = children.as_ref().unwrap()[ix];
-                            let virtcols = Some(&key_exprs[ix]);
-
-                            // Add partitioning columns to the Repartition projection
-                            Self::append_virt_cols(lop_graph, child_lop_key, virtcols);
-                        }
+            if let LOP::HashJoin { lhs_join_keys, rhs_join_keys } = &lop.value {
+                // Only push down projections that are NOT column references. Singleton columns are already a part of the projection.
+                let lhs_has_columns_only = lhs_join_keys.iter().all(|e| e.is_column(&self.expr_graph));
+                let rhs_has_columns_only = rhs_join_keys.iter().all(|e| e.is_column(&self.expr_graph));
+                if !(lhs_has_columns_only && rhs_has_columns_only) {
+                    let children = lop.children.clone();
+                    let (lhs_join_keys, rhs_join_keys) = (lhs_join_keys.clone(), rhs_join_keys.clone());
+                    let key_exprs = [lhs_join_keys, rhs_join_keys];
+                    for ix in [0, 1] {
+                        let child_lop_key = children.as_ref().unwrap()[ix];
+                        let virtcols = Some(&key_exprs[ix]);
+
+                        // Add partitioning columns to the Repartition projection
+                        Self::append_virt_cols(lop_graph, child_lop_key, virtcols);
                     }
                 }
-                _ => {}
             }
         }
         root_lop_key
@@ -281,7 +282,7 @@ impl QGM {
 
     pub fn build_qblock_logical_plan(
         self: &QGM, env: &Env, qblock_key: QueryBlockKey, aps_context: &APSContext, lop_graph: &mut LOPGraph, expected_partitioning: Option<&PartDesc>,
-    ) -> Result<LOPKey, String> {
+    ) -> Result<LOPKey, KonaError> {
         let qblock = &self.qblock_graph.get(qblock_key).value;
         let mut worklist: Vec<LOPKey> = vec![];
 
@@ -289,8 +290,11 @@ impl QGM {
 
         let all_preds = &aps_context.all_preds;
 
+        // Only the result rows need an order. ORDER BY in a nested query block doesn't change its result.
+        let order_by = qblock.order_by.as_ref().filter(|_| self.is_main_qblock(qblock_key));
+
         // Process select-list: Collect all QunCols
-        let select_list_quncol = self.collect_selectlist_quncols(aps_context, qblock);
+        let select_list_quncol = self.collect_selectlist_quncols(aps_context, qblock, order_by);
 
         // Process predicates: Collect quns, quncols. Also collect lhs/rhs quns for equi-join candidates
         let (mut pred_map, eqclass) = self.collect_preds(aps_context, qblock);
@@ -300,103 +304,94 @@ impl QGM {
 
         // Run greedy join enumeration
         let n = qblock.quns.len();
+        // Hints may only name tables of query blocks that join
+        let join_hints = if n > 1 { Some(qblock.join_hints(env)?) } else { None };
         for _ix in (2..=n).rev() {
+            let join_hints = join_hints.as_ref().unwrap();
             let mut join_status = None;
 
             // Iterate over pairs of all plans in work-list
-            'outer: for (_ix1, &lhs_plan_key) in worklist.iter().enumerate() {
-                for (_ix2, &rhs_plan_key) in worklist.iter().enumerate() {
-                    if lhs_plan_key == rhs_plan_key {
-                        continue;
-                    }
-
-                    let lhs_props = &lop_graph.get(lhs_plan_key).properties;
-                    let rhs_props = &lop_graph.get(rhs_plan_key).properties;
-
-                    let join_quns = &lhs_props.quns | &rhs_props.quns;
-
-                    // Are there any join predicates between two subplans?
-                    // P1.quns should be superset of LHS quns
-                    // P2.quns should be superset of RHS quns
-                    let join_preds = pred_map
-                        .iter()
-                        .filter_map(|(pred_key, pred_desc)| {
-                            let PredDesc { quns, .. } = pred_desc;
-                            let is_subset = (quns & &join_quns) == *quns;
-                            if is_subset {
-                                Some(*pred_key)
-                            } else {
-                                None
-                            }
-                        })
-                        .collect::<Vec<_>>();
-
-                    // Only select equality predicates (hash/merge joins only)
-                    let mut equi_join_preds = join_preds
-                        .iter()
-                        .filter_map(|&pred_key| {
-                            if let Some(eqjoin_desc) = pred_map.get(&pred_key).unwrap().eqjoin_desc.as_ref() {
-                                let join_class = Self::classify_predicate(eqjoin_desc, lhs_props, rhs_props);
-                                if join_class.0 == PredicateType::EquiJoin {
-                                    Some((pred_key, join_class.1))
-                                } else {
-                                    None
-                                }
+            for (lhs_plan_key, rhs_plan_key) in Self::join_candidates(lop_graph, &worklist, &join_hints.leading) {
+                let lhs_props = &lop_graph.get(lhs_plan_key).properties;
+                let rhs_props = &lop_graph.get(rhs_plan_key).properties;
+
+                let join_quns = &lhs_props.quns | &rhs_props.quns;
+
+                // Are there any join predicates between two subplans?
+                // P1.quns should be superset of LHS quns
+                // P2.quns should be superset of RHS quns
+                let join_preds = pred_map
+                    .iter()
+                    .filter_map(|(pred_key, pred_desc)| {
+                        let PredDesc { quns, .. } = pred_desc;
+                        let is_subset = (quns & &join_quns) == *quns;
+                        if is_subset {
+                            Some(*pred_key)
+                        } else {
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                // Only select equality predicates (hash/merge joins only)
+                let mut equi_join_preds = join_preds
+                    .iter()
+                    .filter_map(|&pred_key| {
+                        if let Some(eqjoin_desc) = pred_map.get(&pred_key).unwrap().eqjoin_desc.as_ref() {
+                            let join_class = Self::classify_predicate(eqjoin_desc, lhs_props, rhs_props);
+                            if join_class.0 == PredicateType::EquiJoin {
+                                Some((pred_key, join_class.1))
                             } else {
                                 None
                             }
-                        })
-                        .collect::<Vec<_>>();
+                        } else {
+                            None
+                        }
+                    })
+                    .collect::<Vec<_>>();
 
-                    // Sort preds since the preceding hash-based ordering can be random
-                    equi_join_preds.sort_by(|a, b| a.0.cmp(&b.0));
-                    let eqq = equi_join_preds.iter().map(|e| e.0).collect::<Vec<_>>();
+                // Sort preds since the preceding hash-based ordering can be random
+                equi_join_preds.sort_by_key(|a| a.0);
+                let eqq = equi_join_preds.iter().map(|e| e.0).collect::<Vec<_>>();
 
-                    if !equi_join_preds.is_empty() {
-                        let mut preds = all_preds.clone_metadata();
+                if !equi_join_preds.is_empty() {
+                    let mut preds = all_preds.clone_metadata();
 
-                        for pred_key in join_preds.iter() {
-                            // Don't add equijoin preds to the after-join list
-                            if !eqq.contains(pred_key) {
-                                preds.set(*pred_key);
-                            }
-                            pred_map.remove_entry(pred_key);
+                    for pred_key in join_preds.iter() {
+                        // Don't add equijoin preds to the after-join list
+                        if !eqq.contains(pred_key) {
+                            preds.set(*pred_key);
                         }
+                        pred_map.remove_entry(pred_key);
+                    }
 
-                        // Initialize join properties
-                        let quns = &lhs_props.quns | &rhs_props.quns;
-                        let mut cols = &lhs_props.cols | &rhs_props.cols;
-
-                        // Compute cols to flow through. Retain all cols in the select-list + unbound preds
-                        let mut flowcols = select_list_quncol.clone();
-                        for (_, PredDesc { quncols, .. }) in pred_map.iter() {
-                            flowcols |= quncols;
-                        }
-                        cols &= flowcols;
+                    // Initialize join properties
+                    let quns = &lhs_props.quns | &rhs_props.quns;
+                    let mut cols = &lhs_props.cols | &rhs_props.cols;
 
-                        let (new_lhs_plan_key, new_rhs_plan_key, lhs_join_keys, rhs_join_keys, cpartitions) =
-                            self.repartition_join_legs(env, lop_graph, lhs_plan_key, rhs_plan_key, &equi_join_preds, &eqclass);
+                    // Compute cols to flow through. Retain all cols in the select-list + unbound preds
+                    let mut flowcols = select_list_quncol.clone();
+                    for (_, PredDesc { quncols, .. }) in pred_map.iter() {
+                        flowcols |= quncols;
+                    }
+                    cols &= flowcols;
 
-                        // Join partitioning is identical to partitioning of the LHS.
-                        let lhs_props = &lop_graph.get(new_lhs_plan_key).properties;
-                        let mut partdesc = lhs_props.partdesc.clone();
-                        partdesc.npartitions = cpartitions;
+                    let (new_lhs_plan_key, new_rhs_plan_key, lhs_join_keys, rhs_join_keys, partdesc) =
+                        self.repartition_join_legs(lop_graph, join_hints, lhs_plan_key, rhs_plan_key, &equi_join_preds, &eqclass)?;
 
-                        let props = LOPProps::new(quns, cols, None, preds, partdesc);
+                    let props = LOPProps::new(quns, cols, None, preds, partdesc);
 
-                        let join_lop_key =
-                            lop_graph.add_node_with_props(LOP::HashJoin { lhs_join_keys, rhs_join_keys }, props, Some(vec![new_lhs_plan_key, new_rhs_plan_key]));
+                    let join_lop_key = lop_graph.add_node_with_props(LOP::HashJoin { lhs_join_keys, rhs_join_keys }, props, Some(vec![new_lhs_plan_key, new_rhs_plan_key]));
 
-                        join_status = Some((lhs_plan_key, rhs_plan_key, join_lop_key));
+                    join_status = Some((lhs_plan_key, rhs_plan_key, join_lop_key));
 
-                        // For now, go with the first equi-join
-                        break 'outer;
-                    }
+                    // For now, go with the first equi-join
+                    break;
                 }
             }
 
             if let Some((plan1_key, plan2_key, join_node)) = join_status {
-                worklist.retain(|&elem| (elem != plan1_key && elem != plan2_key));
+                worklist.retain(|&elem| elem != plan1_key && elem != plan2_key);
                 worklist.insert(0, join_node);
             } else {
                 panic!("No join found!!!")
@@ -409,20 +404,52 @@ impl QGM {
                 root_lop_key = self.repartition_if_needed(lop_graph, root_lop_key, expected_partitioning, &eqclass);
             }
 
+            // Rows are sorted once all of them are in a single partition
+            if order_by.is_some() {
+                root_lop_key = self.repartition_if_needed(lop_graph, root_lop_key, &PartDesc::new(1, PartType::BROADCAST), &eqclass);
+            }
+
             // Only the select-list expressions flow out of a queryblock. We can clear the column bitset.
             let props = &mut lop_graph.get_mut(root_lop_key).properties;
             let virtcols = qblock.select_list.iter().map(|ne| ne.expr_key).collect::<Vec<_>>();
-            props.virtcols = Some(virtcols);
+            props.virtcols = Some(virtcols.clone());
             props.cols = props.cols.clone_metadata();
 
+            // Sort keys flow out along with the select-list, which is all that flows out of the sort
+            if let Some(order_by) = order_by {
+                props.virtcols.as_mut().unwrap().extend(order_by.iter().map(|&(expr_key, _)| expr_key));
+                let props = LOPProps::new(props.quns.clone(), props.cols.clone(), Some(virtcols), props.preds.clone_metadata(), PartDesc::new(1, PartType::RAW));
+                root_lop_key = lop_graph.add_node_with_props(LOP::Sort { sort_keys: order_by.clone() }, props, Some(vec![root_lop_key]));
+            }
+
             info!("Created logical plan for qblock id: {}", qblock.id);
 
             Ok(root_lop_key)
         } else {
-            Err("Cannot find plan for qblock".to_string())
+            Err(KonaError::Plan("Cannot find plan for qblock".to_string()))
         }
     }
 
+    // Pairs of plans to try joining, in order. Under a LEADING hint, the plan holding the leading tables joined so far
+    // comes first, paired with the next leading table.
+    fn join_candidates(lop_graph: &LOPGraph, worklist: &[LOPKey], leading: &[QunId]) -> Vec<(LOPKey, LOPKey)> {
+        let mut pairs = worklist.iter().flat_map(|&lhs| worklist.iter().filter(move |&&rhs| rhs != lhs).map(move |&rhs| (lhs, rhs))).collect::<Vec<_>>();
+
+        let plan_with = |qun_id: QunId| worklist.iter().copied().find(|&lop_key| lop_graph.get_properties(lop_key).quns.get(qun_id));
+        if let Some(lead_plan_key) = leading.first().and_then(|&qun_id| plan_with(qun_id)) {
+            let lead_quns = &lop_graph.get_properties(lead_plan_key).quns;
+            let nlead = lead_quns.len();
+            if nlead < leading.len() && leading[..nlead].iter().all(|&qun_id| lead_quns.get(qun_id)) {
+                if let Some(next_plan_key) = plan_with(leading[nlead]) {
+                    let ix = pairs.iter().position(|&pair| pair == (lead_plan_key, next_plan_key)).unwrap();
+                    let pair = pairs.remove(ix);
+                    pairs.insert(0, pair);
+                }
+            }
+        }
+        pairs
+    }
+
     pub fn classify_predicate(eqjoin_desc: &EqJoinDesc, lhs_props: &LOPProps, rhs_props: &LOPProps) -> (PredicateType, PredicateAlignment) {
         let (lhs_pred_quns, rhs_pred_quns) = (&eqjoin_desc.lhs_quns, &eqjoin_desc.rhs_quns);
 
@@ -437,9 +464,17 @@ impl QGM {
         }
     }
 
-    fn collect_selectlist_quncols(&self, aps_context: &APSContext, qblock: &QueryBlock) -> Bitset<QunCol> {
+    // Columns that flow out of the query block: those of the select-list and of the sort keys
+    fn collect_selectlist_quncols(&self, aps_context: &APSContext, qblock: &QueryBlock, order_by: Option<&Vec<(ExprKey, Ordering)>>) -> Bitset<QunCol> {
         let mut select_list_quncol = aps_context.all_quncols.clone_metadata();
-        qblock.select_list.iter().flat_map(|ne| ne.expr_key.iter_quncols(&self.expr_graph)).for_each(|quncol| select_list_quncol.set(quncol));
+        let sort_keys = order_by.into_iter().flatten().map(|&(expr_key, _)| expr_key);
+        qblock
+            .select_list
+            .iter()
+            .map(|ne| ne.expr_key)
+            .chain(sort_keys)
+            .flat_map(|expr_key| expr_key.iter_quncols(&self.expr_graph))
+            .for_each(|quncol| select_list_quncol.set(quncol));
         select_list_quncol
     }
 
@@ -513,7 +548,7 @@ impl QGM {
     pub fn build_unary_plans(
         self: &QGM, env: &Env, aps_context: &APSContext, qblock: &QueryBlock, lop_graph: &mut LOPGraph, pred_map: &mut PredMap, select_list_quncol: &Bitset<QunCol>,
         worklist: &mut Vec<LOPKey>,
-    ) -> Result<(), String> {
+    ) -> Result<(), KonaError> {
         let APSContext { all_quncols, all_quns, all_preds } = aps_context;
 
         // Build unary POPs first
@@ -561,7 +596,7 @@ impl QGM {
                     debug!("expected: {:?}", e.describe(&self.expr_graph, false))
                 }
 
-                let expected_partitioning = PartDesc { npartitions: env.settings.parallel_degree.unwrap_or(1), part_type: PartType::HASHEXPR(expected_partitioning_expr) };
+                let expected_partitioning = PartDesc { npartitions: qblock.parallel_degree(env), part_type: PartType::HASHEXPR(expected_partitioning_expr) };
 
                 let child_lop_key = self.build_qblock_logical_plan(env, child_qblock_key, aps_context, lop_graph, Some(&expected_partitioning))?;
 
@@ -569,11 +604,18 @@ impl QGM {
 
                 let props = LOPProps::new(quns, output_quncols, None, preds, expected_partitioning);
                 lop_graph.add_node_with_props(LOP::Aggregation { key_len }, props, children)
+            } else if let Some(child_qblock_key) = qun.get_qblock() {
+                // Derived table: its columns are the child's select-list, by position
+                let child_lop_key = self.build_qblock_logical_plan(env, child_qblock_key, aps_context, lop_graph, None)?;
+                let partdesc = lop_graph.get_properties(child_lop_key).partdesc.clone();
+
+                let props = LOPProps::new(quns, output_quncols, None, preds, partdesc);
+                lop_graph.add_node_with_props(LOP::DerivedTable, props, Some(vec![child_lop_key]))
             } else {
                 let npartitions = if let Some(tabledesc) = qun.tabledesc.as_ref() {
                     tabledesc.get_part_desc().unwrap().npartitions
                 } else {
-                    env.settings.parallel_degree.unwrap_or(1) * 2 // todo: temporary hack to force different partition counts in a plan
+                    qblock.parallel_degree(env) * 2 // todo: temporary hack to force different partition counts in a plan
                 };
                 let partdesc = PartDesc::new(npartitions, PartType::RAW);
 
@@ -0,0 +1,349 @@
+// lop_estimate: Cardinality and row width estimates for logical plans
+
+use std::cmp::Ordering;
+
+use crate::{
+    analyze::compare,
+    expr::{Expr::*, *},
+    graph::{ExprKey, LOPKey},
+    includes::*,
+    lop::{LOPGraph, LOP},
+    metadata::{ColumnStats, PartType, TableStats, DEFAULT_NROWS},
+    QGM,
+};
+
+// Selectivities used when a predicate can't be matched against column statistics
+const DEFAULT_EQ_SEL: f64 = 0.1;
+const DEFAULT_RANGE_SEL: f64 = 1.0 / 3.0;
+const DEFAULT_BETWEEN_SEL: f64 = 0.25;
+const DEFAULT_LIKE_SEL: f64 = 0.1;
+const DEFAULT_NULL_SEL: f64 = 0.01;
+const DEFAULT_SEL: f64 = 0.5;
+
+// Fraction of its input that a grouping key is assumed to keep when the key has no NDV
+const DEFAULT_GROUP_RATIO: f64 = 0.1;
+const DEFAULT_VARLEN_WIDTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Estimate {
+    pub nrows: f64,
+    pub row_width: usize, // Bytes per row, over the columns this operator projects
+}
+
+impl Estimate {
+    pub fn describe(&self) -> String {
+        format!("rows = {:.0}, width = {}", self.nrows, self.row_width)
+    }
+}
+
+fn type_width(data_type: &DataType) -> usize {
+    match data_type {
+        DataType::Boolean => 1,
+        DataType::Int8 => 1,
+        DataType::Int16 => 2,
+        DataType::Int32 | DataType::Date32 => 4,
+        DataType::Decimal(..) | DataType::Interval(_) => 16,
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Binary | DataType::LargeBinary => DEFAULT_VARLEN_WIDTH,
+        _ => 8,
+    }
+}
+
+fn numeric(value: &Datum) -> Option<f64> {
+    match value {
+        Int32(value) | Date32(value) => Some(*value as f64),
+        Int64(value) | Timestamp(value, _) => Some(*value as f64),
+        Float64(value) => Some(f64::from(*value)),
+        _ => None,
+    }
+}
+
+// Literals and column values only compare if both are numbers or both are the same kind of datum
+fn compare_values(lhs: &Datum, rhs: &Datum) -> Option<Ordering> {
+    match (numeric(lhs), numeric(rhs)) {
+        (Some(lhs), Some(rhs)) => lhs.partial_cmp(&rhs),
+        _ if std::mem::discriminant(lhs) == std::mem::discriminant(rhs) => Some(compare(lhs, rhs)),
+        _ => None,
+    }
+}
+
+// A column of a base table, with the stats collected for it by ANALYZE TABLE
+struct ColumnInfo {
+    table_stats: TableStats,
+    column_stats: Option<ColumnStats>,
+}
+
+impl ColumnInfo {
+    fn non_null_fraction(&self) -> f64 {
+        match &self.column_stats {
+            Some(column_stats) if self.table_stats.nrows > 0 => 1.0 - column_stats.null_count as f64 / self.table_stats.nrows as f64,
+            _ => 1.0,
+        }
+    }
+
+    fn eq_sel(&self, value: Option<&Datum>) -> f64 {
+        match &self.column_stats {
+            Some(column_stats) if column_stats.ndv > 0 => {
+                let out_of_range = value.is_some_and(|value| {
+                    let below = column_stats.min.as_ref().and_then(|min| compare_values(value, min)) == Some(Ordering::Less);
+                    let above = column_stats.max.as_ref().and_then(|max| compare_values(value, max)) == Some(Ordering::Greater);
+                    below || above
+                });
+                if out_of_range {
+                    0.0
+                } else {
+                    self.non_null_fraction() / column_stats.ndv as f64
+                }
+            }
+            _ => DEFAULT_EQ_SEL,
+        }
+    }
+
+    // Fraction of the non-null values below `value`, read off the histogram's buckets and interpolated within one
+    fn cdf(&self, value: &Datum) -> Option<f64> {
+        let column_stats = self.column_stats.as_ref()?;
+        let (min, max) = (column_stats.min.as_ref()?, column_stats.max.as_ref()?);
+        if compare_values(value, min)? != Ordering::Greater {
+            return Some(0.0);
+        }
+        if compare_values(value, max)? != Ordering::Less {
+            return Some(1.0);
+        }
+
+        let histogram = &column_stats.histogram;
+        let (bucket, lower, upper, nbuckets) = if histogram.is_empty() {
+            (0, min, max, 1)
+        } else {
+            let bucket = histogram.iter().take_while(|bound| compare_values(bound, value) == Some(Ordering::Less)).count().min(histogram.len() - 1);
+            let lower = if bucket == 0 { min } else { &histogram[bucket - 1] };
+            (bucket, lower, &histogram[bucket], histogram.len())
+        };
+        let within = match (numeric(value), numeric(lower), numeric(upper)) {
+            (Some(value), Some(lower), Some(upper)) if upper > lower => ((value - lower) / (upper - lower)).clamp(0.0, 1.0),
+            _ => 0.5,
+        };
+        Some((bucket as f64 + within) / nbuckets as f64)
+    }
+}
+
+impl QGM {
+    // Estimates every operator of a plan bottom-up and records the results in its properties
+    pub fn estimate_logical_plan(&self, lop_graph: &mut LOPGraph, lop_key: LOPKey) -> Estimate {
+        let children = lop_graph.get(lop_key).children.clone().unwrap_or_default();
+        let child_estimates = children.iter().map(|&child_key| self.estimate_logical_plan(lop_graph, child_key)).collect::<Vec<_>>();
+
+        let (lop, props, _) = lop_graph.get3(lop_key);
+        let nrows = match lop {
+            LOP::TableScan { .. } => {
+                let qunid = props.quns.elements()[0];
+                self.metadata.get_tabledesc(qunid).and_then(|tabledesc| tabledesc.get_stats().map(|stats| stats.nrows)).unwrap_or(DEFAULT_NROWS) as f64
+            }
+            LOP::HashJoin { lhs_join_keys, rhs_join_keys } => {
+                let (lhs, rhs) = (child_estimates[0].nrows, child_estimates[1].nrows);
+                // Every key value on the side with fewer of them is assumed to find matches on the other side
+                lhs_join_keys.iter().zip(rhs_join_keys.iter()).fold(lhs * rhs, |nrows, (&lhs_key, &rhs_key)| {
+                    let ndv = self.key_ndv(lhs_key, lhs).unwrap_or(lhs).max(self.key_ndv(rhs_key, rhs).unwrap_or(rhs));
+                    if ndv > 0.0 {
+                        nrows / ndv
+                    } else {
+                        nrows
+                    }
+                })
+            }
+            LOP::Aggregation { key_len } => {
+                let input = child_estimates[0].nrows;
+                let keys = if let PartType::HASHEXPR(keys) = &props.partdesc.part_type { &keys[..] } else { &[] };
+                let ngroups = keys.iter().take(*key_len).map(|&key| self.key_ndv(key, input).unwrap_or(input * DEFAULT_GROUP_RATIO).max(1.0)).product::<f64>();
+                ngroups.min(input)
+            }
+            LOP::Repartition { .. } | LOP::DerivedTable | LOP::Sort { .. } => child_estimates[0].nrows,
+        };
+        let selectivity = props.preds.elements().iter().map(|&pred_key| self.selectivity(pred_key)).product::<f64>();
+        let nrows = if nrows > 0.0 { (nrows * selectivity).max(1.0) } else { 0.0 };
+
+        let mut row_width = props.cols.elements().iter().map(|&quncol| self.column_width(quncol)).sum::<usize>();
+        if let Some(virtcols) = &props.virtcols {
+            row_width += virtcols.iter().map(|virtcol| self.expr_width(*virtcol)).sum::<usize>();
+        }
+
+        let estimate = Estimate { nrows, row_width };
+        lop_graph.get_mut(lop_key).properties.estimate = estimate;
+        estimate
+    }
+
+    fn column_info(&self, expr_key: ExprKey) -> Option<ColumnInfo> {
+        if let Column { qunid, colid, .. } = self.expr_graph.get(expr_key).value {
+            let tabledesc = self.metadata.get_tabledesc(qunid)?;
+            let table_stats = tabledesc.get_stats()?.clone();
+            let column_stats = table_stats.columns.get(&tabledesc.fields()[colid].name).cloned();
+            Some(ColumnInfo { table_stats, column_stats })
+        } else {
+            None
+        }
+    }
+
+    fn literal(&self, expr_key: ExprKey) -> Option<&Datum> {
+        if let Literal(value) = &self.expr_graph.get(expr_key).value {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    // Distinct values of a key expression, no more than the rows it is computed over
+    fn key_ndv(&self, expr_key: ExprKey, nrows: f64) -> Option<f64> {
+        let column_stats = self.column_info(expr_key)?.column_stats?;
+        Some((column_stats.ndv as f64).min(nrows))
+    }
+
+    fn column_width(&self, quncol: QunCol) -> usize {
+        let field = match self.metadata.get_field(quncol) {
+            Some(field) => field,
+            None => return type_width(&DataType::Null),
+        };
+        let tabledesc = self.metadata.get_tabledesc(quncol.0).unwrap();
+        let column_stats = tabledesc.get_stats().and_then(|stats| stats.columns.get(&field.name));
+        match column_stats {
+            Some(column_stats) if column_stats.avg_width > 0 => column_stats.avg_width,
+            _ => type_width(field.data_type()),
+        }
+    }
+
+    fn expr_width(&self, expr_key: ExprKey) -> usize {
+        if let Column { qunid, colid, .. } = self.expr_graph.get(expr_key).value {
+            if self.metadata.get_field(QunCol(qunid, colid)).is_some() {
+                return self.column_width(QunCol(qunid, colid));
+            }
+        }
+        type_width(expr_key.get_data_type(&self.expr_graph))
+    }
+
+    pub fn selectivity(&self, pred_key: ExprKey) -> f64 {
+        let (expr, _, children) = self.expr_graph.get3(pred_key);
+        let children = children.map(|children| &children[..]).unwrap_or_default();
+        let selectivity = match expr {
+            LogExpr(LogOp::And) => children.iter().map(|&child| self.selectivity(child)).product(),
+            LogExpr(LogOp::Or) => children.iter().fold(0.0, |sel, &child| {
+                let child_sel = self.selectivity(child);
+                sel + child_sel - sel * child_sel
+            }),
+            LogExpr(LogOp::Not) => 1.0 - self.selectivity(children[0]),
+            RelExpr(op) => self.relexpr_selectivity(*op, children[0], children[1]),
+            BetweenExpr => {
+                let column = self.column_info(children[0]);
+                let bounds = (self.literal(children[1]), self.literal(children[2]));
+                match (column, bounds) {
+                    (Some(column), (Some(lower), Some(upper))) => match (column.cdf(lower), column.cdf(upper)) {
+                        (Some(lower_sel), Some(upper_sel)) => (upper_sel - lower_sel).max(0.0) * column.non_null_fraction(),
+                        _ => DEFAULT_BETWEEN_SEL,
+                    },
+                    _ => DEFAULT_BETWEEN_SEL,
+                }
+            }
+            InListExpr => {
+                let column = self.column_info(children[0]);
+                children[1..]
+                    .iter()
+                    .map(|&item| match &column {
+                        Some(column) => column.eq_sel(self.literal(item)),
+                        None => DEFAULT_EQ_SEL,
+                    })
+                    .sum()
+            }
+            _ => DEFAULT_SEL,
+        };
+        selectivity.clamp(0.0, 1.0)
+    }
+
+    fn relexpr_selectivity(&self, op: RelOp, lhs: ExprKey, rhs: ExprKey) -> f64 {
+        // Put the column on the left, flipping the comparison if needed
+        let (op, lhs, rhs) = if self.column_info(lhs).is_none() && self.column_info(rhs).is_some() {
+            let op = match op {
+                RelOp::Lt => RelOp::Gt,
+                RelOp::Le => RelOp::Ge,
+                RelOp::Gt => RelOp::Lt,
+                RelOp::Ge => RelOp::Le,
+                op => op,
+            };
+            (op, rhs, lhs)
+        } else {
+            (op, lhs, rhs)
+        };
+        let column = self.column_info(lhs);
+        let value = self.literal(rhs);
+
+        match op {
+            RelOp::Eq | RelOp::Ne => {
+                let eq_sel = match (&column, self.column_info(rhs)) {
+                    // col1 = col2
+                    (Some(lhs), Some(rhs)) => lhs.eq_sel(None).min(rhs.eq_sel(None)),
+                    (Some(column), None) => column.eq_sel(value),
+                    _ => DEFAULT_EQ_SEL,
+                };
+                if op == RelOp::Eq {
+                    eq_sel
+                } else {
+                    column.map_or(1.0, |column| column.non_null_fraction()) - eq_sel
+                }
+            }
+            RelOp::Lt | RelOp::Le | RelOp::Gt | RelOp::Ge => {
+                let below = column.as_ref().zip(value).and_then(|(column, value)| column.cdf(value));
+                match (column, below) {
+                    (Some(column), Some(below)) => {
+                        let below = if matches!(op, RelOp::Le | RelOp::Gt) { below + column.eq_sel(value) } else { below }.min(1.0);
+                        let sel = if matches!(op, RelOp::Lt | RelOp::Le) { below } else { 1.0 - below };
+                        sel * column.non_null_fraction()
+                    }
+                    _ => DEFAULT_RANGE_SEL,
+                }
+            }
+            RelOp::Is => match column {
+                Some(ColumnInfo { table_stats, column_stats: Some(column_stats) }) if table_stats.nrows > 0 => column_stats.null_count as f64 / table_stats.nrows as f64,
+                _ => DEFAULT_NULL_SEL,
+            },
+            RelOp::Like => match (column, value) {
+                // A pattern without wildcards is an equality
+                (Some(column), Some(Utf8(pattern))) if !pattern.contains(['%', '_']) => column.eq_sel(value),
+                _ => DEFAULT_LIKE_SEL,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    // Row count estimated for the scan of T
+    fn estimated_rows(session: &mut TestSession, predicate: &str) -> String {
+        let rows = query(session, &f!("EXPLAIN LOGICAL SELECT ID FROM T WHERE {predicate}")).unwrap();
+        let line = rows.iter().find_map(|row| if let Utf8(line) = &row[0] { line.split("rows = ").nth(1).map(String::from) } else { None }).unwrap();
+        line.split(',').next().unwrap().to_string()
+    }
+
+    #[test]
+    fn predicates_are_estimated_from_column_stats() {
+        let mut session = session();
+        let rows = (1..=100).map(|id| f!("{id},{}", ["a", "b", "c", "d"][id % 4])).collect::<Vec<_>>();
+        create_table(&mut session, "T", "ID INT, CAT STRING", &rows.iter().map(|row| row.as_str()).collect::<Vec<_>>());
+        // Without stats, tables are assumed to have DEFAULT_NROWS rows and predicates have fixed selectivities
+        for (predicate, expected) in [("ID = 5", "100"), ("ID < 26", "333"), ("CAT = 'a' AND ID < 51", "33")] {
+            assert_eq!(estimated_rows(&mut session, predicate), expected, "{}", predicate);
+        }
+        execute(&mut session, "ANALYZE TABLE T").unwrap();
+
+        for (predicate, expected) in [
+            ("ID = 5", "1"),
+            ("ID = 500", "1"), // Out of range, at least one row is always assumed
+            ("CAT = 'a'", "25"),
+            ("CAT <> 'a'", "75"),
+            ("ID < 26", "26"),
+            ("26 > ID", "26"),
+            ("ID >= 91", "9"),
+            ("CAT = 'a' OR CAT = 'b'", "44"),
+            ("CAT = 'a' AND ID < 51", "13"),
+        ] {
+            assert_eq!(estimated_rows(&mut session, predicate), expected, "{}", predicate);
+        }
+    }
+}
@@ -2,13 +2,18 @@
 
 use crate::{
     expr::{Expr::*, ExprGraph, *},
-    graph::{ExprKey, Graph, LOPKey},
+    graph::{ExprKey, LOPKey},
+    hints::JoinHints,
     includes::*,
     lop::{ExprEqClass, LOPGraph, LOPProps, PredicateAlignment, VirtCol, LOP},
+    lop_estimate::Estimate,
     metadata::{PartDesc, PartType},
     QGM,
 };
 
+// Both join legs after repartitioning, their join keys and the partitioning of the join
+type RepartitionedLegs = (LOPKey, LOPKey, Vec<ExprKey>, Vec<ExprKey>, PartDesc);
+
 impl QGM {
     pub fn repartition_if_needed(self: &QGM, lop_graph: &mut LOPGraph, lop_key: LOPKey, expected_partitioning: &PartDesc, eqclass: &ExprEqClass) -> LOPKey {
         let props = lop_graph.get_properties(lop_key);
@@ -23,29 +28,51 @@ impl QGM {
             let cpartitions = partdesc.npartitions;
             partdesc.npartitions = actual_partitioning.npartitions;
 
-            let props = LOPProps { quns: props.quns.clone(), cols: props.cols.clone(), virtcols: None, preds: props.preds.clone_metadata(), partdesc };
+            let props =
+                LOPProps { quns: props.quns.clone(), cols: props.cols.clone(), virtcols: None, preds: props.preds.clone_metadata(), partdesc, estimate: Estimate::default() };
             lop_graph.add_node_with_props(LOP::Repartition { cpartitions }, props, Some(vec![lop_key]))
         }
     }
 
     pub fn repartition_join_legs(
-        self: &QGM, env: &Env, lop_graph: &mut Graph<LOPKey, LOP, LOPProps>, lhs_plan_key: LOPKey, rhs_plan_key: LOPKey, equi_join_preds: &[(ExprKey, PredicateAlignment)],
+        self: &QGM, lop_graph: &mut LOPGraph, join_hints: &JoinHints, lhs_plan_key: LOPKey, rhs_plan_key: LOPKey, equi_join_preds: &[(ExprKey, PredicateAlignment)],
         eqclass: &ExprEqClass,
-    ) -> (LOPKey, LOPKey, Vec<ExprKey>, Vec<ExprKey>, usize) {
+    ) -> Result<RepartitionedLegs, KonaError> {
+        let (lhs_bytes, rhs_bytes) = (self.broadcast_bytes(lop_graph, join_hints, lhs_plan_key), self.broadcast_bytes(lop_graph, join_hints, rhs_plan_key));
         let lhs_props = &lop_graph.get(lhs_plan_key).properties;
         let rhs_props = &lop_graph.get(rhs_plan_key).properties;
+        let (lhs_npartitions, rhs_npartitions) = (lhs_props.partdesc.npartitions, rhs_props.partdesc.npartitions);
+        let is_hinted = |props: &LOPProps| props.quns.elements().iter().all(|qun_id| join_hints.broadcast.contains(qun_id));
 
-        // Repartition join legs as needed
-        let (lhs_partdesc, rhs_partdesc, lhs_join_keys, rhs_join_keys, cpartitions) =
-            Self::harmonize_partitions(env, lop_graph, lhs_plan_key, rhs_plan_key, &self.expr_graph, equi_join_preds, eqclass);
+        // Hinted legs are broadcast first. Otherwise the smaller leg that fits under BROADCAST_THRESHOLD is.
+        let broadcast_rhs = is_hinted(rhs_props) || (!is_hinted(lhs_props) && rhs_bytes.is_some_and(|rhs_bytes| lhs_bytes.is_none_or(|lhs_bytes| rhs_bytes <= lhs_bytes)));
+        let broadcast_lhs = !broadcast_rhs && (is_hinted(lhs_props) || lhs_bytes.is_some());
+
+        // Repartition join legs as needed. A broadcast leg is copied whole to every partition of the other leg, which stays put.
+        let (lhs_partdesc, rhs_partdesc, lhs_join_keys, rhs_join_keys, cpartitions) = if broadcast_rhs {
+            let (lhs_join_keys, rhs_join_keys) = Self::compute_join_partitioning_keys(&self.expr_graph, equi_join_preds);
+            (None, Some(PartDesc::new(rhs_npartitions, PartType::BROADCAST)), lhs_join_keys, rhs_join_keys, lhs_npartitions)
+        } else if broadcast_lhs {
+            let (lhs_join_keys, rhs_join_keys) = Self::compute_join_partitioning_keys(&self.expr_graph, equi_join_preds);
+            (Some(PartDesc::new(lhs_npartitions, PartType::BROADCAST)), None, lhs_join_keys, rhs_join_keys, rhs_npartitions)
+        } else if join_hints.no_repartition {
+            // Trust the hint that the legs are co-located, which they can't be if their partition counts differ
+            if lhs_npartitions != rhs_npartitions {
+                return Err(KonaError::Plan(f!("NO_REPARTITION cannot join legs with {lhs_npartitions} and {rhs_npartitions} partitions without repartitioning them")));
+            }
+            let (lhs_join_keys, rhs_join_keys) = Self::compute_join_partitioning_keys(&self.expr_graph, equi_join_preds);
+            (None, None, lhs_join_keys, rhs_join_keys, lhs_npartitions)
+        } else {
+            Self::harmonize_partitions(join_hints.parallel, lop_graph, lhs_plan_key, rhs_plan_key, &self.expr_graph, equi_join_preds, eqclass)
+        };
 
         let lhs_repart_props = lhs_partdesc.map(|partdesc| {
             let virtcols = None;
-            LOPProps { quns: lhs_props.quns.clone(), cols: lhs_props.cols.clone(), preds: lhs_props.preds.clone_metadata(), partdesc, virtcols }
+            LOPProps { quns: lhs_props.quns.clone(), cols: lhs_props.cols.clone(), preds: lhs_props.preds.clone_metadata(), partdesc, virtcols, estimate: Estimate::default() }
         });
         let rhs_repart_props = rhs_partdesc.map(|partdesc| {
             let virtcols = None;
-            LOPProps { quns: rhs_props.quns.clone(), cols: rhs_props.cols.clone(), preds: rhs_props.preds.clone_metadata(), partdesc, virtcols }
+            LOPProps { quns: rhs_props.quns.clone(), cols: rhs_props.cols.clone(), preds: rhs_props.preds.clone_metadata(), partdesc, virtcols, estimate: Estimate::default() }
         });
 
         //let (lhs_partitions, rhs_partitions) = (npartitions, npartitions);
@@ -63,7 +90,23 @@ impl QGM {
         } else {
             rhs_plan_key
         };
-        (new_lhs_plan_key, new_rhs_plan_key, lhs_join_keys, rhs_join_keys, cpartitions)
+
+        // Join partitioning is identical to partitioning of the LHS, unless the LHS was broadcast
+        let lhs_partdesc = &lop_graph.get_properties(new_lhs_plan_key).partdesc;
+        let mut partdesc =
+            if matches!(lhs_partdesc.part_type, PartType::BROADCAST) { lop_graph.get_properties(new_rhs_plan_key).partdesc.clone() } else { lhs_partdesc.clone() };
+        partdesc.npartitions = cpartitions;
+
+        Ok((new_lhs_plan_key, new_rhs_plan_key, lhs_join_keys, rhs_join_keys, partdesc))
+    }
+
+    // Estimated size of a join leg in bytes, if it is small enough to broadcast without a hint
+    fn broadcast_bytes(&self, lop_graph: &mut LOPGraph, join_hints: &JoinHints, plan_key: LOPKey) -> Option<f64> {
+        if join_hints.broadcast_threshold == 0 {
+            return None;
+        }
+        let estimate = self.estimate_logical_plan(lop_graph, plan_key);
+        Some(estimate.nrows * estimate.row_width as f64).filter(|&nbytes| nbytes <= join_hints.broadcast_threshold as f64)
     }
 
     pub(crate) fn partdesc_to_virtcols(expr_graph: &ExprGraph, partdesc: &PartDesc) -> Option<Vec<VirtCol>> {
@@ -77,7 +120,7 @@ impl QGM {
         None
     }
 
-    pub fn compare_part_keys(expr_graph: &ExprGraph, keys1: &Vec<ExprKey>, keys2: &Vec<ExprKey>, eqclass: &ExprEqClass) -> bool {
+    pub fn compare_part_keys(expr_graph: &ExprGraph, keys1: &[ExprKey], keys2: &[ExprKey], eqclass: &ExprEqClass) -> bool {
         if keys1.len() == keys2.len() {
             keys2.iter().zip(keys1.iter()).all(|(key1, key2)| eqclass.check_eq(expr_graph, *key1, *key2))
         } else {
@@ -99,7 +142,7 @@ impl QGM {
 
     // harmonize_partitions: Return a triplet indicating whether either/both legs of a join need to be repartitioned
     pub fn harmonize_partitions(
-        env: &Env, lop_graph: &LOPGraph, lhs_plan_key: LOPKey, rhs_plan_key: LOPKey, expr_graph: &ExprGraph, join_preds: &[(ExprKey, PredicateAlignment)],
+        npartitions: usize, lop_graph: &LOPGraph, lhs_plan_key: LOPKey, rhs_plan_key: LOPKey, expr_graph: &ExprGraph, join_preds: &[(ExprKey, PredicateAlignment)],
         eqclass: &ExprEqClass,
     ) -> (Option<PartDesc>, Option<PartDesc>, Vec<ExprKey>, Vec<ExprKey>, usize) {
         // Compare expected vs actual partitioning keys on both sides of the join
@@ -128,8 +171,6 @@ impl QGM {
             Some(PartDesc { npartitions: rhs_props.partdesc.npartitions, part_type: PartType::HASHEXPR(rhs_join_keys.clone()) })
         };
 
-        let npartitions = env.settings.parallel_degree.unwrap_or(1);
-
         (lhs_partdesc, rhs_partdesc, lhs_join_keys, rhs_join_keys, npartitions)
     }
 
@@ -137,7 +178,7 @@ impl QGM {
         env: &Env, expr_graph: &ExprGraph, join_preds: &[(ExprKey, PredicateAlignment)],
     ) -> (PartDesc, PartDesc, Vec<ExprKey>, Vec<ExprKey>) {
         let (lhs_join_keys, rhs_join_keys) = Self::compute_join_partitioning_keys(expr_graph, join_preds);
-        let npartitions = env.settings.parallel_degree.unwrap_or(1);
+        let npartitions = env.settings.parallel_degree();
 
         let lhs_part_desc = PartDesc { npartitions, part_type: PartType::HASHEXPR(lhs_join_keys.clone()) };
 
@@ -169,3 +210,72 @@ impl QGM {
         (lhs_join_keys, rhs_join_keys)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    // The Repartition operators of a query's logical plan, each as "<how> of <quns>"
+    fn repartitions(session: &mut TestSession, sql: &str) -> Vec<String> {
+        let rows = query_in_order(session, &f!("EXPLAIN LOGICAL {sql}")).unwrap();
+        let lines = rows.iter().map(|row| if let Utf8(line) = &row[0] { line.trim().to_string() } else { panic!("{:?}", row) }).collect::<Vec<_>>();
+        lines
+            .windows(2)
+            .filter(|pair| pair[0].starts_with("Repartition-"))
+            .map(|pair| f!("{} of {}", pair[0].split_once(": ").unwrap().1, pair[1].split(", p =").next().unwrap()))
+            .collect()
+    }
+
+    fn join_tables(session: &mut TestSession) {
+        create_table(session, "A", "ID INT, X INT", &["1,10", "2,20", "3,30"]);
+        create_table(session, "B", "ID INT, Y INT", &["1,100", "2,200"]);
+    }
+
+    #[test]
+    fn broadcast_hint_replicates_the_named_leg() {
+        let mut session = session();
+        join_tables(&mut session);
+        let expected = vec![vec![Int64(10), Int64(100)], vec![Int64(20), Int64(200)]];
+
+        let sql = "SELECT A.X, B.Y FROM A, B WHERE A.ID = B.ID";
+        assert_eq!(repartitions(&mut session, sql), ["c = 1, keys = A.ID of quns = [1]", "c = 1, keys = B.ID of quns = [2]"]);
+        for (hint, expected_plan) in [("BROADCAST(B)", "c = 1, broadcast of quns = [2]"), ("BROADCAST(A)", "c = 1, broadcast of quns = [1]")] {
+            let sql = f!("SELECT /*+ {hint} */ A.X, B.Y FROM A, B WHERE A.ID = B.ID");
+            assert_eq!(repartitions(&mut session, &sql), [expected_plan], "{}", hint);
+            assert_eq!(query(&mut session, &sql).unwrap(), expected, "{}", hint);
+        }
+    }
+
+    #[test]
+    fn broadcast_threshold_picks_the_smaller_leg() {
+        let mut session = session();
+        join_tables(&mut session);
+        execute(&mut session, "ANALYZE TABLE A").unwrap();
+        execute(&mut session, "ANALYZE TABLE B").unwrap();
+        let sql = "SELECT A.X, B.Y FROM A, B WHERE A.ID = B.ID";
+
+        execute(&mut session, "SET BROADCAST_THRESHOLD = 1000").unwrap();
+        assert_eq!(repartitions(&mut session, sql), ["c = 1, broadcast of quns = [2]"]);
+        let sql = "SELECT A.X, B.Y FROM B, A WHERE A.ID = B.ID";
+        assert_eq!(repartitions(&mut session, sql), ["c = 1, broadcast of quns = [1]"]);
+
+        // Legs larger than the threshold are repartitioned on their join keys
+        execute(&mut session, "SET BROADCAST_THRESHOLD = 1").unwrap();
+        assert_eq!(repartitions(&mut session, sql), ["c = 1, keys = B.ID of quns = [1]", "c = 1, keys = A.ID of quns = [2]"]);
+    }
+
+    #[test]
+    fn no_repartition_hint_joins_legs_in_place() {
+        let mut session = session();
+        join_tables(&mut session);
+        let sql = "SELECT /*+ NO_REPARTITION */ A.X, B.Y FROM A, B WHERE A.ID = B.ID";
+        assert_eq!(repartitions(&mut session, sql), Vec::<String>::new());
+        assert_eq!(query(&mut session, sql).unwrap(), vec![vec![Int64(10), Int64(100)], vec![Int64(20), Int64(200)]]);
+
+        // Legs with different partition counts can't be co-located
+        execute(&mut session, "ALTER TABLE A SET (PARTITIONS = 2)").unwrap();
+        let err = query(&mut session, sql).unwrap_err();
+        assert!(matches!(&err, KonaError::Plan(msg) if msg.contains("NO_REPARTITION cannot join legs with 2 and 1 partitions")), "{}", err);
+    }
+}
@@ -4,168 +4,140 @@
 #![allow(clippy::too_many_arguments)]
 #![deny(elided_lifetimes_in_paths)]
 
+use std::io::{IsTerminal, Read};
 #[cfg(test)]
 use std::{fs, process::Command};
 
-use ast::AST;
-use flow::Flow;
-use pop::POP;
-use qgm::QGM;
-
-use crate::{includes::*, qgm::ParserState};
-
-#[macro_use]
-extern crate lalrpop_util;
+use kona::{includes::*, repl, run_script};
 
 #[macro_use]
 extern crate fstrings;
 
-extern crate lazy_static;
-
-extern crate tracing;
-
-lalrpop_mod!(pub sqlparser); // synthesized by LALRPOP
-
-#[macro_use]
-extern crate derivative;
-
-pub mod bitset;
-pub mod env;
-pub mod graph;
-pub mod graphviz;
-pub mod includes;
-pub mod logging;
-pub mod metadata;
-
-pub mod ast;
-pub mod expr;
-pub mod qgm;
-pub mod qgmiter;
-
-pub mod lop;
-pub mod lop_repartition;
-pub mod qst;
-
-pub mod flow;
-pub mod pcode;
-pub mod pop;
-pub mod pop_compile;
-pub mod pop_csv;
-pub mod pop_hash;
-pub mod pop_hashagg;
-pub mod pop_hashmatch;
-pub mod pop_parquet;
-pub mod pop_repartition;
-pub mod pop_run;
-
-pub mod datum;
-pub mod scheduler;
-pub mod stage;
-pub mod task;
-
-pub mod print;
-
-pub use tracing::{debug, event, info, Level};
-
 #[cfg(feature = "dhat-heap")]
 #[global_allocator]
 static ALLOC: dhat::Alloc = dhat::Alloc;
 
-/***************************************************************************************************/
-pub fn run_flow(env: &mut Env, flow: &Flow) -> Result<(), String> {
-    // Clear output directories
-    let dirname = format!("{}/flow", TEMPDIR);
-    //std::fs::remove_dir_all(&dirname).map_err(|err| format!("Cannot remove temporary directory: {}", dirname))?;
-    std::fs::remove_dir_all(dirname).unwrap_or_default();
-
-    // Run the flow
-    env.scheduler.run_flow(env, flow)?;
-    Ok(())
-}
-
-pub fn enable_tracing(env: &mut Env, astlist: &mut Vec<AST>, run_trace: bool) -> Result<(), String> {
-    let mut ix_trace = None;
-    for (ix, ast) in astlist.iter().enumerate() {
-        match ast {
-            AST::SetOption { name, value } => {
-                if name.to_uppercase() == "TRACE" {
-                    if ix_trace.is_some() {
-                        return Err("Multiple SET TRACE statements found.".to_owned());
-                    }
-                    if run_trace {
-                        env.set_option(name.clone(), value.clone())?;
-                    }
-                    ix_trace = Some(ix);
-                }
-            }
-            _ => {}
+// Run every statement of a job, then stop the scheduler's threads
+fn run_job(env: &mut Env, source: &JobSource, run_trace: bool) -> Result<(), KonaError> {
+    match source {
+        JobSource::File(pathname) => {
+            let contents = fs::read_to_string(pathname).map_err(|err| KonaError::io(f!("Cannot open file {pathname}"), err))?;
+            run_script(env, pathname, &contents, run_trace)?;
         }
+        JobSource::Command(sql) => run_script(env, "<command line>", sql, run_trace)?,
+        JobSource::Stdin => {
+            let mut contents = String::new();
+            std::io::stdin().read_to_string(&mut contents).map_err(|err| KonaError::io("Cannot read stdin", err))?;
+            run_script(env, "<stdin>", &contents, run_trace)?;
+        }
+        JobSource::Repl => repl::run(env)?,
     }
 
-    if let Some(ix_trace) = ix_trace {
-        astlist.remove(ix_trace);
-    } else if run_trace {
-        // Default trace setting
-        logging::init("info");
-    }
+    env.scheduler.end_all_threads()?;
+    env.scheduler.join()?;
+
     Ok(())
 }
 
-fn run_job(env: &mut Env, run_trace: bool) -> Result<(), String> {
-    let pathname = &env.input_pathname;
-    let contents = fs::read_to_string(pathname).map_err(|err| stringify1("Cannot open file: {}", err))?;
-
-    let mut parser_state = ParserState::default();
-
-    let qgm_raw_pathname = format!("{}/{}", env.output_dir, "qgm_raw.dot");
-    let qgm_resolved_pathname = format!("{}/{}", env.output_dir, "qgm_resolved.dot");
-
-    let mut astlist: Vec<AST> = sqlparser::JobParser::new().parse(&mut parser_state, &contents).unwrap();
-
-    // Run any SET TRACE statement right away, if required. Additionally, ensure only one such statement exists in the job.
-    enable_tracing(env, &mut astlist, run_trace)?;
-
-    for ast in astlist.into_iter() {
-        match ast {
-            AST::CatalogTable { name, options } => {
-                env.metadata.catalog_table(name, options)?;
-            }
-            AST::DescribeTable { name } => {
-                env.metadata.describe_table(name)?;
-            }
-            AST::SetOption { name, value } => {
-                env.set_option(name, value)?;
-            }
-            AST::QGM(mut qgm) => {
-                // Resolve QGM
-                qgm.write_qgm_to_graphviz(&qgm_raw_pathname, false)?;
-                qgm.resolve(env)?;
-                qgm.write_qgm_to_graphviz(&qgm_resolved_pathname, false)?;
-
-                // Build LOPs
-                let (lop_graph, lop_key) = qgm.build_logical_plan(env)?;
+/*
+********************************** main ****************************************************************
+*/
 
-                if !env.settings.parse_only.unwrap_or(false) {
-                    let flow = POP::compile_flow(env, &mut qgm, &lop_graph, lop_key).unwrap();
+const USAGE: &str = "\
+Usage: kona [OPTIONS] [sqlfilename | -]
+
+Runs the statements in sqlfilename, in -e or on stdin (-). With none of them and a terminal on stdin, starts a shell.
+
+Options:
+    -e SQL                  Run the statements in SQL
+    --threads N             Threads that run tasks [default: 1]
+    --output-format FORMAT  Format of query results: CSV, TABLE, PARQUET, IPC or JSON
+    --catalog DIR           Keep tables and views in DIR across jobs [default: $KONA_CATALOG]
+    --config FILE           Read startup settings from FILE [default: $KONA_CONFIG]
+    --temp-dir DIR          Directory for intermediate and output files [default: $KONA_TEMP_DIR]
+    --data-dir DIR          Directory that relative table paths are resolved against [default: $KONA_DATA_DIR]";
+
+// Where the statements of a job come from
+enum JobSource {
+    File(String),
+    Command(String),
+    Stdin,
+    Repl,
+}
 
-                    // Build POPs
-                    run_flow(env, &flow)?;
+struct Args {
+    source: JobSource,
+    threads: usize,
+    output_format: Option<String>,
+    catalog: Option<String>,
+    config: Option<String>,
+    temp_dir: Option<String>,
+    data_dir: Option<String>,
+}
 
-                    display_output_dir(&flow);
+impl Args {
+    // An empty error asks for the usage text alone
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+        let (mut input_pathname, mut command, mut threads, mut output_format) = (None, None, None, None);
+        let (mut catalog, mut config, mut temp_dir, mut data_dir) = (None, None, None, None);
+        while let Some(arg) = args.next() {
+            let target = match arg.as_str() {
+                "-e" => &mut command,
+                "--threads" => &mut threads,
+                "--output-format" => &mut output_format,
+                "--catalog" => &mut catalog,
+                "--config" => &mut config,
+                "--temp-dir" => &mut temp_dir,
+                "--data-dir" => &mut data_dir,
+                "-h" | "--help" => return Err(String::new()),
+                _ if arg.starts_with('-') && arg != "-" => return Err(f!("Unknown option {arg}")),
+                _ if input_pathname.is_none() => {
+                    input_pathname = Some(arg);
+                    continue;
                 }
-            }
+                _ => return Err(f!("Unexpected argument {arg}")),
+            };
+            *target = Some(args.next().ok_or_else(|| f!("{arg} needs a value"))?);
         }
-    }
 
-    env.scheduler.end_all_threads()?;
-    env.scheduler.join()?;
+        let source = match (command, input_pathname) {
+            (Some(_), Some(_)) => return Err(String::from("-e cannot be combined with a sqlfilename")),
+            (Some(sql), None) => JobSource::Command(sql),
+            (None, Some(pathname)) if pathname == "-" => JobSource::Stdin,
+            (None, Some(pathname)) => JobSource::File(pathname),
+            (None, None) if std::io::stdin().is_terminal() => JobSource::Repl,
+            (None, None) => JobSource::Stdin,
+        };
+        let threads = match threads {
+            Some(threads) => threads.parse::<usize>().ok().filter(|&threads| threads > 0).ok_or_else(|| f!("Invalid --threads {threads}"))?,
+            None => 1,
+        };
+        Ok(Args { source, threads, output_format, catalog, config, temp_dir, data_dir })
+    }
 
-    Ok(())
+    // Relative table paths are resolved against the directory of the job file, if there is one
+    fn input_pathname(&self) -> String {
+        match &self.source {
+            JobSource::File(pathname) => pathname.clone(),
+            _ => String::new(),
+        }
+    }
 }
 
-/*
-********************************** main ****************************************************************
-*/
+// Startup settings. A config file is read first, then the environment, then the command line, each overriding the last.
+fn configure(env: &mut Env, args: &Args) -> Result<(), KonaError> {
+    if let Some(config) = args.config.clone().or_else(|| std::env::var("KONA_CONFIG").ok()) {
+        env.load_config(&config)?;
+    }
+    let startup_options =
+        [("TEMP_DIR", Some("KONA_TEMP_DIR"), &args.temp_dir), ("DATA_DIR", Some("KONA_DATA_DIR"), &args.data_dir), ("OUTPUT_FORMAT", None, &args.output_format)];
+    for (name, envvar, arg) in startup_options {
+        if let Some(value) = arg.clone().or_else(|| envvar.and_then(|envvar| std::env::var(envvar).ok())) {
+            env.set_startup_option(name, &value)?;
+        }
+    }
+    env.init_output_dir()
+}
 
 fn main() -> Result<(), String> {
     #[cfg(feature = "dhat-heap")]
@@ -179,23 +151,39 @@ fn main() -> Result<(), String> {
     // Initialize logger with default setting. This is overridden by RUST_LOG?
     //logging::init("debug");
 
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        println!("\n\nUsage: kona sqlfilename");
-        println!("... exiting");
-        return Ok(());
-    }
-
-    let input_pathname = args[1].clone();
-    let output_dir = f!("tmp");
-
-    let mut env = Env::new(99, 1, input_pathname, output_dir);
-
-    let jobres = run_job(&mut env, true);
-    if let Err(errstr) = &jobres {
-        let errstr = errstr.to_string();
-        error!("{}", errstr);
-        return Err(errstr);
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) if err.is_empty() => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+        Err(err) => {
+            println!("{}\n\n{}", err, USAGE);
+            println!("... exiting");
+            return Ok(());
+        }
+    };
+
+    // Tables and views persist across jobs when --catalog or KONA_CATALOG names a catalog directory
+    let catalog_dir = args.catalog.clone().or_else(|| std::env::var("KONA_CATALOG").ok());
+    let env = Env::new(99, args.threads, args.input_pathname(), String::new(), catalog_dir.as_deref()).and_then(|mut env| {
+        configure(&mut env, &args)?;
+        Ok(env)
+    });
+    let mut env = match env {
+        Ok(env) => env,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+
+    let jobres = run_job(&mut env, &args.source, true);
+    if let Err(err) = &jobres {
+        // Diagnostics span several lines, so print them as is rather than through the Debug of main's Result
+        error!("{}", err);
+        eprintln!("{}", err);
+        std::process::exit(1);
     }
 
     Ok(())
@@ -211,8 +199,9 @@ fn run_unit_tests() -> Result<(), String> {
     let mut ntotal = 0;
     //let diffcmd = "/Applications/DiffMerge.app/Contents/MacOS/DiffMerge";
     let diffcmd = "diff";
+    const TOPDIR: &str = env!("CARGO_MANIFEST_DIR");
 
-    for (id, test) in vec!["rst", "repartition", "groupby", "spja"].iter().enumerate() {
+    for (id, test) in ["rst", "repartition", "groupby", "spja"].iter().enumerate() {
         let input_pathname = f!("{TOPDIR}/sql/{test}.fsql");
         let output_dir = f!("{TOPDIR}/tests/output/{test}/");
 
@@ -220,11 +209,11 @@ fn run_unit_tests() -> Result<(), String> {
         std::fs::remove_dir_all(&output_dir).map_err(stringify)?;
         std::fs::create_dir_all(&output_dir).map_err(stringify)?;
 
-        ntotal = ntotal + 1;
-        let mut env = Env::new(id, 1, input_pathname, output_dir.clone());
+        ntotal += 1;
+        let mut env = Env::new(id, 1, input_pathname.clone(), output_dir.clone(), None).map_err(|err| err.to_string())?;
         env.set_option("PARSE_ONLY".to_string(), Utf8(String::from("true"))).unwrap();
 
-        let jobres = run_job(&mut env, false);
+        let jobres = run_job(&mut env, &JobSource::File(input_pathname), false);
         if let Err(errstr) = jobres {
             let errstr = format!("{}", &errstr);
             error!("{}", errstr);
@@ -235,7 +224,7 @@ fn run_unit_tests() -> Result<(), String> {
         let output = Command::new(diffcmd).arg(gold_dir).arg(output_dir).output().expect("failed to execute process");
 
         let mut mismatch = false;
-        for (tag, buf) in vec![("out", output.stdout), ("err", output.stderr)].iter() {
+        for (tag, buf) in [("out", output.stdout), ("err", output.stderr)].iter() {
             if !buf.is_empty() {
                 mismatch = true;
                 let s = String::from_utf8_lossy(buf);
@@ -243,34 +232,35 @@ fn run_unit_tests() -> Result<(), String> {
             }
         }
         if !mismatch {
-            npassed = npassed + 1
+            npassed += 1
         }
     }
 
     println!("---------- Completed: {}/{} subtests passed", npassed, ntotal);
+    assert_eq!(npassed, ntotal);
     Ok(())
 }
 
-fn display_output_dir(flow: &Flow) {
-    println!("---------- output ----------");
-    let output_dir = get_output_dir(flow.id);
-    let files = list_files(&output_dir).unwrap();
-    for file_path in files.iter() {
-        let contents = fs::read_to_string(file_path).expect("Should have been able to read the file");
-        let lines = contents.split('\n').collect::<Vec<_>>();
-        for line in lines.iter().take(10) {
-            print!("{}\n", line);
-        }
-        if lines.len() > 10 {
-            println!("[{} lines not shown]", lines.len());
-        }
+#[test]
+fn configure_reads_config_then_environment_then_arguments() {
+    let dir = std::env::temp_dir().join(f!("kona-test-{}-configure", std::process::id())).display().to_string();
+    fs::create_dir_all(&dir).unwrap();
+    let config = f!("{dir}/kona.conf");
+    fs::write(&config, f!("PARALLEL_DEGREE = 3\nDATA_DIR = /config/data\nTEMP_DIR = {dir}/config-temp\n")).unwrap();
+
+    std::env::set_var("KONA_CONFIG", &config);
+    std::env::set_var("KONA_DATA_DIR", "/env/data");
+    std::env::set_var("KONA_TEMP_DIR", f!("{dir}/env-temp"));
+    let args = Args::parse(["--temp-dir", &f!("{dir}/arg-temp"), "-e", "SELECT 1"].iter().map(|arg| arg.to_string())).unwrap();
+    let mut env = Env::new(0, 1, args.input_pathname(), String::new(), None).unwrap();
+    let result = configure(&mut env, &args);
+    for envvar in ["KONA_CONFIG", "KONA_DATA_DIR", "KONA_TEMP_DIR"] {
+        std::env::remove_var(envvar);
     }
-    println!("----------------------------");
-    println!("");
-}
+    result.unwrap();
 
-/*
-fn test(from: PrimitiveArray<i128>) {
-    cast::integer_to_decimal(&from, 5, 2);
+    assert_eq!(env.settings.parallel_degree(), 3);
+    assert_eq!(env.settings.data_dir().as_deref(), Some("/env/data"));
+    assert_eq!(env.settings.temp_dir(), f!("{dir}/arg-temp"));
+    assert_eq!(env.output_dir, f!("{dir}/arg-temp/plans-0"));
 }
-*/
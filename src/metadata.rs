@@ -1,22 +1,37 @@
 // metadata
 
-use std::{collections::HashMap, fs::File, rc::Rc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    rc::Rc,
+};
 
 use arrow2::io::csv::read;
 
-use crate::{expr::ExprGraph, graph::ExprKey, includes::*, Datum};
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+use crate::{
+    catalog::{Catalog, CatalogEntry, CatalogObject, CATALOG_VERSION},
+    datum::parse_datatype,
+    expr::ExprGraph,
+    graph::ExprKey,
+    includes::*,
+    resultset::ResultSet,
+    sink::SinkFormat,
+    Datum,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TableType {
     CSV,
     Parquet,
     Query,
+    View,
 }
 
 #[derive(Debug, Clone)]
 pub enum PartType {
     RAW,
     HASHEXPR(Vec<ExprKey>),
+    BROADCAST, // Every partition holds all rows
 }
 
 #[derive(Debug, Clone)]
@@ -33,6 +48,7 @@ impl PartDesc {
     pub fn describe(&self, expr_graph: &ExprGraph, do_escape: bool) -> String {
         let part_type_str = match &self.part_type {
             PartType::RAW => String::from("RAW"),
+            PartType::BROADCAST => String::from("BROADCAST"),
             PartType::HASHEXPR(exprs) => {
                 let mut exprstr = String::from("");
                 for (ix, expr_key) in exprs.iter().enumerate() {
@@ -48,11 +64,53 @@ impl PartDesc {
     }
 }
 
-#[allow(dead_code)] // FIXME
-#[derive(Debug)]
+// Row count assumed for tables cataloged without NROWS and not yet analyzed
+pub const DEFAULT_NROWS: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TableStats {
-    nrows: usize,
-    avg_row_size: usize,
+    pub nrows: usize,
+    pub avg_row_size: usize,
+    #[serde(default)]
+    pub columns: BTreeMap<String, ColumnStats>, // Filled in by ANALYZE TABLE, by column name
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ColumnStats {
+    pub ndv: usize, // Estimated number of distinct non-null values
+    pub null_count: usize,
+    pub min: Option<Datum>,
+    pub max: Option<Datum>,
+    pub histogram: Vec<Datum>, // Upper bounds of equi-depth buckets, each holding about the same number of rows
+    #[serde(default)]
+    pub avg_width: usize, // Average bytes per non-null value
+}
+
+impl ColumnStats {
+    pub fn describe(&self) -> String {
+        let describe_value = |value: &Datum| value.to_string();
+        let describe_bound = |value: &Option<Datum>| value.as_ref().map_or(String::from("NULL"), describe_value);
+        let histogram = self.histogram.iter().map(describe_value).collect::<Vec<_>>().join(", ");
+        format!(
+            "ndv {}, nulls {}, width {}, min {}, max {}, histogram [{}]",
+            self.ndv,
+            self.null_count,
+            self.avg_width,
+            describe_bound(&self.min),
+            describe_bound(&self.max),
+            histogram
+        )
+    }
+}
+
+// DESCRIBE lists a row per column with its name, type and whether it may be NULL
+fn describe_columns(fields: &[Field]) -> impl Iterator<Item = Vec<String>> + '_ {
+    fields.iter().map(|field| vec![f!("COLUMN {}", field.name), f!("{:?}{}", field.data_type, if field.is_nullable { "" } else { " NOT NULL" })])
+}
+
+// Unquoted identifiers reach us uppercased and match a catalog name in any case. Quoted identifiers must match exactly.
+pub fn column_name_matches(name: &str, colname: &str, quoted: bool) -> bool {
+    name == colname || (!quoted && name.to_uppercase() == colname)
 }
 
 pub trait TableDesc {
@@ -64,12 +122,28 @@ pub trait TableDesc {
     fn describe(&self) -> String {
         String::from("")
     }
-    fn get_column(&self, colname: &str) -> Option<(usize, &Field)>;
+    // An exact match wins over a case-insensitive one
+    fn get_column(&self, colname: &str, quoted: bool) -> Option<(usize, &Field)> {
+        let mut fields = self.fields().iter().enumerate();
+        fields.clone().find(|(_, field)| field.name == colname).or_else(|| fields.find(|(_, field)| column_name_matches(&field.name, colname, quoted)))
+    }
     fn get_part_desc(&self) -> Option<&PartDesc>;
     fn get_stats(&self) -> Option<&TableStats>;
+    // The SELECT text of a view, which is expanded in place of its name when a query is resolved
+    fn view_definition(&self) -> Option<&str> {
+        None
+    }
+    // ALTER TABLE: a copy of this descriptor with the given options applied
+    fn alter(&self, _options: &HashMap<String, Datum>) -> Result<Rc<dyn TableDesc>, KonaError> {
+        Err(KonaError::Bind(String::from("Only cataloged tables can be altered.")))
+    }
+    // ANALYZE TABLE: a copy of this descriptor with new stats
+    fn with_stats(&self, _table_stats: TableStats) -> Result<Rc<dyn TableDesc>, KonaError> {
+        Err(KonaError::Bind(String::from("Only cataloged tables can be analyzed.")))
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct CSVDesc {
     typ: TableType,
     pathname: Rc<String>,
@@ -83,21 +157,23 @@ pub struct CSVDesc {
 impl CSVDesc {
     pub fn new(
         typ: TableType, pathname: Rc<String>, columns: Vec<Field>, separator: char, header: bool, part_desc: PartDesc, table_stats: TableStats,
-    ) -> Result<Self, String> {
+    ) -> Result<Self, KonaError> {
         let csvdesc = CSVDesc { typ, pathname, header, separator, columns, part_desc, table_stats };
         Ok(csvdesc)
     }
 
-    pub fn infer_metadata(pathname: &str, separator: char, header: bool) -> Result<Vec<Field>, String> {
+    pub fn infer_metadata(pathname: &str, separator: char, header: bool) -> Result<Vec<Field>, KonaError> {
         // Create a CSV reader. This is typically created on the thread that reads the file and
         // thus owns the read head.
-        let mut reader = read::ReaderBuilder::new().has_headers(header).delimiter(separator as u8).from_path(pathname).map_err(|err| stringify1(err, pathname))?;
+        let mut reader = read::ReaderBuilder::new()
+            .has_headers(header)
+            .delimiter(separator as u8)
+            .from_path(pathname)
+            .map_err(|err| KonaError::io(f!("Cannot read {pathname}"), err.into()))?;
 
         // Infers the fields using the default inferer. The inferer is just a function that maps bytes
         // to a `DataType`.
-        let (fields, _) = read::infer_schema(&mut reader, None, true, &read::infer).map_err(|err| stringify1(err, pathname))?;
-
-        let fields = fields.iter().map(|f| Field::new(f.name.to_uppercase(), f.data_type.clone(), f.is_nullable)).collect();
+        let (fields, _) = read::infer_schema(&mut reader, None, true, &read::infer).map_err(|err| KonaError::arrow(f!("Cannot infer the columns of {pathname}"), err))?;
 
         Ok(fields)
     }
@@ -120,10 +196,6 @@ impl TableDesc for CSVDesc {
         format!("Type: CSV, {:?}", self)
     }
 
-    fn get_column(&self, colname: &str) -> Option<(usize, &Field)> {
-        self.columns.iter().enumerate().find(|(_, cd)| cd.name == *colname)
-    }
-
     fn get_part_desc(&self) -> Option<&PartDesc> {
         Some(&self.part_desc)
     }
@@ -139,69 +211,212 @@ impl TableDesc for CSVDesc {
     fn get_stats(&self) -> Option<&TableStats> {
         Some(&self.table_stats)
     }
+
+    fn alter(&self, options: &HashMap<String, Datum>) -> Result<Rc<dyn TableDesc>, KonaError> {
+        let mut desc = self.clone();
+        Metadata::alter_options(options, &mut desc.pathname, &mut desc.part_desc, &mut desc.table_stats)?;
+        Ok(Rc::new(desc))
+    }
+
+    fn with_stats(&self, table_stats: TableStats) -> Result<Rc<dyn TableDesc>, KonaError> {
+        Ok(Rc::new(Self { table_stats, ..self.clone() }))
+    }
 }
 
-#[derive(Default)]
+// Tables that aren't cataloged into a named schema land here
+pub const DEFAULT_SCHEMA: &str = "PUBLIC";
+
+type SchemaTables = HashMap<String, Rc<dyn TableDesc>>;
+
 pub struct Metadata {
-    tables: HashMap<String, Rc<dyn TableDesc>>,
+    schemas: HashMap<String, SchemaTables>,
+    search_path: Vec<String>, // Schemas searched, in order, for unqualified table names
+    catalog: Option<Catalog>, // Where changes are persisted, if anywhere
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        let schemas = HashMap::from([(DEFAULT_SCHEMA.to_string(), HashMap::new())]);
+        Metadata { schemas, search_path: vec![DEFAULT_SCHEMA.to_string()], catalog: None }
+    }
 }
 
 impl Metadata {
-    pub fn parse_columns(hm: &HashMap<String, Datum>) -> Result<Vec<Field>, String> {
-        // Parse: COLUMNS = "name=STRING,age=INT,emp_dept_id=INT"
+    // Loads everything persisted in the catalog directory. Later changes are written back to it.
+    pub fn open(catalog_dir: &str) -> Result<Self, KonaError> {
+        let catalog = Catalog::open(catalog_dir)?;
+        let mut metadata = Metadata::default();
+        for (schema, entries) in catalog.load()? {
+            let tables = metadata.schemas.entry(schema.clone()).or_default();
+            for entry in entries {
+                tables.insert(entry.name.clone(), Self::from_catalog_entry(entry));
+            }
+            info!("Loaded schema {} from catalog {}", &schema, catalog_dir);
+        }
+        catalog.create_schema(DEFAULT_SCHEMA)?;
+        metadata.catalog = Some(catalog);
+        Ok(metadata)
+    }
+
+    fn from_catalog_entry(entry: CatalogEntry) -> Rc<dyn TableDesc> {
+        match entry.object {
+            CatalogObject::Table { typ: TableType::CSV, path, header, separator, partitions, stats, columns } => {
+                let part_desc = PartDesc::new(partitions, PartType::RAW);
+                Rc::new(CSVDesc {
+                    typ: TableType::CSV,
+                    pathname: Rc::new(path),
+                    header: header.unwrap_or(true),
+                    separator: separator.unwrap_or(','),
+                    columns,
+                    part_desc,
+                    table_stats: stats,
+                })
+            }
+            CatalogObject::Table { typ, path, partitions, stats, columns, .. } => {
+                Rc::new(ParquetDesc { typ, pathname: Rc::new(path), columns, part_desc: PartDesc::new(partitions, PartType::RAW), table_stats: stats })
+            }
+            CatalogObject::View { sql, columns } => Rc::new(ViewDesc::new(sql, columns)),
+        }
+    }
+
+    fn to_catalog_entry(name: &str, tbldesc: &dyn TableDesc) -> CatalogEntry {
+        let columns = tbldesc.fields().clone();
+        let object = if let Some(sql) = tbldesc.view_definition() {
+            CatalogObject::View { sql: sql.to_string(), columns }
+        } else {
+            let typ = tbldesc.get_type();
+            let (header, separator) = if typ == TableType::CSV { (Some(tbldesc.header()), Some(tbldesc.separator())) } else { (None, None) };
+            CatalogObject::Table {
+                typ,
+                path: tbldesc.pathname().clone(),
+                header,
+                separator,
+                partitions: tbldesc.get_part_desc().map_or(1, |part_desc| part_desc.npartitions),
+                stats: tbldesc.get_stats().cloned().unwrap(),
+                columns,
+            }
+        };
+        CatalogEntry { version: CATALOG_VERSION, name: name.to_string(), object }
+    }
+
+    // Writes a table or view back to the catalog after it has changed in memory
+    fn persist(&self, schema: &str, name: &str) -> Result<(), KonaError> {
+        if let Some(catalog) = self.catalog.as_ref() {
+            catalog.save(schema, &Self::to_catalog_entry(name, self.schemas[schema][name].as_ref()))?;
+        }
+        Ok(())
+    }
+
+    pub fn create_schema(&mut self, name: String) -> Result<(), KonaError> {
+        let name = name.to_uppercase();
+        if self.schemas.contains_key(&name) {
+            return Err(KonaError::Bind(f!("Schema {name} already exists.")));
+        }
+        if let Some(catalog) = self.catalog.as_ref() {
+            catalog.create_schema(&name)?;
+        }
+        self.schemas.insert(name.clone(), HashMap::new());
+        info!("Created schema {}", &name);
+        Ok(())
+    }
+
+    // SET SCHEMA = 'tpch, public'
+    pub fn set_search_path(&mut self, path: &str) -> Result<(), KonaError> {
+        let search_path: Vec<String> = path.split(',').map(|name| name.trim().to_uppercase()).collect();
+        if let Some(name) = search_path.iter().find(|name| !self.schemas.contains_key(*name)) {
+            return Err(KonaError::Bind(f!("Schema {name} does not exist.")));
+        }
+        self.search_path = search_path;
+        Ok(())
+    }
+
+    // New tables go into the first schema on the search path unless one is named
+    fn target_schema(&mut self, schema: Option<String>) -> Result<(String, &mut SchemaTables), KonaError> {
+        let schema = schema.map(|name| name.to_uppercase()).unwrap_or_else(|| self.search_path[0].clone());
+        let tables = self.schemas.get_mut(&schema).ok_or_else(|| KonaError::Bind(f!("Schema {schema} does not exist.")))?;
+        Ok((schema, tables))
+    }
+
+    pub fn parse_columns(hm: &HashMap<String, Datum>) -> Result<Vec<Field>, KonaError> {
+        // Parse: COLUMNS = "name=STRING,age=INT NOT NULL,salary=DECIMAL(10,2) NULL"
 
         let colstr = hm.get("COLUMNS");
         let colstr = match colstr {
             Some(Utf8(fieldstr)) => fieldstr,
-            _ => return Err(f!("Invalid value for option COLUMNS: '{colstr:?}'")),
+            _ => return Err(KonaError::Bind(f!("Invalid value for option COLUMNS: '{colstr:?}'"))),
         };
 
+        // Commas inside parentheses belong to the type, e.g. "price=DECIMAL(15,2)"
+        let mut depth = 0;
+        let parts = colstr.split(|ch| {
+            match ch {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            ch == ',' && depth == 0
+        });
+
         let mut fields = vec![];
-        for part in colstr.split(',') {
+        for part in parts {
             let mut colname_and_type = part.split('=');
-            let err = "Cannot parse COLUMN specification".to_string();
-            let (name, datatype) = (colname_and_type.next().ok_or(err.clone())?.to_string().to_uppercase(), colname_and_type.next().ok_or(err)?);
-            let datatype = match datatype {
-                "STRING" => DataType::Utf8,
-                "INT" => DataType::Int64,
-                _ => return Err(f!("Invalid datatype {datatype} in COLUMN specification")),
-            };
-            let field = Field::new(name, datatype, false);
+            let err = || KonaError::Bind(f!("Cannot parse COLUMN specification '{part}'"));
+            let (name, typestr) = (colname_and_type.next().ok_or_else(err)?.trim().to_string(), colname_and_type.next().ok_or_else(err)?);
+            let (typestr, is_nullable) = Self::parse_nullability(typestr);
+            let datatype = parse_datatype(&typestr).map_err(|err| KonaError::Bind(f!("{err} in COLUMN specification for {name}")))?;
+            let field = Field::new(name, datatype, is_nullable);
             fields.push(field)
         }
         Ok(fields)
     }
 
-    fn get_table_type(hm: &HashMap<String, Datum>, name: &String) -> Result<TableType, String> {
-        let typ = hm.get("TYPE").ok_or(f!("Table {name} does not specify a TYPE."))?;
-        let typ = typ.try_as_str().ok_or(f!("Table {name} has invalid TYPE."))?;
+    // Split a trailing NULL / NOT NULL off a column type. Columns are nullable unless declared otherwise.
+    fn parse_nullability(typestr: &str) -> (String, bool) {
+        let typestr = typestr.split_whitespace().collect::<Vec<_>>().join(" ").to_uppercase();
+        if let Some(typestr) = typestr.strip_suffix(" NOT NULL") {
+            (typestr.to_string(), false)
+        } else if let Some(typestr) = typestr.strip_suffix(" NULL") {
+            (typestr.to_string(), true)
+        } else {
+            (typestr, true)
+        }
+    }
+
+    fn get_table_type(hm: &HashMap<String, Datum>, name: &String) -> Result<TableType, KonaError> {
+        let typ = hm.get("TYPE").ok_or_else(|| KonaError::Bind(f!("Table {name} does not specify a TYPE.")))?;
+        let typ = typ.try_as_str().ok_or_else(|| KonaError::Bind(f!("Table {name} has invalid TYPE.")))?;
 
         let typ = typ.to_uppercase();
         let typ = typ.as_str();
         let typ = match typ {
             "CSV" => TableType::CSV,
             "PARQUET" => TableType::Parquet,
-            _ => return Err(f!("Table {name} has invalid TYPE.")),
+            _ => return Err(KonaError::Bind(f!("Table {name} has invalid TYPE."))),
         };
         Ok(typ)
     }
 
-    fn get_header_parm(hm: &HashMap<String, Datum>) -> Result<bool, String> {
+    fn get_path_parm<'a>(hm: &'a HashMap<String, Datum>, name: &str) -> Result<&'a str, KonaError> {
+        let path = hm.get("PATH").ok_or_else(|| KonaError::Bind(f!("Table {name} does not specify a PATH.")))?;
+        path.try_as_str().ok_or_else(|| KonaError::Bind(f!("PATH does not hold a string for table {name}.")))
+    }
+
+    fn get_header_parm(hm: &HashMap<String, Datum>) -> Result<bool, KonaError> {
         let header = hm.get("HEADER");
         let header = match header {
-            Some(Utf8(header)) => yes_or_no(header).ok_or(f!("Invalid value for option HEADER: '{header}'"))?,
+            Some(Utf8(header)) => yes_or_no(header).ok_or_else(|| KonaError::Bind(f!("Invalid value for option HEADER: '{header}'")))?,
             None => true,
-            _ => return Err(f!("Invalid value for option HEADER: '{header:?}'")),
+            _ => return Err(KonaError::Bind(f!("Invalid value for option HEADER: '{header:?}'"))),
         };
         Ok(header)
     }
 
-    fn get_separator_parm(hm: &HashMap<String, Datum>) -> Result<char, String> {
+    fn get_separator_parm(hm: &HashMap<String, Datum>) -> Result<char, KonaError> {
         let separator = match hm.get("SEPARATOR") {
             Some(Utf8(sep)) => {
                 let sep = sep.as_str();
                 if sep.len() != 1 {
-                    return Err(f!("Invalid value for option SEPARATOR: '{sep}'"));
+                    return Err(KonaError::Bind(f!("Invalid value for option SEPARATOR: '{sep}'")));
                 } else {
                     sep.chars().next().unwrap()
                 }
@@ -211,64 +426,53 @@ impl Metadata {
         Ok(separator)
     }
 
-    fn get_table_stats(hm: &HashMap<String, Datum>) -> Result<TableStats, String> {
+    fn get_table_stats(hm: &HashMap<String, Datum>) -> Result<TableStats, KonaError> {
         let nrows = match hm.get("NROWS") {
-            Some(Int64(nrows)) => {
-                if *nrows > 0 {
-                    *nrows as usize
-                } else {
-                    return Err(String::from("Invalid value for option NROWS"));
-                }
-            }
-            None => 1usize,
-            _ => return Err(String::from("Invalid value for option NROWS")),
+            Some(Int64(nrows)) if *nrows > 0 => *nrows as usize,
+            None => DEFAULT_NROWS,
+            _ => return Err(KonaError::Bind(String::from("Invalid value for option NROWS"))),
         };
 
         let avg_row_size = match hm.get("AVG_ROW_SIZE") {
-            Some(Int64(avg_row_size)) => {
-                if *avg_row_size > 0 {
-                    *avg_row_size as usize
-                } else {
-                    return Err(String::from("Invalid value for option AVG_ROW_SIZE"));
-                }
-            }
+            Some(Int64(avg_row_size)) if *avg_row_size > 0 => *avg_row_size as usize,
             None => 1usize,
-            _ => return Err(String::from("Invalid value for option AVG_ROW_SIZE")),
+            _ => return Err(KonaError::Bind(String::from("Invalid value for option AVG_ROW_SIZE"))),
         };
-        let table_stats = TableStats { nrows, avg_row_size };
+        let table_stats = TableStats { nrows, avg_row_size, columns: BTreeMap::new() };
         Ok(table_stats)
     }
 
-    fn get_part_desc(hm: &HashMap<String, Datum>) -> Result<PartDesc, String> {
+    fn get_part_desc(hm: &HashMap<String, Datum>) -> Result<PartDesc, KonaError> {
         let npartitions = match hm.get("PARTITIONS") {
-            Some(Int64(npartitions)) => {
-                if *npartitions > 0 {
-                    *npartitions as usize
-                } else {
-                    return Err(String::from("Invalid value for option PARTITIONS"));
-                }
-            }
+            Some(Int64(npartitions)) if *npartitions > 0 => *npartitions as usize,
             None => 1usize,
-            _ => return Err(String::from("Invalid value for option PARTITIONS")),
+            _ => return Err(KonaError::Bind(String::from("Invalid value for option PARTITIONS"))),
         };
 
         let part_desc = PartDesc { npartitions, part_type: PartType::RAW };
         Ok(part_desc)
     }
 
-    pub fn catalog_table(&mut self, name: String, options: Vec<(String, Datum)>) -> Result<(), String> {
+    // Columns come either from the SQL-style column list (`columns`) or from the COLUMNS option, not both
+    // CATALOG OR REPLACE TABLE swaps out an existing table's definition
+    pub fn catalog_table(
+        &mut self, schema: Option<String>, name: String, columns: Option<Vec<Field>>, options: Vec<(String, Datum)>, or_replace: bool,
+    ) -> Result<(), KonaError> {
         let name = name.to_uppercase();
-        if self.tables.contains_key(&name) {
-            return Err(f!("Table {name} cannot be cataloged more than once."));
+        let (schema, tables) = self.target_schema(schema)?;
+        match tables.get(&name).map(|tbldesc| tbldesc.get_type()) {
+            Some(TableType::View) => return Err(KonaError::Bind(f!("{schema}.{name} is a view."))),
+            Some(_) if !or_replace => return Err(KonaError::Bind(f!("Table {schema}.{name} cannot be cataloged more than once."))),
+            _ => {}
         }
-        let hm: HashMap<String, Datum> = options.into_iter().collect();
+        let hm: HashMap<String, Datum> = options.into_iter().map(|(option, value)| (option.to_uppercase(), value)).collect();
 
         let typ = Self::get_table_type(&hm, &name)?;
 
         match typ {
             TableType::CSV => {
                 // PATH, HEADER, SEPARATOR
-                let path = hm.get("PATH").ok_or("Table {name} does not specify a PATH")?.try_as_str().ok_or("PATH does not hold a string for table {name}")?;
+                let path = Self::get_path_parm(&hm, &name)?;
                 let path = Rc::new(String::from(path));
 
                 let header = Self::get_header_parm(&hm)?;
@@ -276,7 +480,12 @@ impl Metadata {
                 let part_desc = Self::get_part_desc(&hm)?;
                 let table_stats = Self::get_table_stats(&hm)?;
 
-                let columns = if hm.get("COLUMNS").is_some() {
+                let columns = if let Some(columns) = columns {
+                    if hm.contains_key("COLUMNS") {
+                        return Err(KonaError::Bind(f!("Table {name} specifies both a column list and a COLUMNS option.")));
+                    }
+                    columns
+                } else if hm.contains_key("COLUMNS") {
                     Self::parse_columns(&hm)?
                 } else if matches!(typ, TableType::CSV) {
                     CSVDesc::infer_metadata(&path, separator, header)?
@@ -285,22 +494,21 @@ impl Metadata {
                 };
 
                 let csvdesc = Rc::new(CSVDesc::new(typ, path, columns, separator, header, part_desc, table_stats)?);
-                self.tables.insert(name.to_string(), csvdesc);
-                info!("Cataloged table {}", &name);
+                tables.insert(name.to_string(), csvdesc);
             }
             TableType::Parquet => {
                 // PATH, HEADER, SEPARATOR
-                let path = hm.get("PATH").ok_or("Table {name} does not specify a PATH")?.try_as_str().ok_or("PATH does not hold a string for table {name}")?;
+                let path = Self::get_path_parm(&hm, &name)?;
                 let path = Rc::new(String::from(path));
 
-                if hm.get("HEADER").is_some() {
-                    return Err(f!("HEADER cannot be specified for Parquet files."));
+                if hm.contains_key("HEADER") {
+                    return Err(KonaError::Bind(f!("HEADER cannot be specified for Parquet files.")));
                 }
-                if hm.get("SEPARATOR").is_some() {
-                    return Err(f!("HEADER cannot be specified for Parquet files."));
+                if hm.contains_key("SEPARATOR") {
+                    return Err(KonaError::Bind(f!("HEADER cannot be specified for Parquet files.")));
                 }
-                if hm.get("COLUMNS").is_some() {
-                    return Err(f!("COLUMNS cannot be specified for Parquet files."));
+                if hm.contains_key("COLUMNS") || columns.is_some() {
+                    return Err(KonaError::Bind(f!("COLUMNS cannot be specified for Parquet files.")));
                 }
 
                 let part_desc = Self::get_part_desc(&hm)?;
@@ -308,43 +516,204 @@ impl Metadata {
 
                 let columns = ParquetDesc::infer_metadata(&path)?;
                 let csvdesc = Rc::new(ParquetDesc::new(typ, path, columns, part_desc, table_stats)?);
-                self.tables.insert(name.to_string(), csvdesc);
-                info!("Cataloged table {}", &name);
+                tables.insert(name.to_string(), csvdesc);
             }
             unexpected => panic!("Unexpected type: {:?}", unexpected),
         }
+        self.persist(&schema, &name)?;
+        info!("Cataloged table {}.{}", &schema, &name);
         Ok(())
     }
 
-    pub fn describe_table(&self, name: String) -> Result<(), String> {
+    // CREATE TABLE AS SELECT: the table must be new and its options must name a file format and PATH we can write
+    pub fn new_table_sink(&mut self, schema: Option<String>, name: &str, options: &[(String, Datum)]) -> Result<(SinkFormat, String), KonaError> {
         let name = name.to_uppercase();
-        let tbldesc = self.tables.get(&name);
-        if tbldesc.is_none() {
-            return Err(format!("Table {} does not exist.", name));
+        let (schema, tables) = self.target_schema(schema)?;
+        if tables.contains_key(&name) {
+            return Err(KonaError::Bind(f!("Table {schema}.{name} already exists.")));
         }
-        let tbldesc = tbldesc.unwrap();
-        println!("Table {}", name);
-        println!("  pathname = \"{}\"", tbldesc.pathname());
-        if tbldesc.get_type() == TableType::CSV {
-            info!("  HEADER = {}", tbldesc.header());
-            info!("  SEPARATOR = '{}'", tbldesc.separator());
+        let hm: HashMap<String, Datum> = options.iter().map(|(option, value)| (option.to_uppercase(), value.clone())).collect();
+        let path = Self::get_path_parm(&hm, &name)?;
+        let format = match Self::get_table_type(&hm, &name)? {
+            TableType::CSV => SinkFormat::CSV { header: Self::get_header_parm(&hm)?, separator: Self::get_separator_parm(&hm)? },
+            _ => SinkFormat::Parquet,
+        };
+        Ok((format, path.to_string()))
+    }
+
+    // CREATE VIEW: `columns` carry the view's column names with the types its query resolved to
+    pub fn create_view(&mut self, schema: Option<String>, name: String, sql: String, columns: Vec<Field>, or_replace: bool) -> Result<(), KonaError> {
+        let name = name.to_uppercase();
+        let (schema, tables) = self.target_schema(schema)?;
+        match tables.get(&name).map(|tbldesc| tbldesc.get_type()) {
+            Some(TableType::View) if or_replace => {}
+            Some(TableType::View) => return Err(KonaError::Bind(f!("View {schema}.{name} already exists."))),
+            Some(_) => return Err(KonaError::Bind(f!("Table {schema}.{name} already exists."))),
+            None => {}
+        }
+        tables.insert(name.clone(), Rc::new(ViewDesc::new(sql, columns)));
+        self.persist(&schema, &name)?;
+        info!("Created view {}.{}", &schema, &name);
+        Ok(())
+    }
+
+    // INSERT INTO: rows are appended to the table's file in its format, and have to match its columns
+    pub fn table_sink(&self, schema: Option<&str>, name: &str) -> Result<(SinkFormat, String, Vec<Field>), KonaError> {
+        let (schema, tbldesc) = self.get_tabledesc(schema, name)?;
+        let format = match tbldesc.get_type() {
+            TableType::CSV => SinkFormat::CSV { header: tbldesc.header(), separator: tbldesc.separator() },
+            TableType::Parquet => SinkFormat::Parquet,
+            TableType::Query | TableType::View => return Err(KonaError::Bind(f!("Cannot insert into {schema}.{}.", name.to_uppercase()))),
+        };
+        Ok((format, tbldesc.pathname().to_string(), tbldesc.fields().clone()))
+    }
+
+    // DROP TABLE / DROP VIEW. Tables and views can only be dropped by the statement for their kind.
+    pub fn drop_table(&mut self, schema: Option<String>, name: String, if_exists: bool, is_view: bool) -> Result<(), KonaError> {
+        let (schema, tbldesc) = match self.get_tabledesc(schema.as_deref(), &name) {
+            Ok(found) => found,
+            Err(_) if if_exists => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        let name = name.to_uppercase();
+        match (tbldesc.get_type() == TableType::View, is_view) {
+            (true, false) => return Err(KonaError::Bind(f!("{schema}.{name} is a view. Use DROP VIEW."))),
+            (false, true) => return Err(KonaError::Bind(f!("{schema}.{name} is not a view. Use DROP TABLE."))),
+            _ => {}
+        }
+        self.schemas.get_mut(&schema).unwrap().remove(&name);
+        if let Some(catalog) = self.catalog.as_ref() {
+            catalog.remove(&schema, &name)?;
         }
-        println!("  PARTITIONS = {:?}", tbldesc.get_part_desc());
-        println!("  STATS = {:?}", tbldesc.get_stats());
-        println!("  {} COLUMNS", tbldesc.fields().len());
-        for cd in tbldesc.fields() {
-            println!("      {} {:?}", cd.name, cd.data_type);
+        info!("Dropped {}.{}", &schema, &name);
+        Ok(())
+    }
+
+    // ALTER TABLE t SET (PATH = ..., PARTITIONS = ..., NROWS = ..., AVG_ROW_SIZE = ...)
+    pub fn alter_table(&mut self, schema: Option<String>, name: String, options: Vec<(String, Datum)>) -> Result<(), KonaError> {
+        let (schema, tbldesc) = self.get_tabledesc(schema.as_deref(), &name)?;
+        let name = name.to_uppercase();
+        let hm: HashMap<String, Datum> = options.into_iter().map(|(option, value)| (option.to_uppercase(), value)).collect();
+        let tbldesc = tbldesc.alter(&hm).map_err(|err| match err {
+            KonaError::Bind(message) => KonaError::Bind(f!("Cannot alter {schema}.{name}: {message}")),
+            err => err,
+        })?;
+        self.schemas.get_mut(&schema).unwrap().insert(name.clone(), tbldesc);
+        self.persist(&schema, &name)?;
+        info!("Altered table {}.{}", &schema, &name);
+        Ok(())
+    }
+
+    fn alter_options(hm: &HashMap<String, Datum>, pathname: &mut Rc<String>, part_desc: &mut PartDesc, table_stats: &mut TableStats) -> Result<(), KonaError> {
+        if let Some(option) = hm.keys().find(|option| !["PATH", "PARTITIONS", "NROWS", "AVG_ROW_SIZE"].contains(&option.as_str())) {
+            return Err(KonaError::Bind(f!("Option {option} cannot be altered.")));
         }
+        if let Some(path) = hm.get("PATH") {
+            *pathname = Rc::new(path.try_as_str().ok_or_else(|| KonaError::Bind(String::from("PATH does not hold a string")))?.to_string());
+        }
+        if hm.contains_key("PARTITIONS") {
+            *part_desc = Self::get_part_desc(hm)?;
+        }
+
+        // Stats that aren't named keep their current values
+        let mut stats = HashMap::from([(String::from("NROWS"), Int64(table_stats.nrows as i64)), (String::from("AVG_ROW_SIZE"), Int64(table_stats.avg_row_size as i64))]);
+        for (option, value) in hm.iter() {
+            if stats.contains_key(option) {
+                stats.insert(option.clone(), value.clone());
+            }
+        }
+        let TableStats { nrows, avg_row_size, .. } = Self::get_table_stats(&stats)?;
+        table_stats.nrows = nrows;
+        table_stats.avg_row_size = avg_row_size;
         Ok(())
     }
 
-    pub fn get_tabledesc(&self, name: &str) -> Option<Rc<dyn TableDesc>> {
-        let val = self.tables.get(&name.to_uppercase());
-        val.cloned()
+    pub fn set_table_stats(&mut self, schema: &str, name: &str, table_stats: TableStats) -> Result<(), KonaError> {
+        let tbldesc = self.get_tabledesc(Some(schema), name)?.1.with_stats(table_stats)?;
+        self.schemas.get_mut(schema).unwrap().insert(name.to_string(), tbldesc);
+        self.persist(schema, name)
+    }
+
+    // SHOW TABLES: every table and view, by schema
+    pub fn show_tables(&self) -> ResultSet {
+        let mut rows = vec![];
+        for (schema, tables) in self.schemas.iter() {
+            for (name, tbldesc) in tables.iter() {
+                rows.push(vec![schema.clone(), name.clone(), f!("{:?}", tbldesc.get_type()).to_uppercase()]);
+            }
+        }
+        rows.sort();
+        ResultSet::new(&["SCHEMA", "NAME", "TYPE"], rows)
+    }
+
+    pub fn show_columns(&self, schema: Option<String>, name: String) -> Result<ResultSet, KonaError> {
+        let (_, tbldesc) = self.get_tabledesc(schema.as_deref(), &name)?;
+        let rows =
+            tbldesc.fields().iter().map(|field| vec![field.name.clone(), f!("{:?}", field.data_type), String::from(if field.is_nullable { "YES" } else { "NO" })]).collect();
+        Ok(ResultSet::new(&["NAME", "TYPE", "NULLABLE"], rows))
+    }
+
+    pub fn describe_table(&self, schema: Option<String>, name: String) -> Result<ResultSet, KonaError> {
+        let (schema, tbldesc) = self.get_tabledesc(schema.as_deref(), &name)?;
+        let name = name.to_uppercase();
+        if tbldesc.get_type() == TableType::View {
+            return Err(KonaError::Bind(f!("{schema}.{name} is a view. Use DESCRIBE VIEW.")));
+        }
+        let mut rows = vec![
+            vec![String::from("NAME"), f!("{schema}.{name}")],
+            vec![String::from("TYPE"), f!("{:?}", tbldesc.get_type()).to_uppercase()],
+            vec![String::from("PATH"), tbldesc.pathname().clone()],
+        ];
+        if tbldesc.get_type() == TableType::CSV {
+            rows.push(vec![String::from("HEADER"), String::from(if tbldesc.header() { "YES" } else { "NO" })]);
+            rows.push(vec![String::from("SEPARATOR"), tbldesc.separator().to_string()]);
+        }
+        if let Some(part_desc) = tbldesc.get_part_desc() {
+            rows.push(vec![String::from("PARTITIONS"), part_desc.npartitions.to_string()]);
+        }
+        if let Some(table_stats) = tbldesc.get_stats() {
+            rows.push(vec![String::from("NROWS"), table_stats.nrows.to_string()]);
+            rows.push(vec![String::from("AVG_ROW_SIZE"), table_stats.avg_row_size.to_string()]);
+        }
+        rows.extend(describe_columns(tbldesc.fields()));
+        if let Some(table_stats) = tbldesc.get_stats() {
+            for field in tbldesc.fields().iter() {
+                if let Some(column_stats) = table_stats.columns.get(&field.name) {
+                    rows.push(vec![f!("STATS {}", field.name), column_stats.describe()]);
+                }
+            }
+        }
+        Ok(ResultSet::new(&["PROPERTY", "VALUE"], rows))
+    }
+
+    pub fn describe_view(&self, schema: Option<String>, name: String) -> Result<ResultSet, KonaError> {
+        let (schema, tbldesc) = self.get_tabledesc(schema.as_deref(), &name)?;
+        let name = name.to_uppercase();
+        let sql = tbldesc.view_definition().ok_or_else(|| KonaError::Bind(f!("{schema}.{name} is not a view.")))?;
+        let mut rows = vec![vec![String::from("NAME"), f!("{schema}.{name}")], vec![String::from("DEFINITION"), sql.to_string()]];
+        rows.extend(describe_columns(tbldesc.fields()));
+        Ok(ResultSet::new(&["PROPERTY", "VALUE"], rows))
+    }
+
+    // Qualified names look in their own schema only; unqualified names take the first match along the search path.
+    // Returns the schema the table was found in along with its descriptor.
+    pub fn get_tabledesc(&self, schema: Option<&str>, name: &str) -> Result<(String, Rc<dyn TableDesc>), KonaError> {
+        let name = name.to_uppercase();
+        if let Some(schema) = schema {
+            let schema = schema.to_uppercase();
+            let tables = self.schemas.get(&schema).ok_or_else(|| KonaError::Bind(f!("Schema {schema} does not exist.")))?;
+            let tbldesc = tables.get(&name).ok_or_else(|| KonaError::Bind(f!("Table {schema}.{name} not cataloged.")))?;
+            Ok((schema, Rc::clone(tbldesc)))
+        } else {
+            self.search_path
+                .iter()
+                .find_map(|schema| self.schemas[schema].get(&name).map(|tbldesc| (schema.clone(), Rc::clone(tbldesc))))
+                .ok_or_else(|| KonaError::Bind(f!("Table {name} not cataloged.")))
+        }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ParquetDesc {
     typ: TableType,
     pathname: Rc<String>,
@@ -354,23 +723,21 @@ pub struct ParquetDesc {
 }
 
 impl ParquetDesc {
-    pub fn new(typ: TableType, pathname: Rc<String>, columns: Vec<Field>, part_desc: PartDesc, table_stats: TableStats) -> Result<Self, String> {
+    pub fn new(typ: TableType, pathname: Rc<String>, columns: Vec<Field>, part_desc: PartDesc, table_stats: TableStats) -> Result<Self, KonaError> {
         let csvdesc = ParquetDesc { typ, pathname, columns, part_desc, table_stats };
         Ok(csvdesc)
     }
 
-    pub fn infer_metadata(pathname: &str) -> Result<Vec<Field>, String> {
+    pub fn infer_metadata(pathname: &str) -> Result<Vec<Field>, KonaError> {
         use arrow2::io::parquet::read;
 
-        let mut reader = File::open(pathname).map_err(stringify)?;
-
-        let metadata = read::read_metadata(&mut reader).map_err(stringify)?;
+        let mut reader = File::open(pathname).map_err(|err| KonaError::io(f!("Cannot open {pathname}"), err))?;
 
-        let schema = read::infer_schema(&metadata).map_err(stringify)?;
+        let metadata = read::read_metadata(&mut reader).map_err(|err| KonaError::arrow(f!("Cannot read the metadata of {pathname}"), err))?;
 
-        let fields = schema.fields.iter().map(|f| Field::new(f.name.to_uppercase(), f.data_type.clone(), f.is_nullable)).collect();
+        let schema = read::infer_schema(&metadata).map_err(|err| KonaError::arrow(f!("Cannot infer the columns of {pathname}"), err))?;
 
-        Ok(fields)
+        Ok(schema.fields)
     }
 }
 
@@ -391,10 +758,6 @@ impl TableDesc for ParquetDesc {
         format!("Type: Parquet, {:?}", self)
     }
 
-    fn get_column(&self, colname: &str) -> Option<(usize, &Field)> {
-        self.columns.iter().enumerate().find(|(_, cd)| cd.name == *colname)
-    }
-
     fn get_part_desc(&self) -> Option<&PartDesc> {
         Some(&self.part_desc)
     }
@@ -410,6 +773,16 @@ impl TableDesc for ParquetDesc {
     fn get_stats(&self) -> Option<&TableStats> {
         Some(&self.table_stats)
     }
+
+    fn alter(&self, options: &HashMap<String, Datum>) -> Result<Rc<dyn TableDesc>, KonaError> {
+        let mut desc = self.clone();
+        Metadata::alter_options(options, &mut desc.pathname, &mut desc.part_desc, &mut desc.table_stats)?;
+        Ok(Rc::new(desc))
+    }
+
+    fn with_stats(&self, table_stats: TableStats) -> Result<Rc<dyn TableDesc>, KonaError> {
+        Ok(Rc::new(Self { table_stats, ..self.clone() }))
+    }
 }
 
 #[derive(Debug)]
@@ -440,8 +813,50 @@ impl TableDesc for QueryDesc {
         format!("Type: Query, {:?}", self)
     }
 
-    fn get_column(&self, colname: &str) -> Option<(usize, &Field)> {
-        self.columns.iter().enumerate().find(|(_, cd)| cd.name == *colname)
+    fn get_part_desc(&self) -> Option<&PartDesc> {
+        None
+    }
+
+    fn header(&self) -> bool {
+        todo!()
+    }
+
+    fn separator(&self) -> char {
+        todo!()
+    }
+
+    fn get_stats(&self) -> Option<&TableStats> {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct ViewDesc {
+    sql: String,
+    columns: Vec<Field>,
+}
+
+impl ViewDesc {
+    pub fn new(sql: String, columns: Vec<Field>) -> Self {
+        ViewDesc { sql, columns }
+    }
+}
+
+impl TableDesc for ViewDesc {
+    fn get_type(&self) -> TableType {
+        TableType::View
+    }
+
+    fn fields(&self) -> &Vec<Field> {
+        &self.columns
+    }
+
+    fn pathname(&self) -> &String {
+        panic!("No pathname")
+    }
+
+    fn describe(&self) -> String {
+        format!("Type: View, {:?}", self)
     }
 
     fn get_part_desc(&self) -> Option<&PartDesc> {
@@ -459,4 +874,170 @@ impl TableDesc for QueryDesc {
     fn get_stats(&self) -> Option<&TableStats> {
         None
     }
+
+    fn view_definition(&self) -> Option<&str> {
+        Some(&self.sql)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    fn utf8(s: &str) -> Datum {
+        Utf8(s.to_string())
+    }
+
+    #[test]
+    fn sql_column_lists() {
+        let mut session = session();
+        let columns = "ID BIGINT NOT NULL, NAME VARCHAR(20), PRICE DECIMAL(10,2) NULL, D DATE, TS TIMESTAMP, OK BOOLEAN, F DOUBLE PRECISION";
+        create_table(&mut session, "T", columns, &["1,a,1.5,2020-01-01,2020-01-01 10:00:00,true,0.5"]);
+        let rows = query(&mut session, "SHOW COLUMNS FROM T").unwrap();
+        let expected = [
+            ["D", "Date32", "YES"],
+            ["F", "Float64", "YES"],
+            ["ID", "Int64", "NO"],
+            ["NAME", "Utf8", "YES"],
+            ["OK", "Boolean", "YES"],
+            ["PRICE", "Decimal(10, 2)", "YES"],
+            ["TS", "Timestamp(Microsecond, None)", "YES"],
+        ];
+        assert_eq!(rows, expected.iter().map(|row| row.iter().map(|s| utf8(s)).collect::<Vec<_>>()).collect::<Vec<_>>());
+
+        let rows = query(&mut session, "SELECT ID, PRICE, OK FROM T").unwrap();
+        assert_eq!(rows, vec![vec![Int64(1), Decimal(150, 10, 2), Boolean(true)]]);
+    }
+
+    #[test]
+    fn columns_option() {
+        let hm = HashMap::from([(String::from("COLUMNS"), utf8("name=STRING, age=INT NOT NULL, salary=DECIMAL(10,2) NULL"))]);
+        let fields = Metadata::parse_columns(&hm).unwrap();
+        assert_eq!(fields, vec![Field::new("name", DataType::Utf8, true), Field::new("age", DataType::Int64, false), Field::new("salary", DataType::Decimal(10, 2), true)]);
+
+        let hm = HashMap::from([(String::from("COLUMNS"), utf8("name=STRING,age"))]);
+        assert!(Metadata::parse_columns(&hm).is_err());
+    }
+
+    #[test]
+    fn unsupported_types_are_rejected() {
+        let mut session = session();
+        for typ in ["SMALLINT", "TINYINT", "INT16", "UINT32", "FLOAT32"] {
+            let result = execute(&mut session, &f!("CATALOG TABLE T (C {typ}) WITH (TYPE = 'CSV', PATH = 'data/emp.csv')"));
+            assert!(result.is_err(), "{}", typ);
+            let result = execute(&mut session, &f!("CATALOG TABLE T (TYPE = 'CSV', PATH = 'data/emp.csv', COLUMNS = 'C={typ}')"));
+            assert!(result.is_err(), "{}", typ);
+        }
+        let result = execute(&mut session, "CATALOG TABLE T (C INT) WITH (TYPE = 'CSV', PATH = 'data/emp.csv', COLUMNS = 'C=INT')");
+        assert!(result.unwrap_err().to_string().contains("both a column list and a COLUMNS option"));
+    }
+
+    #[test]
+    fn not_null_columns_reject_missing_values() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT NOT NULL, NAME STRING", &["1,a", ",b"]);
+        let err = query(&mut session, "SELECT ID, NAME FROM T").unwrap_err();
+        assert!(err.to_string().contains("Column ID is declared NOT NULL"), "{}", err);
+    }
+
+    #[test]
+    fn quoted_column_names_match_exactly() {
+        assert!(column_name_matches("Name", "NAME", false));
+        assert!(column_name_matches("Name", "Name", true));
+        assert!(!column_name_matches("Name", "NAME", true));
+        assert!(!column_name_matches("name", "Name", false));
+
+        let mut session = session();
+        create_table(&mut session, "T", r#""id" INT, "Id" INT, "Name" STRING"#, &["1,2,a"]);
+        let value = |session: &mut TestSession, sql: &str| query_value(session, sql).unwrap_or_else(|err| panic!("{}: {}", sql, err));
+        assert_eq!(value(&mut session, r#"SELECT "id" FROM T"#), Int64(1));
+        assert_eq!(value(&mut session, r#"SELECT "Id" FROM T"#), Int64(2));
+        assert_eq!(value(&mut session, r#"SELECT T."Name" FROM T"#), utf8("a"));
+        assert_eq!(value(&mut session, "SELECT name FROM T"), utf8("a"));
+        for sql in [r#"SELECT "ID" FROM T"#, r#"SELECT "NAME" FROM T"#, r#"SELECT T."name" FROM T"#] {
+            let err = query(&mut session, sql).unwrap_err();
+            assert!(matches!(&err, KonaError::Bind(msg) if msg.contains("not found")), "{}: {}", sql, err);
+        }
+        assert!(execute(&mut session, r#"ANALYZE TABLE T COLUMNS ("Name")"#).is_ok());
+        assert!(matches!(execute(&mut session, r#"ANALYZE TABLE T COLUMNS ("NAME")"#), Err(KonaError::Bind(_))));
+    }
+
+    fn strs(rows: &[&[&str]]) -> Vec<Vec<Datum>> {
+        rows.iter().map(|row| row.iter().map(|s| utf8(s)).collect()).collect()
+    }
+
+    #[test]
+    fn describe_lists_each_column() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT NOT NULL, NAME STRING", &["1,a"]);
+        execute(&mut session, "CREATE VIEW V AS SELECT NAME FROM T").unwrap();
+
+        let rows = query_in_order(&mut session, "DESCRIBE TABLE T").unwrap();
+        let columns = rows.iter().filter(|row| matches!(&row[0], Utf8(property) if property.starts_with("COLUMN"))).cloned().collect::<Vec<_>>();
+        assert_eq!(columns, strs(&[&["COLUMN ID", "Int64 NOT NULL"], &["COLUMN NAME", "Utf8"]]));
+
+        let rows = query_in_order(&mut session, "DESCRIBE VIEW V").unwrap();
+        assert_eq!(rows, strs(&[&["NAME", "PUBLIC.V"], &["DEFINITION", "SELECT NAME FROM T"], &["COLUMN NAME", "Utf8"]]));
+    }
+
+    #[test]
+    fn show_and_drop_tables() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT", &["1"]);
+        execute(&mut session, "CREATE VIEW V AS SELECT ID FROM T").unwrap();
+        assert_eq!(query(&mut session, "SHOW TABLES").unwrap(), strs(&[&["PUBLIC", "T", "CSV"], &["PUBLIC", "V", "VIEW"]]));
+
+        for (sql, message) in [("DROP TABLE V", "is a view. Use DROP VIEW"), ("DROP VIEW T", "is not a view. Use DROP TABLE"), ("DROP TABLE NOPE", "Table NOPE not cataloged")]
+        {
+            let err = execute(&mut session, sql).unwrap_err();
+            assert!(matches!(&err, KonaError::Bind(msg) if msg.contains(message)), "{}: {}", sql, err);
+        }
+        execute(&mut session, "DROP TABLE IF EXISTS NOPE").unwrap();
+
+        execute(&mut session, "DROP VIEW V").unwrap();
+        execute(&mut session, "DROP TABLE T").unwrap();
+        assert_eq!(query(&mut session, "SHOW TABLES").unwrap(), strs(&[]));
+        assert!(matches!(query(&mut session, "SELECT ID FROM T"), Err(KonaError::Bind(_))));
+    }
+
+    #[test]
+    fn alter_table_options() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT", &["1", "2"]);
+        let path = f!("{}/U.csv", temp_dir("ALTER"));
+        fs::write(&path, "3\n").unwrap();
+
+        execute(&mut session, &f!("ALTER TABLE T SET (PATH = '{path}', PARTITIONS = 3, NROWS = 1000)")).unwrap();
+        let rows = query_in_order(&mut session, "DESCRIBE TABLE T").unwrap();
+        for property in [["PATH", path.as_str()], ["PARTITIONS", "3"], ["NROWS", "1000"]] {
+            assert!(rows.contains(&strs(&[&property])[0]), "{:?}", property);
+        }
+        assert_eq!(query(&mut session, "SELECT ID FROM T").unwrap(), vec![vec![Int64(3)]]);
+
+        for (sql, message) in [("ALTER TABLE T SET (HEADER = 'Y')", "Option HEADER cannot be altered"), ("ALTER TABLE NOPE SET (NROWS = 1)", "Table NOPE not cataloged")] {
+            let err = execute(&mut session, sql).unwrap_err();
+            assert!(matches!(&err, KonaError::Bind(msg) if msg.contains(message)), "{}: {}", sql, err);
+        }
+    }
+
+    #[test]
+    fn or_replace() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT", &["1"]);
+        let path = f!("{}/U.csv", temp_dir("REPLACE"));
+        fs::write(&path, "a,2\n").unwrap();
+
+        let catalog = |or_replace: &str| f!("CATALOG {or_replace}TABLE T (S STRING, ID INT) WITH (TYPE = 'CSV', PATH = '{path}', HEADER = 'N')");
+        let err = execute(&mut session, &catalog("")).unwrap_err();
+        assert!(err.to_string().contains("cannot be cataloged more than once"), "{}", err);
+        execute(&mut session, &catalog("OR REPLACE ")).unwrap();
+        assert_eq!(query(&mut session, "SELECT S, ID FROM T").unwrap(), vec![vec![utf8("a"), Int64(2)]]);
+
+        execute(&mut session, "CREATE VIEW V AS SELECT ID FROM T").unwrap();
+        let err = execute(&mut session, "CREATE VIEW V AS SELECT S FROM T").unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{}", err);
+        execute(&mut session, "CREATE OR REPLACE VIEW V AS SELECT S FROM T").unwrap();
+        assert_eq!(query(&mut session, "SELECT S FROM V").unwrap(), vec![vec![utf8("a")]]);
+    }
 }
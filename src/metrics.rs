@@ -0,0 +1,156 @@
+// metrics: Per-operator runtime counters, gathered by every task and reported by EXPLAIN ANALYZE
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use arrow2::compute::aggregate::estimated_bytes_size;
+
+use crate::{graph::POPKey, includes::*, pop::POPContext, stage::Stage, Flow};
+
+#[derive(Debug, Clone, Default)]
+pub struct OpMetrics {
+    pub rows_in: usize, // Only counted by scans. Other operators take theirs from their children.
+    pub chunks_in: usize,
+    pub rows_out: usize,
+    pub chunks_out: usize,
+    pub bytes_out: usize,
+    pub elapsed: Duration, // Wall time spent in `next`, including the time spent in children
+    pub hash_table_entries: Option<usize>,
+}
+
+impl OpMetrics {
+    pub fn add(&mut self, other: &OpMetrics) {
+        self.rows_in += other.rows_in;
+        self.chunks_in += other.chunks_in;
+        self.rows_out += other.rows_out;
+        self.chunks_out += other.chunks_out;
+        self.bytes_out += other.bytes_out;
+        self.elapsed += other.elapsed;
+        if let Some(entries) = other.hash_table_entries {
+            *self.hash_table_entries.get_or_insert(0) += entries;
+        }
+    }
+}
+
+// The counters of every operator run by one task
+pub type TaskMetrics = HashMap<POPKey, OpMetrics>;
+
+// Task metrics of a flow, by stage and then by partition
+#[derive(Debug, Default)]
+pub struct FlowMetrics {
+    pub stages: Vec<Vec<TaskMetrics>>,
+    pub elapsed: Duration,
+}
+
+impl FlowMetrics {
+    pub fn new(flow: &Flow) -> Self {
+        let stages = flow.stage_graph.stages.iter().map(|stage| vec![TaskMetrics::default(); stage.npartitions]).collect();
+        FlowMetrics { stages, elapsed: Duration::default() }
+    }
+
+    pub fn set_task_metrics(&mut self, stage_id: StageId, partition_id: PartitionId, metrics: TaskMetrics) {
+        self.stages[stage_id][partition_id] = metrics
+    }
+
+    // An operator's counters in each partition of its stage
+    pub fn partitions(&self, stage_id: StageId, pop_key: POPKey) -> Vec<OpMetrics> {
+        self.stages[stage_id].iter().map(|task_metrics| task_metrics.get(&pop_key).cloned().unwrap_or_default()).collect()
+    }
+}
+
+/***************************************************************************************************/
+// Wraps the context of an operator to count what it returns and time its calls. The counters are handed to the task
+// when the context is dropped.
+pub struct MeteredContext {
+    pop_key: POPKey,
+    context: Box<dyn POPContext>,
+    metrics: OpMetrics,
+    task_metrics: Rc<RefCell<TaskMetrics>>,
+}
+
+impl MeteredContext {
+    pub fn wrap(pop_key: POPKey, context: Box<dyn POPContext>, task_metrics: Rc<RefCell<TaskMetrics>>) -> Box<dyn POPContext> {
+        Box::new(MeteredContext { pop_key, context, metrics: OpMetrics::default(), task_metrics })
+    }
+}
+
+impl POPContext for MeteredContext {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self.context.as_any_mut()
+    }
+
+    fn next(&mut self, flow: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, KonaError> {
+        let start = Instant::now();
+        let chunk = self.context.next(flow, stage)?;
+        self.metrics.elapsed += start.elapsed();
+
+        if let Some(chunk) = chunk.as_ref() {
+            self.metrics.rows_out += chunk.len();
+            self.metrics.chunks_out += 1;
+            self.metrics.bytes_out += chunk.arrays().iter().map(|array| estimated_bytes_size(&**array)).sum::<usize>();
+        }
+        Ok(chunk)
+    }
+}
+
+impl Drop for MeteredContext {
+    fn drop(&mut self) {
+        let mut metrics = std::mem::take(&mut self.metrics);
+        self.context.add_metrics(&mut metrics);
+        self.task_metrics.borrow_mut().insert(self.pop_key, metrics);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    // Each operator of an EXPLAIN ANALYZE with the rows it read and returned, over all partitions
+    fn rows_per_operator(session: &mut TestSession, sql: &str) -> Vec<String> {
+        let rows = query_in_order(session, &f!("EXPLAIN ANALYZE {sql}")).unwrap();
+        let lines = rows.iter().map(|row| if let Utf8(line) = &row[0] { line.trim().to_string() } else { panic!("{:?}", row) }).collect::<Vec<_>>();
+        let mut operators = vec![];
+        for (ix, line) in lines.iter().enumerate() {
+            if let Some(counts) = line.strip_prefix("rows in = ") {
+                // The nearest header above, e.g. "HashAgg-2v1: keys = [0], ..."
+                let is_header = |line: &&String| line.split(':').next().is_some_and(|head| head.contains('-') && !head.contains(' '));
+                let label = lines[..ix].iter().rev().find(is_header).unwrap().split('-').next().unwrap();
+                let (rows_in, rest) = counts.split_once(',').unwrap();
+                let rows_out = rest.split("rows out = ").nth(1).unwrap().split(',').next().unwrap();
+                operators.push(f!("{label}: {rows_in} -> {rows_out}"));
+            }
+        }
+        operators
+    }
+
+    #[test]
+    fn join_and_aggregate_count_their_rows() {
+        let mut session = session();
+        create_table(&mut session, "A", "ID INT, CAT STRING", &["1,a", "2,a", "3,b", "4,c"]);
+        create_table(&mut session, "B", "ID INT, N INT", &["1,10", "2,20", "3,30", "5,50", "6,60"]);
+        let sql = "SELECT /*+ PARALLEL(2) */ A.CAT, SUM(B.N) FROM A, B WHERE A.ID = B.ID GROUP BY A.CAT";
+        assert_eq!(
+            rows_per_operator(&mut session, sql),
+            [
+                // Stage 0 aggregates the 3 joined rows into 2 groups
+                "HashAgg: 3 -> 2",
+                "RepartitionRead: 3 -> 3",
+                // Stage 1 joins 4 rows of A with 5 rows of B. Repartition writers send their rows to other stages.
+                "RepartitionWrite: 3 -> 0",
+                "HashMatch: 9 -> 3",
+                "RepartitionRead: 4 -> 4",
+                "RepartitionRead: 5 -> 5",
+                // Stages 2 and 3 scan A and B
+                "RepartitionWrite: 4 -> 0",
+                "CSV: 4 -> 4",
+                "RepartitionWrite: 5 -> 0",
+                "CSV: 5 -> 5",
+            ]
+        );
+    }
+}
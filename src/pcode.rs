@@ -4,14 +4,16 @@
 
 use std::fmt;
 
-use arrow2::{scalar::{PrimitiveScalar, Scalar, Utf8Scalar}, compute::cast::{CastOptions, self}};
+use arrow2::{array::{get_display, new_null_array}, scalar::{PrimitiveScalar, Scalar, Utf8Scalar}, compute::cast::{CastOptions, self}, types::months_days_ns};
 
 use crate::{
     datum::Datum,
+    decimal::{self, is_decimal},
     expr::{ArithOp, Expr, ExprGraph, LogOp, RelOp},
     graph::ExprKey,
     includes::*,
     pop::{Projection, ProjectionMap},
+    temporal::{self, DateField},
 };
 
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -29,12 +31,16 @@ pub enum ControlOp {
 pub enum PInstruction {
     Column(ColId),
     Literal(Datum),
+    Parameter(usize), // Replaced by a literal when a prepared statement is executed
     NegatedExpr,
     BinaryExpr(ArithOp),
     RelExpr(RelOp),
     LogExpr(LogOp),
     ControlOp(ControlOp),
     Cast(DataType),
+    TryCast(DataType),
+    Extract(DateField),
+    DateTrunc(DateField),
 }
 
 impl ExprKey {
@@ -61,6 +67,7 @@ impl ExprKey {
             match expr {
                 Expr::CID(_, colid) => PInstruction::Column(*colid),
                 Expr::Literal(value) => PInstruction::Literal(value.clone()),
+                Expr::Parameter(ix) => PInstruction::Parameter(*ix),
                 Expr::Column { qunid, colid, .. } => {
                     let prj = Projection::QunCol(QunCol(*qunid, *colid));
                     let colid = proj_map.get(prj).unwrap();
@@ -80,6 +87,9 @@ impl ExprKey {
                     }
                 }
                 Expr::Cast => PInstruction::Cast(props.data_type.clone()),
+                Expr::TryCast => PInstruction::TryCast(props.data_type.clone()),
+                Expr::Extract(field) => PInstruction::Extract(*field),
+                Expr::DateTrunc(field) => PInstruction::DateTrunc(*field),
                 _ => panic!("Expression not compilable yet: {:?}", expr),
             }
         };
@@ -119,12 +129,54 @@ impl<'a> fmt::Debug for PCodeStack<'a> {
 }
 
 impl PCode {
+    // Rebuilds the expression from its postfix instructions. Columns are shown by their position in the input chunk.
+    pub fn describe(&self) -> String {
+        // Each operand remembers whether it needs parentheses when nested in another operator
+        let mut stack: Vec<(String, bool)> = vec![];
+        let operand = |(s, is_compound): (String, bool)| if is_compound { f!("({s})") } else { s };
+        for inst in self.instructions.iter() {
+            let item = match inst {
+                PInstruction::Column(id) => (f!("#{id}"), false),
+                PInstruction::Literal(datum) => (datum.to_string(), false),
+                PInstruction::Parameter(ix) => (f!("${}", ix + 1), false),
+                PInstruction::NegatedExpr => (f!("-{}", operand(stack.pop().unwrap())), false),
+                PInstruction::LogExpr(LogOp::Not) => (f!("NOT {}", operand(stack.pop().unwrap())), true),
+                PInstruction::BinaryExpr(_) | PInstruction::RelExpr(_) | PInstruction::LogExpr(_) => {
+                    let (rhs, lhs) = (operand(stack.pop().unwrap()), operand(stack.pop().unwrap()));
+                    let op = match inst {
+                        PInstruction::BinaryExpr(op) => op.to_string(),
+                        PInstruction::RelExpr(op) => op.to_string(),
+                        PInstruction::LogExpr(op) => op.to_string(),
+                        _ => unreachable!(),
+                    };
+                    (f!("{lhs} {op} {rhs}"), true)
+                }
+                PInstruction::ControlOp(_) => continue,
+                PInstruction::Cast(data_type) => (f!("CAST({} AS {data_type:?})", stack.pop().unwrap().0), false),
+                PInstruction::TryCast(data_type) => (f!("TRY_CAST({} AS {data_type:?})", stack.pop().unwrap().0), false),
+                PInstruction::Extract(field) => (f!("EXTRACT({field} FROM {})", stack.pop().unwrap().0), false),
+                PInstruction::DateTrunc(field) => (f!("DATE_TRUNC('{field}', {})", stack.pop().unwrap().0), false),
+            };
+            stack.push(item);
+        }
+        stack.into_iter().map(|(s, _)| s).collect::<Vec<_>>().join(", ")
+    }
+
+    // Substitutes the values of a prepared statement's parameters
+    pub fn bind(&mut self, values: &[Datum]) {
+        for inst in self.instructions.iter_mut() {
+            if let PInstruction::Parameter(ix) = inst {
+                *inst = PInstruction::Literal(values[*ix].clone());
+            }
+        }
+    }
+
     pub fn push(&mut self, inst: PInstruction) {
         debug!("Instruction: {:?}", inst);
         self.instructions.push(inst)
     }
 
-    pub fn eval(&self, input: &ChunkBox) -> Box<dyn Array> {
+    pub fn eval(&self, input: &ChunkBox) -> Result<Box<dyn Array>, KonaError> {
 
         debug!("eval: {:?}", self);
         
@@ -133,10 +185,40 @@ impl PCode {
             match inst {
                 PInstruction::Column(id) => stack.push(PCodeStack::Column(Column::Ref(&input[*id]))),
                 PInstruction::Literal(datum) => stack.push(PCodeStack::Datum(datum.clone())),
+                PInstruction::Parameter(ix) => return Err(KonaError::execution(f!("Parameter ${} is not bound", ix + 1))),
                 PInstruction::BinaryExpr(op) => {
                     let (rhs, lhs) = (stack.pop().unwrap(), stack.pop().unwrap());
 
+                    // A literal on the left, e.g. DATE '1999-01-01' - O_ORDERDATE, 7 + ID or a bound parameter, is evaluated as a column
+                    let lhs = match lhs {
+                        PCodeStack::Datum(datum) if datum != Datum::Null => PCodeStack::Column(Column::Owned(datum.to_array(input.len()))),
+                        lhs => lhs,
+                    };
+
                     match (lhs, op, rhs) {
+                        (PCodeStack::Column(lhs), ArithOp::Sub, PCodeStack::Column(rhs)) if *lhs.get().data_type() == DataType::Date32 => {
+                            let array = temporal::date_diff(&**lhs.get(), &**rhs.get()).map_err(KonaError::execution)?;
+                            stack.push(PCodeStack::Column(Column::Owned(array)));
+                        }
+                        (PCodeStack::Column(lhs), ArithOp::Sub, PCodeStack::Datum(Datum::Date32(days))) => {
+                            let array = temporal::date_diff_scalar(&**lhs.get(), days).map_err(KonaError::execution)?;
+                            stack.push(PCodeStack::Column(Column::Owned(array)));
+                        }
+                        (PCodeStack::Column(lhs), arithop, PCodeStack::Datum(Datum::Interval(months, days, nanos))) => {
+                            let interval = months_days_ns::new(months, days, nanos);
+                            let interval = if *arithop == ArithOp::Sub { -interval } else { interval };
+                            let array = temporal::add_interval(&**lhs.get(), interval).map_err(KonaError::execution)?;
+                            stack.push(PCodeStack::Column(Column::Owned(array)));
+                        }
+                        (PCodeStack::Column(lhs), arithop, PCodeStack::Column(rhs)) if is_decimal(lhs.get().data_type()) || is_decimal(rhs.get().data_type()) => {
+                            let array = decimal::arith(&**lhs.get(), *arithop, &**rhs.get()).map_err(KonaError::execution)?;
+                            stack.push(PCodeStack::Column(Column::Owned(array)));
+                        }
+                        (PCodeStack::Column(lhs), arithop, PCodeStack::Datum(Datum::Decimal(value, precision, scale))) => {
+                            let rhs = decimal::literal_to_array(value, precision, scale, lhs.get().len());
+                            let array = decimal::arith(&**lhs.get(), *arithop, &*rhs).map_err(KonaError::execution)?;
+                            stack.push(PCodeStack::Column(Column::Owned(array)));
+                        }
                         (PCodeStack::Column(lhs), arithop, PCodeStack::Column(rhs)) => {
                             let lhs = &**lhs.get();
                             let rhs = &**rhs.get();
@@ -148,9 +230,25 @@ impl PCode {
                             };
                             stack.push(PCodeStack::Column(Column::Owned(array)));
                         }
+                        (PCodeStack::Column(col), _, PCodeStack::Datum(Datum::Null)) | (PCodeStack::Datum(Datum::Null), _, PCodeStack::Column(col)) => {
+                            // Arithmetic with NULL yields NULL
+                            let array = new_null_array(col.get().data_type().clone(), col.get().len());
+                            stack.push(PCodeStack::Column(Column::Owned(array)));
+                        }
+                        (PCodeStack::Column(lhs), arithop, PCodeStack::Datum(Datum::Int32(i))) => {
+                            let lhs = lhs.get().as_any().downcast_ref::<PrimitiveArray<i32>>().unwrap();
+                            let rhs = &i;
+                            let array: Box<dyn Array> = match arithop {
+                                ArithOp::Add => Box::new(arithmetics::basic::add_scalar(lhs, rhs)),
+                                ArithOp::Sub => Box::new(arithmetics::basic::sub_scalar(lhs, rhs)),
+                                ArithOp::Mul => Box::new(arithmetics::basic::mul_scalar(lhs, rhs)),
+                                ArithOp::Div => Box::new(arithmetics::basic::div_scalar(lhs, rhs)),
+                            };
+                            stack.push(PCodeStack::Column(Column::Owned(array)));
+                        }
                         (PCodeStack::Column(lhs), arithop, PCodeStack::Datum(Datum::Int64(i))) => {
                             let lhs = lhs.get().as_any().downcast_ref::<PrimitiveArray<i64>>().unwrap();
-                            let rhs = &(i as i64);
+                            let rhs = &i;
                             let array: Box<dyn Array> = match arithop {
                                 ArithOp::Add => Box::new(arithmetics::basic::add_scalar(lhs, rhs)),
                                 ArithOp::Sub => Box::new(arithmetics::basic::sub_scalar(lhs, rhs)),
@@ -192,11 +290,21 @@ impl PCode {
                             };
                             stack.push(PCodeStack::Column(Column::Owned(array)));
                         }
+                        (PCodeStack::Column(lhs), RelOp::Is, PCodeStack::Datum(Datum::Null)) => {
+                            let array = Box::new(boolean::is_null(&**lhs.get()));
+                            stack.push(PCodeStack::Column(Column::Owned(array)));
+                        }
+                        (PCodeStack::Column(lhs), _, PCodeStack::Datum(Datum::Null)) => {
+                            // Comparisons with NULL are unknown, i.e. NULL
+                            let array = Box::new(BooleanArray::new_null(DataType::Boolean, lhs.get().len()));
+                            stack.push(PCodeStack::Column(Column::Owned(array)));
+                        }
                         (PCodeStack::Column(lhs), relop, PCodeStack::Datum(d)) => {
                             let scalar_i64; // = PrimitiveScalar::new(DataType::Int64, Some(0 as i64));
                             let scalar_utf8; // = PrimitiveScalar::new(DataType::Int64, Some(0 as i64));
                             let scalar_i32;
                             let scalar_f64;
+                            let scalar_i128;
 
                             let lhs = &**lhs.get();
                             let rhs: &dyn Scalar = match d {
@@ -214,14 +322,22 @@ impl PCode {
                                     &scalar_utf8
                                 }
                                 Datum::Date32(d) => {
-                                    scalar_i32 = PrimitiveScalar::new(DataType::Date32, Some(d as i32));
+                                    scalar_i32 = PrimitiveScalar::new(DataType::Date32, Some(d));
                                     &scalar_i32
                                 }
+                                Datum::Timestamp(ts, tz) => {
+                                    scalar_i64 = PrimitiveScalar::new(temporal::timestamp_datatype(tz), Some(ts));
+                                    &scalar_i64
+                                }
                                 Datum::Float64(fvalue) => {
                                     let f = &f64::from(fvalue);
                                     scalar_f64 = PrimitiveScalar::new(DataType::Float64, Some(*f));
                                     &scalar_f64
                                 }
+                                Datum::Decimal(value, precision, scale) => {
+                                    scalar_i128 = PrimitiveScalar::new(DataType::Decimal(precision, scale), Some(value));
+                                    &scalar_i128
+                                }
                                 _ => todo!(),
                             };
                             let array: Box<dyn Array> = match relop {
@@ -240,6 +356,14 @@ impl PCode {
                         }
                     }
                 }
+                PInstruction::LogExpr(LogOp::Not) => match stack.pop().unwrap() {
+                    PCodeStack::Column(lhs) => {
+                        let lhs = lhs.get().as_any().downcast_ref::<BooleanArray>().unwrap();
+                        let array = Box::new(boolean::not(lhs));
+                        stack.push(PCodeStack::Column(Column::Owned(array)));
+                    }
+                    _ => todo!(),
+                },
                 PInstruction::LogExpr(op) => {
                     let (rhs, lhs) = (stack.pop().unwrap(), stack.pop().unwrap());
                     match (lhs, op, rhs) {
@@ -267,13 +391,21 @@ impl PCode {
                         _ => todo!(),
                     }
                 }
-                PInstruction::Cast(to_datatype) => {
+                PInstruction::Cast(to_datatype) | PInstruction::TryCast(to_datatype) => {
+                    let is_strict = matches!(inst, PInstruction::Cast(_));
+                    let array = match stack.pop().unwrap() {
+                        PCodeStack::Column(lhs) => cast_array(&**lhs.get(), to_datatype, is_strict).map_err(KonaError::execution)?,
+                        PCodeStack::Datum(datum) => cast_array(&*datum.to_array(input.len()), to_datatype, is_strict).map_err(KonaError::execution)?,
+                    };
+                    stack.push(PCodeStack::Column(Column::Owned(array)));
+                }
+                PInstruction::Extract(field) | PInstruction::DateTrunc(field) => {
                     let lhs = stack.pop().unwrap();
                     match lhs {
                         PCodeStack::Column(lhs) => {
                             let lhs = &**lhs.get();
-                            let cast_options = CastOptions::default();
-                            let array = cast::cast(lhs, to_datatype, cast_options).unwrap();
+                            let array = if matches!(inst, PInstruction::Extract(_)) { temporal::extract(lhs, *field) } else { temporal::date_trunc(lhs, *field) };
+                            let array = array.map_err(KonaError::execution)?;
                             stack.push(PCodeStack::Column(Column::Owned(array)));
                         }
                         _ => todo!(),
@@ -286,10 +418,74 @@ impl PCode {
             }
         }
         let array = stack.pop().unwrap();
-        match array {
+        let array = match array {
             PCodeStack::Column(Column::Owned(array)) => array,
             PCodeStack::Column(Column::Ref(array)) => array.clone(),
-            _ => panic!("unexpected value"),
-        }
+            PCodeStack::Datum(datum) => datum.to_array(input.len()),
+        };
+        Ok(array)
+    }
+}
+
+// Runtime CAST. Arrow turns values it cannot convert into NULLs, which is what TRY_CAST wants; a strict CAST
+// reports the first such value instead.
+fn cast_array(array: &dyn Array, to_datatype: &DataType, is_strict: bool) -> Result<Box<dyn Array>, String> {
+    let result = if temporal::is_temporal(to_datatype) {
+        temporal::cast_temporal(array, to_datatype)?
+    } else if is_decimal(array.data_type()) || is_decimal(to_datatype) {
+        decimal::cast_array(array, to_datatype)?
+    } else {
+        cast::cast(array, to_datatype, CastOptions::default()).map_err(stringify)?
+    };
+    if is_strict && result.null_count() > array.null_count() {
+        let ix = (0..array.len()).find(|&ix| array.is_valid(ix) && !result.is_valid(ix)).unwrap();
+        let mut value = String::new();
+        get_display(array, "NULL")(&mut value, ix).map_err(stringify)?;
+        return Err(f!("Cannot CAST '{}' to {:?}", value, to_datatype));
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow2::array::Utf8Array;
+
+    use super::*;
+    use crate::{datum::F64, testing::*};
+
+    #[test]
+    fn strict_casts_report_the_bad_value() {
+        let array = Utf8Array::<i32>::from([Some("1"), None, Some("x")]);
+        let err = cast_array(&array, &DataType::Int64, true).unwrap_err();
+        assert_eq!(err, "Cannot CAST 'x' to Int64");
+
+        let result = cast_array(&array, &DataType::Int64, false).unwrap();
+        assert_eq!((result.len(), result.null_count()), (3, 2));
+    }
+
+    #[test]
+    fn cast_and_try_cast_of_columns() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT, S STRING", &["1,42", "2,2020-01-02", "3,1.25"]);
+
+        let rows = query(&mut session, "SELECT ID, TRY_CAST(S AS INT), TRY_CAST(S AS DATE), TRY_CAST(S AS DECIMAL(5,2)) FROM T").unwrap();
+        let date = Date32(temporal::parse_date("2020-01-02").unwrap());
+        assert_eq!(rows, vec![vec![Int64(1), Int64(42), Null, Decimal(4200, 5, 2)], vec![Int64(2), Null, date, Null], vec![Int64(3), Null, Null, Decimal(125, 5, 2)]]);
+        assert_eq!(query(&mut session, "SELECT CAST(ID AS STRING), CAST(ID AS DOUBLE) FROM T WHERE ID = 1").unwrap(), vec![vec![Utf8("1".into()), Float64(F64::from(1.0))]]);
+
+        // CAST fails the query unless CAST_MODE is LENIENT
+        let err = query(&mut session, "SELECT ID, CAST(S AS INT) FROM T").unwrap_err();
+        assert!(matches!(&err, KonaError::Execution { message, .. } if message.contains("Cannot CAST")), "{}", err);
+        execute(&mut session, "SET CAST_MODE = 'LENIENT'").unwrap();
+        assert_eq!(query(&mut session, "SELECT ID, CAST(S AS INT) FROM T WHERE ID = 2").unwrap(), vec![vec![Int64(2), Null]]);
+    }
+
+    #[test]
+    fn literal_casts_are_folded() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT", &["1"]);
+        assert_eq!(query(&mut session, "SELECT ID, CAST('7' AS INT) + ID FROM T").unwrap(), vec![vec![Int64(1), Int64(8)]]);
+        assert!(matches!(query(&mut session, "SELECT ID, CAST('x' AS INT) FROM T"), Err(KonaError::Bind(_))));
+        assert!(matches!(query(&mut session, "SELECT CAST(ID AS BOOLEAN) FROM T"), Err(KonaError::Bind(_))));
     }
 }
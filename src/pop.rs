@@ -5,19 +5,23 @@ use std::{
     io::{self, Write},
 };
 
-use arrow2::{io::csv::write, compute::filter::filter_chunk};
+use arrow2::{array::get_display, compute::filter::filter_chunk, io::csv::write};
 
 use crate::{
+    decimal,
     expr::AggType,
     flow::Flow,
     graph::{ExprKey, Graph, POPKey},
     includes::*,
+    metrics::OpMetrics,
     pcode::PCode,
     pop_csv::CSV,
     pop_hashagg::HashAgg,
     pop_hashmatch::HashMatch,
     pop_parquet::Parquet,
+    pop_project::Project,
     pop_repartition::{RepartitionRead, RepartitionWrite},
+    pop_sort::Sort,
     stage::Stage,
 };
 
@@ -97,6 +101,8 @@ pub enum POP {
     Parquet(Parquet),
     HashMatch(HashMatch),
     HashAgg(HashAgg),
+    Project(Project),
+    Sort(Sort),
     RepartitionWrite(RepartitionWrite),
     RepartitionRead(RepartitionRead),
 }
@@ -104,7 +110,10 @@ pub enum POP {
 /***************************************************************************************************/
 pub trait POPContext {
     fn as_any_mut(&mut self) -> &mut dyn Any;
-    fn next(&mut self, flow: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, String>;
+    fn next(&mut self, flow: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, KonaError>;
+
+    // Called once the context is exhausted, to report counters only the operator itself knows about
+    fn add_metrics(&self, _metrics: &mut OpMetrics) {}
 }
 
 struct VecWriter {
@@ -148,38 +157,64 @@ pub fn chunk_to_string(chunk: &ChunkBox, header: &str) -> String {
     let mut writer = VecWriter::new();
     let options = write::SerializeOptions::default();
     writer.write_fmt(format_args!("\n---------- {} ----------\n", header)).unwrap();
-    write::write_chunk(&mut writer, chunk, &options).unwrap();
+    write::write_chunk(&mut writer, &decimal::format_chunk(chunk), &options).unwrap();
     if len > 10 {
         writer.write_fmt(format_args!("---------- [{} rows not shown] ----------", len - 10)).unwrap();
     }
     writer.into_string()
 }
 
-#[allow(unused_variables)]
-pub fn chunk_to_tabularstring(chunk: &ChunkBox, header: &str) -> String {
-    let mut writer = VecWriter::new();
-    let options = write::SerializeOptions::default();
-    write::write_chunk(&mut writer, chunk, &options).unwrap();
-    let s = writer.into_string();
-
-    let mut writer = VecWriter::new();
+// Results as a table with aligned columns, numbers to the right. Only the first max_rows rows are shown, 0 shows them all.
+pub fn chunks_to_tabularstring(schema: &Schema, chunks: &[ChunkBox], max_rows: usize) -> String {
+    let nrows = chunks.iter().map(|chunk| chunk.len()).sum::<usize>();
+    let nshown = if max_rows == 0 { nrows } else { nrows.min(max_rows) };
+
+    let mut rows: Vec<Vec<String>> = vec![schema.fields.iter().map(|field| field.name.clone()).collect()];
+    for chunk in chunks.iter() {
+        let chunk = decimal::format_chunk(chunk);
+        let displays = chunk.arrays().iter().map(|array| get_display(&**array, "NULL")).collect::<Vec<_>>();
+        for rx in 0..chunk.len().min(nshown + 1 - rows.len()) {
+            let row = displays
+                .iter()
+                .map(|display| {
+                    let mut cell = String::new();
+                    display(&mut cell, rx).unwrap();
+                    cell
+                })
+                .collect();
+            rows.push(row);
+        }
+    }
 
-    let rows = s.split('\n').collect::<Vec<_>>();
+    let is_numeric = schema
+        .fields
+        .iter()
+        .map(|field| {
+            matches!(field.data_type, DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 | DataType::Float32 | DataType::Float64 | DataType::Decimal(..))
+        })
+        .collect::<Vec<_>>();
+    let widths = (0..schema.fields.len()).map(|cx| rows.iter().map(|row| row[cx].chars().count()).max().unwrap()).collect::<Vec<_>>();
+    let border = f!("+{}+", widths.iter().map(|width| "-".repeat(width + 2)).collect::<Vec<_>>().join("+"));
+
+    let mut lines = vec![border.clone()];
     for (rx, row) in rows.iter().enumerate() {
-        let cols = row.split(',').collect::<Vec<_>>();
-        if rx == 0 || rx == rows.len() - 1 {
-            let len = rows[0].split(',').collect::<Vec<_>>().len();
-            for _ in 0..len {
-                writer.write_fmt(format_args!("{:10}", "+----------")).unwrap();
-            }
-            writer.write(b"+\n").unwrap();
-        }
-        if rx < rows.len() - 1 {
-            for col in cols.iter() {
-                writer.write_fmt(format_args!("|{:10}", col)).unwrap();
-            }
-            writer.write(b"|\n").unwrap();
+        let cells = row
+            .iter()
+            .enumerate()
+            .map(|(cx, cell)| if is_numeric[cx] && rx > 0 { format!(" {:>width$} ", cell, width = widths[cx]) } else { format!(" {:width$} ", cell, width = widths[cx]) })
+            .collect::<Vec<_>>();
+        lines.push(f!("|{}|", cells.join("|")));
+        if rx == 0 {
+            lines.push(border.clone());
         }
     }
-    writer.into_string()
+    lines.push(border);
+
+    let rows_label = if nrows == 1 { "row" } else { "rows" };
+    if nshown < nrows {
+        lines.push(f!("({nrows} {rows_label}, {nshown} shown)"));
+    } else {
+        lines.push(f!("({nrows} {rows_label})"));
+    }
+    lines.join("\n")
 }
@@ -15,13 +15,15 @@ use crate::{
     pop_csv::CSV,
     pop_hashagg, pop_hashmatch,
     pop_parquet::Parquet,
+    pop_project::Project,
     pop_repartition,
+    pop_sort::Sort,
     qgm::QGM,
     stage::{StageGraph, StageLink},
 };
 
 impl POP {
-    pub fn compile_flow(env: &Env, qgm: &mut QGM, lop_graph: &LOPGraph, lop_key: LOPKey) -> Result<Flow, String> {
+    pub fn compile_flow(env: &Env, qgm: &mut QGM, lop_graph: &LOPGraph, lop_key: LOPKey) -> Result<Flow, KonaError> {
         // Build physical plan
         let mut stage_graph = StageGraph::default();
 
@@ -33,19 +35,24 @@ impl POP {
         stage_graph.print();
 
         let plan_pathname = format!("{}/{}", env.output_dir, "pop.dot");
-        QGM::write_physical_plan_to_graphviz(qgm, &stage_graph, &plan_pathname)?;
-
-        // Get schema
-        let schema = lop_key.get_schema(qgm, lop_graph);
+        QGM::write_physical_plan_to_graphviz(qgm, &stage_graph, &plan_pathname).map_err(KonaError::Plan)?;
+        graphviz::render(env, &plan_pathname).map_err(KonaError::Plan)?;
+
+        // Get schema. Result columns are named after the select list.
+        let mut schema = lop_key.get_schema(qgm, lop_graph);
+        let names = qgm.output_column_names();
+        if names.len() == schema.fields.len() {
+            schema.fields.iter_mut().zip(names).for_each(|(field, name)| field.name = name);
+        }
 
         // Build flow (POPs + Stages)
-        let flow = Flow { id: env.id, stage_graph, schema };
+        let flow = Flow { id: env.id, stage_graph, schema, sink: None, collect_stats: false, return_chunks: false, settings: env.settings.flow_settings() };
 
         Ok(flow)
     }
 
     #[tracing::instrument(fields(lop = %lop_key), skip_all, parent = None)]
-    pub fn compile_lop(qgm: &mut QGM, lop_graph: &LOPGraph, lop_key: LOPKey, stage_graph: &mut StageGraph, stage_id: StageId) -> Result<POPKey, String> {
+    pub fn compile_lop(qgm: &mut QGM, lop_graph: &LOPGraph, lop_key: LOPKey, stage_graph: &mut StageGraph, stage_id: StageId) -> Result<POPKey, KonaError> {
         let (lop, _, lop_children) = lop_graph.get3(lop_key);
 
         // Do we have a new stage?
@@ -78,6 +85,8 @@ impl POP {
                 Self::compile_repartition_write(qgm, lop_graph, lop_key, stage_graph, stage_link.unwrap(), pop_children, schema.clone().unwrap(), *cpartitions)?
             }
             LOP::Aggregation { .. } => Self::compile_aggregation(qgm, lop_graph, lop_key, stage_graph, effective_stage_id, pop_children)?,
+            LOP::DerivedTable => Self::compile_derived_table(qgm, lop_graph, lop_key, stage_graph, effective_stage_id, pop_children)?,
+            LOP::Sort { .. } => Self::compile_sort(qgm, lop_graph, lop_key, stage_graph, effective_stage_id, pop_children)?,
         };
 
         debug!("[{:?}] compiled to {:?} in stage {}", lop_key, pop_key, effective_stage_id);
@@ -97,7 +106,7 @@ impl POP {
     }
 
     #[tracing::instrument(fields(lop = lop_key.to_string()), skip_all, parent = None)]
-    pub fn compile_scan(qgm: &mut QGM, lop_graph: &LOPGraph, lop_key: LOPKey, stage_graph: &mut StageGraph, stage_id: StageId) -> Result<POPKey, String> {
+    pub fn compile_scan(qgm: &mut QGM, lop_graph: &LOPGraph, lop_key: LOPKey, stage_graph: &mut StageGraph, stage_id: StageId) -> Result<POPKey, KonaError> {
         let (lop, lopprops, ..) = lop_graph.get3(lop_key);
 
         let qunid = lopprops.quns.elements()[0];
@@ -109,7 +118,7 @@ impl POP {
             let input_projection = input_projection.elements().iter().map(|&quncol| quncol.1).collect::<Vec<ColId>>();
             (input_projection, proj_map)
         } else {
-            return Err(String::from("Internal error: compile_scan() received a POP that isn't a TableScan"));
+            return Err(KonaError::Plan(String::from("Internal error: compile_scan() received a POP that isn't a TableScan")));
         };
         debug!("[{:?}] input_projection: {:?}", lop_key, input_projection);
 
@@ -120,7 +129,7 @@ impl POP {
         let pop = match tbldesc.get_type() {
             TableType::CSV => {
                 let inner =
-                    CSV::new(tbldesc.pathname().clone(), tbldesc.fields().clone(), tbldesc.header(), tbldesc.separator(), lopprops.partdesc.npartitions, input_projection);
+                    CSV::new(tbldesc.pathname().clone(), tbldesc.fields().clone(), tbldesc.header(), tbldesc.separator(), lopprops.partdesc.npartitions, input_projection)?;
                 POP::CSV(inner)
             }
             TableType::Parquet => {
@@ -212,7 +221,7 @@ impl POP {
         }
     }
 
-    pub fn compile_exprs(qgm: &QGM, exprs: &Vec<ExprKey>, proj_map: &mut ProjectionMap) -> Option<Vec<PCode>> {
+    pub fn compile_exprs(qgm: &QGM, exprs: &[ExprKey], proj_map: &mut ProjectionMap) -> Option<Vec<PCode>> {
         let mut pcodevec = vec![];
         if !exprs.is_empty() {
             for expr_key in exprs.iter() {
@@ -230,7 +239,7 @@ impl POP {
     pub fn compile_repartition_write(
         qgm: &mut QGM, lop_graph: &LOPGraph, lop_key: LOPKey, stage_graph: &mut StageGraph, stage_link: StageLink, pop_children: Vec<POPKey>, schema: Rc<Schema>,
         cpartitions: usize,
-    ) -> Result<POPKey, String> {
+    ) -> Result<POPKey, KonaError> {
         let stage_id = stage_link.0;
         let (_, lopprops, children) = lop_graph.get3(lop_key);
 
@@ -247,11 +256,13 @@ impl POP {
         let (cols, virtcols) = Self::compile_projection(qgm, lop_key, lopprops, &mut proj_map);
         let props = POPProps::new(predicates, cols, virtcols, lopprops.partdesc.npartitions);
 
-        let repart_key = if let PartType::HASHEXPR(partkey) = &lopprops.partdesc.part_type {
-            debug!("Compile pkey start");
-            Self::compile_exprs(qgm, partkey, &mut proj_map).unwrap()
-        } else {
-            panic!("Invalid partitioning type")
+        let repart_key = match &lopprops.partdesc.part_type {
+            PartType::HASHEXPR(partkey) => {
+                debug!("Compile pkey start");
+                Self::compile_exprs(qgm, partkey, &mut proj_map).unwrap()
+            }
+            PartType::BROADCAST => vec![],
+            PartType::RAW => panic!("Invalid partitioning type"),
         };
         debug!("Compile pkey end");
 
@@ -269,7 +280,7 @@ impl POP {
     #[tracing::instrument(fields(lop = lop_key.to_string()), skip_all, parent = None)]
     pub fn compile_repartition_read(
         _qgm: &mut QGM, lop_graph: &LOPGraph, lop_key: LOPKey, stage_graph: &mut StageGraph, stage_link: StageLink, schema: Rc<Schema>, npartitions: usize,
-    ) -> Result<POPKey, String> {
+    ) -> Result<POPKey, KonaError> {
         debug!("[{:?}] compile_repartition_read: schema = {:?}", lop_key, &schema);
 
         let stage_id = stage_link.1;
@@ -300,7 +311,7 @@ impl POP {
     #[tracing::instrument(fields(lop = lop_key.to_string()), skip_all, parent = None)]
     pub fn compile_join(
         qgm: &mut QGM, lop_graph: &LOPGraph, lop_key: LOPKey, stage_graph: &mut StageGraph, stage_id: StageId, pop_children: Vec<POPKey>,
-    ) -> Result<POPKey, String> {
+    ) -> Result<POPKey, KonaError> {
         let (lop, lopprops, children) = lop_graph.get3(lop_key);
         if let LOP::HashJoin { lhs_join_keys, rhs_join_keys } = lop {
             let keyexprs = [lhs_join_keys, rhs_join_keys];
@@ -365,7 +376,7 @@ impl POP {
     #[tracing::instrument(fields(lop = lop_key.to_string()), skip_all, parent = None)]
     pub fn compile_aggregation(
         qgm: &mut QGM, lop_graph: &LOPGraph, lop_key: LOPKey, stage_graph: &mut StageGraph, stage_id: StageId, pop_children: Vec<POPKey>,
-    ) -> Result<POPKey, String> {
+    ) -> Result<POPKey, KonaError> {
         let (lop, lopprops, children) = lop_graph.get3(lop_key);
         if let LOP::Aggregation { key_len } = lop {
             let qunid = lopprops.quns.elements()[0];
@@ -404,6 +415,69 @@ impl POP {
         }
     }
 
+    #[tracing::instrument(fields(lop = lop_key.to_string()), skip_all, parent = None)]
+    pub fn compile_derived_table(
+        qgm: &mut QGM, lop_graph: &LOPGraph, lop_key: LOPKey, stage_graph: &mut StageGraph, stage_id: StageId, pop_children: Vec<POPKey>,
+    ) -> Result<POPKey, KonaError> {
+        let (_, lopprops, children) = lop_graph.get3(lop_key);
+        let qunid = lopprops.quns.elements()[0];
+
+        // The child only produces its select-list, so column #n of the derived table is the child's column #n
+        let child_lop_key = children.unwrap()[0];
+        let child_lopprops = lop_graph.get_properties(child_lop_key);
+        let ncols = child_lopprops.virtcols.as_ref().map_or(0, |virtcols| virtcols.len());
+        let mut proj_map = ProjectionMap::default();
+        for colid in 0..ncols {
+            proj_map.set(Projection::QunCol(QunCol(qunid, colid)), colid);
+        }
+
+        let (cols, virtcols) = Self::compile_projection(qgm, lop_key, lopprops, &mut proj_map);
+        let predicates = Self::compile_predicates(qgm, &lopprops.preds, &mut proj_map);
+        debug!("[{:?}] predicates {:?}", lop_key, predicates);
+
+        let props = POPProps::new(predicates, cols, virtcols, lopprops.partdesc.npartitions);
+
+        let pop_graph = &mut stage_graph.stages[stage_id].pop_graph;
+        let pop_key = pop_graph.add_node_with_props(POP::Project(Project::new(qunid)), props, Some(pop_children));
+
+        debug!("[{:?}] end compile_derived_table", lop_key);
+
+        Ok(pop_key)
+    }
+
+    #[tracing::instrument(fields(lop = lop_key.to_string()), skip_all, parent = None)]
+    pub fn compile_sort(
+        qgm: &mut QGM, lop_graph: &LOPGraph, lop_key: LOPKey, stage_graph: &mut StageGraph, stage_id: StageId, pop_children: Vec<POPKey>,
+    ) -> Result<POPKey, KonaError> {
+        let (lop, lopprops, children) = lop_graph.get3(lop_key);
+        if let LOP::Sort { sort_keys } = lop {
+            // The child computes the sort keys along with the select-list
+            let child_lopprops = lop_graph.get_properties(children.unwrap()[0]);
+            let mut proj_map = Self::compute_projection_map(&child_lopprops.cols, child_lopprops.virtcols.as_ref());
+
+            let (cols, virtcols) = Self::compile_projection(qgm, lop_key, lopprops, &mut proj_map);
+            let props = POPProps::new(None, cols, virtcols, lopprops.partdesc.npartitions);
+
+            let sort_keys = sort_keys
+                .iter()
+                .map(|&(expr_key, ordering)| {
+                    let mut pcode = PCode::default();
+                    expr_key.compile(&qgm.expr_graph, &mut pcode, &mut proj_map);
+                    (pcode, ordering)
+                })
+                .collect::<Vec<_>>();
+
+            let pop_graph = &mut stage_graph.stages[stage_id].pop_graph;
+            let pop_key = pop_graph.add_node_with_props(POP::Sort(Sort::new(sort_keys)), props, Some(pop_children));
+
+            debug!("[{:?}] end compile_sort", lop_key);
+
+            Ok(pop_key)
+        } else {
+            panic!("Bad LOP")
+        }
+    }
+
     pub fn compute_initial_agg_projection_map(qunid: QunId, key_len: usize) -> ProjectionMap {
         let mut proj_map = ProjectionMap::default();
         for colid in 0..key_len {
@@ -5,18 +5,24 @@ use std::{
     io::{prelude::*, BufReader, SeekFrom},
 };
 
-use arrow2::io::csv::{
-    read,
-    read::{ByteRecord, Reader, ReaderBuilder},
+use arrow2::{
+    datatypes::TimeUnit,
+    io::csv::{
+        read,
+        read::{ByteRecord, Reader, ReaderBuilder},
+    },
 };
 use csv::Position;
 
 use crate::{
+    decimal,
     flow::Flow,
     graph::POPKey,
     includes::*,
+    metrics::OpMetrics,
     pop::{chunk_to_string, POPContext},
     stage::Stage,
+    temporal,
 };
 
 /***************************************************************************************************/
@@ -29,37 +35,40 @@ pub struct CSVContext {
     rows: Vec<ByteRecord>,
     partition_id: PartitionId,
     partition: TextFilePartition,
+    rows_read: usize,
+    chunks_read: usize,
 }
 
 impl CSVContext {
-    pub fn try_new(pop_key: POPKey, csv: &CSV, partition_id: PartitionId) -> Result<Box<dyn POPContext>, String> {
+    pub fn try_new(pop_key: POPKey, csv: &CSV, partition_id: PartitionId, chunk_size: usize) -> Result<Box<dyn POPContext>, KonaError> {
         let has_headers = if partition_id == 0 { csv.header } else { false };
         let partition = csv.partitions[partition_id];
 
         let mut reader =
-            ReaderBuilder::new().has_headers(has_headers).delimiter(csv.separator as u8).from_path(&csv.pathname).map_err(|err| stringify1(err, &csv.pathname))?;
+            ReaderBuilder::new().has_headers(has_headers).delimiter(csv.separator as u8).from_path(&csv.pathname).map_err(|err| KonaError::io(&csv.pathname, err.into()))?;
 
         // Position iterator to beginning of partition
         if partition_id > 0 {
             let mut pos = Position::new();
             pos.set_byte(partition.0);
-            reader.seek(pos).map_err(stringify)?;
+            reader.seek(pos).map_err(|err| KonaError::io(&csv.pathname, err.into()))?;
         }
 
-        let rows = vec![ByteRecord::default(); CHUNK_SIZE];
+        let rows = vec![ByteRecord::default(); chunk_size];
 
-        let csvctx = CSVContext { pop_key, fields: csv.fields.clone(), projection: csv.input_projection.clone(), reader, rows, partition_id, partition };
+        let csvctx =
+            CSVContext { pop_key, fields: csv.fields.clone(), projection: csv.input_projection.clone(), reader, rows, partition_id, partition, rows_read: 0, chunks_read: 0 };
 
         Ok(Box::new(csvctx))
     }
 
-    pub fn read_rows(&mut self) -> Result<usize, String> {
+    pub fn read_rows(&mut self) -> Result<usize, KonaError> {
         let reader = &mut self.reader;
         let rows = &mut self.rows;
 
         let mut row_number = 0;
         for row in rows.iter_mut() {
-            let has_more = reader.read_byte_record(row).map_err(|e| (format!(" at line {}", row_number), Box::new(e))).map_err(stringify)?;
+            let has_more = reader.read_byte_record(row).map_err(|err| KonaError::io(f!("Cannot read CSV row {row_number}"), err.into()))?;
             let pos = reader.position();
             if pos.byte() > self.partition.1 {
                 break;
@@ -72,11 +81,47 @@ impl CSVContext {
         Ok(row_number)
     }
 
-    fn next0(&mut self) -> Result<ChunkBox, String> {
-        let rows_read = self.read_rows().map_err(stringify)?;
+    fn next0(&mut self) -> Result<ChunkBox, KonaError> {
+        let rows_read = self.read_rows()?;
 
+        if rows_read > 0 {
+            self.rows_read += rows_read;
+            self.chunks_read += 1;
+        }
         let rows = &self.rows[..rows_read];
-        read::deserialize_batch(rows, &self.fields, Some(&self.projection), 0, read::deserialize_column).map_err(stringify)
+        let chunk =
+            read::deserialize_batch(rows, &self.fields, Some(&self.projection), 0, deserialize_column).map_err(|err| KonaError::arrow("Cannot decode CSV rows", err))?;
+
+        // Empty or unparseable values come back as NULLs, which NOT NULL columns don't allow
+        for (&colid, array) in self.projection.iter().zip(chunk.arrays()) {
+            let field = &self.fields[colid];
+            if !field.is_nullable && array.null_count() > 0 {
+                return Err(KonaError::execution(f!("Column {} is declared NOT NULL but has missing or invalid values", field.name)));
+            }
+        }
+        Ok(chunk)
+    }
+}
+
+// Arrow only accepts `T`-separated naive timestamps and mis-scales decimals with fewer fractional digits than the
+// column's scale (1.5 is read as 0.15 in a DECIMAL(p,2)), so handle those here and defer everything else
+fn deserialize_column(rows: &[ByteRecord], column: usize, datatype: DataType, line_number: usize) -> A2Result<Box<dyn Array>> {
+    match datatype {
+        DataType::Timestamp(TimeUnit::Microsecond, None) => {
+            let iter = rows.iter().map(|row| {
+                let value = row.get(column).and_then(|bytes| std::str::from_utf8(bytes).ok());
+                value.and_then(|s| temporal::parse_timestamp(s).ok()).map(|(ts, _)| ts)
+            });
+            Ok(Box::new(PrimitiveArray::<i64>::from_trusted_len_iter(iter).to(datatype)))
+        }
+        DataType::Decimal(precision, scale) => {
+            let iter = rows.iter().map(|row| {
+                let value = row.get(column).and_then(|bytes| std::str::from_utf8(bytes).ok());
+                value.and_then(|s| decimal::parse_decimal_as(s, precision, scale).ok())
+            });
+            Ok(Box::new(PrimitiveArray::<i128>::from_trusted_len_iter(iter).to(datatype)))
+        }
+        _ => read::deserialize_column(rows, column, datatype, line_number),
     }
 }
 
@@ -85,7 +130,7 @@ impl POPContext for CSVContext {
         self
     }
 
-    fn next(&mut self, _: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, String> {
+    fn next(&mut self, _: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, KonaError> {
         let pop_key = self.pop_key;
         let props = stage.pop_graph.get_properties(pop_key);
 
@@ -95,10 +140,10 @@ impl POPContext for CSVContext {
 
         if !chunk.is_empty() {
             // Run predicates and virtcols, if any
-            chunk = POPKey::eval_predicates(props, chunk);
+            chunk = POPKey::eval_predicates(props, chunk)?;
             //debug!("After preds: \n{}", chunk_to_string(&chunk, "After preds"));
 
-            let projection_chunk = POPKey::eval_projection(props, &chunk);
+            let projection_chunk = POPKey::eval_projection(props, &chunk)?;
             let headerstr = format!("CSVContext::next Stage = {}, {:?}, Partition = {}", stage.stage_id, pop_key, self.partition_id);
             debug!("{}", chunk_to_string(&projection_chunk, &headerstr));
             Ok(Some(projection_chunk))
@@ -106,6 +151,11 @@ impl POPContext for CSVContext {
             Ok(None)
         }
     }
+
+    fn add_metrics(&self, metrics: &mut OpMetrics) {
+        metrics.rows_in = self.rows_read;
+        metrics.chunks_in = self.chunks_read;
+    }
 }
 
 /***************************************************************************************************/
@@ -120,17 +170,17 @@ pub struct CSV {
 }
 
 impl CSV {
-    pub fn new(pathname: String, fields: Vec<Field>, header: bool, separator: char, npartitions: usize, input_projection: Vec<ColId>) -> CSV {
-        let partitions = Self::compute_partitions(&pathname, npartitions as u64).unwrap();
+    pub fn new(pathname: String, fields: Vec<Field>, header: bool, separator: char, npartitions: usize, input_projection: Vec<ColId>) -> Result<CSV, KonaError> {
+        let partitions = Self::compute_partitions(&pathname, npartitions as u64)?;
 
-        CSV { pathname, fields, header, separator, partitions, input_projection }
+        Ok(CSV { pathname, fields, header, separator, partitions, input_projection })
     }
 
-    fn compute_partitions(pathname: &str, nsplits: u64) -> Result<Vec<TextFilePartition>, String> {
-        let f = fs::File::open(pathname).map_err(|err| stringify1(err, pathname))?;
+    fn compute_partitions(pathname: &str, nsplits: u64) -> Result<Vec<TextFilePartition>, KonaError> {
+        let f = fs::File::open(pathname).map_err(|err| KonaError::io(pathname, err))?;
         let mut reader = BufReader::new(f);
 
-        let metadata = fs::metadata(pathname).map_err(|err| stringify1(err, pathname))?;
+        let metadata = fs::metadata(pathname).map_err(|err| KonaError::io(pathname, err))?;
         let sz = metadata.len();
         let blk_size = sz / nsplits;
 
@@ -145,9 +195,9 @@ impl CSV {
             if end > sz {
                 end = sz;
             } else {
-                reader.seek(SeekFrom::Start(end)).map_err(|err| stringify1(err, pathname))?;
+                reader.seek(SeekFrom::Start(end)).map_err(|err| KonaError::io(pathname, err))?;
                 line.clear();
-                reader.read_line(&mut line).map_err(|err| stringify1(err, pathname))?;
+                reader.read_line(&mut line).map_err(|err| KonaError::io(pathname, err))?;
                 end += line.len() as u64;
             }
             splits.push(TextFilePartition(begin, end));
@@ -166,7 +216,7 @@ impl CSV {
 
 impl fmt::Debug for CSV {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let pathname = self.pathname.split('/').last().unwrap();
+        let pathname = self.pathname.split('/').next_back().unwrap();
         fmt.debug_struct("").field("file", &pathname).finish()
     }
 }
@@ -1,9 +1,8 @@
 // pop_hash.rs
 
-use ahash::RandomState;
-use arrow2::{array::Utf8Array, datatypes::PhysicalType, types::PrimitiveType};
+use arrow2::compute::{arity::unary, hash};
 
-use crate::includes::*;
+use crate::{flow::Flow, includes::*};
 
 pub type SplitId = usize;
 pub type HashValue = u64;
@@ -12,33 +11,42 @@ pub type ProbeRowId = usize;
 pub type MatchRIDPair = (ProbeRowId, Option<(SplitId, BuildRowId)>);
 pub type MatchRIDList = Vec<MatchRIDPair>;
 
-pub const NSPLITS: usize = 1;
+const NULL_HASH: HashValue = 0x9e37_79b9_7f4a_7c15;
 
-macro_rules! hash_array {
-    ($array_type:ty,$array:expr,$state:expr,$hash_array:expr) => {{
-        let array_inner: &$array_type = $array.as_any().downcast_ref().unwrap();
-        for (ix, elem) in array_inner.values_iter().enumerate() {
-            let hv = $state.hash_one(elem);
-            $hash_array[ix] += hv;
-            $hash_array[ix] = 999;
+// Hash of each row over all of the chunk's columns. NULLs hash alike, so rows with NULL keys land together; whether
+// they match is up to the caller.
+pub fn hash_rows(chunk: &ChunkBox) -> Result<Vec<HashValue>, KonaError> {
+    let mut hash_array: Vec<HashValue> = vec![0; chunk.len()];
+    for array in chunk.arrays() {
+        let hashes = match array.data_type() {
+            // 0.0 and -0.0 are equal, so they need the same hash
+            DataType::Float64 => {
+                let array = array.as_any().downcast_ref::<PrimitiveArray<f64>>().unwrap();
+                hash::hash_primitive(&unary(array, |value| if value == 0.0 { 0 } else { value.to_bits() }, DataType::UInt64))
+            }
+            _ => hash::hash(&**array).map_err(|err| KonaError::arrow("Cannot hash keys", err))?,
+        };
+        for (hash_value, hash) in hash_array.iter_mut().zip(hashes.iter()) {
+            *hash_value = hash_value.rotate_left(5) ^ hash.copied().unwrap_or(NULL_HASH);
         }
-    }};
+    }
+    Ok(hash_array)
 }
 
-pub fn hash_chunk(chunk: &ChunkBox, state: &RandomState) -> (Vec<HashValue>, Vec<SplitId>) {
-    // Initialize hash array
-    let mut hash_array = vec![0; chunk.len()];
+pub fn hash_chunk(chunk: &ChunkBox, nsplits: usize) -> Result<(Vec<HashValue>, Vec<SplitId>), KonaError> {
+    let hash_array = hash_rows(chunk)?;
+    let split_ids = hash_array.iter().map(|&e| e as usize % nsplits).collect::<Vec<_>>();
+    Ok((hash_array, split_ids))
+}
 
-    for array in chunk.arrays() {
-        match array.data_type().to_physical_type() {
-            PhysicalType::Primitive(PrimitiveType::Int64) => hash_array!(PrimitiveArray<i64>, array, state, &mut hash_array),
-            PhysicalType::Utf8 => hash_array!(Utf8Array<i32>, array, state, &mut hash_array),
-            PhysicalType::Boolean => hash_array!(BooleanArray, array, state, &mut hash_array),
-            t => panic!("Hash not implemented for type: {:?}", t),
-        }
+// Hash tables may not grow past MEMORY_LIMIT
+pub fn check_memory_limit(flow: &Flow, operator: &str, nbytes: usize) -> Result<(), KonaError> {
+    let limit = flow.settings.memory_limit;
+    if limit > 0 && nbytes > limit {
+        Err(KonaError::execution(f!("{operator} needs more than MEMORY_LIMIT = {limit} bytes for its hash table")))
+    } else {
+        Ok(())
     }
-    let split_ids = hash_array.iter().map(|&e| e as usize % NSPLITS).collect::<Vec<_>>();
-    (hash_array, split_ids)
 }
 
 pub fn eval_cols(cols: &[ColId], input: &ChunkBox) -> ChunkBox {
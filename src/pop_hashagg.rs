@@ -2,26 +2,24 @@
 
 #![allow(warnings)]
 
-use std::{
-    collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
-};
+use std::{cmp::Ordering, hash::Hash};
 
 use arrow2::{
     array::{MutableArray, MutableBooleanArray, MutablePrimitiveArray, MutableUtf8Array, Utf8Array},
     datatypes::PhysicalType,
     types::PrimitiveType,
 };
-use fasthash::xx;
 
 use crate::{
     datum::F64,
+    decimal,
     expr::AggType,
     flow::Flow,
     graph::POPKey,
     includes::*,
+    metrics::OpMetrics,
     pop::{chunk_to_string, Agg, POPContext, POP},
-    pop_hash::NSPLITS,
+    pop_hash::{check_memory_limit, hash_rows},
     stage::Stage,
     Datum,
 };
@@ -76,19 +74,19 @@ pub struct HashAggContext {
 
 impl HashAggContext {
     #[tracing::instrument(fields(pop_key), skip_all)]
-    pub fn try_new(pop_key: POPKey, _: &HashAgg, children: Vec<Box<dyn POPContext>>, partition_id: PartitionId) -> Result<Box<dyn POPContext>, String> {
+    pub fn try_new(pop_key: POPKey, _: &HashAgg, children: Vec<Box<dyn POPContext>>, partition_id: PartitionId) -> Result<Box<dyn POPContext>, KonaError> {
         let ctxt = HashAggContext { pop_key, children, partition_id, splits: vec![], output_split: 0 };
         debug!("{:?}", ctxt);
         Ok(Box::new(ctxt))
     }
 
     //#[tracing::instrument(fields(), skip_all, parent = None)]
-    fn next_agg(&mut self, flow: &Flow, stage: &Stage, hash_agg: &HashAgg) -> Result<Option<ChunkBox>, String> {
+    fn next_agg(&mut self, flow: &Flow, stage: &Stage, hash_agg: &HashAgg) -> Result<Option<ChunkBox>, KonaError> {
         // Initialize hash-tables
         if self.splits.is_empty() {
             // Initialize splits
             if self.splits.is_empty() {
-                self.splits = (0..NSPLITS).map(|_| HashAggSplit::new()).collect();
+                self.splits = (0..flow.settings.nsplits).map(|_| HashAggSplit::new()).collect();
             }
         }
 
@@ -96,169 +94,45 @@ impl HashAggContext {
         while let Some(chunk) = self.children[0].next(flow, stage)? {
             if !chunk.is_empty() {
                 self.upsert(hash_agg, chunk)?;
+                check_memory_limit(flow, "HashAgg", self.hash_table_bytes(hash_agg))?;
             }
         }
 
         self.contruct_internal_output(stage, hash_agg)
     }
 
-    fn hash_chunk(chunk: &ChunkBox, hash_agg: &HashAgg) -> Vec<u64> {
-        let mut hasharr = vec![0u64; chunk.len()];
-        let keylen = hash_agg.keycols[0].len();
-
-        for array in chunk.arrays().iter().take(keylen) {
-            match array.data_type().to_physical_type() {
-                PhysicalType::Primitive(PrimitiveType::Int64) => {
-                    let primarr = array.as_any().downcast_ref::<PrimitiveArray<i64>>().unwrap();
-                    hasharr.iter_mut().zip(primarr.iter()).for_each(|(hashval, newval)| *hashval = *hashval ^ *newval.unwrap() as u64);
-                }
-                PhysicalType::Utf8 => {
-                    let primarr = array.as_any().downcast_ref::<Utf8Array<i32>>().unwrap();
-                    hasharr.iter_mut().zip(primarr.iter()).for_each(|(hashval, newval)| *hashval = *hashval ^ xx::hash64(newval.unwrap()));
-                }
-                _ => todo!(),
-            }
-        }
-        hasharr
+    // Each entry holds a row of keys and a row of accumulators
+    fn hash_table_bytes(&self, hash_agg: &HashAgg) -> usize {
+        let nentries = self.splits.iter().map(|split| split.hash_map.len()).sum::<usize>();
+        nentries * (hash_agg.keylen() + hash_agg.aggs.len()) * std::mem::size_of::<Option<Datum>>()
     }
 
     //#[tracing::instrument(fields(key, value), skip_all, parent = None)]
-    fn upsert(&mut self, hash_agg: &HashAgg, chunk: ChunkBox) -> Result<(), String> {
-        let keycols = &hash_agg.keycols[0];
+    fn upsert(&mut self, hash_agg: &HashAgg, chunk: ChunkBox) -> Result<(), KonaError> {
         let keylen = hash_agg.keylen();
-        let hash_arr = Self::hash_chunk(&chunk, hash_agg);
-        let split_arr = hash_arr.iter().map(|&hash_value| hash_value as usize % NSPLITS).collect::<Vec<_>>();
+        let hash_arr = hash_rows(&Chunk::new(chunk.arrays()[..keylen].to_vec()))?;
 
-        for ix in 0..chunk.len() {
-            let hash_value = hash_arr[ix];
-            let split_id = split_arr[ix];
+        for (ix, &hash_value) in hash_arr.iter().enumerate() {
+            let split_id = hash_value as usize % self.splits.len();
             let split = &mut self.splits[split_id];
 
+            // The low bits pick the split, so buckets within a split are picked by the high bits
             let cmp_key = |key: &DataRow| -> bool { compare_key(&chunk, ix, key) };
-            let gen_key = || -> DataRow { build_key(&chunk, keylen, ix) };
+            let gen_entry = || -> Result<(DataRow, DataRow), KonaError> { Ok((build_key(&chunk, keylen, ix)?, Self::init_accumulators(hash_agg))) };
+            let entry = split.hash_map.find(hash_value.rotate_left(32) as usize, cmp_key, gen_entry)?;
 
-            // PERF TODO: Only allocate key if it's not in hash table
-            let entry = split.hash_map.find(hash_value as usize, cmp_key, gen_key, || Self::init_accumulators(hash_agg, &chunk, ix));
-            let do_init = matches!(entry, MyEntry::Inserted(_));
-            if do_init {
-                continue;
-            }
-
-            let bucket = entry.bucket();
-            let mut key_value = split.hash_map.key_value_mut(bucket);
-            let (key, accumulators) = &mut key_value.as_mut().unwrap();
-
-            //for (accnum, &(Agg { agg_type, input_colid, .. }, _)) in hash_agg.aggs.iter().enumerate() {
+            let (_, accumulators) = split.hash_map.key_value_mut(entry.bucket()).unwrap();
             for (acc, &(Agg { agg_type, input_colid, .. }, _)) in accumulators.iter_mut().zip(hash_agg.aggs.iter()) {
-                let array = &chunk.arrays()[input_colid].as_any();
-                let input_type = &hash_agg.child_physical_types[input_colid];
-                match (agg_type, input_type) {
-                    (AggType::COUNT, _) => {
-                        acc.as_mut().unwrap().add_i64(1);
-                    }
-                    (AggType::SUM, PhysicalType::Primitive(PrimitiveType::Int64)) => {
-                        let primarr = array.downcast_ref::<PrimitiveArray<i64>>().unwrap();
-                        let cur_value = primarr.get(ix).unwrap();
-                        let old_sum = acc.as_ref().map_or(0, |e| e.try_as_i64().unwrap());
-                        *acc = Some(Int64(old_sum + cur_value));
-                    }
-                    (AggType::SUM, PhysicalType::Primitive(PrimitiveType::Float64)) => {
-                        let primarr = array.downcast_ref::<PrimitiveArray<f64>>().unwrap();
-                        let cur_value = primarr.get(ix).unwrap();
-                        acc.as_mut().unwrap().add_f64(cur_value);
-                    }
-                    (AggType::MAX | AggType::MIN, PhysicalType::Primitive(PrimitiveType::Int64)) => {
-                        let primarr = array.downcast_ref::<PrimitiveArray<i64>>().unwrap();
-                        let cur_datum = primarr.get(ix).map(|ival| Int64(ival));
-                        if let Some(Int64(cur_int)) = cur_datum {
-                            if let Some(Int64(acc_int)) = acc {
-                                if (agg_type == AggType::MAX) && (cur_int > *acc_int) {
-                                    *acc = cur_datum;
-                                } else if (agg_type == AggType::MIN) && (cur_int < *acc_int) {
-                                    *acc = cur_datum;
-                                }
-                            } else {
-                                // Accumulator is NULL
-                                todo!("What to do here?")
-                            }
-                        } else {
-                            // Current value is NULL
-                            todo!("What to do here?")
-                        }
-                    }
-                    (AggType::MAX | AggType::MIN, PhysicalType::Utf8) => {
-                        let primarr = array.downcast_ref::<Utf8Array<i32>>().unwrap();
-                        let cur_datum = primarr.get(ix).map(|sval| Utf8(sval.to_string()));
-                        if let Some(Utf8(cur_str)) = cur_datum.as_ref() {
-                            if let Some(Utf8(acc_str)) = acc {
-                                let cur_str = cur_str.as_str();
-                                let acc_str = acc_str.as_str();
-                                if (agg_type == AggType::MAX) && (cur_str > acc_str) {
-                                    *acc = cur_datum;
-                                } else if (agg_type == AggType::MIN) && (cur_str < acc_str) {
-                                    *acc = cur_datum;
-                                }
-                            } else {
-                                // Accumulator is NULL
-                                todo!("What to do here?")
-                            }
-                        } else {
-                            // Current value is NULL
-                            todo!("What to do here?")
-                        }
-                    }
-                    _ => panic!("HashAggContext::insert(): Combination of {:?} not yet supported", (agg_type, input_type)),
-                }
+                accumulate(agg_type, acc, &*chunk.arrays()[input_colid], ix)?;
             }
         }
 
         Ok(())
     }
 
-    fn init_accumulators(hash_agg: &HashAgg, chunk: &ChunkBox, ix: usize) -> DataRow {
-        let mut accumulators = vec![];
-        for &(Agg { agg_type, input_colid, .. }, _) in hash_agg.aggs.iter() {
-            let array = &chunk.arrays()[input_colid].as_any();
-            let input_type = &hash_agg.child_physical_types[input_colid];
-
-            match (agg_type, input_type) {
-                (AggType::COUNT, _) => {
-                    accumulators.push(Some(Int64(1)));
-                }
-                (AggType::SUM, PhysicalType::Primitive(PrimitiveType::Int64)) => {
-                    let primarr = array.downcast_ref::<PrimitiveArray<i64>>().unwrap();
-                    let cur_value = primarr.get(ix).unwrap();
-                    accumulators.push(Some(Int64(cur_value)));
-                }
-                (AggType::SUM, PhysicalType::Primitive(PrimitiveType::Float64)) => {
-                    let primarr = array.downcast_ref::<PrimitiveArray<f64>>().unwrap();
-                    let cur_value = primarr.get(ix).unwrap();
-                    accumulators.push(Some(Float64(F64::from(cur_value))));
-                }
-                (AggType::MAX | AggType::MIN, PhysicalType::Primitive(PrimitiveType::Int64)) => {
-                    let primarr = array.downcast_ref::<PrimitiveArray<i64>>().unwrap();
-                    let cur_datum = primarr.get(ix).map(|ival| Int64(ival));
-                    if cur_datum.is_some() {
-                        accumulators.push(cur_datum);
-                    } else {
-                        // Current value is NULL
-                        accumulators.push(None);
-                    }
-                }
-                (AggType::MAX | AggType::MIN, PhysicalType::Utf8) => {
-                    let primarr = array.downcast_ref::<Utf8Array<i32>>().unwrap();
-                    let cur_datum = primarr.get(ix).map(|sval| Utf8(sval.to_string()));
-                    if cur_datum.is_some() {
-                        accumulators.push(cur_datum);
-                    } else {
-                        // Current value is NULL
-                        accumulators.push(None);
-                    }
-                }
-                _ => panic!("HashAggContext::init_accumulators(): Combination of {:?} not yet supported", (agg_type, input_type)),
-            }
-        }
-        accumulators
+    // COUNT starts at zero; every other aggregate is NULL until it sees a non-NULL value
+    fn init_accumulators(hash_agg: &HashAgg) -> DataRow {
+        hash_agg.aggs.iter().map(|(agg, _)| if agg.agg_type == AggType::COUNT { Some(Int64(0)) } else { None }).collect()
     }
 
     fn init_mutable_array(data_type: &DataType, len: usize) -> Box<dyn MutableArray> {
@@ -266,9 +140,11 @@ impl HashAggContext {
             DataType::Int32 => Box::new(MutablePrimitiveArray::<i32>::with_capacity(len)),
             DataType::Date32 => Box::new(MutablePrimitiveArray::<i32>::with_capacity(len).to(DataType::Date32)),
             DataType::Int64 => Box::new(MutablePrimitiveArray::<i64>::with_capacity(len)),
+            DataType::Timestamp(..) => Box::new(MutablePrimitiveArray::<i64>::with_capacity(len).to(data_type.clone())),
             DataType::Utf8 => Box::new(MutableUtf8Array::<i32>::with_capacity(len)),
             DataType::Boolean => Box::new(MutableBooleanArray::with_capacity(len)),
             DataType::Float64 => Box::new(MutablePrimitiveArray::<f64>::with_capacity(len)),
+            DataType::Decimal(..) => Box::new(MutablePrimitiveArray::<i128>::with_capacity(len).to(data_type.clone())),
             typ => todo!("not implemented: {:?}", typ),
         }
     }
@@ -287,12 +163,19 @@ impl HashAggContext {
                 let mutarr = mutarr.as_mut_any().downcast_mut::<MutablePrimitiveArray<f64>>().unwrap();
                 mutarr.push(datum.map(|ivalue| ivalue.try_as_f64().unwrap()));
             }
+            PhysicalType::Primitive(PrimitiveType::Int128) => {
+                let mutarr = mutarr.as_mut_any().downcast_mut::<MutablePrimitiveArray<i128>>().unwrap();
+                mutarr.push(datum.map(|ivalue| ivalue.try_as_i128().unwrap()));
+            }
             PhysicalType::Utf8 => {
                 let mutarr = mutarr.as_mut_any().downcast_mut::<MutableUtf8Array<i32>>().unwrap();
-                let a = datum.map(|ivalue| ivalue.to_string());
-                mutarr.push(a);
+                // Group keys hold the string itself, not its quoted Display form
+                mutarr.push(datum.map(|svalue| svalue.try_as_str().unwrap()));
+            }
+            PhysicalType::Boolean => {
+                let mutarr = mutarr.as_mut_any().downcast_mut::<MutableBooleanArray>().unwrap();
+                mutarr.push(datum.map(|bvalue| matches!(bvalue, Boolean(true))));
             }
-
             _ => todo!(),
         }
     }
@@ -311,10 +194,10 @@ impl HashAggContext {
                         let arr: Box<dyn Array> = Box::new(arr);
                         arr
                     }
-                    DataType::Int64 => {
+                    DataType::Int64 | DataType::Timestamp(..) => {
                         let mutarr = mutarr.as_any().downcast_ref::<MutablePrimitiveArray<i64>>().unwrap().clone();
                         let iter = mutarr.iter().map(|i| i.cloned());
-                        let arr = PrimitiveArray::<i64>::from_trusted_len_iter(iter);
+                        let arr = PrimitiveArray::<i64>::from_trusted_len_iter(iter).to(data_type.clone());
                         let arr: Box<dyn Array> = Box::new(arr);
                         arr
                     }
@@ -325,6 +208,13 @@ impl HashAggContext {
                         let arr: Box<dyn Array> = Box::new(arr);
                         arr
                     }
+                    DataType::Decimal(..) => {
+                        let mutarr = mutarr.as_any().downcast_ref::<MutablePrimitiveArray<i128>>().unwrap().clone();
+                        let iter = mutarr.iter().map(|i| i.cloned());
+                        let arr = PrimitiveArray::<i128>::from_trusted_len_iter(iter).to(data_type.clone());
+                        let arr: Box<dyn Array> = Box::new(arr);
+                        arr
+                    }
                     DataType::Utf8 => {
                         let mutarr = mutarr.as_any().downcast_ref::<MutableUtf8Array<i32>>().unwrap().clone();
                         let iter = mutarr.iter().map(|s| s.to_owned());
@@ -332,13 +222,19 @@ impl HashAggContext {
                         let arr: Box<dyn Array> = Box::new(arr);
                         arr
                     }
+                    DataType::Boolean => {
+                        let mutarr = mutarr.as_any().downcast_ref::<MutableBooleanArray>().unwrap().clone();
+                        let arr: BooleanArray = mutarr.into();
+                        let arr: Box<dyn Array> = Box::new(arr);
+                        arr
+                    }
                     _ => todo!(),
                 }
             })
             .collect()
     }
 
-    fn contruct_internal_output(&mut self, stage: &Stage, hash_agg: &HashAgg) -> Result<Option<ChunkBox>, String> {
+    fn contruct_internal_output(&mut self, stage: &Stage, hash_agg: &HashAgg) -> Result<Option<ChunkBox>, KonaError> {
         let props = stage.pop_graph.get_properties(self.pop_key);
 
         while self.output_split < self.splits.len() {
@@ -378,9 +274,9 @@ impl HashAggContext {
                 chunk_to_string(&chunk, "Aggregation internal output");
 
                 // Run predicates, if any
-                let chunk = POPKey::eval_predicates(props, chunk);
+                let chunk = POPKey::eval_predicates(props, chunk)?;
 
-                let projection_chunk = POPKey::eval_projection(props, &chunk);
+                let projection_chunk = POPKey::eval_projection(props, &chunk)?;
                 debug!("hash_agg projection: \n{}", chunk_to_string(&projection_chunk, "hash_agg projection"));
 
                 return Ok(Some(projection_chunk));
@@ -390,63 +286,78 @@ impl HashAggContext {
     }
 }
 
-fn compare_key(chunk: &ChunkBox, ix: usize, key: &DataRow) -> bool {
-    let keylen = key.len();
-    for (array, keydatum) in chunk.arrays().iter().take(keylen).zip(key.iter()) {
-        let keydatum = keydatum.as_ref();
-        let cmpstat = match array.data_type() {
-            DataType::Utf8 => {
-                let basearr = array.as_any().downcast_ref::<Utf8Array<i32>>().unwrap();
-                let e = basearr.get(ix);
-                match (&e, &keydatum) {
-                    (Some(s1), Some(Utf8(s2))) => (s1 == s2),
-                    (None, None) => true,
-                    _ => false,
+// Fold the value at `ix` into an accumulator. NULL values don't contribute to any aggregate.
+fn accumulate(agg_type: AggType, acc: &mut Option<Datum>, array: &dyn Array, ix: usize) -> Result<(), KonaError> {
+    let value = match Datum::from_array(array, ix).map_err(KonaError::execution)? {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+    match agg_type {
+        AggType::COUNT => acc.get_or_insert(Int64(0)).add_i64(1),
+        AggType::SUM => {
+            let overflow = || KonaError::execution(f!("Overflow in SUM() of {:?} values", array.data_type()));
+            let sum = match (acc.take(), value) {
+                (None, Decimal(value, _, scale)) => Decimal(value, decimal::MAX_PRECISION, scale),
+                (None, value) => value,
+                (Some(Int32(sum)), Int32(value)) => Int32(sum.checked_add(value).ok_or_else(overflow)?),
+                (Some(Int64(sum)), Int64(value)) => Int64(sum.checked_add(value).ok_or_else(overflow)?),
+                (Some(Float64(sum)), Float64(value)) => Float64(F64::from(f64::from(sum) + f64::from(value))),
+                (Some(Decimal(sum, precision, scale)), Decimal(value, ..)) => {
+                    let sum = sum.checked_add(value).filter(|&sum| decimal::fits_precision(sum, precision)).ok_or_else(overflow)?;
+                    Decimal(sum, precision, scale)
                 }
+                (Some(sum), value) => return Err(KonaError::execution(f!("SUM() cannot add {value:?} to {sum:?}"))),
+            };
+            *acc = Some(sum);
+        }
+        AggType::MIN | AggType::MAX => {
+            let replace = match acc {
+                None => true,
+                Some(cur) => {
+                    let ordering = compare_values(&value, cur);
+                    if agg_type == AggType::MIN {
+                        ordering == Ordering::Less
+                    } else {
+                        ordering == Ordering::Greater
+                    }
+                }
+            };
+            if replace {
+                *acc = Some(value);
             }
-            typ => panic!("array_to_iter(), todo: {:?}", typ),
-        };
-        if !cmpstat {
-            // Comparison failed ... bail out
-            return false;
         }
+        AggType::AVG => return Err(KonaError::execution("AVG() should have been rewritten as SUM() / COUNT()")),
     }
-    true
+    Ok(())
 }
 
-fn build_key(chunk: &ChunkBox, keylen: usize, ix: usize) -> Vec<Option<Datum>> {
-    let mut key = Vec::with_capacity(keylen);
-    for array in chunk.arrays().iter().take(keylen) {
-        match array.data_type() {
-            DataType::Date32 => {
-                let basearr = array.as_any().downcast_ref::<PrimitiveArray<i32>>().unwrap();
-                let e = basearr.get(ix).map(|e| Date32(e));
-                key.push(e)
-            }
-            DataType::Int64 => {
-                let basearr = array.as_any().downcast_ref::<PrimitiveArray<i64>>().unwrap();
-                let e = basearr.get(ix).map(|e| Int64(e));
-                key.push(e)
-            }
-            DataType::Utf8 => {
-                let basearr = array.as_any().downcast_ref::<Utf8Array<i32>>().unwrap();
-                let e = basearr.get(ix).map(|e| Utf8(e.to_string()));
-                key.push(e)
-            }
-            DataType::Boolean => {
-                let basearr = array.as_any().downcast_ref::<BooleanArray>().unwrap();
-                let e = basearr.get(ix).map(|e| Boolean(e));
-                key.push(e)
-            }
-            DataType::Float64 => {
-                let basearr = array.as_any().downcast_ref::<PrimitiveArray<f64>>().unwrap();
-                let e = basearr.get(ix).map(|e| Float64(F64::from(e)));
-                key.push(e)
-            }
-            typ => panic!("array_to_iter(), todo: {:?}", typ),
-        }
+// Datums of one column, ordered by value. Float64 datums hold raw bits, which don't order negative numbers.
+fn compare_values(lhs: &Datum, rhs: &Datum) -> Ordering {
+    match (lhs, rhs) {
+        (Float64(lhs), Float64(rhs)) => f64::from(*lhs).total_cmp(&f64::from(*rhs)),
+        _ => lhs.cmp(rhs),
     }
-    key
+}
+
+// A grouping key value. 0.0 and -0.0 are the same group.
+fn key_datum(array: &dyn Array, ix: usize) -> Result<Option<Datum>, KonaError> {
+    let datum = Datum::from_array(array, ix).map_err(|msg| KonaError::execution(f!("Cannot group by column: {msg}")))?;
+    Ok(match datum {
+        Some(Float64(value)) if f64::from(value) == 0.0 => Some(Float64(F64::from(0.0))),
+        datum => datum,
+    })
+}
+
+fn compare_key(chunk: &ChunkBox, ix: usize, key: &DataRow) -> bool {
+    chunk.arrays().iter().zip(key.iter()).all(|(array, keydatum)| match (array.data_type(), keydatum) {
+        // Avoid allocating a string for each comparison
+        (DataType::Utf8, Some(Utf8(s2))) => array.as_any().downcast_ref::<Utf8Array<i32>>().unwrap().get(ix) == Some(s2.as_str()),
+        _ => matches!(key_datum(&**array, ix), Ok(datum) if datum == *keydatum),
+    })
+}
+
+fn build_key(chunk: &ChunkBox, keylen: usize, ix: usize) -> Result<DataRow, KonaError> {
+    chunk.arrays().iter().take(keylen).map(|array| key_datum(&**array, ix)).collect()
 }
 
 impl POPContext for HashAggContext {
@@ -454,7 +365,7 @@ impl POPContext for HashAggContext {
         self
     }
 
-    fn next(&mut self, flow: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, String> {
+    fn next(&mut self, flow: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, KonaError> {
         let pop_key = self.pop_key;
         let pop = stage.pop_graph.get_value(pop_key);
 
@@ -464,15 +375,21 @@ impl POPContext for HashAggContext {
             panic!("ugh");
         }
     }
+
+    fn add_metrics(&self, metrics: &mut OpMetrics) {
+        metrics.hash_table_entries = Some(self.splits.iter().map(|split| split.hash_map.len()).sum());
+    }
 }
 
 #[derive(Debug)]
 struct MyHashTable<K, V>
 where
     K: PartialEq + Eq + Hash + Clone,
-    V: Clone, {
+    V: Clone,
+{
     noccupied: usize,
     hvec: Vec<Option<(K, V)>>,
+    hashes: Vec<usize>, // Hash of each occupied bucket's key
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -496,7 +413,7 @@ where
     V: Clone,
 {
     fn new(default_size: usize) -> Self {
-        MyHashTable { noccupied: 0, hvec: vec![None; default_size] }
+        MyHashTable { noccupied: 0, hvec: vec![None; default_size], hashes: vec![0; default_size] }
     }
 
     fn len(&self) -> usize {
@@ -513,37 +430,110 @@ where
         r
     }
 
-    fn find<C, G, D>(&mut self, hsh: usize, cmp_key: C, gen_key: G, default_v: D) -> MyEntry
+    fn find<C, G>(&mut self, hsh: usize, cmp_key: C, gen_entry: G) -> Result<MyEntry, KonaError>
     where
         C: Fn(&K) -> bool,
-        G: Fn() -> K,
-        D: Fn() -> V, {
-        // Ensure minimum 50% occupancy
-        debug_assert!(self.noccupied < self.hvec.len() / 2);
+        G: FnOnce() -> Result<(K, V), KonaError>,
+    {
+        // Keep occupancy at 50% or below
+        if (self.noccupied + 1) * 2 > self.hvec.len() {
+            self.grow();
+        }
 
-        // Compute hash bucket
+        // Linear probing
         let mut bucket = hsh % self.hvec.len();
+        while let Some(entry) = &self.hvec[bucket] {
+            if self.hashes[bucket] == hsh && cmp_key(&entry.0) {
+                return Ok(MyEntry::Found(bucket));
+            }
+            bucket = (bucket + 1) % self.hvec.len();
+        }
 
-        // Linear probing
-        loop {
-            if let Some(entry) = &mut self.hvec[bucket] {
-                if cmp_key(&entry.0) {
-                    return MyEntry::Found(bucket);
-                } else {
-                    if bucket == self.hvec.len() - 1 {
-                        // Wrap-around
-                        bucket = 0;
-                    } else {
-                        bucket += 1;
-                    }
+        // Not found ... insert
+        self.hvec[bucket] = Some(gen_entry()?);
+        self.hashes[bucket] = hsh;
+        self.noccupied += 1;
+        Ok(MyEntry::Inserted(bucket))
+    }
+
+    fn grow(&mut self) {
+        let nbuckets = self.hvec.len() * 2;
+        let hvec = std::mem::replace(&mut self.hvec, vec![None; nbuckets]);
+        let hashes = std::mem::replace(&mut self.hashes, vec![0; nbuckets]);
+        for (entry, hsh) in hvec.into_iter().zip(hashes.into_iter()) {
+            if entry.is_some() {
+                let mut bucket = hsh % nbuckets;
+                while self.hvec[bucket].is_some() {
+                    bucket = (bucket + 1) % nbuckets;
                 }
-            } else {
-                // Not found ... insert
-                let key = gen_key();
-                self.hvec[bucket] = Some((key, default_v()));
-                self.noccupied += 1;
-                return MyEntry::Inserted(bucket);
+                self.hvec[bucket] = entry;
+                self.hashes[bucket] = hsh;
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{datum::F64, includes::*, testing::*};
+
+    #[test]
+    fn group_by_each_type_and_null() {
+        let mut session = session();
+        for (ix, (typ, a, b, da, db)) in typed_values().into_iter().enumerate() {
+            let name = f!("T{ix}");
+            create_table(&mut session, &name, &f!("K {typ}, V {typ}"), &[&f!("{a},{a}"), &f!("{a},{b}"), &f!("{b},"), &f!(",{a}"), &f!(",{b}"), ","]);
+
+            let rows = query(&mut session, &f!("SELECT K, COUNT(V), MIN(V), MAX(V) FROM {name} GROUP BY K")).unwrap();
+            let mut expected = if typ == "STRING" {
+                // CSV reads an empty STRING field as '', not NULL
+                let empty = Utf8("".into());
+                vec![vec![da.clone(), Int64(2), da, db.clone()], vec![db.clone(), Int64(1), empty.clone(), empty.clone()], vec![empty.clone(), Int64(3), empty, db]]
+            } else {
+                vec![vec![da.clone(), Int64(2), da.clone(), db.clone()], vec![db.clone(), Int64(0), Null, Null], vec![Null, Int64(2), da, db]]
+            };
+            expected.sort();
+            assert_eq!(rows, expected, "{}", typ);
+        }
+    }
+
+    #[test]
+    fn sum_skips_nulls() {
+        let mut session = session();
+        create_table(&mut session, "T", "K STRING, I INT32, L BIGINT, F DOUBLE, D DECIMAL(10,2)", &["a,1,10,1.5,1.25", "a,,,,", "a,2,20,-0.5,2.50", "b,,,,"]);
+
+        let rows = query(&mut session, "SELECT K, SUM(I), SUM(L), SUM(F), SUM(D) FROM T GROUP BY K").unwrap();
+        assert_eq!(rows, vec![vec![Utf8("a".into()), Int32(3), Int64(30), Float64(F64::from(1.0)), Decimal(375, 38, 2)], vec![Utf8("b".into()), Null, Null, Null, Null],]);
+    }
+
+    #[test]
+    fn group_by_merges_zero_and_negative_zero() {
+        let mut session = session();
+        create_table(&mut session, "T", "K DOUBLE, V BIGINT", &["0.0,1", "-0.0,2"]);
+        assert_eq!(query(&mut session, "SELECT K, SUM(V) FROM T GROUP BY K").unwrap(), vec![vec![Float64(F64::from(0.0)), Int64(3)]]);
+    }
+
+    // The hash table starts at 1000 buckets per split and has to grow
+    #[test]
+    fn group_by_many_keys() {
+        let mut session = session();
+        let rows = (0..5000).map(|ix| f!("{ix},{}", ix % 7)).collect::<Vec<_>>();
+        create_table(&mut session, "T", "K BIGINT, V BIGINT", &rows.iter().map(String::as_str).collect::<Vec<_>>());
+
+        let rows = query(&mut session, "SELECT K, SUM(V) FROM T GROUP BY K").unwrap();
+        assert_eq!(rows.len(), 5000);
+        assert_eq!(rows[4999], vec![Int64(4999), Int64(4999 % 7)]);
+    }
+
+    #[test]
+    fn sum_overflow_is_an_error() {
+        let mut session = session();
+        let max = i64::MAX;
+        create_table(&mut session, "T", "K STRING, V BIGINT, D DECIMAL(38,0)", &[&f!("a,{max},{}", "9".repeat(38)), &f!("a,1,1")]);
+
+        for sql in ["SELECT K, SUM(V) FROM T GROUP BY K", "SELECT K, SUM(D) FROM T GROUP BY K"] {
+            let err = query(&mut session, sql).unwrap_err();
+            assert!(matches!(&err, KonaError::Execution { message, .. } if message.contains("Overflow in SUM()")), "{}: {}", sql, err);
+        }
+    }
+}
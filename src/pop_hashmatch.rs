@@ -2,19 +2,16 @@
 
 use std::collections::HashMap;
 
-use ahash::RandomState;
 use arrow2::{
-    array::{MutableArray, MutableBooleanArray, MutablePrimitiveArray, MutableUtf8Array, Utf8Array},
-    compute::{filter::filter_chunk, take},
-    datatypes::PhysicalType,
-    types::PrimitiveType,
+    array::{growable::make_growable, new_empty_array},
+    compute::{aggregate::estimated_bytes_size, filter::filter_chunk, take},
 };
-use itertools::izip;
 
 use crate::{
     flow::Flow,
     graph::POPKey,
     includes::*,
+    metrics::OpMetrics,
     pop::{chunk_to_string, POPContext, POP},
     pop_hash::*,
     stage::Stage,
@@ -30,61 +27,25 @@ pub struct HashMatch {
 /***************************************************************************************************/
 struct HashMatchSplit {
     id: SplitId,
-    mut_arrays: Vec<Box<dyn MutableArray>>,
-    arrays: Vec<Box<dyn Array>>,
+    chunks: Vec<ChunkBox>,       // Build rows that hash to this split, as they arrive
+    arrays: Vec<Box<dyn Array>>, // The same rows in one array per column, once the build input is consumed
+    nrows: usize,
     hash_map: HashMap<HashValue, Vec<BuildRowId>>, // Hash-of-keys -> {Row-Id}*
 }
 
 impl HashMatchSplit {
     fn new(id: SplitId) -> Self {
-        HashMatchSplit { id, mut_arrays: vec![], arrays: vec![], hash_map: HashMap::new() }
+        HashMatchSplit { id, chunks: vec![], arrays: vec![], nrows: 0, hash_map: HashMap::new() }
     }
 }
 
-macro_rules! copy_to_build_array {
-    ($from_array_typ:ty, $from_array:expr, $to_array_typ:ty, $to_array:expr, $split_ids:expr, $cur_split_id:expr, $hash_array:expr, $hash_map:expr) => {{
-        let primarr = $from_array.as_any().downcast_ref::<$from_array_typ>().unwrap();
-        let mutarr = $to_array.as_mut_any().downcast_mut::<$to_array_typ>().unwrap();
-        let hash_map = $hash_map;
-        let mutarr_height = mutarr.len();
-
-        $split_ids.iter().enumerate().filter(|(_, &split_id)| split_id == $cur_split_id).for_each(|(rid, _)| {
-            let value = primarr.get(rid);
-            mutarr.push(value);
-            debug!("copy_to_build_array: inserted {:?} into split {}", value, $cur_split_id);
-
-            let hash_value = $hash_array[rid];
-            let rids = hash_map.entry(hash_value).or_insert(vec![]);
-            rids.push(rid + mutarr_height)
-        });
-    }};
-}
-
-macro_rules! copy_from_build_array {
-    ($from_array_typ:ty, $self:expr, $rids:expr, $colid:expr) => {{
-        let iter = $rids.iter().map(|&(_, build_rid)| {
-            if let Some((split_id, build_rid)) = build_rid {
-                let split = &$self.splits[split_id];
-                let array = &split.arrays[$colid];
-                let primarr = array.as_any().downcast_ref::<$from_array_typ>().unwrap();
-                primarr.get(build_rid)
-            } else {
-                panic!("copy_from_build_array() was passed rid-list with no build rids.")
-            }
-        });
-        let arr = <$from_array_typ>::from_trusted_len_iter(iter);
-        let arr: Box<dyn Array> = Box::new(arr);
-        arr
-    }};
-}
-
 /***************************************************************************************************/
 pub struct HashMatchContext {
     pop_key: POPKey,
     children: Vec<Box<dyn POPContext>>,
     partition_id: PartitionId,
-    state: RandomState,
     splits: Vec<HashMatchSplit>,
+    build_bytes: usize,
 }
 
 impl POPContext for HashMatchContext {
@@ -92,7 +53,7 @@ impl POPContext for HashMatchContext {
         self
     }
 
-    fn next(&mut self, flow: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, String> {
+    fn next(&mut self, flow: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, KonaError> {
         let pop_key = self.pop_key;
         let pop = stage.pop_graph.get_value(pop_key);
 
@@ -102,16 +63,18 @@ impl POPContext for HashMatchContext {
             panic!("ugh");
         }
     }
+
+    fn add_metrics(&self, metrics: &mut OpMetrics) {
+        metrics.hash_table_entries = Some(self.splits.iter().map(|split| split.hash_map.len()).sum());
+    }
 }
 
 impl HashMatchContext {
-    pub fn try_new(pop_key: POPKey, _: &HashMatch, children: Vec<Box<dyn POPContext>>, partition_id: PartitionId) -> Result<Box<dyn POPContext>, String> {
-        let state = RandomState::with_seeds(97, 31, 45, 21);
-
-        Ok(Box::new(HashMatchContext { pop_key, children, partition_id, state, splits: vec![] }))
+    pub fn try_new(pop_key: POPKey, _: &HashMatch, children: Vec<Box<dyn POPContext>>, partition_id: PartitionId) -> Result<Box<dyn POPContext>, KonaError> {
+        Ok(Box::new(HashMatchContext { pop_key, children, partition_id, splits: vec![], build_bytes: 0 }))
     }
 
-    fn next_join(&mut self, flow: &Flow, stage: &Stage, hash_match: &HashMatch) -> Result<Option<ChunkBox>, String> {
+    fn next_join(&mut self, flow: &Flow, stage: &Stage, hash_match: &HashMatch) -> Result<Option<ChunkBox>, KonaError> {
         // Build hash-tables
         if self.splits.is_empty() {
             self.process_join_build_input(flow, stage, hash_match)?;
@@ -129,88 +92,76 @@ impl HashMatchContext {
         Ok(None)
     }
 
-    fn process_join_build_input(&mut self, flow: &Flow, stage: &Stage, hash_match: &HashMatch) -> Result<(), String> {
+    fn process_join_build_input(&mut self, flow: &Flow, stage: &Stage, hash_match: &HashMatch) -> Result<(), KonaError> {
         // Initialize splits
         if self.splits.is_empty() {
-            self.splits = (0..NSPLITS).map(|split_id| HashMatchSplit::new(split_id)).collect();
+            self.splits = (0..flow.settings.nsplits).map(HashMatchSplit::new).collect();
         }
 
         let child = &mut self.children[1];
         while let Some(chunk) = child.next(flow, stage)? {
+            self.build_bytes += chunk.arrays().iter().map(|array| estimated_bytes_size(&**array)).sum::<usize>();
+            check_memory_limit(flow, "HashMatch", self.build_bytes)?;
+
             // Compute hash + split-# for each row in the chunk
             let keycols = &hash_match.keycols[1];
             let keys = eval_cols(keycols, &chunk);
-            let (hash_array, split_ids) = hash_chunk(&keys, &self.state);
+            let (hash_array, split_ids) = hash_chunk(&keys, self.splits.len())?;
 
             for split in self.splits.iter_mut() {
-                Self::insert(hash_match, split, &chunk, &hash_array, &split_ids);
+                Self::insert(split, &chunk, &hash_array, &split_ids)?;
             }
         }
 
         for split in self.splits.iter_mut() {
-            Self::demut_build_arrays(split);
+            Self::concatenate_build_arrays(hash_match, split);
         }
 
         Ok(())
     }
 
-    fn insert(hash_match: &HashMatch, split: &mut HashMatchSplit, build_chunk: &ChunkBox, hash_array: &[u64], split_ids: &[SplitId]) {
-        if split.mut_arrays.is_empty() {
-            Self::alloc_build_arrays(hash_match, split, build_chunk);
-        }
-
-        let cur_split_id = split.id;
-        for (from_array, to_array, typ) in izip!(build_chunk.arrays(), split.mut_arrays.iter_mut(), &hash_match.children_data_types[1]) {
-            match typ.to_physical_type() {
-                PhysicalType::Primitive(PrimitiveType::Int64) => {
-                    copy_to_build_array!(PrimitiveArray<i64>, from_array, MutablePrimitiveArray<i64>, to_array, split_ids, cur_split_id, hash_array, &mut split.hash_map)
-                }
-                PhysicalType::Utf8 => {
-                    copy_to_build_array!(Utf8Array<i32>, from_array, MutableUtf8Array<i32>, to_array, split_ids, cur_split_id, hash_array, &mut split.hash_map)
-                }
-                PhysicalType::Boolean => {
-                    copy_to_build_array!(BooleanArray, from_array, MutableBooleanArray, to_array, split_ids, cur_split_id, hash_array, &mut split.hash_map)
-                }
-                typ => panic!("insert(), todo: {:?}", typ),
+    fn insert(split: &mut HashMatchSplit, build_chunk: &ChunkBox, hash_array: &[u64], split_ids: &[SplitId]) -> Result<(), KonaError> {
+        let first_rid = split.nrows;
+        for (rid, &split_id) in split_ids.iter().enumerate() {
+            if split_id == split.id {
+                split.hash_map.entry(hash_array[rid]).or_default().push(split.nrows);
+                split.nrows += 1;
             }
         }
+        if split.nrows > first_rid {
+            let filter = BooleanArray::from_trusted_len_values_iter(split_ids.iter().map(|&split_id| split_id == split.id));
+            split.chunks.push(filter_chunk(build_chunk, &filter).map_err(|err| KonaError::arrow("Cannot split build rows", err))?);
+        }
+        Ok(())
     }
 
-    #[allow(unused_variables)]
-    fn alloc_build_arrays(hash_match: &HashMatch, split: &mut HashMatchSplit, chunk: &ChunkBox) {
-        // Compute physical types of each build array
-        let types = &hash_match.children_data_types[1];
-
-        debug!("alloc_build_arrays(): types = {:?}", &types);
-
-        let arrays: Vec<Box<dyn MutableArray>> = types
+    fn concatenate_build_arrays(hash_match: &HashMatch, split: &mut HashMatchSplit) {
+        let chunks = std::mem::take(&mut split.chunks);
+        split.arrays = hash_match.children_data_types[1]
             .iter()
-            .map(|typ| {
-                let mut_array: Box<dyn MutableArray> = match typ.to_physical_type() {
-                    PhysicalType::Primitive(PrimitiveType::Int64) => Box::new(MutablePrimitiveArray::<i64>::new()),
-                    PhysicalType::Utf8 => Box::new(MutableUtf8Array::<i32>::new()),
-                    PhysicalType::Boolean => Box::new(MutableBooleanArray::new()),
-                    typ => panic!("alloc_build_arrays(), todo: {:?}", typ),
-                };
-                mut_array
+            .enumerate()
+            .map(|(colid, typ)| {
+                if chunks.is_empty() {
+                    return new_empty_array(typ.clone());
+                }
+                let arrays = chunks.iter().map(|chunk| &*chunk.arrays()[colid]).collect::<Vec<_>>();
+                let mut growable = make_growable(&arrays, true, split.nrows);
+                for (ix, array) in arrays.iter().enumerate() {
+                    growable.extend(ix, 0, array.len());
+                }
+                growable.as_box()
             })
             .collect();
-        split.mut_arrays = arrays;
-    }
-
-    fn demut_build_arrays(split: &mut HashMatchSplit) {
-        let mut_arrays = std::mem::take(&mut split.mut_arrays);
-        split.arrays = mut_arrays.into_iter().map(|mut mut_array| mut_array.as_box()).collect();
     }
 
     #[allow(unused_variables)]
-    fn process_join_probe_input(&mut self, flow: &Flow, stage: &Stage, hash_match: &HashMatch, chunk: ChunkBox) -> Result<ChunkBox, String> {
+    fn process_join_probe_input(&mut self, flow: &Flow, stage: &Stage, hash_match: &HashMatch, chunk: ChunkBox) -> Result<ChunkBox, KonaError> {
         let props = stage.pop_graph.get_properties(self.pop_key);
         let keycols = &hash_match.keycols[0];
 
         // Hash input keys
         let keys = eval_cols(keycols, &chunk);
-        let (hash_array, split_ids) = hash_chunk(&keys, &self.state);
+        let (hash_array, split_ids) = hash_chunk(&keys, self.splits.len())?;
 
         debug!(
             "HashMatchContext {:?} partition = {}, hash = {:?}{}{}",
@@ -230,10 +181,10 @@ impl HashMatchContext {
             let chunk = Self::contruct_joined_chunk(hash_match, build_chunk, probe_chunk)?;
 
             // Run predicates, if any
-            let chunk = POPKey::eval_predicates(props, chunk);
+            let chunk = POPKey::eval_predicates(props, chunk)?;
             //debug!("After join preds: \n{}", chunk_to_string(&chunk, "After join preds"));
 
-            let projection_chunk = POPKey::eval_projection(props, &chunk);
+            let projection_chunk = POPKey::eval_projection(props, &chunk)?;
             debug!("hash_join_projection: \n{}", chunk_to_string(&projection_chunk, "hash_join_projection"));
             Ok(projection_chunk)
         } else {
@@ -259,22 +210,24 @@ impl HashMatchContext {
         rid_matches
     }
 
-    fn contruct_build_output(&mut self, hash_match: &HashMatch, rids: &[MatchRIDPair]) -> Result<ChunkBox, String> {
+    fn contruct_build_output(&mut self, hash_match: &HashMatch, rids: &[MatchRIDPair]) -> Result<ChunkBox, KonaError> {
         if self.splits.is_empty() {
             return Ok(Chunk::new(vec![]));
         }
 
-        let types = &hash_match.children_data_types[1];
-        let build_arrays = types
-            .into_iter()
-            .enumerate()
-            .map(|(colid, typ)| match typ.to_physical_type() {
-                PhysicalType::Primitive(PrimitiveType::Int64) => copy_from_build_array!(PrimitiveArray<i64>, self, rids, colid),
-                PhysicalType::Utf8 => copy_from_build_array!(Utf8Array<i32>, self, rids, colid),
-                PhysicalType::Boolean => copy_from_build_array!(BooleanArray, self, rids, colid),
-                typ => panic!("contruct_build_output(), todo: {:?}", typ),
+        // Gather the matching rows from across the splits
+        let ncols = hash_match.children_data_types[1].len();
+        let build_arrays = (0..ncols)
+            .map(|colid| {
+                let arrays = self.splits.iter().map(|split| &*split.arrays[colid]).collect::<Vec<_>>();
+                let mut growable = make_growable(&arrays, true, rids.len());
+                for &(_, build_rid) in rids.iter() {
+                    let (split_id, build_rid) = build_rid.ok_or_else(|| KonaError::execution("Join output needs a build row for every probe row"))?;
+                    growable.extend(split_id, build_rid, 1);
+                }
+                Ok(growable.as_box())
             })
-            .collect();
+            .collect::<Result<Vec<_>, KonaError>>()?;
 
         let build_chunk = Chunk::new(build_arrays);
 
@@ -283,20 +236,7 @@ impl HashMatchContext {
         Ok(build_chunk)
     }
 
-    #[allow(dead_code)]
-    fn copy_from_build_arrays0(&mut self, rids: &[(usize, (usize, usize))], colid: usize) -> Box<dyn Array> {
-        let iter = rids.iter().map(|&(_, (split_id, build_rid))| {
-            let split = &self.splits[split_id];
-            let array = &split.arrays[colid];
-            let primarr = array.as_any().downcast_ref::<PrimitiveArray<i64>>().unwrap();
-            primarr.get(build_rid)
-        });
-        let arr = PrimitiveArray::<i64>::from_trusted_len_iter(iter);
-        let arr: Box<dyn Array> = Box::new(arr);
-        arr
-    }
-
-    fn contruct_probe_output(&mut self, rids: &MatchRIDList, keys: &ChunkBox) -> Result<ChunkBox, String> {
+    fn contruct_probe_output(&mut self, rids: &MatchRIDList, keys: &ChunkBox) -> Result<ChunkBox, KonaError> {
         let probe_rids: PrimitiveArray<u64> = rids.iter().map(|e| Some(e.0 as u64)).collect();
         let probe_arrays = Self::take_chunk(keys, probe_rids)?;
         let probe_chunk = Chunk::new(probe_arrays);
@@ -312,7 +252,7 @@ impl HashMatchContext {
         Ok(probe_chunk)
     }
 
-    fn contruct_joined_chunk(hash_match: &HashMatch, build_chunk: ChunkBox, probe_chunk: ChunkBox) -> Result<ChunkBox, String> {
+    fn contruct_joined_chunk(hash_match: &HashMatch, build_chunk: ChunkBox, probe_chunk: ChunkBox) -> Result<ChunkBox, KonaError> {
         // So far, we've only matched build/probe based on hash-values. Make sure the actual keys match.
         assert!(build_chunk.len() == probe_chunk.len());
 
@@ -325,8 +265,9 @@ impl HashMatchContext {
         let mut build_arrays = build_chunk.into_arrays();
         let mut probe_arrays = probe_chunk.into_arrays();
 
-        let build_keys = build_arrays.iter().enumerate().filter_map(|(ix, array)| if build_cols.contains(&ix) { Some(&**array) } else { None }).collect::<Vec<_>>();
-        let probe_keys = probe_arrays.iter().enumerate().filter_map(|(ix, array)| if probe_cols.contains(&ix) { Some(&**array) } else { None }).collect::<Vec<_>>();
+        // Keys pair up in the order of the join's key lists. NULL keys compare as NULL, so they never match.
+        let build_keys = build_cols.iter().map(|&colid| &*build_arrays[colid]).collect::<Vec<_>>();
+        let probe_keys = probe_cols.iter().map(|&colid| &*probe_arrays[colid]).collect::<Vec<_>>();
 
         // Compare key columns
         let mut filter = BooleanArray::from(vec![Some(true); chunk_height]);
@@ -335,13 +276,64 @@ impl HashMatchContext {
             filter = boolean::and(&filter, &filter2);
         }
 
+        // A NULL comparison isn't a match
+        let filter = BooleanArray::from_trusted_len_values_iter(filter.iter().map(|matched| matched == Some(true)));
+
         // Join and filter final chunk
         probe_arrays.append(&mut build_arrays);
         let chunk = Chunk::new(probe_arrays);
-        filter_chunk(&chunk, &filter).map_err(stringify)
+        filter_chunk(&chunk, &filter).map_err(|err| KonaError::arrow("Cannot filter joined rows", err))
+    }
+
+    fn take_chunk(chunk: &ChunkBox, rids: PrimitiveArray<u64>) -> Result<Vec<Box<dyn Array>>, KonaError> {
+        chunk.arrays().iter().map(|array| take::take(&**array, &rids).map_err(|err| KonaError::arrow("Cannot gather joined rows", err))).collect::<Result<Vec<_>, KonaError>>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{includes::*, testing::*};
+
+    #[test]
+    fn join_on_each_type() {
+        let mut session = session();
+        for (ix, (typ, a, b, ..)) in typed_values().into_iter().enumerate() {
+            let (l, r) = (f!("L{ix}"), f!("R{ix}"));
+            create_table(&mut session, &l, &f!("K {typ}, A BIGINT"), &[&f!("{a},1"), &f!("{b},2"), ",3"]);
+            create_table(&mut session, &r, &f!("K {typ}, B BIGINT"), &[&f!("{a},10"), &f!("{a},11"), ",12"]);
+
+            let rows = query(&mut session, &f!("SELECT A, B FROM {l}, {r} WHERE {l}.K = {r}.K")).unwrap();
+            // NULL keys never match. CSV reads an empty STRING field as '', which does.
+            let mut expected = vec![vec![Int64(1), Int64(10)], vec![Int64(1), Int64(11)]];
+            if typ == "STRING" {
+                expected.push(vec![Int64(3), Int64(12)]);
+            }
+            assert_eq!(rows, expected, "{}", typ);
+        }
+    }
+
+    // Keys pair up in the order they're written, whatever the order of the columns
+    #[test]
+    fn join_on_several_keys() {
+        let mut session = session();
+        create_table(&mut session, "L", "X BIGINT, Y STRING, A BIGINT", &["1,a,1", "1,b,2", "2,a,3"]);
+        create_table(&mut session, "R", "Y STRING, B BIGINT, X BIGINT", &["a,10,1", "b,20,2", "a,30,2"]);
+
+        let rows = query(&mut session, "SELECT A, B FROM L, R WHERE L.X = R.X AND L.Y = R.Y").unwrap();
+        assert_eq!(rows, vec![vec![Int64(1), Int64(10)], vec![Int64(3), Int64(30)]]);
     }
 
-    fn take_chunk(chunk: &ChunkBox, rids: PrimitiveArray<u64>) -> Result<Vec<Box<dyn Array>>, String> {
-        chunk.arrays().iter().map(|array| Ok(take::take(&**array, &rids).map_err(stringify)?)).collect::<Result<Vec<_>, String>>()
+    #[test]
+    fn join_many_rows() {
+        let mut session = session();
+        let rows = (0..3000).map(|ix| f!("{ix},{}", ix * 2)).collect::<Vec<_>>();
+        let rows = rows.iter().map(String::as_str).collect::<Vec<_>>();
+        create_table(&mut session, "L", "K BIGINT, A BIGINT", &rows);
+        create_table(&mut session, "R", "K BIGINT, B BIGINT", &rows[1000..]);
+
+        let rows = query(&mut session, "SELECT L.K, A, B FROM L, R WHERE L.K = R.K").unwrap();
+        assert_eq!(rows.len(), 2000);
+        assert!(rows.iter().all(|row| row[1] == row[2]));
+        assert_eq!(rows[0][0], Int64(1000));
     }
 }
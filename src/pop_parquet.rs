@@ -8,6 +8,7 @@ use crate::{
     flow::Flow,
     graph::POPKey,
     includes::*,
+    metrics::OpMetrics,
     pop::{chunk_to_string, POPContext},
     stage::Stage,
 };
@@ -20,6 +21,8 @@ pub struct ParquetContext {
     pop_key: POPKey,
     input_projection_final_ordering: Vec<usize>,
     partition_id: PartitionId,
+    rows_read: usize,
+    chunks_read: usize,
 
     #[derivative(Debug = "ignore")]
     file_reader: FileReader<File>,
@@ -27,21 +30,20 @@ pub struct ParquetContext {
 
 impl ParquetContext {
     #[tracing::instrument(fields(pop_key), skip_all)]
-    pub fn try_new(pop_key: POPKey, pq: &Parquet, partition_id: PartitionId) -> Result<Box<dyn POPContext>, String> {
-        let mut reader = File::open(&pq.pathname).map_err(stringify)?;
-        let metadata = read::read_metadata(&mut reader).map_err(stringify)?;
-        let schema = read::infer_schema(&metadata).map_err(stringify)?;
+    pub fn try_new(pop_key: POPKey, pq: &Parquet, partition_id: PartitionId) -> Result<Box<dyn POPContext>, KonaError> {
+        let mut reader = File::open(&pq.pathname).map_err(|err| KonaError::io(&pq.pathname, err))?;
+        let metadata = read::read_metadata(&mut reader).map_err(|err| KonaError::arrow(&pq.pathname, err))?;
+        let schema = read::infer_schema(&metadata).map_err(|err| KonaError::arrow(&pq.pathname, err))?;
         let schema = schema.filter(|ix, _field| pq.input_projection.iter().find(|&&jx| ix == jx).is_some());
 
-        let row_groups = metadata.row_groups.into_iter().enumerate().map(|(_, row_group)| row_group).collect::<Vec<_>>();
-        let file_reader = read::FileReader::new(reader, row_groups, schema, Some(1024 * 8 * 8), None, None);
+        let file_reader = read::FileReader::new(reader, metadata.row_groups, schema, Some(1024 * 8 * 8), None, None);
 
         let mut input_projection_pairs: Vec<(ColId, usize)> = pq.input_projection.iter().cloned().enumerate().collect::<Vec<_>>();
-        input_projection_pairs.sort_by(|a, b| a.1.cmp(&b.1));
+        input_projection_pairs.sort_by_key(|a| a.1);
 
         let input_projection_final_ordering: Vec<usize> = input_projection_pairs.iter().map(|e| e.0).collect();
 
-        let pqctx = ParquetContext { pop_key, input_projection_final_ordering, file_reader, partition_id };
+        let pqctx = ParquetContext { pop_key, input_projection_final_ordering, file_reader, partition_id, rows_read: 0, chunks_read: 0 };
         debug!("input_projection {:?}", pq.input_projection);
 
         debug!("{:?}", pqctx);
@@ -56,21 +58,23 @@ impl POPContext for ParquetContext {
     }
 
     #[tracing::instrument(fields(stage_id = stage.stage_id, pop_key = %self.pop_key, partition_id = self.partition_id), skip_all, parent = None)]
-    fn next(&mut self, _: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, String> {
+    fn next(&mut self, _: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, KonaError> {
         let pop_key = self.pop_key;
         let props = stage.pop_graph.get_properties(pop_key);
 
         let chunk = self.file_reader.next();
 
         if let Some(chunk) = chunk {
-            let chunk = chunk.map_err(stringify)?;
+            let chunk = chunk.map_err(|err| KonaError::arrow("Cannot read Parquet row group", err))?;
+            self.rows_read += chunk.len();
+            self.chunks_read += 1;
 
             //debug!("ParquetContext:next(): \n{}", chunk_to_string(&chunk, "ParquetContext:next before reorder"));
 
             // Parquet readers read columns by ordinal # but the input projection could be unordered
             // We need to re-build the chunk based on unordered input projection.
             let mut arrays = chunk.into_arrays().into_iter().zip(self.input_projection_final_ordering.iter()).collect::<Vec<_>>();
-            arrays.sort_by(|a, b| a.1.cmp(&b.1));
+            arrays.sort_by_key(|a| *a.1);
             let arrays = arrays.into_iter().map(|(a, _)| a).collect::<Vec<_>>();
 
             let chunk = Chunk::new(arrays);
@@ -81,7 +85,7 @@ impl POPContext for ParquetContext {
             }
 
             // Compute predicates, if any
-            let chunk = POPKey::eval_predicates(props, chunk);
+            let chunk = POPKey::eval_predicates(props, chunk)?;
 
             #[cfg(debug_assertions)]
             if !chunk.is_empty() {
@@ -89,7 +93,7 @@ impl POPContext for ParquetContext {
             }
 
             // Project and return
-            let chunk = POPKey::eval_projection(props, &chunk);
+            let chunk = POPKey::eval_projection(props, &chunk)?;
 
             #[cfg(debug_assertions)]
             if !chunk.is_empty() {
@@ -101,6 +105,11 @@ impl POPContext for ParquetContext {
             Ok(None)
         }
     }
+
+    fn add_metrics(&self, metrics: &mut OpMetrics) {
+        metrics.rows_in = self.rows_read;
+        metrics.chunks_in = self.chunks_read;
+    }
 }
 
 /***************************************************************************************************/
@@ -119,7 +128,7 @@ impl Parquet {
 
 impl fmt::Debug for Parquet {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let pathname = self.pathname.split('/').last().unwrap();
+        let pathname = self.pathname.split('/').next_back().unwrap();
         fmt.debug_struct("").field("file", &pathname).finish()
     }
 }
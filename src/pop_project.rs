@@ -0,0 +1,65 @@
+// pop_project: filter and project the rows of a derived table
+
+use std::fmt;
+
+use crate::{
+    flow::Flow,
+    graph::POPKey,
+    includes::*,
+    pop::{chunk_to_string, POPContext},
+    stage::Stage,
+};
+
+/***************************************************************************************************/
+pub struct ProjectContext {
+    pop_key: POPKey,
+    children: Vec<Box<dyn POPContext>>,
+    partition_id: PartitionId,
+}
+
+impl ProjectContext {
+    pub fn try_new(pop_key: POPKey, _: &Project, children: Vec<Box<dyn POPContext>>, partition_id: PartitionId) -> Result<Box<dyn POPContext>, KonaError> {
+        Ok(Box::new(ProjectContext { pop_key, children, partition_id }))
+    }
+}
+
+impl POPContext for ProjectContext {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    #[tracing::instrument(fields(stage_id = stage.stage_id, pop_key = %self.pop_key, partition_id = self.partition_id), skip_all, parent = None)]
+    fn next(&mut self, flow: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, KonaError> {
+        let props = stage.pop_graph.get_properties(self.pop_key);
+
+        let child = &mut self.children[0];
+        while let Some(chunk) = child.next(flow, stage)? {
+            let chunk = POPKey::eval_predicates(props, chunk)?;
+            if !chunk.is_empty() {
+                let chunk = POPKey::eval_projection(props, &chunk)?;
+                debug!("ProjectContext::next \n{}", chunk_to_string(&chunk, "ProjectContext::next"));
+                return Ok(Some(chunk));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/***************************************************************************************************/
+// Columns of the derived table are the child's output columns, by position
+#[derive(Serialize, Deserialize)]
+pub struct Project {
+    pub qunid: QunId,
+}
+
+impl Project {
+    pub fn new(qunid: QunId) -> Project {
+        Project { qunid }
+    }
+}
+
+impl fmt::Debug for Project {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("").field("qun", &self.qunid).finish()
+    }
+}
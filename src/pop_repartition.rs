@@ -1,15 +1,15 @@
 // pop_repartition
 
-use std::{fs::File, rc::Rc};
+use std::{fs::File, io, rc::Rc};
 
 use arrow2::{
-    compute::{arithmetics::ArrayRem, filter::filter_chunk, hash::hash},
+    compute::{arithmetics::ArrayRem, filter::filter_chunk},
     io::ipc::{
         read::{read_file_metadata, FileReader},
         write::{FileWriter, WriteOptions},
     },
 };
-use getset::Getters;
+use getset::{Getters, MutGetters};
 use self_cell::self_cell;
 
 use crate::{
@@ -18,6 +18,7 @@ use crate::{
     includes::*,
     pcode::PCode,
     pop::{chunk_to_string, POPContext, POP},
+    pop_hash::hash_rows,
     stage::{Stage, StageLink},
 };
 
@@ -30,78 +31,83 @@ pub struct RepartitionWriteContext {
 }
 
 impl RepartitionWriteContext {
-    pub fn try_new(pop_key: POPKey, rpw: &RepartitionWrite, children: Vec<Box<dyn POPContext>>, partition_id: PartitionId) -> Result<Box<dyn POPContext>, String> {
+    pub fn try_new(pop_key: POPKey, rpw: &RepartitionWrite, children: Vec<Box<dyn POPContext>>, partition_id: PartitionId) -> Result<Box<dyn POPContext>, KonaError> {
         let writers = (0..rpw.cpartitions).map(|_| None).collect();
 
         Ok(Box::new(RepartitionWriteContext { pop_key, children, partition_id, writers }))
     }
 
-    fn eval_repart_keys(repart_code: &[PCode], input: &ChunkBox) -> ChunkBox {
-        let arrays = repart_code.iter().map(|code| code.eval(input)).collect();
-        Chunk::new(arrays)
+    fn eval_repart_keys(repart_code: &[PCode], input: &ChunkBox) -> Result<ChunkBox, KonaError> {
+        let arrays = repart_code.iter().map(|code| code.eval(input)).collect::<Result<Vec<_>, _>>()?;
+        Ok(Chunk::new(arrays))
     }
 
-    fn hash_chunk(chunk: ChunkBox) -> Result<PrimitiveArray<u64>, String> {
-        // FIXME: We hash the first column only. Need to include all columns.
-        let last_ix = chunk.arrays().len() - 1;
-        let arr0 = &chunk.arrays()[last_ix];
-        hash(&**arr0).map_err(stringify)
+    fn hash_chunk(chunk: ChunkBox) -> Result<PrimitiveArray<u64>, KonaError> {
+        Ok(PrimitiveArray::from_vec(hash_rows(&chunk)?))
     }
 
     fn compute_partitions(hashed: PrimitiveArray<u64>, npartitions: PartitionId) -> PrimitiveArray<u64> {
         hashed.rem(&(npartitions as u64))
     }
 
-    fn get_writer(&mut self, flow_id: usize, rpw: &RepartitionWrite, cpartition: PartitionId) -> Result<&mut FileWriter<File>, String> {
+    fn get_writer(&mut self, flow: &Flow, rpw: &RepartitionWrite, cpartition: PartitionId) -> Result<&mut FileWriter<File>, KonaError> {
         if self.writers[cpartition].is_none() {
-            let dirname = get_partition_dir(flow_id, rpw.stage_link, cpartition);
+            let dirname = flow.partition_dir(rpw.stage_link, cpartition);
             let path = format!("{}/producer-{}.arrow", dirname, self.partition_id);
-            std::fs::create_dir_all(dirname).map_err(stringify)?;
+            std::fs::create_dir_all(&dirname).map_err(|err| KonaError::io(&dirname, err))?;
 
-            let file = File::create(path).map_err(stringify)?;
+            let file = File::create(&path).map_err(|err| KonaError::io(&path, err))?;
 
             let options = WriteOptions { compression: None };
             let schema = &*rpw.schema.clone();
             let mut writer = FileWriter::new(file, schema.clone(), None, options);
-            writer.start().map_err(stringify)?;
+            writer.start().map_err(|err| KonaError::arrow(&path, err))?;
 
             self.writers[cpartition] = Some(writer);
         }
         Ok(self.writers[cpartition].as_mut().unwrap())
     }
 
-    fn finish_writers(&mut self, rpw: &RepartitionWrite) -> Result<(), String> {
+    fn finish_writers(&mut self, rpw: &RepartitionWrite) -> Result<(), KonaError> {
         for cpartition in 0..rpw.cpartitions {
             if let Some(writer) = self.writers[cpartition].as_mut() {
-                writer.finish().map_err(stringify)?;
+                writer.finish().map_err(|err| KonaError::arrow("Cannot finish repartition output", err))?;
             }
         }
         Ok(())
     }
 
-    fn filter_partition(chunk: &ChunkBox, part_array: &PrimitiveArray<u64>, cpartition: PartitionId) -> Result<ChunkBox, String> {
+    fn filter_partition(chunk: &ChunkBox, part_array: &PrimitiveArray<u64>, cpartition: PartitionId) -> Result<ChunkBox, KonaError> {
         use arrow2::compute::comparison::primitive::eq_scalar;
         let arr = eq_scalar(part_array, cpartition as u64);
-        filter_chunk(chunk, &arr).map_err(stringify)
+        filter_chunk(chunk, &arr).map_err(|err| KonaError::arrow("Cannot filter repartition output", err))
     }
 
-    fn write_partitions(&mut self, flow_id: usize, rpw: &RepartitionWrite, chunk: ChunkBox, part_array: PrimitiveArray<u64>) -> Result<(), String> {
+    fn write_partitions(&mut self, flow: &Flow, rpw: &RepartitionWrite, chunk: ChunkBox, part_array: PrimitiveArray<u64>) -> Result<(), KonaError> {
         let partition_id = self.partition_id;
 
         for cpartition in 0..rpw.cpartitions {
             // Filter chunk to only grab this partition
             let filtered_chunk = Self::filter_partition(&chunk, &part_array, cpartition)?;
             if !filtered_chunk.is_empty() {
-                let writer = self.get_writer(flow_id, rpw, cpartition)?;
+                let writer = self.get_writer(flow, rpw, cpartition)?;
 
                 let headerstr = format!("RepartitionWriteContext Stage {} -> {}, Partition {}, Consumer {}", rpw.stage_link.0, rpw.stage_link.1, partition_id, cpartition);
                 debug!("{}", chunk_to_string(&filtered_chunk, &headerstr));
 
-                writer.write(&filtered_chunk, None).map_err(stringify)?
+                writer.write(&filtered_chunk, None).map_err(|err| KonaError::arrow("Cannot write repartition output", err))?
             }
         }
         Ok(())
     }
+
+    fn broadcast(&mut self, flow: &Flow, rpw: &RepartitionWrite, chunk: &ChunkBox) -> Result<(), KonaError> {
+        for cpartition in 0..rpw.cpartitions {
+            let writer = self.get_writer(flow, rpw, cpartition)?;
+            writer.write(chunk, None).map_err(|err| KonaError::arrow("Cannot write repartition output", err))?
+        }
+        Ok(())
+    }
 }
 
 impl POPContext for RepartitionWriteContext {
@@ -109,7 +115,7 @@ impl POPContext for RepartitionWriteContext {
         self
     }
 
-    fn next(&mut self, flow: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, String> {
+    fn next(&mut self, flow: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, KonaError> {
         let pop_key = self.pop_key;
         let pop = stage.pop_graph.get_value(pop_key);
         let props = stage.pop_graph.get_properties(pop_key);
@@ -119,10 +125,15 @@ impl POPContext for RepartitionWriteContext {
 
             while let Some(chunk) = self.children[0].next(flow, stage)? {
                 if !chunk.is_empty() {
-                    let chunk = POPKey::eval_projection(props, &chunk);
+                    let chunk = POPKey::eval_projection(props, &chunk)?;
+
+                    if repart_key_code.is_empty() {
+                        self.broadcast(flow, rpw, &chunk)?;
+                        continue;
+                    }
 
                     // Compute partitioning keys
-                    let repart_keys = Self::eval_repart_keys(repart_key_code, &chunk);
+                    let repart_keys = Self::eval_repart_keys(repart_key_code, &chunk)?;
 
                     // Compute hash
                     let repart_hash = Self::hash_chunk(repart_keys)?;
@@ -137,7 +148,7 @@ impl POPContext for RepartitionWriteContext {
                     */
 
                     // Write partitions
-                    self.write_partitions(flow.id, rpw, chunk, part_array)?;
+                    self.write_partitions(flow, rpw, chunk, part_array)?;
                 }
             }
             self.finish_writers(rpw)?;
@@ -149,10 +160,10 @@ impl POPContext for RepartitionWriteContext {
 }
 
 /***************************************************************************************************/
-#[derive(Debug, Serialize, Deserialize, Getters)]
+#[derive(Debug, Serialize, Deserialize, Getters, MutGetters)]
 pub struct RepartitionWrite {
-    #[getset(get = "pub")]
-    repart_key: Vec<PCode>,
+    #[getset(get = "pub", get_mut = "pub")]
+    repart_key: Vec<PCode>, // Empty when broadcasting: every consumer gets every row
 
     #[getset(get = "pub")]
     schema: Rc<Schema>,
@@ -211,17 +222,13 @@ impl RepartitionReadCell {
 }
 
 impl RepartitionReadContext {
-    pub fn try_new(flow_id: usize, pop_key: POPKey, rpw: &RepartitionRead, partition_id: PartitionId) -> Result<Box<dyn POPContext>, String> {
+    pub fn try_new(flow: &Flow, pop_key: POPKey, rpw: &RepartitionRead, partition_id: PartitionId) -> Result<Box<dyn POPContext>, KonaError> {
         // Enumerate directory
-        let dirname = get_partition_dir(flow_id, rpw.stage_link, partition_id);
-        let files = list_files(&dirname);
-        let files = if let Err(errstr) = files {
-            if !errstr.contains("kind: NotFound") {
-                return Err(errstr);
-            }
-            vec![]
-        } else {
-            files.unwrap()
+        let dirname = flow.partition_dir(rpw.stage_link, partition_id);
+        let files = match list_files(&dirname) {
+            Ok(files) => files,
+            Err(KonaError::Io { source, .. }) if source.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err),
         };
         debug!("[{:?}] RepartitionReadContext::new, partition = {}, files = {:?}", pop_key, partition_id, &files);
 
@@ -246,7 +253,7 @@ impl POPContext for RepartitionReadContext {
         self
     }
 
-    fn next(&mut self, _: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, String> {
+    fn next(&mut self, _: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, KonaError> {
         let pop_key = self.pop_key;
         let pop = stage.pop_graph.get_value(pop_key);
 
@@ -263,17 +270,3 @@ impl POPContext for RepartitionReadContext {
         Ok(None)
     }
 }
-
-pub fn list_files(dirname: &String) -> Result<Vec<String>, String> {
-    let dir = fs::read_dir(dirname).map_err(|err| stringify1(err, dirname))?;
-    let mut pathnames = vec![];
-    for entry in dir {
-        let entry = entry.map_err(stringify)?;
-        let path = entry.path();
-        if !path.is_dir() {
-            let pathstr = path.into_os_string().into_string().map_err(stringify)?;
-            pathnames.push(pathstr)
-        }
-    }
-    Ok(pathnames)
-}
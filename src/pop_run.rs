@@ -5,20 +5,20 @@ use arrow2::compute::filter::filter_chunk;
 use crate::{graph::POPKey, includes::*, pop::POPProps};
 
 impl POPKey {
-    pub fn eval_predicates(props: &POPProps, input: ChunkBox) -> ChunkBox {
+    pub fn eval_predicates(props: &POPProps, input: ChunkBox) -> Result<ChunkBox, KonaError> {
         let mut filtered_chunk = input;
         if let Some(preds) = props.predicates.as_ref() {
             for pred in preds.iter() {
-                let bool_chunk = pred.eval(&filtered_chunk);
+                let bool_chunk = pred.eval(&filtered_chunk)?;
                 let bool_array = bool_chunk.as_any().downcast_ref::<BooleanArray>().unwrap();
 
                 filtered_chunk = filter_chunk(&filtered_chunk, bool_array).unwrap();
             }
         }
-        filtered_chunk
+        Ok(filtered_chunk)
     }
 
-    pub fn eval_projection(props: &POPProps, input: &ChunkBox) -> ChunkBox {
+    pub fn eval_projection(props: &POPProps, input: &ChunkBox) -> Result<ChunkBox, KonaError> {
         let mut output = vec![];
         let arrays = input.arrays();
 
@@ -31,10 +31,10 @@ impl POPKey {
 
         if let Some(virtcols) = props.virtcols.as_ref() {
             for pcode in virtcols.iter() {
-                let arr = pcode.eval(input);
+                let arr = pcode.eval(input)?;
                 output.push(arr)
             }
         }
-        Chunk::new(output)
+        Ok(Chunk::new(output))
     }
 }
@@ -0,0 +1,121 @@
+// pop_sort: sort all rows of a partition
+
+use std::fmt;
+
+use arrow2::{
+    array::growable::make_growable,
+    compute::{
+        sort::{lexsort_to_indices, SortColumn, SortOptions},
+        take::take,
+    },
+};
+
+use crate::{
+    flow::Flow,
+    graph::POPKey,
+    includes::*,
+    pcode::PCode,
+    pop::{chunk_to_string, POPContext, POP},
+    qgm::Ordering,
+    stage::Stage,
+};
+
+/***************************************************************************************************/
+pub struct SortContext {
+    pop_key: POPKey,
+    children: Vec<Box<dyn POPContext>>,
+    partition_id: PartitionId,
+    done: bool,
+}
+
+impl SortContext {
+    pub fn try_new(pop_key: POPKey, _: &Sort, children: Vec<Box<dyn POPContext>>, partition_id: PartitionId) -> Result<Box<dyn POPContext>, KonaError> {
+        Ok(Box::new(SortContext { pop_key, children, partition_id, done: false }))
+    }
+
+    // All input rows in one chunk
+    fn collect_input(&mut self, flow: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, KonaError> {
+        let mut chunks = vec![];
+        while let Some(chunk) = self.children[0].next(flow, stage)? {
+            if !chunk.is_empty() {
+                chunks.push(chunk);
+            }
+        }
+        if chunks.len() <= 1 {
+            return Ok(chunks.pop());
+        }
+
+        let ncols = chunks[0].arrays().len();
+        let nrows = chunks.iter().map(|chunk| chunk.len()).sum();
+        let arrays = (0..ncols)
+            .map(|colid| {
+                let arrays = chunks.iter().map(|chunk| &*chunk.arrays()[colid]).collect::<Vec<_>>();
+                let mut growable = make_growable(&arrays, true, nrows);
+                for (ix, array) in arrays.iter().enumerate() {
+                    growable.extend(ix, 0, array.len());
+                }
+                growable.as_box()
+            })
+            .collect();
+        Ok(Some(Chunk::new(arrays)))
+    }
+}
+
+impl POPContext for SortContext {
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    #[tracing::instrument(fields(stage_id = stage.stage_id, pop_key = %self.pop_key, partition_id = self.partition_id), skip_all, parent = None)]
+    fn next(&mut self, flow: &Flow, stage: &Stage) -> Result<Option<ChunkBox>, KonaError> {
+        if self.done {
+            return Ok(None);
+        }
+        self.done = true;
+
+        let (pop, props, ..) = stage.pop_graph.get3(self.pop_key);
+        let sort = if let POP::Sort(sort) = pop { sort } else { panic!("SortContext::next(): POP {:?} isn't a Sort", self.pop_key) };
+
+        let chunk = match self.collect_input(flow, stage)? {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+
+        // NULLs sort last in ascending order and first in descending order, as if they were larger than any value
+        let keys = sort.sort_keys.iter().map(|(code, _)| code.eval(&chunk)).collect::<Result<Vec<_>, _>>()?;
+        let columns = keys
+            .iter()
+            .zip(sort.sort_keys.iter())
+            .map(|(values, (_, ordering))| {
+                let descending = matches!(ordering, Ordering::Desc);
+                SortColumn { values: &**values, options: Some(SortOptions { descending, nulls_first: descending }) }
+            })
+            .collect::<Vec<_>>();
+        let indices = lexsort_to_indices::<u64>(&columns, None).map_err(|err| KonaError::arrow("Cannot sort rows", err))?;
+        let arrays = chunk.arrays().iter().map(|array| take(&**array, &indices).map_err(|err| KonaError::arrow("Cannot gather sorted rows", err)));
+        let chunk = Chunk::new(arrays.collect::<Result<Vec<_>, _>>()?);
+
+        let chunk = POPKey::eval_projection(props, &chunk)?;
+        debug!("SortContext::next \n{}", chunk_to_string(&chunk, "SortContext::next"));
+        Ok(Some(chunk))
+    }
+}
+
+/***************************************************************************************************/
+// ORDER BY: each key is computed over the input rows
+#[derive(Serialize, Deserialize)]
+pub struct Sort {
+    pub sort_keys: Vec<(PCode, Ordering)>,
+}
+
+impl Sort {
+    pub fn new(sort_keys: Vec<(PCode, Ordering)>) -> Sort {
+        Sort { sort_keys }
+    }
+}
+
+impl fmt::Debug for Sort {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("").field("sort_keys", &self.sort_keys).finish()
+    }
+}
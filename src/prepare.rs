@@ -0,0 +1,225 @@
+// prepare: PREPARE resolves and plans a query with parameter markers once. EXECUTE compiles the cached plan, binds the
+// values to the markers and runs it. A statement whose tables have since been dropped or redefined is prepared again
+// from its text.
+
+use std::rc::Rc;
+
+use crate::{datum::cast_literal, expr::Expr, graph::LOPKey, includes::*, lop::LOPGraph, pop::POP, qgm::ParserState, resultset::ResultSet, sqlparser, QGM};
+
+pub struct PreparedStatement {
+    sql: String,
+    qgm: QGM,
+    lop_graph: LOPGraph,
+    lop_key: LOPKey,
+    param_types: Vec<DataType>,
+}
+
+impl QGM {
+    pub fn has_parameters(&self) -> bool {
+        self.expr_graph.sm.values().any(|node| matches!(node.value, Expr::Parameter(_)))
+    }
+
+    // Types of the parameter markers by position, once the QGM has been resolved. Every position from $1 up to the
+    // highest one used needs a marker.
+    fn parameter_types(&self) -> Result<Vec<DataType>, KonaError> {
+        let mut param_types: Vec<Option<DataType>> = vec![];
+        for node in self.expr_graph.sm.values() {
+            if let Expr::Parameter(ix) = node.value {
+                let datatype = node.properties.data_type();
+                if *datatype == DataType::Null {
+                    return Err(KonaError::Bind(f!("Cannot infer the type of parameter ${}. Compare it with a column or CAST it.", ix + 1)));
+                }
+                if param_types.len() <= ix {
+                    param_types.resize(ix + 1, None);
+                }
+                match &param_types[ix] {
+                    Some(other) if other != datatype => return Err(KonaError::Bind(f!("Parameter ${} is used both as {:?} and as {:?}", ix + 1, other, datatype))),
+                    _ => param_types[ix] = Some(datatype.clone()),
+                }
+            }
+        }
+        param_types.into_iter().enumerate().map(|(ix, datatype)| datatype.ok_or_else(|| KonaError::Bind(f!("Parameter ${} is never used", ix + 1)))).collect()
+    }
+}
+
+impl Flow {
+    fn bind_parameters(&mut self, values: &[Datum]) {
+        for stage in self.stage_graph.stages.iter_mut() {
+            for node in stage.pop_graph.sm.values_mut() {
+                let props = &mut node.properties;
+                for pcode in props.predicates.iter_mut().chain(props.virtcols.iter_mut()).flatten() {
+                    pcode.bind(values)
+                }
+                if let POP::RepartitionWrite(rpw) = &mut node.value {
+                    rpw.repart_key_mut().iter_mut().for_each(|pcode| pcode.bind(values))
+                }
+            }
+        }
+    }
+}
+
+pub fn prepare(env: &mut Env, name: String, sql: String, qgm: QGM) -> Result<ResultSet, KonaError> {
+    if env.prepared_statements.contains_key(&name) {
+        return Err(KonaError::Bind(f!("Prepared statement {name} already exists.")));
+    }
+    let stmt = PreparedStatement::new(env, sql, qgm)?;
+
+    let status = ResultSet::status(f!("Prepared statement {name} with {} parameters", stmt.param_types.len()));
+    env.prepared_statements.insert(name, stmt);
+    Ok(status)
+}
+
+// PARSE_ONLY jobs get no flow
+pub fn execute(env: &mut Env, name: String, values: Vec<Datum>) -> Result<Option<Flow>, KonaError> {
+    let mut stmt = env.prepared_statements.remove(&name).ok_or_else(|| KonaError::Bind(f!("Prepared statement {name} not found.")))?;
+    if stmt.is_stale(env) {
+        stmt = match stmt.reprepare(env) {
+            Ok(stmt) => stmt,
+            Err(KonaError::Bind(msg)) => {
+                env.prepared_statements.insert(name.clone(), stmt);
+                return Err(KonaError::Bind(f!("Prepared statement {name} cannot be prepared again after its tables changed: {msg}")));
+            }
+            Err(err) => {
+                env.prepared_statements.insert(name, stmt);
+                return Err(err);
+            }
+        };
+    }
+    let flow = stmt.compile(env, &name, values);
+    env.prepared_statements.insert(name, stmt);
+    flow
+}
+
+pub fn deallocate(env: &mut Env, name: Option<String>) -> Result<(), KonaError> {
+    match name {
+        Some(name) => {
+            env.prepared_statements.remove(&name).ok_or_else(|| KonaError::Bind(f!("Prepared statement {name} not found.")))?;
+        }
+        None => env.prepared_statements.clear(),
+    }
+    Ok(())
+}
+
+impl PreparedStatement {
+    fn new(env: &Env, sql: String, mut qgm: QGM) -> Result<Self, KonaError> {
+        qgm.resolve(env)?;
+        let param_types = qgm.parameter_types()?;
+        let (lop_graph, lop_key) = qgm.build_logical_plan(env)?;
+        Ok(PreparedStatement { sql, qgm, lop_graph, lop_key, param_types })
+    }
+
+    // True once a table or view the plan was built from is gone, or the catalog now holds another descriptor for it
+    fn is_stale(&self, env: &Env) -> bool {
+        self.qgm.metadata.table_refs().iter().any(|(schema, name, tabledesc)| match env.metadata.get_tabledesc(schema.as_deref(), name) {
+            Ok((_, current)) => !Rc::ptr_eq(tabledesc, &current),
+            Err(_) => true,
+        })
+    }
+
+    fn reprepare(&self, env: &Env) -> Result<Self, KonaError> {
+        let mut errors = vec![];
+        let qgm = match sqlparser::QueryParser::new().parse(&mut ParserState::default(), &mut errors, &self.sql) {
+            Ok(AST::QGM(qgm)) => qgm,
+            Ok(_) => unreachable!(),
+            Err(err) => return Err(KonaError::Parse(SourceFile::new("<prepare>", &self.sql).describe_error(&err))),
+        };
+        let stmt = PreparedStatement::new(env, self.sql.clone(), qgm)?;
+        if stmt.param_types != self.param_types {
+            return Err(KonaError::Bind(f!("parameter types changed from {:?} to {:?}", self.param_types, stmt.param_types)));
+        }
+        Ok(stmt)
+    }
+
+    fn compile(&mut self, env: &Env, name: &str, values: Vec<Datum>) -> Result<Option<Flow>, KonaError> {
+        if values.len() != self.param_types.len() {
+            return Err(KonaError::Bind(f!("Prepared statement {name} takes {} parameters but {} were given.", self.param_types.len(), values.len())));
+        }
+        let values = values
+            .iter()
+            .zip(self.param_types.iter())
+            .enumerate()
+            .map(|(ix, (value, datatype))| cast_literal(value, datatype).map_err(|err| KonaError::Bind(f!("Parameter ${}: {err}", ix + 1))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if env.settings.parse_only() {
+            return Ok(None);
+        }
+        let mut flow = POP::compile_flow(env, &mut self.qgm, &self.lop_graph, self.lop_key)?;
+        flow.bind_parameters(&values);
+        Ok(Some(flow))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, create_table, query, session, temp_dir};
+
+    // A CSV file of its own holding `rows`
+    fn csv_file(name: &str, rows: &[&str]) -> String {
+        let path = f!("{}/{name}.csv", temp_dir(name));
+        fs::write(&path, rows.iter().map(|row| f!("{row}\n")).collect::<String>()).unwrap();
+        path
+    }
+
+    #[test]
+    fn execute_binds_parameters() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT, S STRING", &["1,a", "2,b", "3,c"]);
+        let status = testing::query_value(&mut session, "PREPARE P AS SELECT S FROM T WHERE ID > $1").unwrap();
+        assert_eq!(status, Utf8("Prepared statement P with 1 parameters".into()));
+
+        assert_eq!(query(&mut session, "EXECUTE P USING 1").unwrap(), vec![vec![Utf8("b".into())], vec![Utf8("c".into())]]);
+        assert_eq!(query(&mut session, "EXECUTE P USING 2").unwrap(), vec![vec![Utf8("c".into())]]);
+
+        for (sql, message) in [
+            ("PREPARE P AS SELECT S FROM T", "already exists"),
+            ("PREPARE Q AS SELECT S FROM T WHERE ID > $2", "never used"),
+            ("EXECUTE P", "takes 1 parameters but 0 were given"),
+            ("EXECUTE Q USING 1", "not found"),
+        ] {
+            let err = query(&mut session, sql).unwrap_err();
+            assert!(matches!(&err, KonaError::Bind(msg) if msg.contains(message)), "{}: {}", sql, err);
+        }
+    }
+
+    #[test]
+    fn changed_tables_are_prepared_again() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT, S STRING", &["1,a", "2,b"]);
+        testing::execute(&mut session, "PREPARE P AS SELECT S FROM T WHERE ID = $1").unwrap();
+        assert_eq!(query(&mut session, "EXECUTE P USING 2").unwrap(), vec![vec![Utf8("b".into())]]);
+
+        let path = csv_file("T2", &["2,x"]);
+        testing::execute(&mut session, &f!("ALTER TABLE T SET (PATH = '{path}')")).unwrap();
+        assert_eq!(query(&mut session, "EXECUTE P USING 2").unwrap(), vec![vec![Utf8("x".into())]]);
+
+        // The new definition has its columns in another order
+        let path = csv_file("T3", &["y,2"]);
+        testing::execute(&mut session, &f!("CATALOG OR REPLACE TABLE T (S STRING, ID INT) WITH (TYPE = 'CSV', PATH = '{path}', HEADER = 'N')")).unwrap();
+        assert_eq!(query(&mut session, "EXECUTE P USING 2").unwrap(), vec![vec![Utf8("y".into())]]);
+    }
+
+    #[test]
+    fn dropped_or_retyped_tables_fail_execute() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT, S STRING", &["1,a"]);
+        create_table(&mut session, "U", "ID INT", &["1"]);
+        testing::execute(&mut session, "CREATE VIEW V AS SELECT ID FROM U").unwrap();
+        testing::execute(&mut session, "PREPARE P AS SELECT S FROM T WHERE ID = $1").unwrap();
+        testing::execute(&mut session, "PREPARE Q AS SELECT ID FROM V").unwrap();
+
+        testing::execute(&mut session, "DROP TABLE T").unwrap();
+        testing::execute(&mut session, "DROP VIEW V").unwrap();
+        for (sql, message) in [("EXECUTE P USING 1", "cannot be prepared again after its tables changed"), ("EXECUTE Q", "cannot be prepared again after its tables changed")]
+        {
+            let err = query(&mut session, sql).unwrap_err();
+            assert!(matches!(&err, KonaError::Bind(msg) if msg.contains(message)), "{}: {}", sql, err);
+        }
+
+        // ID now holds strings, so $1 would have to change type
+        create_table(&mut session, "T", "ID STRING, S STRING", &["1,a"]);
+        let err = query(&mut session, "EXECUTE P USING 1").unwrap_err();
+        assert!(matches!(&err, KonaError::Bind(msg) if msg.contains("parameter types changed")), "{}", err);
+    }
+}
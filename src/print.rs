@@ -1,6 +1,6 @@
 // Print: Diagnostics, Graphviz,
 
-use std::{fs::File, io::Write, process::Command};
+use std::{fs::File, io::Write};
 
 use crate::{
     bitset::Bitset,
@@ -52,13 +52,6 @@ impl QGM {
 
         fprint!(file, "}}\n");
 
-        drop(file);
-
-        let oflag = format!("-o{}.jpg", pathname);
-
-        // dot -Tjpg -oex.jpg exampl1.dot
-        let _cmd = Command::new("/usr/local/bin/dot").arg("-Tjpg").arg(oflag).arg(pathname).status().expect("failed to execute process");
-
         Ok(())
     }
 
@@ -101,11 +94,17 @@ impl QGM {
                 let extrastr = format!("key_len = {}", key_len);
                 (String::from("Aggregation"), extrastr)
             }
+            LOP::DerivedTable => (String::from("DerivedTable"), String::new()),
+            LOP::Sort { sort_keys } => {
+                let keys = sort_keys.iter().map(|(expr_key, _)| *expr_key).collect::<Vec<_>>();
+                let extrastr = describe_preds(&keys, self, true, false);
+                (String::from("Sort"), extrastr)
+            }
         };
 
         fprint!(
             file,
-            "    lopkey{}[label=\"{}-{}|{:?}|{}|{}|{}|{}\"];\n",
+            "    lopkey{}[label=\"{}-{}|{:?}|{}|{}|{}|{}|{}\"];\n",
             id,
             label,
             lop_key.id(),
@@ -113,7 +112,8 @@ impl QGM {
             colstring,
             predstring,
             props.partdesc.describe(&self.expr_graph, true),
-            extrastr
+            extrastr,
+            props.estimate.describe()
         );
 
         Ok(())
@@ -151,13 +151,6 @@ impl QGM {
 
         fprint!(file, "}}\n");
 
-        drop(file);
-
-        let oflag = format!("-o{}.jpg", pathname);
-
-        // dot -Tjpg -oex.jpg exampl1.dot
-        let _cmd = Command::new("/usr/local/bin/dot").arg("-Tjpg").arg(oflag).arg(pathname).status().expect("failed to execute process");
-
         Ok(())
     }
 }
@@ -180,14 +173,14 @@ impl Stage {
 
         let (label, extrastr) = match &pop {
             POP::CSV(csv) => {
-                let pathname = csv.pathname.split('/').last().unwrap_or(&csv.pathname);
+                let pathname = csv.pathname.split('/').next_back().unwrap_or(&csv.pathname);
                 //let mut projection = csv.projection.clone();
                 //projection.sort_by(|a, b| a.cmp(b));
                 let extrastr = format!("file: {}, input_projection: {:?}", pathname, &csv.input_projection).replace('{', "(").replace('}', ")");
                 (String::from("CSV"), extrastr)
             }
             POP::Parquet(pq) => {
-                let pathname = pq.pathname.split('/').last().unwrap_or(&pq.pathname);
+                let pathname = pq.pathname.split('/').next_back().unwrap_or(&pq.pathname);
                 //let mut projection = csv.projection.clone();
                 //projection.sort_by(|a, b| a.cmp(b));
                 let extrastr = format!("file: {}, input_projection: {:?}", pathname, &pq.input_projection).replace('{', "(").replace('}', ")");
@@ -201,6 +194,14 @@ impl Stage {
                 let extrastr = String::new();
                 (String::from("HashAgg"), extrastr)
             }
+            POP::Project(prj) => {
+                let extrastr = format!("qun = {}", prj.qunid);
+                (String::from("Project"), extrastr)
+            }
+            POP::Sort { .. } => {
+                let extrastr = String::new();
+                (String::from("Sort"), extrastr)
+            }
             POP::RepartitionWrite(rpw) => {
                 let extrastr = format!("c = {}", rpw.cpartitions());
                 (String::from("RepartitionWrite"), extrastr)
@@ -340,7 +341,7 @@ impl Bitset<ExprKey> {
     }
 }
 
-pub fn describe_preds(preds: &Vec<ExprKey>, qgm: &QGM, do_escape: bool, do_column_split: bool) -> String {
+pub fn describe_preds(preds: &[ExprKey], qgm: &QGM, do_escape: bool, do_column_split: bool) -> String {
     let mut predstring = String::from("");
 
     if do_column_split {
@@ -363,7 +364,7 @@ pub fn describe_preds(preds: &Vec<ExprKey>, qgm: &QGM, do_escape: bool, do_colum
     predstring
 }
 
-pub fn describe_virtcols(preds: &Vec<VirtCol>, qgm: &QGM, do_escape: bool) -> String {
+pub fn describe_virtcols(preds: &[VirtCol], qgm: &QGM, do_escape: bool) -> String {
     let mut predstring = String::from("");
     for (ix, expr_key) in preds.iter().enumerate() {
         let predstr = expr_key.describe(&qgm.expr_graph, do_escape);
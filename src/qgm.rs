@@ -1,10 +1,11 @@
 // qgm: query graph model
 
-use std::{collections::HashMap, fmt, io::Write, process::Command, rc::Rc};
+use std::{collections::HashMap, fmt, io::Write, rc::Rc};
 
 use crate::{
-    expr::ExprGraph,
+    expr::{Expr, ExprGraph},
     graph::{ExprKey, Graph, QueryBlockKey},
+    hints::Hints,
     includes::*,
     metadata::TableDesc,
 };
@@ -14,6 +15,7 @@ pub type QueryBlockGraph = Graph<QueryBlockKey, QueryBlock, ()>;
 #[derive(Default)]
 pub struct QGMMetadata {
     tabledescmap: HashMap<QunId, Rc<dyn TableDesc>>,
+    table_refs: Vec<(Option<String>, String, Rc<dyn TableDesc>)>, // Catalog lookups: schema as written, name, descriptor found
 }
 
 impl QGMMetadata {
@@ -25,6 +27,15 @@ impl QGMMetadata {
         self.tabledescmap.get(&qunid).cloned()
     }
 
+    pub fn add_table_ref(&mut self, schema: Option<String>, name: &str, tabledesc: Rc<dyn TableDesc>) {
+        self.table_refs.push((schema, name.to_string(), tabledesc));
+    }
+
+    // Every table and view the query was resolved against
+    pub fn table_refs(&self) -> &[(Option<String>, String, Rc<dyn TableDesc>)] {
+        &self.table_refs
+    }
+
     pub fn get_fieldname(&self, quncol: QunCol) -> String {
         if let Some(tabledesc) = self.tabledescmap.get(&quncol.0) {
             tabledesc.fields()[quncol.1].name.clone()
@@ -56,11 +67,13 @@ pub struct QGM {
     pub qblock_graph: QueryBlockGraph,
     pub expr_graph: ExprGraph,
     pub metadata: QGMMetadata,
+    pub view_path: Vec<String>,        // Views being expanded, outermost first
+    output_names: Option<Vec<String>>, // Result column names, taken before GROUP BY rewrites the select list
 }
 
 impl QGM {
     pub fn new(main_qblock: QueryBlockKey, cte_list: Vec<QueryBlockKey>, qblock_graph: QueryBlockGraph, expr_graph: ExprGraph) -> QGM {
-        QGM { main_qblock_key: main_qblock, cte_list, qblock_graph, expr_graph, metadata: QGMMetadata::default() }
+        QGM { main_qblock_key: main_qblock, cte_list, qblock_graph, expr_graph, metadata: QGMMetadata::default(), view_path: vec![], output_names: None }
     }
 }
 
@@ -121,9 +134,13 @@ pub struct AnsiJoin {
 pub struct Quantifier {
     pub id: QunId,
     source: QuantifierSource,
+    schema: Option<String>,
     alias: Option<String>,
+    implicit_alias: bool, // No AS clause: the alias is the table name, qualified by its schema
     #[serde(skip)]
     pub tabledesc: Option<Rc<dyn TableDesc>>,
+    #[serde(skip)]
+    pub view: Option<(String, Rc<dyn TableDesc>)>, // Qualified name and descriptor of the view this qun was expanded from
 }
 
 impl fmt::Debug for Quantifier {
@@ -135,18 +152,20 @@ impl fmt::Debug for Quantifier {
 impl Quantifier {
     pub fn new(id: QunId, source: QuantifierSource, alias: Option<String>) -> Self {
         let mut alias = alias;
+        let mut implicit_alias = false;
         if alias.is_none() {
             // For unaliased base references, the basename becomes the alias i.e. "SELECT * FROM TABLE" -> "SELECT * FROM TABLE AS TABLE"
             if let QuantifierSource::Basename(bn) = &source {
-                alias = Some(bn.clone())
+                alias = Some(bn.clone());
+                implicit_alias = true;
             }
         }
-        Quantifier { id, source, alias, tabledesc: None }
+        Quantifier { id, source, schema: None, alias, implicit_alias, tabledesc: None, view: None }
     }
 
-    pub fn new_base(id: QunId, name: String, alias: Option<String>) -> Self {
+    pub fn new_base(id: QunId, schema: Option<String>, name: String, alias: Option<String>) -> Self {
         let source = QuantifierSource::Basename(name);
-        Quantifier::new(id, source, alias)
+        Quantifier { schema, ..Quantifier::new(id, source, alias) }
     }
 
     pub fn new_qblock(id: QunId, qblock: QueryBlockKey, alias: Option<String>) -> Self {
@@ -159,6 +178,14 @@ impl Quantifier {
         Quantifier::new(id, source, alias)
     }
 
+    // A reference to a view becomes a derived table over the view's query
+    pub fn expand_view(&mut self, qblock: QueryBlockKey, schema: String, viewdesc: Rc<dyn TableDesc>) {
+        let name = f!("{}.{}", schema, self.get_basename().unwrap().to_uppercase());
+        self.source = QuantifierSource::QueryBlock(qblock);
+        self.schema = Some(schema);
+        self.view = Some((name, viewdesc));
+    }
+
     pub fn is_base_table(&self) -> bool {
         matches!(self.source, QuantifierSource::Basename(_))
     }
@@ -167,6 +194,21 @@ impl Quantifier {
         self.get_basename().map(|e| e == prefix).unwrap_or(false) || self.get_alias().map(|e| e == prefix).unwrap_or(false)
     }
 
+    // A SCHEMA.TABLE prefix only matches a table or view referenced without an alias
+    pub fn matches_prefix(&self, schema: Option<&String>, prefix: &String) -> bool {
+        match schema {
+            Some(schema) => self.implicit_alias && self.schema.as_ref() == Some(schema) && self.alias.as_ref() == Some(prefix),
+            None => self.matches_name_or_alias(prefix),
+        }
+    }
+
+    // The name a quantifier is known by in its query block. Implicit aliases include the schema, so that
+    // FROM TPCH.ORDERS, NORTHWIND.ORDERS is allowed
+    pub fn qualified_alias(&self) -> Option<(Option<&String>, &String)> {
+        let schema = if self.implicit_alias { self.schema.as_ref() } else { None };
+        self.alias.as_ref().map(|alias| (schema, alias))
+    }
+
     pub fn display(&self) -> String {
         format!("QUN_{} {}/{}", self.id, self.get_basename().unwrap_or(&"".to_string()), self.get_alias().unwrap_or(&"".to_string()),)
     }
@@ -183,6 +225,15 @@ impl Quantifier {
         }
     }
 
+    pub fn get_schemaname(&self) -> Option<&String> {
+        self.schema.as_ref()
+    }
+
+    // Set once the table is found, to the schema it was found in
+    pub fn set_schemaname(&mut self, schema: String) {
+        self.schema = Some(schema)
+    }
+
     pub fn get_qblock(&self) -> Option<QueryBlockKey> {
         if let QuantifierSource::QueryBlock(qblock) = self.source {
             Some(qblock)
@@ -196,7 +247,7 @@ impl Quantifier {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub enum Ordering {
     Asc,
     Desc,
@@ -221,6 +272,7 @@ pub struct QueryBlock {
     pub order_by: Option<Vec<(ExprKey, Ordering)>>,
     pub distinct: DistinctProperty,
     pub top_n: Option<usize>,
+    pub hints: Hints,
 }
 
 impl QueryBlock {
@@ -228,7 +280,7 @@ impl QueryBlock {
         id: QBId, name: Option<String>, qbtype: QueryBlockType, select_list: Vec<NamedExpr>, quns: Vec<Quantifier>, pred_list: Option<Vec<ExprKey>>,
         group_by: Option<Vec<ExprKey>>, having_clause: Option<Vec<ExprKey>>, order_by: Option<Vec<(ExprKey, Ordering)>>, distinct: DistinctProperty, top_n: Option<usize>,
     ) -> Self {
-        QueryBlock { id, name, qbtype, select_list, quns, pred_list, group_by, having_clause, order_by, distinct, top_n }
+        QueryBlock { id, name, qbtype, select_list, quns, pred_list, group_by, having_clause, order_by, distinct, top_n, hints: Hints::default() }
     }
 
     pub fn new0(id: QBId, qbtype: QueryBlockType) -> Self {
@@ -244,6 +296,7 @@ impl QueryBlock {
             order_by: None,
             distinct: DistinctProperty::All,
             top_n: None,
+            hints: Hints::default(),
         }
     }
 
@@ -269,6 +322,7 @@ impl QueryBlock {
 pub struct ParserState {
     pub qblock_graph: QueryBlockGraph,
     pub expr_graph: ExprGraph,
+    pub nparams: usize, // `?` markers seen so far in the current statement
 }
 
 impl QueryBlockGraph {
@@ -278,6 +332,31 @@ impl QueryBlockGraph {
 }
 
 impl QGM {
+    // Names of the query's result columns: aliases, else the names of plain column references
+    pub fn output_column_names(&self) -> Vec<String> {
+        self.output_names.clone().unwrap_or_else(|| self.select_list_names())
+    }
+
+    // A GROUP BY query's select list becomes references to the aggregated rows when it is resolved, so its names are
+    // kept beforehand
+    pub fn save_output_column_names(&mut self) {
+        if self.main_qblock().group_by.is_some() {
+            self.output_names = Some(self.select_list_names());
+        }
+    }
+
+    fn select_list_names(&self) -> Vec<String> {
+        let qblock = self.main_qblock();
+        qblock
+            .select_list
+            .iter()
+            .map(|ne| match (&ne.alias, self.expr_graph.get_value(ne.expr_key)) {
+                (None, Expr::Column { colname, .. }) => colname.clone(),
+                _ => ne.get_name(),
+            })
+            .collect()
+    }
+
     pub fn main_qblock(&self) -> &QueryBlock {
         let qbkey = self.main_qblock_key;
         let qblock = &self.qblock_graph.get(qbkey).value;
@@ -288,7 +367,7 @@ impl QGM {
         self.main_qblock_key == qbkey
     }
 
-    pub fn write_qgm_to_graphviz(&self, pathname: &str, open_jpg: bool) -> Result<(), String> {
+    pub fn write_qgm_to_graphviz(&self, pathname: &str) -> Result<(), String> {
         let mut file = std::fs::File::create(pathname).map_err(|err| f!("{:?}: {}", err, pathname))?;
 
         fprint!(file, "digraph example1 {{\n");
@@ -313,17 +392,6 @@ impl QGM {
 
         fprint!(file, "}}\n");
 
-        drop(file);
-
-        let opathname = format!("{}.jpg", pathname);
-        let oflag = format!("-o{}.jpg", pathname);
-
-        // dot -Tjpg -oex.jpg exampl1.dot
-        let _cmd = Command::new("/usr/local/bin/dot").arg("-Tjpg").arg(oflag).arg(pathname).status().expect("failed to execute process");
-
-        if open_jpg {
-            let _cmd = Command::new("open").arg(opathname).status().expect("failed to execute process");
-        }
         Ok(())
     }
 }
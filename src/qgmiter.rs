@@ -85,12 +85,16 @@ impl QueryBlockKey {
         let mut iter: Box<dyn Iterator<Item = ExprKey>> = Box::new(qblock.select_list.iter().map(|ne| ne.expr_key));
 
         // Append pred_list, group_by and having_clause expressions
-        // todo: order_by
         for &expr_list in &[&qblock.pred_list, &qblock.group_by, &qblock.having_clause] {
             if let Some(expr_list) = expr_list {
                 iter = Box::new(iter.chain(expr_list.iter().copied()));
             }
         }
+
+        // Append order_by expressions
+        if let Some(order_by) = &qblock.order_by {
+            iter = Box::new(iter.chain(order_by.iter().map(|&(expr_key, _)| expr_key)));
+        }
         iter
     }
 
@@ -1,22 +1,31 @@
 // QST: Query Semantic Transforms
 
 use core::panic;
-use std::rc::Rc;
+use std::{convert::TryFrom, rc::Rc};
 
 use arrow2::compute::cast::can_cast_types;
 use itertools::Itertools;
 
 use crate::{
-    datum::{get_rank, is_numeric},
+    datum::{cast_literal, check_castability, common_supertype, is_numeric, CastResult, F64},
+    decimal::{self, is_decimal},
+    diagnostic::SourceFile,
     expr::{AggType, ArithOp, Expr, Expr::*, ExprGraph, ExprProp},
     graph::{ExprKey, Node, QueryBlockKey},
     includes::*,
-    metadata::{QueryDesc, TableDesc},
-    qgm::{NamedExpr, QGMMetadata, Quantifier, QueryBlock, QueryBlockGraph, QueryBlockType, QGM},
+    metadata::{column_name_matches, QueryDesc, TableDesc},
+    qgm::{NamedExpr, ParserState, QGMMetadata, Quantifier, QueryBlock, QueryBlockGraph, QueryBlockType, QGM},
+    sqlparser,
+    temporal::{self, is_temporal},
 };
 
+// Resolved expression (if rewritten), its datatype, and its (possibly rewritten) children
+type ResolvedExpr = (Option<Expr>, DataType, Option<Vec<ExprKey>>);
+
 impl QGM {
-    pub fn resolve(&mut self, env: &Env) -> Result<(), String> {
+    pub fn resolve(&mut self, env: &Env) -> Result<(), KonaError> {
+        self.save_output_column_names();
+
         // Resolve top-level QB
         let qbkey = self.main_qblock_key;
         QueryBlock::resolve(qbkey, env, self)?;
@@ -30,9 +39,11 @@ impl QGM {
 }
 
 impl QueryBlock {
-    pub fn resolve(qbkey: QueryBlockKey, env: &Env, qgm: &mut QGM) -> Result<Rc<dyn TableDesc>, String> {
+    pub fn resolve(qbkey: QueryBlockKey, env: &Env, qgm: &mut QGM) -> Result<Rc<dyn TableDesc>, KonaError> {
         // Resolve group-by/having clauses, if they exist
         // If a GROUP BY is present, all select_list expressions must either by included in the group_by, or they must be aggregate functions
+        Self::resolve_output_references(qbkey, env, qgm)?;
+
         let qblock = &mut qgm.qblock_graph.get_mut(qbkey).value;
         //let qbid = qblock.id;
 
@@ -46,17 +57,27 @@ impl QueryBlock {
         let qblock = &mut qgm.qblock_graph.get_mut(qbkey).value;
         let is_group_by = qblock.qbtype == QueryBlockType::GroupBy;
 
-        // Ensure that every quantifier in this qblock is uniquely identifiable
-        let qun_aliases = qblock.quns.iter().filter_map(|qun| qun.get_alias().cloned()).collect::<Vec<_>>();
-        if has_duplicates(&qun_aliases) {
-            return Err("Query has two or more quantifiers with the same aliases.".to_owned());
-        }
+        Self::expand_views(qbkey, env, qgm)?;
 
         // Resolve nested query blocks first
-        let qbkey_children: Vec<(QunId, QueryBlockKey)> = qblock.quns.iter().filter_map(|qun| qun.get_qblock().map(|qbkey| (qun.id, qbkey))).collect();
-        for (qunid, qbkey) in qbkey_children {
-            let qdesc = Self::resolve(qbkey, env, qgm)?;
-            qgm.metadata.add_tabledesc(qunid, qdesc);
+        let qblock = &qgm.qblock_graph.get(qbkey).value;
+        let qbkey_children: Vec<_> = qblock.quns.iter().filter_map(|qun| qun.get_qblock().map(|qbkey| (qun.id, qbkey, qun.view.clone()))).collect();
+        for (qunid, child_qbkey, view) in qbkey_children {
+            if let Some((name, _)) = view.as_ref() {
+                qgm.view_path.push(name.clone());
+            }
+            let qdesc = Self::resolve(child_qbkey, env, qgm)?;
+            let qdesc = if let Some((name, viewdesc)) = view {
+                qgm.view_path.pop();
+                Self::rename_view_columns(&name, &*viewdesc, &*qdesc)?
+            } else {
+                qdesc
+            };
+            qgm.metadata.add_tabledesc(qunid, Rc::clone(&qdesc));
+            let qblock = &mut qgm.qblock_graph.get_mut(qbkey).value;
+            if let Some(qun) = qblock.quns.iter_mut().find(|qun| qun.id == qunid) {
+                qun.tabledesc = Some(qdesc);
+            }
         }
 
         let (qblock_graph, expr_graph, metadata) = qgm.borrow_parts();
@@ -66,16 +87,20 @@ impl QueryBlock {
         // Resolve base table QUNs next
         for qun in qblock.quns.iter_mut() {
             if let Some(tablename) = qun.get_basename() {
-                let tbdesc = env.metadata.get_tabledesc(tablename);
-                if let Some(tbdesc0) = tbdesc.as_ref() {
-                    metadata.add_tabledesc(qun.id, Rc::clone(tbdesc0));
-                    qun.tabledesc = tbdesc;
-                } else {
-                    return Err(format!("Table {} not cataloged.", enquote(tablename)));
-                }
+                let (schema, tbdesc) = env.metadata.get_tabledesc(qun.get_schemaname().map(|s| s.as_str()), tablename)?;
+                metadata.add_tabledesc(qun.id, Rc::clone(&tbdesc));
+                metadata.add_table_ref(qun.get_schemaname().cloned(), tablename, Rc::clone(&tbdesc));
+                qun.tabledesc = Some(tbdesc);
+                qun.set_schemaname(schema);
             }
         }
 
+        // Ensure that every quantifier in this qblock is uniquely identifiable
+        let qun_aliases = qblock.quns.iter().filter_map(|qun| qun.qualified_alias()).collect::<Vec<_>>();
+        if has_duplicates(&qun_aliases) {
+            return Err(KonaError::Bind("Query has two or more quantifiers with the same aliases.".to_owned()));
+        }
+
         // Resolve any stars (*)
         qblock.resolve_star(env, expr_graph)?;
 
@@ -112,6 +137,13 @@ impl QueryBlock {
             qblock.having_clause = Some(boolean_factors);
         }
 
+        // Resolve order-by
+        if let Some(order_by) = qblock.order_by.as_ref() {
+            for &(expr_key, _) in order_by.iter() {
+                qblock.resolve_expr(env, expr_graph, metadata, expr_key, is_group_by)?;
+            }
+        }
+
         info!("Resolved qblock id: {}", qblock_id);
 
         let qdesc = qblock.get_projection(expr_graph);
@@ -119,6 +151,52 @@ impl QueryBlock {
         Ok(qdesc)
     }
 
+    // Replace references to views with derived tables over the views' queries, parsed into this QGM
+    fn expand_views(qbkey: QueryBlockKey, env: &Env, qgm: &mut QGM) -> Result<(), KonaError> {
+        let qblock = &qgm.qblock_graph.get(qbkey).value;
+        let views = qblock
+            .quns
+            .iter()
+            .enumerate()
+            .filter_map(|(ix, qun)| {
+                let (schema, tabledesc) = env.metadata.get_tabledesc(qun.get_schemaname().map(|s| s.as_str()), qun.get_basename()?).ok()?;
+                tabledesc.view_definition()?;
+                let name = f!("{}.{}", schema, qun.get_basename()?.to_uppercase());
+                Some((ix, qun.get_schemaname().cloned(), qun.get_basename()?.to_string(), schema, name, tabledesc))
+            })
+            .collect::<Vec<_>>();
+
+        for (ix, schema_ref, basename, schema, name, viewdesc) in views {
+            qgm.metadata.add_table_ref(schema_ref, &basename, Rc::clone(&viewdesc));
+            if qgm.view_path.contains(&name) {
+                return Err(KonaError::Bind(f!("View {} is defined in terms of itself: {} -> {}", name, qgm.view_path.join(" -> "), name)));
+            }
+
+            let mut parser_state = ParserState { qblock_graph: std::mem::take(&mut qgm.qblock_graph), expr_graph: std::mem::take(&mut qgm.expr_graph), nparams: 0 };
+            let mut errors = vec![];
+            let definition = viewdesc.view_definition().unwrap();
+            let parse_result = sqlparser::SUIEQueryParser::new().parse(&mut parser_state, &mut errors, definition);
+            qgm.qblock_graph = parser_state.qblock_graph;
+            qgm.expr_graph = parser_state.expr_graph;
+            let view_qbkey = parse_result.map_err(|err| KonaError::Bind(f!("Cannot expand view {}: {}", name, SourceFile::new(&name, definition).describe_error(&err))))?;
+
+            let qun = &mut qgm.qblock_graph.get_mut(qbkey).value.quns[ix];
+            qun.expand_view(view_qbkey, schema, viewdesc);
+        }
+        Ok(())
+    }
+
+    // A view's columns keep the names it was created with
+    fn rename_view_columns(name: &str, viewdesc: &dyn TableDesc, qdesc: &dyn TableDesc) -> Result<Rc<dyn TableDesc>, KonaError> {
+        let (view_fields, fields) = (viewdesc.fields(), qdesc.fields());
+        if view_fields.len() != fields.len() {
+            return Err(KonaError::Bind(f!("View {} was created with {} columns but its query now returns {}.", name, view_fields.len(), fields.len())));
+        }
+        let fields =
+            view_fields.iter().zip(fields.iter()).map(|(view_field, field)| Field::new(view_field.name.clone(), field.data_type.clone(), field.is_nullable)).collect();
+        Ok(Rc::new(QueryDesc::new(fields)))
+    }
+
     pub fn get_projection(&self, expr_graph: &ExprGraph) -> Rc<dyn TableDesc> {
         let fields = self
             .select_list
@@ -131,11 +209,10 @@ impl QueryBlock {
                 Field::new(name, typ.clone(), false)
             })
             .collect_vec();
-        let qdesc = Rc::new(QueryDesc::new(fields));
-        qdesc
+        Rc::new(QueryDesc::new(fields))
     }
 
-    pub fn split_groupby(qbkey: QueryBlockKey, qgm: &mut QGM) -> Result<(), String> {
+    pub fn split_groupby(qbkey: QueryBlockKey, qgm: &mut QGM) -> Result<(), KonaError> {
         let (qblock_graph, expr_graph, ..) = qgm.borrow_parts();
 
         let inner_qb_key = qblock_graph.add_node(QueryBlock::new0(expr_graph.next_id(), QueryBlockType::Select), None);
@@ -147,10 +224,10 @@ impl QueryBlock {
         let agg_qun_id = expr_graph.next_id();
 
         // Replace group_by expressions with references to child qun
-        let group_by = replace(&mut outer_qb.group_by, None).unwrap();
+        let group_by = outer_qb.group_by.take().unwrap();
         let group_by_expr_count = group_by.len();
 
-        let having_clause = replace(&mut outer_qb.having_clause, None);
+        let having_clause = outer_qb.having_clause.take();
 
         // Construct inner select-list by first adding GROUP-BY clause expressions
         let mut inner_select_list = group_by.iter().map(|&expr_key| NamedExpr::new(None, expr_key)).collect::<Vec<NamedExpr>>();
@@ -173,6 +250,13 @@ impl QueryBlock {
             None
         };
 
+        // ORDER BY expressions are computed over the aggregated rows as well
+        if let Some(order_by) = outer_qb.order_by.as_mut() {
+            for (expr_key, _) in order_by.iter_mut() {
+                Self::transform_groupby_expr(expr_graph, &mut inner_select_list, group_by_expr_count, agg_qun_id, expr_key)?;
+            }
+        }
+
         let group_by = group_by
             .iter()
             .enumerate()
@@ -186,7 +270,7 @@ impl QueryBlock {
 
         let inner_qb = QueryBlock::new(
             expr_graph.next_id(),
-            replace(&mut outer_qb.name, None),
+            outer_qb.name.take(),
             QueryBlockType::Select,
             inner_select_list,
             std::mem::take(&mut outer_qb.quns),
@@ -197,6 +281,7 @@ impl QueryBlock {
             outer_qb.distinct,
             None,
         );
+        let inner_qb = QueryBlock { hints: outer_qb.hints.clone(), ..inner_qb };
 
         let outer_qun = Quantifier::new_qblock(agg_qun_id, inner_qb_key, None);
         outer_qb.name = None;
@@ -211,6 +296,104 @@ impl QueryBlock {
         Ok(())
     }
 
+    // Replace select-list ordinals (GROUP BY 1) and output aliases (ORDER BY total) with copies of the select-list expressions they
+    // stand for. A name that is both an input column and an alias refers to the input column in GROUP BY and HAVING, and to the
+    // alias in ORDER BY.
+    pub fn resolve_output_references(qbkey: QueryBlockKey, env: &Env, qgm: &mut QGM) -> Result<(), KonaError> {
+        let (qblock_graph, expr_graph, ..) = qgm.borrow_parts();
+        let input_columns = Self::input_column_names(qbkey, env, qblock_graph);
+        let qblock = &mut qblock_graph.get_mut(qbkey).value;
+
+        if let Some(mut group_by) = qblock.group_by.take() {
+            for expr_key in group_by.iter_mut() {
+                *expr_key = qblock.resolve_output_reference(expr_graph, *expr_key, "GROUP BY", &input_columns)?;
+                if expr_graph.true_iter(*expr_key).any(|key| matches!(expr_graph.get_value(key), AggFunction(..))) {
+                    return Err(KonaError::Bind(f!("GROUP BY cannot refer to an aggregate: {}", expr_key.describe(expr_graph, false))));
+                }
+            }
+            qblock.group_by = Some(group_by);
+        }
+
+        if let Some(mut having_clause) = qblock.having_clause.take() {
+            for expr_key in having_clause.iter_mut() {
+                qblock.substitute_aliases(expr_graph, expr_key, &input_columns)?;
+            }
+            qblock.having_clause = Some(having_clause);
+        }
+
+        if let Some(mut order_by) = qblock.order_by.take() {
+            for (expr_key, _) in order_by.iter_mut() {
+                *expr_key = qblock.resolve_output_reference(expr_graph, *expr_key, "ORDER BY", &HashSet::new())?;
+            }
+            qblock.order_by = Some(order_by);
+        }
+        Ok(())
+    }
+
+    // Names of the columns produced by the FROM clause. Derived tables expose their select-list names.
+    fn input_column_names(qbkey: QueryBlockKey, env: &Env, qblock_graph: &QueryBlockGraph) -> HashSet<String> {
+        let qblock = &qblock_graph.get(qbkey).value;
+        let mut colnames = HashSet::new();
+        for qun in qblock.quns.iter() {
+            if let Some((_, tabledesc)) = qun.get_basename().and_then(|name| env.metadata.get_tabledesc(qun.get_schemaname().map(|s| s.as_str()), name).ok()) {
+                colnames.extend(tabledesc.fields().iter().map(|field| field.name.clone()));
+            } else if let Some(child_qbkey) = qun.get_qblock() {
+                colnames.extend(qblock_graph.get(child_qbkey).value.select_list.iter().map(|ne| ne.get_name()));
+            }
+        }
+        colnames
+    }
+
+    // A top-level GROUP BY or ORDER BY item that is an ordinal or an output alias
+    fn resolve_output_reference(&self, expr_graph: &mut ExprGraph, expr_key: ExprKey, clause: &str, input_columns: &HashSet<String>) -> Result<ExprKey, KonaError> {
+        let target_key = match expr_graph.get_value(expr_key) {
+            Literal(Int64(position)) => {
+                let position = *position;
+                if self.select_list.iter().any(|ne| matches!(expr_graph.get_value(ne.expr_key), Star { .. })) {
+                    return Err(KonaError::Bind(f!("{clause} position {position} cannot be used with SELECT *")));
+                }
+                let ix = usize::try_from(position).ok().filter(|ix| (1..=self.select_list.len()).contains(ix));
+                let ix = ix.ok_or_else(|| KonaError::Bind(f!("{clause} position {position} is not in select list")))?;
+                self.select_list[ix - 1].expr_key
+            }
+            Column { prefix: None, colname, quoted, .. } if !input_columns.iter().any(|name| column_name_matches(name, colname, *quoted)) => {
+                if let Some(target_key) = self.find_alias(colname, clause)? {
+                    target_key
+                } else {
+                    return Ok(expr_key);
+                }
+            }
+            _ => return Ok(expr_key),
+        };
+        Ok(target_key.deep_copy(expr_graph))
+    }
+
+    fn find_alias(&self, name: &String, clause: &str) -> Result<Option<ExprKey>, KonaError> {
+        let mut matches = self.select_list.iter().filter(|ne| ne.alias.as_ref() == Some(name));
+        match (matches.next(), matches.next()) {
+            (Some(ne), None) => Ok(Some(ne.expr_key)),
+            (Some(_), Some(_)) => Err(KonaError::Bind(f!("{clause} reference {name} is ambiguous"))),
+            _ => Ok(None),
+        }
+    }
+
+    // HAVING: aliases may appear anywhere inside the predicate
+    fn substitute_aliases(&self, expr_graph: &mut ExprGraph, expr_key: &mut ExprKey, input_columns: &HashSet<String>) -> Result<(), KonaError> {
+        if let Column { prefix: None, colname, quoted, .. } = expr_graph.get_value(*expr_key) {
+            if !input_columns.iter().any(|name| column_name_matches(name, colname, *quoted)) {
+                if let Some(target_key) = self.find_alias(colname, "HAVING")? {
+                    *expr_key = target_key.deep_copy(expr_graph);
+                }
+            }
+        } else if let Some(mut children) = expr_graph.get(*expr_key).children.clone() {
+            for child_key in children.iter_mut() {
+                self.substitute_aliases(expr_graph, child_key, input_columns)?;
+            }
+            expr_graph.get_mut(*expr_key).children = Some(children);
+        }
+        Ok(())
+    }
+
     fn find(graph: &ExprGraph, select_list: &[NamedExpr], group_by_expr_count: usize, expr_key: ExprKey) -> Option<usize> {
         // Does this expression already exist in the select_list[..until_index]?
         for (ix, ne) in select_list.iter().enumerate() {
@@ -246,7 +429,7 @@ impl QueryBlock {
     //            Inner QB select list    => c1
     pub fn transform_groupby_expr(
         expr_graph: &mut ExprGraph, select_list: &mut Vec<NamedExpr>, group_by_expr_count: usize, qunid: QunId, expr_key: &mut ExprKey,
-    ) -> Result<(), String> {
+    ) -> Result<(), KonaError> {
         //debug!("transform_groupby_expr: {:?}", expr_key.describe(&expr_graph, false));
 
         let node = expr_graph.get(*expr_key);
@@ -256,10 +439,18 @@ impl QueryBlock {
             let (cid, data_type) = Self::append(expr_graph, select_list, child_key);
             let new_child_key = if aggtype == AggType::AVG {
                 // AVG -> SUM / COUNT
-                let cid = expr_graph.add_node_with_props(CID(qunid, cid), ExprProp::new(data_type.clone()), None);
-                let sum = expr_graph.add_node_with_props(AggFunction(AggType::SUM, false), ExprProp::new(data_type), Some(vec![cid]));
+                // Decimals stay exact: DECIMAL(38,s) / BIGINT, see decimal::result_datatype()
+                let (sum_datatype, avg_datatype) = match data_type {
+                    DataType::Decimal(_, scale) => {
+                        let sum_datatype = (decimal::MAX_PRECISION, scale);
+                        (DataType::Decimal(sum_datatype.0, scale), decimal::result_datatype(ArithOp::Div, sum_datatype, (19, 0)))
+                    }
+                    _ => (data_type.clone(), DataType::Float64),
+                };
+                let cid = expr_graph.add_node_with_props(CID(qunid, cid), ExprProp::new(data_type), None);
+                let sum = expr_graph.add_node_with_props(AggFunction(AggType::SUM, false), ExprProp::new(sum_datatype), Some(vec![cid]));
                 let cnt = expr_graph.add_node_with_props(AggFunction(AggType::COUNT, false), ExprProp::new(DataType::Int64), Some(vec![cid]));
-                expr_graph.add_node_with_props(BinaryExpr(ArithOp::Div), ExprProp::new(DataType::Float64), Some(vec![sum, cnt]))
+                expr_graph.add_node_with_props(BinaryExpr(ArithOp::Div), ExprProp::new(avg_datatype), Some(vec![sum, cnt]))
             } else {
                 expr_graph.add_node_with_props(CID(qunid, cid), ExprProp { data_type }, None)
             };
@@ -276,7 +467,7 @@ impl QueryBlock {
             *expr_key = new_child_key;
         } else if let Column { colname, .. } = &node.value {
             // User error: Unaggregated expression in select-list not in group-by clause
-            return Err(format!("Column {} is referenced in select-list/having-clause but it is not specified in the GROUP-BY list", colname));
+            return Err(KonaError::Bind(format!("Column {} is referenced in select-list/having-clause but it is not specified in the GROUP-BY list", colname)));
         } else if let Some(mut children) = node.children.clone() {
             let mut children2 = vec![];
             for child_key in children.iter_mut() {
@@ -289,34 +480,43 @@ impl QueryBlock {
         Ok(())
     }
 
-    pub fn resolve_column(&self, _env: &Env, prefix: Option<&String>, colname: &String) -> Result<(QunCol, DataType, ColId), String> {
+    pub fn resolve_column(
+        &self, _env: &Env, schema: Option<&String>, prefix: Option<&String>, colname: &String, quoted: bool,
+    ) -> Result<(QunCol, DataType, ColId), KonaError> {
         let mut retval = None;
         let colid = 0;
 
+        // The same table name in two schemas needs its schema, e.g. TPCH.ORDERS.O_ORDERKEY
+        if let Some(prefix) = prefix {
+            if self.quns.iter().filter(|qun| qun.matches_prefix(schema, prefix)).count() > 1 {
+                return Err(KonaError::Bind(format!("Table {} is ambiguous. Qualify {} with a schema or use an alias.", prefix, enquote(colname))));
+            }
+        }
+
         for qun in self.quns.iter() {
             let desc = qun.tabledesc.as_ref().unwrap().clone();
             let field = if let Some(prefix) = prefix {
                 // Prefixed column: look at specific qun
-                if qun.matches_name_or_alias(prefix) {
-                    desc.get_column(colname)
+                if qun.matches_prefix(schema, prefix) {
+                    desc.get_column(colname, quoted)
                 } else {
                     None
                 }
             } else {
                 // Unprefixed column: look at all QUNs
-                desc.get_column(colname)
+                desc.get_column(colname, quoted)
             };
 
             if let Some((colid, field)) = field {
                 if retval.is_none() {
                     retval = Some((QunCol(qun.id, colid), field.data_type.clone()));
                 } else {
-                    return Err(format!("Column {} found in multiple tables. Use tablename prefix to disambiguate.", enquote(colname)));
+                    return Err(KonaError::Bind(format!("Column {} found in multiple tables. Use tablename prefix to disambiguate.", enquote(colname))));
                 }
             }
 
             // Stop looking if we've searched for this column in a specified table
-            if prefix.is_some() && qun.matches_name_or_alias(prefix.unwrap()) {
+            if prefix.is_some() && qun.matches_prefix(schema, prefix.unwrap()) {
                 break;
             }
         }
@@ -324,54 +524,142 @@ impl QueryBlock {
         if let Some(retval) = retval {
             Ok((retval.0, retval.1, colid))
         } else {
-            let colstr = if let Some(prefix) = prefix { format!("{}.{}", prefix, colname) } else { colname.to_string() };
-            Err(format!("Column {} not found in any table.", colstr))
+            let colstr = match (schema, prefix) {
+                (Some(schema), Some(prefix)) => format!("{}.{}.{}", schema, prefix, colname),
+                (None, Some(prefix)) => format!("{}.{}", prefix, colname),
+                _ => colname.to_string(),
+            };
+            Err(KonaError::Bind(format!("Column {} not found in any table.", colstr)))
         }
     }
 
+    // Bring both operands to their common supertype (see datum::common_supertype). A literal that fits the type of the other operand
+    // is converted to it (intcol = 10), otherwise literals are converted in place and other expressions get a CAST node.
     #[tracing::instrument(fields(children = ?children, children_datatypes = ?children_datatypes), skip_all, parent = None)]
-    pub fn harmonize_expr_types(expr_graph: &mut ExprGraph, children: &Vec<ExprKey>, children_datatypes: &Vec<DataType>) -> Result<(DataType, Option<Vec<ExprKey>>), String> {
-        let (lhs, rhs) = (expr_graph.get_value(children[0]), expr_graph.get_value(children[1]));
-        let (lhs_key, rhs_key) = (children[0], children[1]);
+    pub fn harmonize_expr_types(
+        expr_graph: &mut ExprGraph, children: &Vec<ExprKey>, children_datatypes: &Vec<DataType>,
+    ) -> Result<(DataType, Option<Vec<ExprKey>>), KonaError> {
         let (lhs_datatype, rhs_datatype) = (&children_datatypes[0], &children_datatypes[1]);
-        let is_lhs_literal = matches!(lhs, Expr::Literal(_));
-        let is_rhs_literal = matches!(rhs, Expr::Literal(_));
-
-        if children_datatypes[0] != children_datatypes[1] {
-            // Cases:
-            //    Literal vs Literal  (2 == 2.0)
-            //    Expr vs Literal     (intcol vs 2.0) / (floatcol vs 2)
-            //    Literal vs Expr     (same as above, but flipped)
-            //    Expr vs Expr        (intcol = floatcol)
-            match (is_lhs_literal, is_rhs_literal) {
-                (false, false) => {
-                    let (lhs_rank, rhs_rank) = (get_rank(lhs_datatype), get_rank(rhs_datatype));
-                    if lhs_rank > rhs_rank {
-                        if !can_cast_types(rhs_datatype, lhs_datatype) {
-                            return Err(f!("Cannot cast {:?} to {:?}", rhs_datatype, lhs_datatype));
-                        }
-                        // Upcast RHS (e.g. floatcol = intcol) => floatcol = cast(intcol as float)
-                        let cast_props = ExprProp { data_type: lhs_datatype.clone() };
-                        let cast_node = expr_graph.add_node_with_props(Expr::Cast, cast_props, Some(vec![rhs_key]));
-                        return Ok((lhs_datatype.clone(), Some(vec![lhs_key, cast_node])));
-                    } else {
-                        // Upcast LHS (e.g. intcol = floatcol)
-                    }
-                }
-                _ => {
-                    let lhsstr = lhs_key.describe(&expr_graph, false);
-                    let rhsstr = rhs_key.describe(&expr_graph, false);
-                    panic!("Cannot harmonize {}: {:?} and {}: {:?}", lhsstr, lhs_datatype, rhsstr, rhs_datatype);
+        if lhs_datatype == rhs_datatype {
+            return Ok((lhs_datatype.clone(), Some(children.clone())));
+        }
+
+        let is_literal = |key: ExprKey| matches!(expr_graph.get_value(key), Expr::Literal(_));
+        let fitting_literal = (0..2).find(|&ix| {
+            if let (Literal(value), false) = (expr_graph.get_value(children[ix]), is_literal(children[1 - ix])) {
+                cast_literal(value, &children_datatypes[1 - ix]).is_ok()
+            } else {
+                false
+            }
+        });
+
+        let target = if let Some(ix) = fitting_literal {
+            children_datatypes[1 - ix].clone()
+        } else {
+            common_supertype(lhs_datatype, rhs_datatype).ok_or_else(|| {
+                let lhsstr = children[0].describe(expr_graph, false);
+                let rhsstr = children[1].describe(expr_graph, false);
+                KonaError::Bind(f!("Incompatible types: {} is {:?} but {} is {:?}", lhsstr, lhs_datatype, rhsstr, rhs_datatype))
+            })?
+        };
+        let children = Self::coerce_operands(expr_graph, children, children_datatypes, &target)?;
+        Ok((target, Some(children)))
+    }
+
+    // A parameter marker has no type of its own. It takes the type of the other operand of a comparison or arithmetic, or
+    // the target type of a CAST. Markers that remain untyped are rejected by PREPARE.
+    fn infer_parameter_types(expr_graph: &mut ExprGraph, expr_key: ExprKey, children: &[ExprKey], children_datatypes: &mut [DataType]) {
+        let is_untyped =
+            |expr_graph: &ExprGraph, key: ExprKey| matches!(expr_graph.get_value(key), Parameter(_)) && *expr_graph.get_properties(key).data_type() == DataType::Null;
+        let datatype = match expr_graph.get_value(expr_key) {
+            RelExpr(..) | BinaryExpr(..) => {
+                children.iter().zip(children_datatypes.iter()).find(|(&key, _)| !is_untyped(expr_graph, key)).map(|(_, datatype)| datatype.clone())
+            }
+            Cast | TryCast => Some(expr_graph.get_properties(expr_key).data_type().clone()),
+            _ => None,
+        };
+        if let Some(datatype) = datatype {
+            for (&child_key, child_datatype) in children.iter().zip(children_datatypes.iter_mut()) {
+                if is_untyped(expr_graph, child_key) {
+                    expr_graph.get_mut(child_key).properties.set_data_type(datatype.clone());
+                    *child_datatype = datatype.clone();
                 }
             }
-            //return Err(f!("Binary operands don't have identical types: {:?} vs {:?}", children_datatypes[0], children_datatypes[1]));
+        }
+    }
+
+    // Constant-fold arithmetic on two (already harmonized) numeric literals
+    fn fold_numeric_literals(expr_graph: &ExprGraph, arithop: ArithOp, datatype: DataType, children: Vec<ExprKey>) -> Result<ResolvedExpr, KonaError> {
+        let (lhs, rhs) = match (expr_graph.get_value(children[0]), expr_graph.get_value(children[1])) {
+            (Literal(lhs), Literal(rhs)) => (lhs, rhs),
+            _ => return Ok((None, datatype, Some(children))),
+        };
+        let (lhs, rhs) = if arithop == ArithOp::Div && lhs != &Null && rhs != &Null {
+            (&cast_literal(lhs, &DataType::Float64).map_err(KonaError::Bind)?, &cast_literal(rhs, &DataType::Float64).map_err(KonaError::Bind)?)
         } else {
+            (lhs, rhs)
+        };
+        let overflow = || KonaError::Bind(f!("Numeric overflow in {} {} {}", lhs, arithop, rhs));
+        let value = match (lhs, rhs) {
+            (Null, _) | (_, Null) => Null,
+            (Int32(l), Int32(r)) => {
+                let value = match arithop {
+                    ArithOp::Add => l.checked_add(*r),
+                    ArithOp::Sub => l.checked_sub(*r),
+                    ArithOp::Mul => l.checked_mul(*r),
+                    ArithOp::Div => l.checked_div(*r),
+                };
+                Int32(value.ok_or_else(overflow)?)
+            }
+            (Int64(l), Int64(r)) => {
+                let value = match arithop {
+                    ArithOp::Add => l.checked_add(*r),
+                    ArithOp::Sub => l.checked_sub(*r),
+                    ArithOp::Mul => l.checked_mul(*r),
+                    ArithOp::Div => l.checked_div(*r),
+                };
+                Int64(value.ok_or_else(overflow)?)
+            }
+            (Float64(l), Float64(r)) => {
+                let (l, r) = (f64::from(*l), f64::from(*r));
+                let value = match arithop {
+                    ArithOp::Add => l + r,
+                    ArithOp::Sub => l - r,
+                    ArithOp::Mul => l * r,
+                    ArithOp::Div => l / r,
+                };
+                Float64(F64::from(value))
+            }
+            _ => return Ok((None, datatype, Some(children))),
+        };
+        Ok((Some(Literal(value)), datatype, None))
+    }
+
+    // Convert operands to `target`: literals in place, everything else via a CAST node
+    fn coerce_operands(expr_graph: &mut ExprGraph, children: &[ExprKey], children_datatypes: &[DataType], target: &DataType) -> Result<Vec<ExprKey>, KonaError> {
+        let mut children = children.to_vec();
+        for ix in 0..children.len() {
+            if children_datatypes[ix] == *target {
+                continue;
+            }
+            let child_key = children[ix];
+            if let Literal(value) = expr_graph.get_value(child_key) {
+                let value = cast_literal(value, target).map_err(KonaError::Bind)?;
+                let node = expr_graph.get_mut(child_key);
+                node.value = Literal(value);
+                node.properties.set_data_type(target.clone());
+            } else {
+                if !can_cast_types(&children_datatypes[ix], target) {
+                    return Err(KonaError::Bind(f!("Cannot cast {:?} to {:?}", children_datatypes[ix], target)));
+                }
+                children[ix] = expr_graph.add_node_with_props(Expr::Cast, ExprProp::new(target.clone()), Some(vec![child_key]));
+            }
         }
-        Ok((children_datatypes[0].clone(), Some(children.clone())))
+        Ok(children)
     }
 
     #[tracing::instrument(fields(expr = expr_key.to_string()), skip_all, parent = None)]
-    pub fn resolve_expr(&self, env: &Env, expr_graph: &mut ExprGraph, metadata: &mut QGMMetadata, expr_key: ExprKey, agg_fns_allowed: bool) -> Result<(), String> {
+    pub fn resolve_expr(&self, env: &Env, expr_graph: &mut ExprGraph, metadata: &mut QGMMetadata, expr_key: ExprKey, agg_fns_allowed: bool) -> Result<(), KonaError> {
         debug!("Unresolved expression: {} ...", expr_key.describe(expr_graph, false));
 
         let children_agg_fns_allowed = if let AggFunction(_, _) = expr_graph.get(expr_key).value {
@@ -385,11 +673,12 @@ impl QueryBlock {
         let mut children_datatypes = vec![];
         let children = expr_graph.get(expr_key).children.clone();
         if let Some(children) = children.clone() {
-            for child_key in children {
+            for &child_key in children.iter() {
                 self.resolve_expr(env, expr_graph, metadata, child_key, children_agg_fns_allowed)?;
                 let datatype = expr_graph.get(child_key).properties.data_type().clone();
                 children_datatypes.push(datatype);
             }
+            Self::infer_parameter_types(expr_graph, expr_key, &children, &mut children_datatypes);
         }
 
         // Now resolve current expr node
@@ -402,48 +691,54 @@ impl QueryBlock {
                 (None, datatype, children)
             }
             RelExpr(..) => {
-                // Check argument types
-                if children_datatypes[0] != children_datatypes[1] {
-                    return Err(f!("Datatype mismatch: {:?} vs {:?}  ({}:{})", children_datatypes[0], children_datatypes[1], file!(), line!()));
-                }
+                // Dates and timestamps compare against string literals and against each other
+                let mut children = children.unwrap();
+                Self::coerce_temporal_operands(expr_graph, &mut children, &mut children_datatypes)?;
+                Self::coerce_decimal_operands(expr_graph, &mut children, &mut children_datatypes)?;
+                let (_, children) = Self::harmonize_expr_types(expr_graph, &children, &children_datatypes)?;
                 (None, DataType::Boolean, children)
             }
             BinaryExpr(arithop) => {
                 let arithop = *arithop;
                 // Check argument types
-                if is_numeric(&children_datatypes[0]) && is_numeric(&children_datatypes[1]) {
+                if children_datatypes.iter().any(|dt| is_temporal(dt) || *dt == temporal::interval_datatype()) {
+                    Self::resolve_temporal_arithmetic(expr_graph, arithop, children.unwrap(), &children_datatypes)?
+                } else if children_datatypes.iter().any(is_decimal) {
+                    Self::resolve_decimal_arithmetic(expr_graph, arithop, children.unwrap(), &mut children_datatypes)?
+                } else if children_datatypes.iter().all(|dt| is_numeric(dt) || *dt == DataType::Null) {
                     let (datatype, children) = Self::harmonize_expr_types(expr_graph, &children.unwrap(), &children_datatypes)?;
                     let datatype = match arithop {
                         ArithOp::Add | ArithOp::Sub | ArithOp::Mul => datatype,
                         ArithOp::Div => DataType::Float64,
                     };
-                    (None, datatype, children)
+                    Self::fold_numeric_literals(expr_graph, arithop, datatype, children.unwrap())?
                 } else {
-                    return Err("Binary operands must be numeric types".to_string());
+                    return Err(KonaError::Bind("Binary operands must be numeric types".to_string()));
                 }
             }
-            Column { prefix, colname, .. } => {
-                let (quncol, datatype, ..) = self.resolve_column(env, prefix.as_ref(), colname)?;
-                let resolved_expr = Some(Column { prefix: prefix.clone(), colname: colname.clone(), qunid: quncol.0, colid: quncol.1 });
+            Column { schema, prefix, colname, quoted, .. } => {
+                let (quncol, datatype, ..) = self.resolve_column(env, schema.as_ref(), prefix.as_ref(), colname, *quoted)?;
+                let resolved_expr =
+                    Some(Column { schema: schema.clone(), prefix: prefix.clone(), colname: colname.clone(), quoted: *quoted, qunid: quncol.0, colid: quncol.1 });
                 (resolved_expr, datatype, None)
             }
             LogExpr(..) => (None, DataType::Boolean, children),
-            Literal(Utf8(_)) => (None, DataType::Utf8, children),
-            Literal(Int64(_)) => (None, DataType::Int64, children),
-            Literal(Float64(_)) => (None, DataType::Float64, children),
-            Literal(Boolean(_)) => (None, DataType::Boolean, children),
+            Literal(value) => (None, value.datatype(), children),
+            Parameter(_) => (None, props.data_type().clone(), children),
             AggFunction(aggtype, ..) => {
                 if !agg_fns_allowed {
-                    return Err(format!("Aggregate function {:?} not allowed.", aggtype));
+                    return Err(KonaError::Bind(format!("Aggregate function {:?} not allowed.", aggtype)));
                 }
                 let datatype = match aggtype {
                     AggType::COUNT => DataType::Int64,
                     AggType::MIN | AggType::MAX => children_datatypes[0].clone(),
                     AggType::SUM => {
-                        if is_numeric(&children_datatypes[0]) {
+                        if let DataType::Decimal(_, scale) = children_datatypes[0] {
+                            DataType::Decimal(decimal::MAX_PRECISION, scale)
+                        } else if is_numeric(&children_datatypes[0]) {
                             children_datatypes[0].clone()
                         } else {
-                            return Err(format!("SUM() {:?} only allowed for numeric datatypes.", aggtype));
+                            return Err(KonaError::Bind(format!("SUM() {:?} only allowed for numeric datatypes.", aggtype)));
                         }
                     }
                     AggType::AVG => DataType::Float64,
@@ -454,16 +749,52 @@ impl QueryBlock {
                 if is_numeric(&children_datatypes[0]) {
                     (None, children_datatypes[0].clone(), children)
                 } else {
-                    return Err(String::from("Only numeric datatypes can be negated."));
+                    return Err(KonaError::Bind(String::from("Only numeric datatypes can be negated.")));
                 }
             }
-            Cast => {
-                // Perform cast
-                let child_expr_key = expr_graph.get(expr_key).children.as_ref().unwrap()[0];
-                let from_expr = expr_graph.get_value(child_expr_key);
-                let new_value = Self::resolve_cast(from_expr, props.data_type())?;
-                let datatype = new_value.datatype();
-                (Some(Literal(new_value)), datatype, None)
+            Extract(field) | DateTrunc(field) => {
+                let field = *field;
+                if !is_temporal(&children_datatypes[0]) {
+                    return Err(KonaError::Bind(f!("{} requires a date or timestamp argument, found {:?}", expr.name(), children_datatypes[0])));
+                }
+                let is_extract = matches!(expr, Extract(..));
+                let child_key = children.as_ref().unwrap()[0];
+                let kernel = |array: &dyn Array| if is_extract { temporal::extract(array, field) } else { temporal::date_trunc(array, field) };
+                if let Literal(value) = expr_graph.get_value(child_key) {
+                    // Constant-fold
+                    let value = temporal::eval_datum(value, kernel).map_err(KonaError::Bind)?;
+                    let datatype = value.datatype();
+                    (Some(Literal(value)), datatype, None)
+                } else if is_extract {
+                    (None, DataType::Int64, children)
+                } else {
+                    (None, children_datatypes[0].clone(), children)
+                }
+            }
+            Cast | TryCast => {
+                let to_datatype = props.data_type().clone();
+                if let CastResult::CannotCast = check_castability(&children_datatypes[0], &to_datatype) {
+                    return Err(KonaError::Bind(f!("Cannot cast {:?} to {:?}", children_datatypes[0], to_datatype)));
+                }
+                // In lenient mode, a CAST behaves like TRY_CAST
+                let is_try = matches!(expr, TryCast) || env.settings.lenient_cast();
+                let child_key = children.as_ref().unwrap()[0];
+                let folded_value = match expr_graph.get_value(child_key) {
+                    // Constant-fold. NULLs are left to the runtime cast, which gives them the right type.
+                    Literal(value) if *value != Null => match Self::resolve_cast(value, &to_datatype) {
+                        Ok(value) => Some(value),
+                        Err(_) if is_try => None,
+                        Err(err) => return Err(err),
+                    },
+                    _ => None,
+                };
+                if let Some(value) = folded_value {
+                    (Some(Literal(value)), to_datatype, None)
+                } else if is_try {
+                    (Some(TryCast), to_datatype, children)
+                } else {
+                    (None, to_datatype, children)
+                }
             }
             _ => {
                 panic!("Unexpected expression found: {:?}", &expr);
@@ -482,28 +813,196 @@ impl QueryBlock {
         Ok(())
     }
 
-    pub fn resolve_cast(from_expr: &Expr, to_datatype: &DataType) -> Result<Datum, String> {
-        if let Literal(from_value) = from_expr {
-            let to_value = match (from_value, to_datatype) {
-                (Utf8(s), DataType::Int64) => {
-                    let to_value: i64 = s.parse::<i64>().map_err(stringify)?;
-                    Int64(to_value)
+    // Coerce operands of a comparison involving dates/timestamps to a common type. String and date literals are converted in place,
+    // other expressions get a CAST node.
+    pub fn coerce_temporal_operands(expr_graph: &mut ExprGraph, children: &mut [ExprKey], children_datatypes: &mut [DataType]) -> Result<(), KonaError> {
+        let target = match (&children_datatypes[0], &children_datatypes[1]) {
+            (lhs, rhs) if lhs == rhs => return Ok(()),
+            (lhs @ DataType::Timestamp(..), _) => lhs.clone(),
+            (_, rhs @ DataType::Timestamp(..)) => rhs.clone(),
+            (DataType::Date32, _) | (_, DataType::Date32) => DataType::Date32,
+            _ => return Ok(()),
+        };
+
+        for ix in 0..2 {
+            if children_datatypes[ix] == target {
+                continue;
+            }
+            let child_key = children[ix];
+            if let Literal(value) = expr_graph.get_value(child_key) {
+                let value = temporal::coerce_temporal_literal(value, &target).map_err(KonaError::Bind)?;
+                let node = expr_graph.get_mut(child_key);
+                node.value = Literal(value);
+                node.properties.set_data_type(target.clone());
+            } else if is_temporal(&children_datatypes[ix]) {
+                children[ix] = expr_graph.add_node_with_props(Expr::Cast, ExprProp::new(target.clone()), Some(vec![child_key]));
+            } else {
+                continue;
+            }
+            children_datatypes[ix] = target.clone();
+        }
+        Ok(())
+    }
+
+    // Date/timestamp arithmetic:
+    //    DATE|TIMESTAMP +/- INTERVAL  => same type (DATE + sub-day interval yields a TIMESTAMP)
+    //    INTERVAL + DATE|TIMESTAMP    => same as above
+    //    DATE - DATE                  => number of days
+    pub fn resolve_temporal_arithmetic(
+        expr_graph: &mut ExprGraph, arithop: ArithOp, mut children: Vec<ExprKey>, children_datatypes: &[DataType],
+    ) -> Result<ResolvedExpr, KonaError> {
+        let interval_datatype = temporal::interval_datatype();
+        let (lhs_datatype, rhs_datatype) = (&children_datatypes[0], &children_datatypes[1]);
+
+        if arithop == ArithOp::Add && *lhs_datatype == interval_datatype && is_temporal(rhs_datatype) {
+            children.swap(0, 1);
+            return Self::resolve_temporal_arithmetic(expr_graph, arithop, children, &[rhs_datatype.clone(), lhs_datatype.clone()]);
+        }
+
+        match (lhs_datatype, arithop, rhs_datatype) {
+            (lhs, ArithOp::Add | ArithOp::Sub, rhs) if is_temporal(lhs) && *rhs == interval_datatype => {
+                let interval = if let Literal(value) = expr_graph.get_value(children[1]) {
+                    value.try_as_interval().unwrap()
+                } else {
+                    return Err(KonaError::Bind(String::from("Only INTERVAL literals can be added to dates and timestamps.")));
+                };
+                let interval = if arithop == ArithOp::Sub { -interval } else { interval };
+
+                if let Literal(value) = expr_graph.get_value(children[0]) {
+                    // Constant-fold
+                    let value = temporal::eval_datum(value, |array| temporal::add_interval(array, interval)).map_err(KonaError::Bind)?;
+                    let datatype = value.datatype();
+                    Ok((Some(Literal(value)), datatype, None))
+                } else if *lhs == DataType::Date32 && interval.ns() != 0 {
+                    Ok((None, temporal::timestamp_datatype(None), Some(children)))
+                } else {
+                    Ok((None, lhs.clone(), Some(children)))
                 }
-                (Utf8(s), DataType::Date32) => {
-                    let date: chrono::NaiveDate = s.parse::<chrono::NaiveDate>().map_err(stringify)?;
-                    let date: i32 = chrono::Datelike::num_days_from_ce(&date) - arrow2::temporal_conversions::EPOCH_DAYS_FROM_CE;
-                    Date32(date)
+            }
+            (DataType::Date32, ArithOp::Sub, DataType::Date32) => Ok((None, DataType::Int64, Some(children))),
+            _ => Err(KonaError::Bind(f!("Operator {} not supported between {:?} and {:?}", arithop, lhs_datatype, rhs_datatype))),
+        }
+    }
+
+    // Numeric literals used alongside a decimal become exact decimal literals, e.g. 0.05 => DECIMAL(2,2)
+    fn decimalize_literals(expr_graph: &mut ExprGraph, children: &[ExprKey], children_datatypes: &mut [DataType]) {
+        for ix in 0..2 {
+            if !is_decimal(&children_datatypes[1 - ix]) {
+                continue;
+            }
+            let node = expr_graph.get_mut(children[ix]);
+            if let Literal(value) = &node.value {
+                if let Some(value) = decimal::to_decimal_datum(value) {
+                    children_datatypes[ix] = value.datatype();
+                    node.properties.set_data_type(value.datatype());
+                    node.value = Literal(value);
                 }
-                _ => todo!(),
-            };
-            return Ok(to_value);
+            }
+        }
+    }
+
+    // Mixing a decimal with a float yields a float, as in SQL Server. Returns true if such a cast was added.
+    fn cast_decimals_to_float(expr_graph: &mut ExprGraph, children: &mut [ExprKey], children_datatypes: &mut [DataType]) -> bool {
+        if !children_datatypes.contains(&DataType::Float64) {
+            return false;
+        }
+        for ix in 0..2 {
+            if is_decimal(&children_datatypes[ix]) {
+                children[ix] = expr_graph.add_node_with_props(Expr::Cast, ExprProp::new(DataType::Float64), Some(vec![children[ix]]));
+                children_datatypes[ix] = DataType::Float64;
+            }
+        }
+        true
+    }
+
+    // Coerce operands of a comparison involving decimals to a common DECIMAL(p,s). Literals are converted in place,
+    // other expressions get a CAST node.
+    pub fn coerce_decimal_operands(expr_graph: &mut ExprGraph, children: &mut [ExprKey], children_datatypes: &mut [DataType]) -> Result<(), KonaError> {
+        if !children_datatypes.iter().any(is_decimal) || children_datatypes[0] == children_datatypes[1] {
+            return Ok(());
+        }
+        Self::decimalize_literals(expr_graph, children, children_datatypes);
+        if Self::cast_decimals_to_float(expr_graph, children, children_datatypes) {
+            return Ok(());
+        }
+
+        let target = match (decimal::precision_scale(&children_datatypes[0]), decimal::precision_scale(&children_datatypes[1])) {
+            (Some(lhs), Some(rhs)) => decimal::common_datatype(lhs, rhs),
+            _ => return Ok(()),
+        };
+        for ix in 0..2 {
+            if children_datatypes[ix] == target {
+                continue;
+            }
+            let child_key = children[ix];
+            if let Literal(value) = expr_graph.get_value(child_key) {
+                let value = decimal::cast_datum(value, &target).map_err(KonaError::Bind)?;
+                let node = expr_graph.get_mut(child_key);
+                node.value = Literal(value);
+                node.properties.set_data_type(target.clone());
+            } else {
+                children[ix] = expr_graph.add_node_with_props(Expr::Cast, ExprProp::new(target.clone()), Some(vec![child_key]));
+            }
+            children_datatypes[ix] = target.clone();
+        }
+        Ok(())
+    }
+
+    // Decimal arithmetic. Integers take part as DECIMAL(p,0), see decimal::result_datatype() for the result type.
+    pub fn resolve_decimal_arithmetic(
+        expr_graph: &mut ExprGraph, arithop: ArithOp, mut children: Vec<ExprKey>, children_datatypes: &mut [DataType],
+    ) -> Result<ResolvedExpr, KonaError> {
+        Self::decimalize_literals(expr_graph, &children, children_datatypes);
+        if Self::cast_decimals_to_float(expr_graph, &mut children, children_datatypes) {
+            return Ok((None, DataType::Float64, Some(children)));
+        }
+
+        let (lhs, rhs) = match (decimal::precision_scale(&children_datatypes[0]), decimal::precision_scale(&children_datatypes[1])) {
+            (Some(lhs), Some(rhs)) => (lhs, rhs),
+            _ => return Err(KonaError::Bind(f!("Operator {} not supported between {:?} and {:?}", arithop, children_datatypes[0], children_datatypes[1]))),
+        };
+
+        if let (Literal(Decimal(lhs_value, ..)), Literal(Decimal(rhs_value, ..))) = (expr_graph.get_value(children[0]), expr_graph.get_value(children[1])) {
+            // Constant-fold
+            let lhs_array = decimal::literal_to_array(*lhs_value, lhs.0, lhs.1, 1);
+            let rhs_array = decimal::literal_to_array(*rhs_value, rhs.0, rhs.1, 1);
+            let array = decimal::arith(&*lhs_array, arithop, &*rhs_array).map_err(KonaError::Bind)?;
+            let datatype = array.data_type().clone();
+            let (precision, scale) = decimal::precision_scale(&datatype).unwrap();
+            let value = array.as_any().downcast_ref::<PrimitiveArray<i128>>().unwrap().value(0);
+            Ok((Some(Literal(Decimal(value, precision, scale))), datatype, None))
         } else {
-            panic!("resolve_cast(): Can only cast to literals")
+            Ok((None, decimal::result_datatype(arithop, lhs, rhs), Some(children)))
         }
     }
 
-    pub fn resolve_star(&mut self, _env: &Env, expr_graph: &mut ExprGraph) -> Result<(), String> {
-        let select_list = replace(&mut self.select_list, vec![]);
+    // CAST of a literal. Anything not handled here is left to datum::cast_literal().
+    pub fn resolve_cast(from_value: &Datum, to_datatype: &DataType) -> Result<Datum, KonaError> {
+        let error = || KonaError::Bind(f!("Cannot CAST {} to {:?}", from_value, to_datatype));
+        let to_value = match (from_value, to_datatype) {
+            (Utf8(s), DataType::Int32) => Int32(s.trim().parse::<i32>().map_err(|_| error())?),
+            (Utf8(s), DataType::Int64) => Int64(s.trim().parse::<i64>().map_err(|_| error())?),
+            (Utf8(s), DataType::Float64) => Float64(F64::from(s.trim().parse::<f64>().map_err(|_| error())?)),
+            (Utf8(s), DataType::Boolean) => Boolean(yes_or_no(&s.trim().to_uppercase()).or_else(|| s.trim().parse::<bool>().ok()).ok_or_else(error)?),
+            (Float64(value), DataType::Int64) => {
+                // Truncates toward zero
+                let value = f64::from(*value).trunc();
+                if value < i64::MIN as f64 || value >= i64::MAX as f64 {
+                    return Err(error());
+                }
+                Int64(value as i64)
+            }
+            (Int32(value), DataType::Utf8) => Utf8(value.to_string()),
+            (Int64(value), DataType::Utf8) => Utf8(value.to_string()),
+            (Float64(value), DataType::Utf8) => Utf8(f64::from(*value).to_string()),
+            (Boolean(value), DataType::Utf8) => Utf8(value.to_string()),
+            _ => cast_literal(from_value, to_datatype).map_err(|_| error())?,
+        };
+        Ok(to_value)
+    }
+
+    pub fn resolve_star(&mut self, _env: &Env, expr_graph: &mut ExprGraph) -> Result<(), KonaError> {
+        let select_list = std::mem::take(&mut self.select_list);
         let mut new_select_list = vec![];
 
         for ne in select_list.into_iter() {
@@ -516,11 +1015,11 @@ impl QueryBlock {
                 // Handle two cases:
                 //    SELECT * (no prefix)
                 //    SELECT TABLENAME.* (matching prefix)
-                let qun_iter = self.quns.iter().filter(|qun| if prefix.is_none() || qun.get_alias() == prefix.as_ref() { true } else { false });
+                let qun_iter = self.quns.iter().filter(|qun| prefix.is_none() || qun.get_alias() == prefix.as_ref());
                 for qun in qun_iter {
                     let desc = &**qun.tabledesc.as_ref().unwrap();
                     for field in desc.fields().iter() {
-                        let column = Column { prefix: prefix.clone(), colname: field.name.clone(), qunid: 0, colid: 0 };
+                        let column = Column { schema: None, prefix: prefix.clone(), colname: field.name.clone(), quoted: true, qunid: 0, colid: 0 };
                         let new_expr_key = expr_graph.add_node(column, None);
                         let named_expr = NamedExpr { alias: None, expr_key: new_expr_key };
                         new_select_list.push(named_expr);
@@ -534,3 +1033,122 @@ impl QueryBlock {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{includes::*, testing::*};
+
+    fn rows(session: &mut TestSession, sql: &str) -> Vec<Vec<Datum>> {
+        query(session, sql).unwrap_or_else(|err| panic!("{}: {}", sql, err))
+    }
+
+    #[test]
+    fn group_by_ordinals_and_aliases() {
+        let mut session = session();
+        create_table(&mut session, "T", "CAT STRING, N INT", &["a,1", "a,2", "b,5"]);
+        let expected = vec![vec![Utf8("a".into()), Int64(3)], vec![Utf8("b".into()), Int64(5)]];
+        assert_eq!(rows(&mut session, "SELECT CAT, SUM(N) FROM T GROUP BY 1"), expected);
+        assert_eq!(rows(&mut session, "SELECT CAT AS C, SUM(N) FROM T GROUP BY C"), expected);
+        assert_eq!(rows(&mut session, "SELECT CAT AS C, SUM(N) AS TOTAL FROM T GROUP BY C HAVING TOTAL > 4"), vec![vec![Utf8("b".into()), Int64(5)]]);
+        // A column of the table wins over an alias of the same name
+        assert_eq!(rows(&mut session, "SELECT CAT AS N, SUM(N) FROM T GROUP BY CAT"), expected);
+    }
+
+    #[test]
+    fn bad_output_references() {
+        let mut session = session();
+        create_table(&mut session, "T", "CAT STRING, N INT", &["a,1"]);
+        for (sql, message) in [
+            ("SELECT CAT, SUM(N) FROM T GROUP BY 3", "GROUP BY position 3 is not in select list"),
+            ("SELECT CAT, SUM(N) FROM T GROUP BY 0", "GROUP BY position 0 is not in select list"),
+            ("SELECT * FROM T GROUP BY 1", "GROUP BY position 1 cannot be used with SELECT *"),
+            ("SELECT CAT, SUM(N) FROM T GROUP BY 2", "GROUP BY cannot refer to an aggregate"),
+            ("SELECT CAT AS X, N AS X FROM T GROUP BY X", "GROUP BY reference X is ambiguous"),
+        ] {
+            let err = query(&mut session, sql).unwrap_err();
+            assert!(matches!(&err, KonaError::Bind(msg) if msg.contains(message)), "{}: {}", sql, err);
+        }
+    }
+
+    #[test]
+    fn order_by_sorts_the_result() {
+        let mut session = session();
+        create_table(&mut session, "T", "CAT STRING, N INT", &["a,1", "a,2", "b,5", "c,1", "d,"]);
+        let strs = |values: &[&str]| values.iter().map(|&value| vec![Utf8(value.into())]).collect::<Vec<_>>();
+
+        // NULLs sort as if they were larger than any value
+        assert_eq!(
+            query_in_order(&mut session, "SELECT CAT, SUM(N) AS TOTAL FROM T GROUP BY CAT ORDER BY TOTAL DESC").unwrap(),
+            vec![vec![Utf8("d".into()), Null], vec![Utf8("b".into()), Int64(5)], vec![Utf8("a".into()), Int64(3)], vec![Utf8("c".into()), Int64(1)]]
+        );
+        assert_eq!(query_in_order(&mut session, "SELECT CAT AS C FROM T ORDER BY C DESC, N").unwrap(), strs(&["d", "c", "b", "a", "a"]));
+        assert_eq!(query_in_order(&mut session, "SELECT CAT FROM T ORDER BY N, 1").unwrap(), strs(&["a", "c", "a", "b", "d"]));
+        assert_eq!(query_in_order(&mut session, "SELECT CAT FROM T WHERE N > 0 ORDER BY 0 - N, CAT").unwrap(), strs(&["b", "a", "a", "c"]));
+
+        create_table(&mut session, "U", "CAT STRING, NAME STRING", &["a,apple", "b,banana", "c,cherry"]);
+        assert_eq!(query_in_order(&mut session, "SELECT NAME FROM T, U WHERE T.CAT = U.CAT ORDER BY N DESC, NAME").unwrap(), strs(&["banana", "apple", "apple", "cherry"]));
+
+        // References are checked first
+        assert!(matches!(query(&mut session, "SELECT CAT FROM T ORDER BY 2"), Err(KonaError::Bind(_))));
+    }
+
+    #[test]
+    fn same_table_name_in_two_schemas() {
+        let mut session = session();
+        execute(&mut session, "CREATE SCHEMA TPCH").unwrap();
+        execute(&mut session, "CREATE SCHEMA NORTHWIND").unwrap();
+        create_table(&mut session, "TPCH.ORDERS", "ID INT, TOTAL INT", &["1,10", "2,20"]);
+        create_table(&mut session, "NORTHWIND.ORDERS", "ID INT, NAME STRING", &["2,b"]);
+
+        assert_eq!(query(&mut session, "SELECT TPCH.ORDERS.ID, ORDERS.TOTAL FROM TPCH.ORDERS WHERE TPCH.ORDERS.ID = 2").unwrap(), vec![vec![Int64(2), Int64(20)]]);
+        let sql = "SELECT TPCH.ORDERS.TOTAL, NORTHWIND.ORDERS.NAME FROM TPCH.ORDERS, NORTHWIND.ORDERS WHERE TPCH.ORDERS.ID = NORTHWIND.ORDERS.ID";
+        assert!(query(&mut session, sql).unwrap().contains(&vec![Int64(20), Utf8("b".into())]));
+
+        for (sql, message) in [
+            ("SELECT ORDERS.ID FROM TPCH.ORDERS, NORTHWIND.ORDERS", "Table ORDERS is ambiguous"),
+            ("SELECT TOTAL FROM TPCH.ORDERS, TPCH.ORDERS", "same aliases"),
+            ("SELECT ID FROM TPCH.ORDERS AS O, NORTHWIND.ORDERS AS O", "same aliases"),
+            ("SELECT TPCH.ORDERS.ID FROM TPCH.ORDERS AS O", "Column TPCH.ORDERS.ID not found"),
+            ("SELECT NORTHWIND.ORDERS.TOTAL FROM TPCH.ORDERS, NORTHWIND.ORDERS", "Column NORTHWIND.ORDERS.TOTAL not found"),
+        ] {
+            let err = query(&mut session, sql).unwrap_err();
+            assert!(matches!(&err, KonaError::Bind(msg) if msg.contains(message)), "{}: {}", sql, err);
+        }
+    }
+
+    #[test]
+    fn views_are_expanded_with_their_column_names() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT, S STRING", &["1,a", "2,b", "3,c"]);
+        execute(&mut session, "CREATE VIEW V (K, NAME) AS SELECT ID, S FROM T WHERE ID > 1").unwrap();
+        execute(&mut session, "CREATE VIEW W AS SELECT K FROM V WHERE NAME = 'c'").unwrap();
+
+        assert_eq!(query(&mut session, "SELECT NAME, K FROM V WHERE K < 3").unwrap(), vec![vec![Utf8("b".into()), Int64(2)]]);
+        assert_eq!(query(&mut session, "SELECT K FROM W").unwrap(), vec![vec![Int64(3)]]);
+        let rows = query(&mut session, "DESCRIBE VIEW W").unwrap();
+        assert!(rows.contains(&vec![Utf8("DEFINITION".into()), Utf8("SELECT K FROM V WHERE NAME = 'c'".into())]), "{:?}", rows);
+
+        for (sql, message) in [
+            ("CREATE VIEW X (A) AS SELECT ID, S FROM T", "names 1 columns but its query returns 2"),
+            ("CREATE VIEW X AS SELECT ID, ID FROM T", "needs a distinct name for every column"),
+            ("CREATE VIEW V AS SELECT ID FROM T", "View PUBLIC.V already exists"),
+            ("DESCRIBE VIEW T", "PUBLIC.T is not a view"),
+        ] {
+            let err = query(&mut session, sql).unwrap_err();
+            assert!(matches!(&err, KonaError::Bind(msg) if msg.contains(message)), "{}: {}", sql, err);
+        }
+    }
+
+    #[test]
+    fn views_defined_in_terms_of_themselves_are_rejected() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT", &["1"]);
+        execute(&mut session, "CREATE VIEW V1 AS SELECT ID FROM T").unwrap();
+        execute(&mut session, "CREATE VIEW V2 AS SELECT ID FROM V1").unwrap();
+        // V1 still reads T when the new definition is checked, the cycle shows up once V1 is used
+        execute(&mut session, "CREATE OR REPLACE VIEW V1 AS SELECT ID FROM V2").unwrap();
+
+        let err = query(&mut session, "SELECT ID FROM V2").unwrap_err();
+        assert!(matches!(&err, KonaError::Bind(msg) if msg.contains("PUBLIC.V2 -> PUBLIC.V1 -> PUBLIC.V2")), "{}", err);
+    }
+}
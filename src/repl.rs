@@ -0,0 +1,204 @@
+// repl: Interactive shell. Statements end with a `;` outside quotes and comments and may span several lines. Lines starting with `\` are meta
+// commands. All statements run in the one Env, so tables, views, settings and prepared statements carry over.
+
+use std::time::Instant;
+
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+use crate::{includes::*, logging, metadata::TableType, run_script};
+
+const HISTORY_FILE: &str = ".kona_history";
+
+const HELP: &str = "\
+Statements end with ; and may span several lines. Ctrl-C discards the statement being typed, Ctrl-D quits.
+
+\\d                List tables and views
+\\d NAME           Describe a table or view
+\\timing [on|off]  Print the time each statement takes
+\\?                Show this help
+\\q                Quit";
+
+struct Shell {
+    timing: bool,
+}
+
+pub fn run(env: &mut Env) -> Result<(), KonaError> {
+    let mut editor = DefaultEditor::new().map_err(|err| KonaError::io("Cannot start the shell", std::io::Error::other(err)))?;
+    let history = std::env::var("HOME").ok().map(|home| f!("{home}/{HISTORY_FILE}"));
+    if let Some(history) = &history {
+        editor.load_history(history).unwrap_or_default();
+    }
+
+    // Only warnings and errors unless the session asks for more with SET TRACE
+    if !logging::is_started() {
+        logging::init("warn");
+    }
+
+    println!("kona shell. Type \\? for help.");
+    let mut shell = Shell { timing: false };
+    let mut buffer = String::new();
+    loop {
+        let prompt = if buffer.is_empty() { "kona> " } else { "   -> " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                if buffer.is_empty() {
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    if let Some(command) = line.strip_prefix('\\') {
+                        editor.add_history_entry(line).unwrap_or_default();
+                        match shell.meta_command(env, command) {
+                            Ok(true) => break,
+                            Ok(false) => {}
+                            Err(err) => eprintln!("{}", err),
+                        }
+                        continue;
+                    }
+                }
+                buffer.push_str(&line);
+                buffer.push('\n');
+                if is_complete(&buffer) {
+                    let sql = std::mem::take(&mut buffer);
+                    editor.add_history_entry(sql.trim()).unwrap_or_default();
+                    shell.run_statements(env, &sql);
+                }
+            }
+            Err(ReadlineError::Interrupted) => buffer.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(err) => return Err(KonaError::io("Cannot read from the terminal", std::io::Error::other(err))),
+        }
+    }
+
+    if let Some(history) = &history {
+        editor.save_history(history).unwrap_or_default();
+    }
+    Ok(())
+}
+
+#[derive(PartialEq)]
+enum Scan {
+    Code,
+    Quoted(char),
+    LineComment,
+    BlockComment,
+}
+
+// True once the text ends with a `;`, not counting whitespace and comments. A `;` inside a string, a quoted identifier or
+// a comment doesn't end the statement.
+fn is_complete(sql: &str) -> bool {
+    let mut scan = Scan::Code;
+    let mut complete = false;
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        match scan {
+            Scan::Code => match (c, chars.peek()) {
+                ('-', Some('-')) | ('/', Some('/')) => scan = Scan::LineComment,
+                ('/', Some('*')) => {
+                    chars.next();
+                    scan = Scan::BlockComment
+                }
+                ('\'' | '"', _) => {
+                    scan = Scan::Quoted(c);
+                    complete = false
+                }
+                (';', _) => complete = true,
+                _ => complete &= c.is_whitespace(),
+            },
+            Scan::Quoted(quote) if c == quote => scan = Scan::Code,
+            Scan::LineComment if c == '\n' => scan = Scan::Code,
+            Scan::BlockComment if c == '*' && chars.peek() == Some(&'/') => {
+                chars.next();
+                scan = Scan::Code
+            }
+            _ => {}
+        }
+    }
+    complete && matches!(scan, Scan::Code | Scan::LineComment)
+}
+
+impl Shell {
+    // Errors are reported and the shell carries on
+    fn run_statements(&self, env: &mut Env, sql: &str) {
+        let start = Instant::now();
+        if let Err(err) = run_script(env, "<shell>", sql, true) {
+            eprintln!("{}", err);
+        }
+        if self.timing {
+            println!("Time: {:.3} ms", start.elapsed().as_secs_f64() * 1000.0);
+        }
+    }
+
+    // Returns true to quit
+    fn meta_command(&mut self, env: &mut Env, command: &str) -> Result<bool, KonaError> {
+        let mut words = command.split_whitespace();
+        match (words.next().unwrap_or_default(), words.next(), words.next()) {
+            ("q", None, None) => return Ok(true),
+            ("?", None, None) => println!("{}", HELP),
+            ("d", None, None) => println!("{}\n", env.metadata.show_tables()),
+            ("d", Some(name), None) => {
+                let (schema, name) = match name.split_once('.') {
+                    Some((schema, name)) => (Some(schema.to_string()), name.to_string()),
+                    None => (None, name.to_string()),
+                };
+                let (_, tbldesc) = env.metadata.get_tabledesc(schema.as_deref(), &name)?;
+                let description = if tbldesc.get_type() == TableType::View { env.metadata.describe_view(schema, name)? } else { env.metadata.describe_table(schema, name)? };
+                println!("{}\n", description);
+            }
+            ("timing", arg, None) => {
+                self.timing = match arg.map(|arg| arg.to_lowercase()).as_deref() {
+                    None => !self.timing,
+                    Some("on") => true,
+                    Some("off") => false,
+                    Some(arg) => return Err(KonaError::Bind(f!("Invalid argument to \\timing: {arg}. Expected on or off."))),
+                };
+                println!("Timing is {}.", if self.timing { "on" } else { "off" });
+            }
+            _ => return Err(KonaError::Bind(f!("Invalid command \\{command}. Try \\? for help."))),
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statements_end_with_a_semicolon_outside_quotes_and_comments() {
+        for (sql, complete) in [
+            ("SELECT A FROM T;\n", true),
+            ("SELECT A\nFROM T ;  \n", true),
+            ("SELECT A FROM T\n", false),
+            ("SELECT A FROM T; -- done\n", true),
+            ("SELECT A FROM T; /* done */\n", true),
+            ("SELECT A FROM T -- not yet;\n", false),
+            ("SELECT A FROM T // not yet;\n", false),
+            ("SELECT A FROM T /* not yet;\n", false),
+            ("SELECT A FROM T /* not; yet */\n", false),
+            ("SELECT A FROM T WHERE S = 'a;\n", false),
+            ("SELECT A FROM T WHERE S = 'a;\nb';\n", true),
+            ("SELECT \"A;B\" FROM T\n", false),
+            ("SELECT '--' FROM T;\n", true),
+            ("SELECT A FROM T; SELECT B\n", false),
+        ] {
+            assert_eq!(is_complete(sql), complete, "{:?}", sql);
+        }
+    }
+
+    #[test]
+    fn meta_commands() {
+        let mut env = Env::new(0, 1, String::new(), String::new(), None).unwrap();
+        let mut shell = Shell { timing: false };
+        assert!(!shell.meta_command(&mut env, "timing").unwrap());
+        assert!(shell.timing);
+        shell.meta_command(&mut env, "timing OFF").unwrap();
+        assert!(!shell.timing);
+        assert!(shell.meta_command(&mut env, "q").unwrap());
+
+        for (command, message) in [("timing maybe", "Invalid argument to \\timing"), ("x", "Invalid command \\x"), ("d NO_SUCH_TABLE", "NO_SUCH_TABLE")] {
+            let err = shell.meta_command(&mut env, command).unwrap_err();
+            assert!(matches!(&err, KonaError::Bind(msg) if msg.contains(message)), "{}: {}", command, err);
+        }
+    }
+}
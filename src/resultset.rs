@@ -0,0 +1,60 @@
+// resultset: small in-memory results of catalog statements (SHOW, DESCRIBE) and EXPLAIN
+
+use std::fmt;
+
+use arrow2::array::Utf8Array;
+
+use crate::includes::*;
+
+#[derive(Debug)]
+pub struct ResultSet {
+    pub schema: Schema,
+    pub chunk: ChunkBox,
+}
+
+impl ResultSet {
+    // Catalog results are all text, one String per cell
+    pub fn new(colnames: &[&str], rows: Vec<Vec<String>>) -> Self {
+        let fields = colnames.iter().map(|&name| Field::new(name, DataType::Utf8, false)).collect::<Vec<_>>();
+        let arrays = (0..colnames.len())
+            .map(|ix| {
+                let array = Utf8Array::<i32>::from_slice(rows.iter().map(|row| &row[ix]).collect::<Vec<_>>());
+                Box::new(array) as Box<dyn Array>
+            })
+            .collect::<Vec<_>>();
+        ResultSet { schema: Schema::from(fields), chunk: Chunk::new(arrays) }
+    }
+
+    // One-row summary of a statement that changes data or the catalog
+    pub fn status(message: String) -> Self {
+        ResultSet::new(&["STATUS"], vec![vec![message]])
+    }
+
+    pub fn nrows(&self) -> usize {
+        self.chunk.len()
+    }
+
+    fn cell(&self, row: usize, col: usize) -> &str {
+        let array = self.chunk.arrays()[col].as_any().downcast_ref::<Utf8Array<i32>>().unwrap();
+        array.value(row)
+    }
+}
+
+// Column-aligned text table with a header
+impl fmt::Display for ResultSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ncols = self.schema.fields.len();
+        let widths = (0..ncols)
+            .map(|col| (0..self.nrows()).map(|row| self.cell(row, col).chars().count()).chain([self.schema.fields[col].name.chars().count()]).max().unwrap())
+            .collect::<Vec<_>>();
+
+        let header = self.schema.fields.iter().zip(widths.iter()).map(|(field, &width)| format!("{:width$}", field.name)).collect::<Vec<_>>();
+        writeln!(f, "{}", header.join(" | ").trim_end())?;
+        writeln!(f, "{}", widths.iter().map(|&width| "-".repeat(width)).collect::<Vec<_>>().join("-+-"))?;
+        for row in 0..self.nrows() {
+            let cells = (0..ncols).map(|col| format!("{:width$}", self.cell(row, col), width = widths[col])).collect::<Vec<_>>();
+            writeln!(f, "{}", cells.join(" | ").trim_end())?;
+        }
+        write!(f, "({} {})", self.nrows(), if self.nrows() == 1 { "row" } else { "rows" })
+    }
+}
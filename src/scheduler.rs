@@ -1,18 +1,31 @@
 // scheduler
 
-use std::{sync::mpsc, thread, thread::JoinHandle};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc,
+    thread,
+    thread::JoinHandle,
+    time::Instant,
+};
 
 use crate::{
     includes::*,
+    metrics::{FlowMetrics, TaskMetrics},
     stage::{Stage, StageContext},
     task::Task,
     Flow,
 };
 
+// Messages the task threads can queue for the driver. A task sending chunks to a driver that reads them slowly waits
+// here instead of piling them up in memory.
+const T2S_CHANNEL_CAPACITY: usize = 64;
+
 #[derive(Debug)]
 pub enum SchedulerMessage {
     ScheduleTask(Vec<u8>),
-    TaskCompleted { stage_id: StageId, partition_id: usize },
+    TaskOutput { partition_id: usize, chunk: ChunkBox }, // A chunk returned by a stage-0 task, see Flow::return_chunks
+    TaskCompleted { stage_id: StageId, partition_id: usize, metrics: TaskMetrics },
+    TaskFailed { stage_id: StageId, partition_id: usize, error: KonaError },
     StageCompleted { stage_id: StageId },
     EndThread,
 }
@@ -25,10 +38,10 @@ pub struct Scheduler {
 }
 
 impl Scheduler {
-    pub fn join(&mut self) -> Result<(), String> {
+    pub fn join(&mut self) -> Result<(), KonaError> {
         let threads = self.threads.take();
         for thrd in threads.unwrap() {
-            thrd.join().map_err(stringify)?
+            thrd.join().map_err(|err| KonaError::Execution { stage_id: 0, partition_id: 0, message: panic_message(err) })?
         }
         Ok(())
     }
@@ -37,9 +50,9 @@ impl Scheduler {
         self.threads.as_ref().map_or(0, |threads| threads.len())
     }
 
-    pub fn end_all_threads(&mut self) -> Result<(), String> {
+    pub fn end_all_threads(&mut self) -> Result<(), KonaError> {
         for tx in self.s2t_channels_sx.iter() {
-            tx.send(SchedulerMessage::EndThread).map_err(stringify)?
+            tx.send(SchedulerMessage::EndThread).map_err(|err| KonaError::Execution { stage_id: 0, partition_id: 0, message: stringify(err) })?
         }
         Ok(())
     }
@@ -48,7 +61,7 @@ impl Scheduler {
         let mut threads = vec![];
         let mut s2t_channels_sx = vec![];
 
-        let (t2s_channel_tx, t2s_channel_rx) = mpsc::channel::<SchedulerMessage>();
+        let (t2s_channel_tx, t2s_channel_rx) = mpsc::sync_channel::<SchedulerMessage>(T2S_CHANNEL_CAPACITY);
 
         for i in 0..nthreads {
             let t2s_channel_tx_clone = t2s_channel_tx.clone();
@@ -73,15 +86,22 @@ impl Scheduler {
                                 task.partition_id
                             );
                             */
-                            task.run(&flow, &stage).unwrap();
+                            // A failing or panicking task is reported back; the thread stays alive for later flows
+                            let result = panic::catch_unwind(AssertUnwindSafe(|| task.run(&flow, &stage, &t2s_channel_tx_clone)))
+                                .unwrap_or_else(|err| Err(KonaError::execution(panic_message(err))));
+                            let (stage_id, partition_id) = (stage.stage_id, task.partition_id);
+                            let msg = match result {
+                                Ok(metrics) => SchedulerMessage::TaskCompleted { stage_id, partition_id, metrics },
+                                Err(error) => SchedulerMessage::TaskFailed { stage_id, partition_id, error },
+                            };
 
                             // The following send may not succeed if the scheduler is gone
-                            t2s_channel_tx_clone.send(SchedulerMessage::TaskCompleted { stage_id: stage.stage_id, partition_id: task.partition_id }).unwrap_or_default()
-                        }
-                        SchedulerMessage::TaskCompleted { .. } => {
-                            panic!("Invalid message")
+                            t2s_channel_tx_clone.send(msg).unwrap_or_default()
                         }
-                        SchedulerMessage::StageCompleted { .. } => {
+                        SchedulerMessage::TaskOutput { .. }
+                        | SchedulerMessage::TaskCompleted { .. }
+                        | SchedulerMessage::TaskFailed { .. }
+                        | SchedulerMessage::StageCompleted { .. } => {
                             panic!("Invalid message")
                         }
                     }
@@ -99,23 +119,17 @@ impl Scheduler {
         let v = stages
             .iter()
             .zip(stage_status.iter())
-            .filter_map(|(stage, ss)| if stage.nchildren == ss.nchildren_completed && ss.npartitions_completed == 0 { Some(stage) } else { None })
+            .filter_map(|(stage, ss)| if stage.nchildren == ss.nchildren_completed && !ss.is_scheduled { Some(stage) } else { None })
             .collect();
         v
     }
 
-    pub fn init_flow_tmpdir(&self, flow_id: usize) -> Result<(), String> {
-        let dirname: &str = &format!("{}/flow-{}/", TEMPDIR, flow_id);
-
-        // Add some protection against inadvertant deletion
-        if !dirname.contains("tmp") {
-            let errstr = f!("init_flow(): Temporary directory {TEMPDIR} doesn't have substring 'tmp'.");
-            Err(errstr)
-        } else {
-            std::fs::remove_dir_all(dirname).map_err(|e| stringify1(e, dirname)).unwrap_or_default();
-            std::fs::create_dir_all(dirname).map_err(|e| stringify1(e, dirname))?;
-            Ok(())
-        }
+    pub fn init_flow_tmpdir(&self, flow: &Flow) -> Result<(), KonaError> {
+        // Only the flow's own directory under TEMP_DIR is ever cleared
+        let dirname: &str = &format!("{}/", flow.dir());
+        std::fs::remove_dir_all(dirname).unwrap_or_default();
+        std::fs::create_dir_all(dirname).map_err(|e| KonaError::io(dirname, e))?;
+        Ok(())
     }
 
     pub fn set_stage_completed(flow: &Flow, stage_contexts: &mut [StageContext], stage_id: StageId) {
@@ -126,49 +140,105 @@ impl Scheduler {
         }
     }
 
-    pub fn schedule_stages(&self, env: &Env, flow: &Flow, stage_contexts: &[StageContext]) -> Result<usize, String> {
+    // Returns the number of tasks scheduled
+    pub fn schedule_stages(&self, env: &Env, flow: &Flow, stage_contexts: &mut [StageContext]) -> Result<usize, KonaError> {
         let stage_graph = &flow.stage_graph;
 
         let stages = Self::runnable(&stage_graph.stages, stage_contexts);
+        let mut ntasks = 0;
         for stage in stages.iter() {
-            stage.schedule(env, flow).map_err(stringify)?;
+            ntasks += stage.schedule(env, flow)?;
+            stage_contexts[stage.stage_id].is_scheduled = true;
         }
-        Ok(stages.len())
+        Ok(ntasks)
     }
+}
 
-    pub fn run_flow(&self, env: &Env, flow: &Flow) -> Result<(), String> {
-        self.init_flow_tmpdir(env.id)?;
+// A flow being run. The driver pulls the messages of its tasks: each completed task lets dependent stages be scheduled,
+// and flows that return their chunks hand them over as they arrive.
+pub struct FlowRun {
+    stage_contexts: Vec<StageContext>,
+    npending: usize,
+    failure: Option<KonaError>,
+    flow_metrics: FlowMetrics,
+    start: Instant,
+}
 
-        let stage_graph = &flow.stage_graph;
-        let mut stage_contexts = (0..stage_graph.stages.len()).map(|_| StageContext::default()).collect::<Vec<_>>();
+impl FlowRun {
+    pub fn start(env: &Env, flow: &Flow) -> Result<FlowRun, KonaError> {
+        env.scheduler.init_flow_tmpdir(flow)?;
+        let start = Instant::now();
+        let flow_metrics = FlowMetrics::new(flow);
 
-        self.schedule_stages(env, flow, &stage_contexts)?;
+        let mut stage_contexts = (0..flow.stage_graph.stages.len()).map(|_| StageContext::default()).collect::<Vec<_>>();
+        let npending = env.scheduler.schedule_stages(env, flow, &mut stage_contexts)?;
+        Ok(FlowRun { stage_contexts, npending, failure: None, flow_metrics, start })
+    }
 
-        for msg in &self.t2s_channel_rx {
+    // Returns the next chunk returned by a stage-0 task, or None once every task is done.
+    // Once a task fails nothing new is scheduled, but tasks already in flight are drained so that their
+    // messages don't leak into the next flow. The failure is returned after that.
+    pub fn next_chunk(&mut self, env: &Env, flow: &Flow) -> Result<Option<ChunkBox>, KonaError> {
+        let stage_graph = &flow.stage_graph;
+        while self.npending > 0 {
+            let msg = env.scheduler.t2s_channel_rx.recv().map_err(|err| KonaError::Execution { stage_id: 0, partition_id: 0, message: stringify(err) })?;
+            if let SchedulerMessage::TaskOutput { chunk, .. } = msg {
+                if self.failure.is_none() {
+                    return Ok(Some(chunk));
+                }
+                continue;
+            }
             debug!("run_flow message recv: {:?}", msg);
+            self.npending -= 1;
 
             match msg {
-                SchedulerMessage::TaskCompleted { stage_id, .. } => {
-                    let ss = &mut stage_contexts[stage_id];
+                SchedulerMessage::TaskCompleted { stage_id, partition_id, metrics } => {
+                    self.flow_metrics.set_task_metrics(stage_id, partition_id, metrics);
+                    let ss = &mut self.stage_contexts[stage_id];
                     let stage = &stage_graph.stages[stage_id];
 
                     // If this was the last task in a stage, schedule any dependent stages
                     ss.npartitions_completed += 1;
-                    if stage.npartitions == ss.npartitions_completed {
+                    if stage.npartitions == ss.npartitions_completed && self.failure.is_none() {
                         debug!("Stage {} completed", stage_id);
-                        Self::set_stage_completed(flow, &mut stage_contexts, stage_id);
+                        Scheduler::set_stage_completed(flow, &mut self.stage_contexts, stage_id);
 
-                        debug!("Stage contexts: {:?}", &stage_contexts);
-                        if self.schedule_stages(env, flow, &stage_contexts)? == 0 {
-                            break;
-                        }
+                        debug!("Stage contexts: {:?}", &self.stage_contexts);
+                        self.npending += env.scheduler.schedule_stages(env, flow, &mut self.stage_contexts)?;
                     }
                 }
+                SchedulerMessage::TaskFailed { stage_id, partition_id, error } => {
+                    error!("Task failed: stage {}, partition {}: {}", stage_id, partition_id, error);
+                    // Operators don't know which task they run in
+                    let error = match error {
+                        KonaError::Execution { message, .. } => KonaError::Execution { stage_id, partition_id, message },
+                        error => error,
+                    };
+                    self.failure.get_or_insert(error);
+                }
                 _ => {
                     panic!("Unexpected message received by scheduler.")
                 }
             }
         }
-        Ok(())
+        self.failure.take().map_or(Ok(None), Err)
+    }
+
+    // Runs the flow to the end, dropping any chunks it returns. Returns the counters of every task, by stage and partition.
+    pub fn finish(mut self, env: &Env, flow: &Flow) -> Result<FlowMetrics, KonaError> {
+        while self.next_chunk(env, flow)?.is_some() {}
+        self.flow_metrics.elapsed = self.start.elapsed();
+        Ok(self.flow_metrics)
+    }
+}
+
+// Panic payloads are usually a &str or a String
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Task panicked".to_string()
     }
 }
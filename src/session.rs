@@ -0,0 +1,208 @@
+// session: The engine embedded in another program. A Session keeps its tables, views, settings and prepared statements
+// for as long as it lives. `sql` runs one statement and streams the rows of a query back as Arrow chunks, e.g.
+//
+//     let mut session = Session::new(4, None)?;
+//     session.register_table("EMP", None, &[("TYPE", Utf8("CSV".into())), ("PATH", Utf8("data/emp.csv".into())), ("HEADER", Utf8("Y".into()))])?;
+//     let result = session.sql("SELECT name, age FROM emp WHERE age > 30")?;
+//     println!("{:?}", result.schema());
+//     for chunk in result {
+//         let chunk = chunk?;
+//         ...
+//     }
+//
+// Tracing is left to the host program, so SET TRACE statements are skipped.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{ast::AST, compile_query, enable_tracing, includes::*, parse_script, prepare, resultset::ResultSet, run_script, run_statement, scheduler::FlowRun, Flow};
+
+// Flows run under TEMP_DIR in a directory named after their session. Sessions of one process are numbered, and
+// the process id keeps them apart from the sessions of other processes.
+static NSESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Session {
+    env: Env,
+}
+
+impl Session {
+    // Without a catalog directory, tables and views only last as long as the Session
+    pub fn new(nthreads: usize, catalog_dir: Option<&str>) -> Result<Session, KonaError> {
+        let id = std::process::id() as usize * 1000 + NSESSIONS.fetch_add(1, Ordering::Relaxed);
+        let mut env = Env::new(id, nthreads, String::new(), String::new(), catalog_dir)?;
+        env.init_output_dir()?;
+        Ok(Session { env })
+    }
+
+    // Runs one statement. Queries and EXECUTE return their rows as they are produced; SHOW, DESCRIBE and EXPLAIN return
+    // their results; other statements, and queries under PARSE_ONLY, return no columns and no rows.
+    pub fn sql(&mut self, text: &str) -> Result<QueryResult<'_>, KonaError> {
+        let mut astlist = parse_script("<sql>", text)?;
+        enable_tracing(&mut self.env, &mut astlist, false)?;
+        if astlist.len() > 1 {
+            return Err(KonaError::Bind(f!("Session::sql runs one statement but {} were given. Use execute_script to run several.", astlist.len())));
+        }
+
+        let env = &mut self.env;
+        let flow = match astlist.pop() {
+            Some(AST::QGM(mut qgm)) => compile_query(env, &mut qgm)?,
+            Some(AST::Execute { name, values }) => prepare::execute(env, name, values)?,
+            Some(ast) => return Ok(run_statement(env, ast)?.map_or_else(QueryResult::empty, QueryResult::from_resultset)),
+            None => None,
+        };
+        match flow {
+            Some(mut flow) => {
+                flow.return_chunks = true;
+                let run = FlowRun::start(env, &flow)?;
+                Ok(QueryResult { schema: flow.schema.clone(), rows: Rows::Flow { env: &self.env, flow: Box::new(flow), run } })
+            }
+            None => Ok(QueryResult::empty()),
+        }
+    }
+
+    // Same as CATALOG TABLE: `options` are those of its WITH clause, e.g. ("TYPE", Utf8("CSV".into())). Without
+    // columns, they are read from the header of a CSV file or from a Parquet file. Names are uppercased like identifiers.
+    pub fn register_table(&mut self, name: &str, columns: Option<Vec<Field>>, options: &[(&str, Datum)]) -> Result<(), KonaError> {
+        let (schema, name) = match name.split_once('.') {
+            Some((schema, name)) => (Some(schema.to_uppercase()), name.to_uppercase()),
+            None => (None, name.to_uppercase()),
+        };
+        let options = options.iter().map(|(option, value)| (option.to_uppercase(), value.clone())).collect();
+        let options = self.env.resolve_path_options(options);
+        self.env.metadata.catalog_table(schema, name, columns, options, false)
+    }
+
+    // Runs statements the way the kona binary does: query results are written to the flow's output directory and,
+    // like the results of SHOW and DESCRIBE, printed to stdout
+    pub fn execute_script(&mut self, text: &str) -> Result<(), KonaError> {
+        run_script(&mut self.env, "<script>", text, false)
+    }
+}
+
+// Stops the scheduler's threads and removes the files of the session's flows and plans
+impl Drop for Session {
+    fn drop(&mut self) {
+        let scheduler = &mut self.env.scheduler;
+        if scheduler.end_all_threads().is_ok() {
+            scheduler.join().unwrap_or_default();
+        }
+        fs::remove_dir_all(format!("{}/flow-{}", self.env.settings.temp_dir(), self.env.id)).unwrap_or_default();
+        fs::remove_dir_all(&self.env.output_dir).unwrap_or_default();
+    }
+}
+
+// Rows of a statement run by Session::sql. Query rows are chunks sent back by the stage-0 tasks while the flow runs.
+// A failed flow returns its error once the tasks still running have finished. Dropping a result before its end also
+// waits for them.
+pub struct QueryResult<'a> {
+    schema: Schema,
+    rows: Rows<'a>,
+}
+
+enum Rows<'a> {
+    Flow { env: &'a Env, flow: Box<Flow>, run: FlowRun },
+    Chunk(Option<ChunkBox>),
+}
+
+impl QueryResult<'_> {
+    fn empty() -> Self {
+        QueryResult { schema: Schema::from(vec![]), rows: Rows::Chunk(None) }
+    }
+
+    fn from_resultset(resultset: ResultSet) -> Self {
+        QueryResult { schema: resultset.schema, rows: Rows::Chunk(Some(resultset.chunk)) }
+    }
+
+    // Column names are those of the query's SELECT list
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+}
+
+impl Iterator for QueryResult<'_> {
+    type Item = Result<ChunkBox, KonaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.rows {
+            Rows::Flow { env, flow, run } => run.next_chunk(env, flow).transpose(),
+            Rows::Chunk(chunk) => chunk.take().map(Ok),
+        }
+    }
+}
+
+impl Drop for QueryResult<'_> {
+    fn drop(&mut self) {
+        if let Rows::Flow { env, flow, run } = &mut self.rows {
+            while let Ok(Some(_)) = run.next_chunk(env, flow) {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::testing::temp_dir;
+
+    // A session with table T (ID INT, S STRING) holding `nrows` rows
+    fn session_with_table(nrows: usize) -> Session {
+        let path = f!("{}/T.csv", temp_dir("T"));
+        fs::write(&path, (0..nrows).map(|ix| f!("{ix},s{ix}\n")).collect::<String>()).unwrap();
+        let mut session = Session::new(2, None).unwrap();
+        session.sql(&f!("CATALOG TABLE T (ID INT, S STRING) WITH (TYPE = 'CSV', PATH = '{path}', HEADER = 'N')")).unwrap();
+        session
+    }
+
+    // The single value returned by a query
+    fn query_value(session: &mut Session, sql: &str) -> Datum {
+        let chunks = session.sql(sql).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(chunks.iter().map(|chunk| chunk.len()).sum::<usize>(), 1, "{}", sql);
+        let chunk = chunks.iter().find(|chunk| !chunk.is_empty()).unwrap();
+        Datum::from_array(&*chunk.arrays()[0], 0).unwrap().unwrap_or(Null)
+    }
+
+    #[test]
+    fn sql_streams_the_rows_of_a_query() {
+        let mut session = session_with_table(500);
+        // One row per chunk, so that the tasks send far more chunks than the driver's channel holds
+        session.sql("SET CHUNK_SIZE = 1").unwrap();
+
+        let result = session.sql("SELECT ID, S AS NAME FROM T WHERE ID >= 100").unwrap();
+        let names = result.schema().fields.iter().map(|field| field.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["ID", "NAME"]);
+        assert_eq!(result.map(|chunk| chunk.unwrap().len()).sum::<usize>(), 400);
+
+        // A result dropped before its end leaves the session ready for the next statement
+        let mut result = session.sql("SELECT ID FROM T").unwrap();
+        assert!(result.next().is_some());
+        drop(result);
+        assert_eq!(query_value(&mut session, "SELECT S FROM T WHERE ID = 7"), Utf8("s7".into()));
+    }
+
+    #[test]
+    fn sql_runs_one_statement() {
+        let mut session = session_with_table(1);
+
+        let result = session.sql("SET PARALLEL_DEGREE = 2").unwrap();
+        assert!(result.schema().fields.is_empty());
+        assert_eq!(result.count(), 0);
+        let result = session.sql("SHOW PARALLEL_DEGREE").unwrap().next().unwrap().unwrap();
+        assert_eq!(Datum::from_array(&*result.arrays()[1], 0).unwrap(), Some(Utf8("2".into())));
+
+        let err = session.sql("SELECT ID FROM T; SELECT ID FROM T").map(|_| ()).unwrap_err();
+        assert!(matches!(&err, KonaError::Bind(msg) if msg.contains("runs one statement but 2 were given")), "{}", err);
+        let err = session.sql("SELECT ID FROM").map(|_| ()).unwrap_err();
+        assert!(matches!(err, KonaError::Parse(_)), "{}", err);
+    }
+
+    #[test]
+    fn sessions_write_plans_to_directories_of_their_own() {
+        let (one, two) = (Session::new(2, None).unwrap(), Session::new(2, None).unwrap());
+        assert_ne!(one.env.output_dir, two.env.output_dir);
+        assert!(Path::new(&one.env.output_dir).is_dir());
+
+        let output_dir = one.env.output_dir.clone();
+        drop(one);
+        assert!(!Path::new(&output_dir).exists());
+    }
+}
@@ -0,0 +1,423 @@
+// settings: Session settings. Each one is declared once in SETTINGS with its type, default, validation and description.
+// SET changes a setting, SHOW displays it and RESET restores its default.
+
+use std::collections::HashMap;
+
+use crate::{
+    flow::FlowSettings,
+    includes::*,
+    metadata::DEFAULT_SCHEMA,
+    resultset::ResultSet,
+    sink::{Compression, OutputFormat},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingType {
+    Boolean,
+    Integer,
+    String,
+}
+
+impl SettingType {
+    fn describe(&self) -> &'static str {
+        match self {
+            SettingType::Boolean => "BOOLEAN",
+            SettingType::Integer => "INTEGER",
+            SettingType::String => "STRING",
+        }
+    }
+}
+
+pub struct SettingDef {
+    pub name: &'static str,
+    pub typ: SettingType,
+    pub default: &'static str,
+    pub validate: fn(&Datum) -> Result<(), String>,
+    pub description: &'static str,
+}
+
+// Sorted by name, which is the order SHOW ALL lists them in
+pub static SETTINGS: &[SettingDef] = &[
+    SettingDef {
+        name: "BROADCAST_THRESHOLD",
+        typ: SettingType::Integer,
+        default: "0",
+        validate: non_negative,
+        description: "Join legs estimated at no more than this many bytes are broadcast instead of repartitioned. 0 turns this off.",
+    },
+    SettingDef {
+        name: "CAST_MODE",
+        typ: SettingType::String,
+        default: "STRICT",
+        validate: cast_mode,
+        description: "STRICT fails on bad casts, LENIENT turns them into NULLs.",
+    },
+    SettingDef { name: "CHUNK_SIZE", typ: SettingType::Integer, default: "1024", validate: positive, description: "Rows per chunk read from CSV files." },
+    SettingDef { name: "CSV_DELIMITER", typ: SettingType::String, default: ",", validate: single_character, description: "Field delimiter of CSV query results." },
+    SettingDef { name: "CSV_HEADER", typ: SettingType::Boolean, default: "true", validate: any, description: "Start each file of CSV query results with the column names." },
+    SettingDef {
+        name: "DATA_DIR",
+        typ: SettingType::String,
+        default: "",
+        validate: any,
+        description: "Directory that relative PATHs of tables are resolved against. Empty means the directory of the job file.",
+    },
+    SettingDef {
+        name: "GRAPHVIZ",
+        typ: SettingType::String,
+        default: "",
+        validate: any,
+        description: "dot executable that renders plans to .jpg. Empty turns rendering off.",
+    },
+    SettingDef {
+        name: "IPC_COMPRESSION",
+        typ: SettingType::String,
+        default: "UNCOMPRESSED",
+        validate: ipc_compression,
+        description: "Compression of Arrow IPC query results: UNCOMPRESSED, LZ4 or ZSTD.",
+    },
+    SettingDef {
+        name: "MEMORY_LIMIT",
+        typ: SettingType::Integer,
+        default: "0",
+        validate: non_negative,
+        description: "Bytes a task may hold in the hash table of a join or aggregation before failing. 0 means no limit.",
+    },
+    SettingDef { name: "NSPLITS", typ: SettingType::Integer, default: "1", validate: positive, description: "Splits per hash table in joins and aggregations." },
+    SettingDef {
+        name: "OUTPUT_FORMAT",
+        typ: SettingType::String,
+        default: "CSV",
+        validate: output_format,
+        description: "Format of query results: CSV, PARQUET, IPC (Arrow IPC), JSON (JSON Lines) files, or a TABLE printed aligned.",
+    },
+    SettingDef { name: "PARALLEL_DEGREE", typ: SettingType::Integer, default: "1", validate: positive, description: "Partitions of repartitioned joins and aggregations." },
+    SettingDef {
+        name: "PARQUET_COMPRESSION",
+        typ: SettingType::String,
+        default: "SNAPPY",
+        validate: parquet_compression,
+        description: "Compression of Parquet query results: UNCOMPRESSED, SNAPPY, GZIP, LZ4, ZSTD or BROTLI.",
+    },
+    SettingDef { name: "PARSE_ONLY", typ: SettingType::Boolean, default: "false", validate: any, description: "Plan queries without running them." },
+    SettingDef { name: "SCHEMA", typ: SettingType::String, default: DEFAULT_SCHEMA, validate: not_empty, description: "Schemas searched for unqualified table names." },
+    SettingDef {
+        name: "TABLE_MAX_ROWS",
+        typ: SettingType::Integer,
+        default: "100",
+        validate: non_negative,
+        description: "Rows of a TABLE query result that are printed. 0 prints them all.",
+    },
+    SettingDef {
+        name: "TEMP_DIR",
+        typ: SettingType::String,
+        default: "",
+        validate: any,
+        description: "Directory for the intermediate and output files of queries. Empty means kona under the system temp directory.",
+    },
+    SettingDef { name: "TRACE", typ: SettingType::String, default: "", validate: any, description: "Tracing filter, e.g. kona=debug. Only one SET TRACE per job." },
+];
+
+fn any(_: &Datum) -> Result<(), String> {
+    Ok(())
+}
+
+fn positive(value: &Datum) -> Result<(), String> {
+    if value.try_as_i64().is_some_and(|value| value > 0) {
+        Ok(())
+    } else {
+        Err(f!("{value} is not a positive integer"))
+    }
+}
+
+fn non_negative(value: &Datum) -> Result<(), String> {
+    if value.try_as_i64().is_some_and(|value| value >= 0) {
+        Ok(())
+    } else {
+        Err(f!("{value} is negative"))
+    }
+}
+
+fn not_empty(value: &Datum) -> Result<(), String> {
+    if value.try_as_str().is_some_and(|value| !value.trim().is_empty()) {
+        Ok(())
+    } else {
+        Err(String::from("empty value"))
+    }
+}
+
+fn cast_mode(value: &Datum) -> Result<(), String> {
+    match value.try_as_str().map(|mode| mode.to_uppercase()).as_deref() {
+        Some("STRICT" | "LENIENT") => Ok(()),
+        _ => Err(f!("{value}. Expected STRICT or LENIENT")),
+    }
+}
+
+fn output_format(value: &Datum) -> Result<(), String> {
+    match value.try_as_str().map(|format| format.to_uppercase()) {
+        Some(format) if OutputFormat::NAMES.contains(&format.as_str()) => Ok(()),
+        _ => Err(f!("{value}. Expected one of {}", OutputFormat::NAMES.join(", "))),
+    }
+}
+
+fn single_character(value: &Datum) -> Result<(), String> {
+    match value.try_as_str() {
+        Some(s) if s.len() == 1 && s.is_ascii() => Ok(()),
+        _ => Err(f!("{value} is not a single character")),
+    }
+}
+
+fn parquet_compression(value: &Datum) -> Result<(), String> {
+    match value.try_as_str().and_then(Compression::parse) {
+        Some(_) => Ok(()),
+        None => Err(f!("{value}. Expected UNCOMPRESSED, SNAPPY, GZIP, LZ4, ZSTD or BROTLI")),
+    }
+}
+
+fn ipc_compression(value: &Datum) -> Result<(), String> {
+    match value.try_as_str().and_then(Compression::parse) {
+        Some(compression) => match compression.ipc() {
+            Err(KonaError::Bind(message)) => Err(message),
+            _ => Ok(()),
+        },
+        None => Err(f!("{value}. Expected UNCOMPRESSED, LZ4 or ZSTD")),
+    }
+}
+
+impl SettingDef {
+    pub fn lookup(name: &str) -> Result<&'static SettingDef, String> {
+        let name = name.to_uppercase();
+        SETTINGS.iter().find(|def| def.name == name).ok_or_else(|| f!("Invalid option specified: {name}."))
+    }
+
+    pub fn default_value(&self) -> Datum {
+        match self.typ {
+            SettingType::Boolean => Boolean(self.default == "true"),
+            SettingType::Integer => Int64(self.default.parse().unwrap()),
+            SettingType::String => Utf8(self.default.to_string()),
+        }
+    }
+
+    // SET takes integers and strings. Booleans are spelled as strings: TRUE/FALSE, YES/NO and so on.
+    pub fn coerce(&self, value: &Datum) -> Result<Datum, String> {
+        let (name, typ) = (self.name, self.typ.describe());
+        let value = match (self.typ, value) {
+            (SettingType::Integer, Int64(_)) | (SettingType::String, Utf8(_)) => value.clone(),
+            (SettingType::Boolean, Utf8(s)) => match s.to_uppercase().as_str() {
+                "TRUE" | "T" | "YES" | "Y" | "ON" => Boolean(true),
+                "FALSE" | "F" | "NO" | "N" | "OFF" => Boolean(false),
+                _ => return Err(f!("Option {name} needs to be of type {typ}. It holds {value} instead.")),
+            },
+            _ => return Err(f!("Option {name} needs to be of type {typ}. It holds {value} instead.")),
+        };
+        (self.validate)(&value).map_err(|err| f!("Invalid value for option {name}: {err}."))?;
+        Ok(value)
+    }
+
+    // Values from the command line, the environment and config files are text
+    pub fn parse(&self, text: &str) -> Result<Datum, String> {
+        let value = match self.typ {
+            SettingType::Integer => Int64(text.parse().map_err(|_| f!("Option {} needs to be of type INTEGER. It holds {text} instead.", self.name))?),
+            _ => Utf8(text.to_string()),
+        };
+        self.coerce(&value)
+    }
+}
+
+fn show_value(value: &Datum) -> String {
+    match value {
+        Utf8(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+// Values of the settings changed from their defaults. Startup values replace the built-in defaults, so RESET returns to them.
+#[derive(Debug, Default)]
+pub struct EnvSettings {
+    values: HashMap<&'static str, Datum>,
+    defaults: HashMap<&'static str, Datum>,
+}
+
+impl EnvSettings {
+    pub fn get(&self, def: &SettingDef) -> Datum {
+        self.values.get(def.name).cloned().unwrap_or_else(|| self.default_value(def))
+    }
+
+    fn default_value(&self, def: &SettingDef) -> Datum {
+        self.defaults.get(def.name).cloned().unwrap_or_else(|| def.default_value())
+    }
+
+    pub fn set(&mut self, def: &'static SettingDef, value: Datum) {
+        self.values.insert(def.name, value);
+    }
+
+    pub fn set_default(&mut self, def: &'static SettingDef, value: Datum) {
+        self.defaults.insert(def.name, value);
+    }
+
+    pub fn reset(&mut self, def: &SettingDef) {
+        self.values.remove(def.name);
+    }
+
+    pub fn show(&self, name: Option<&str>) -> Result<ResultSet, String> {
+        let defs = match name {
+            Some(name) => vec![SettingDef::lookup(name)?],
+            None => SETTINGS.iter().collect(),
+        };
+        let rows = defs
+            .iter()
+            .map(|def| {
+                vec![def.name.to_string(), show_value(&self.get(def)), def.typ.describe().to_string(), show_value(&self.default_value(def)), def.description.to_string()]
+            })
+            .collect();
+        Ok(ResultSet::new(&["NAME", "VALUE", "TYPE", "DEFAULT", "DESCRIPTION"], rows))
+    }
+
+    fn value(&self, name: &str) -> Datum {
+        self.get(SettingDef::lookup(name).unwrap())
+    }
+
+    fn integer(&self, name: &str) -> usize {
+        self.value(name).try_as_i64().unwrap() as usize
+    }
+
+    fn boolean(&self, name: &str) -> bool {
+        matches!(self.value(name), Boolean(true))
+    }
+
+    fn string(&self, name: &str) -> String {
+        self.value(name).try_as_str().unwrap().to_string()
+    }
+
+    pub fn broadcast_threshold(&self) -> usize {
+        self.integer("BROADCAST_THRESHOLD")
+    }
+
+    pub fn graphviz(&self) -> Option<String> {
+        Some(self.string("GRAPHVIZ")).filter(|dot| !dot.is_empty())
+    }
+
+    pub fn lenient_cast(&self) -> bool {
+        self.string("CAST_MODE").to_uppercase() == "LENIENT"
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        let compression = |name| Compression::parse(&self.string(name)).unwrap();
+        match self.string("OUTPUT_FORMAT").to_uppercase().as_str() {
+            "CSV" => OutputFormat::CSV { header: self.boolean("CSV_HEADER"), delimiter: self.string("CSV_DELIMITER").as_bytes()[0] },
+            "PARQUET" => OutputFormat::Parquet { compression: compression("PARQUET_COMPRESSION") },
+            "IPC" => OutputFormat::IPC { compression: compression("IPC_COMPRESSION") },
+            "JSON" => OutputFormat::JSON,
+            _ => OutputFormat::Table { max_rows: self.integer("TABLE_MAX_ROWS") },
+        }
+    }
+
+    pub fn parallel_degree(&self) -> usize {
+        self.integer("PARALLEL_DEGREE")
+    }
+
+    pub fn parse_only(&self) -> bool {
+        self.boolean("PARSE_ONLY")
+    }
+
+    pub fn data_dir(&self) -> Option<String> {
+        Some(self.string("DATA_DIR")).filter(|dir| !dir.is_empty())
+    }
+
+    pub fn temp_dir(&self) -> String {
+        let temp_dir = self.string("TEMP_DIR");
+        if temp_dir.is_empty() {
+            std::env::temp_dir().join("kona").display().to_string()
+        } else {
+            temp_dir
+        }
+    }
+
+    pub fn flow_settings(&self) -> FlowSettings {
+        FlowSettings {
+            chunk_size: self.integer("CHUNK_SIZE"),
+            nsplits: self.integer("NSPLITS"),
+            temp_dir: self.temp_dir(),
+            memory_limit: self.integer("MEMORY_LIMIT"),
+            output_format: self.output_format(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn settings_are_sorted_and_have_valid_defaults() {
+        let names = SETTINGS.iter().map(|def| def.name).collect::<Vec<_>>();
+        let mut sorted = names.clone();
+        sorted.sort_unstable();
+        assert_eq!(names, sorted);
+
+        for def in SETTINGS {
+            assert_eq!(def.parse(def.default), Ok(def.default_value()), "{}", def.name);
+        }
+    }
+
+    #[test]
+    fn values_are_coerced_and_validated() {
+        let def = |name| SettingDef::lookup(name).unwrap();
+        assert_eq!(def("parse_only").coerce(&Utf8("yes".into())), Ok(Boolean(true)));
+        assert_eq!(def("PARSE_ONLY").coerce(&Utf8("Off".into())), Ok(Boolean(false)));
+        assert_eq!(def("CHUNK_SIZE").parse("64"), Ok(Int64(64)));
+        assert_eq!(def("OUTPUT_FORMAT").coerce(&Utf8("json".into())), Ok(Utf8("json".into())));
+
+        for (name, value, message) in [
+            ("PARSE_ONLY", Utf8("maybe".into()), "needs to be of type BOOLEAN"),
+            ("CHUNK_SIZE", Utf8("64".into()), "needs to be of type INTEGER"),
+            ("CHUNK_SIZE", Int64(0), "is not a positive integer"),
+            ("MEMORY_LIMIT", Int64(-1), "is negative"),
+            ("CAST_MODE", Utf8("LOOSE".into()), "Expected STRICT or LENIENT"),
+            ("CSV_DELIMITER", Utf8(";;".into()), "is not a single character"),
+            ("OUTPUT_FORMAT", Utf8("XML".into()), "Expected one of"),
+            ("PARQUET_COMPRESSION", Utf8("RAR".into()), "Expected UNCOMPRESSED, SNAPPY"),
+            ("IPC_COMPRESSION", Utf8("SNAPPY".into()), "SNAPPY is not supported by Arrow IPC"),
+            ("SCHEMA", Utf8(" ".into()), "empty value"),
+        ] {
+            let err = def(name).coerce(&value).unwrap_err();
+            assert!(err.contains(message), "{} = {}: {}", name, value, err);
+        }
+        assert!(def("CHUNK_SIZE").parse("lots").unwrap_err().contains("needs to be of type INTEGER"));
+        assert!(matches!(SettingDef::lookup("no_such_option"), Err(err) if err.contains("Invalid option specified: NO_SUCH_OPTION")));
+    }
+
+    #[test]
+    fn reset_returns_to_the_startup_value() {
+        let def = SettingDef::lookup("PARALLEL_DEGREE").unwrap();
+        let mut settings = EnvSettings::default();
+        settings.set_default(def, Int64(4));
+        settings.set(def, Int64(8));
+        assert_eq!(settings.parallel_degree(), 8);
+        settings.reset(def);
+        assert_eq!(settings.parallel_degree(), 4);
+    }
+
+    #[test]
+    fn set_show_and_reset() {
+        let mut session = session();
+        execute(&mut session, "SET CAST_MODE = 'lenient'").unwrap();
+        let rows = query(&mut session, "SHOW CAST_MODE").unwrap();
+        assert_eq!(rows[0][..4], [Utf8("CAST_MODE".into()), Utf8("lenient".into()), Utf8("STRING".into()), Utf8("STRICT".into())]);
+        execute(&mut session, "RESET CAST_MODE").unwrap();
+        assert_eq!(query(&mut session, "SHOW CAST_MODE").unwrap()[0][1], Utf8("STRICT".into()));
+        assert_eq!(query(&mut session, "SHOW ALL").unwrap().len(), SETTINGS.len());
+
+        for (sql, message) in [
+            ("SET CHUNK_SIZE = 0", "Invalid value for option CHUNK_SIZE"),
+            ("SET CHUNK_SIZE = 'big'", "needs to be of type INTEGER"),
+            ("SET NO_SUCH_OPTION = 1", "Invalid option specified"),
+            ("SHOW NO_SUCH_OPTION", "Invalid option specified"),
+            ("RESET TRACE", "cannot be reset"),
+        ] {
+            let err = query(&mut session, sql).unwrap_err();
+            assert!(matches!(&err, KonaError::Bind(msg) if msg.contains(message)), "{}: {}", sql, err);
+        }
+    }
+}
@@ -0,0 +1,554 @@
+// sink: write query results into a cataloged table (CREATE TABLE AS SELECT, INSERT INTO ... SELECT)
+
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::mpsc,
+};
+
+use arrow2::{
+    array::{get_display, Utf8Array},
+    io::{
+        csv::write as csv_write,
+        ipc::{read as ipc_read, write as ipc_write},
+        json::write as json_write,
+        ndjson::write::FallibleStreamingIterator,
+        parquet::{
+            read as pq_read,
+            write::{transverse, CompressionOptions, Encoding, FileWriter, RowGroupIterator, Version, WriteOptions},
+        },
+    },
+};
+
+use crate::{analyze::PartitionStats, decimal, flow::Flow, includes::*, scheduler::SchedulerMessage};
+
+// How top-level tasks write the result of a query to the flow's output directory: OUTPUT_FORMAT with the options of that
+// format. TABLE results are written as Arrow IPC and printed once the flow is done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputFormat {
+    CSV { header: bool, delimiter: u8 },
+    Parquet { compression: Compression },
+    IPC { compression: Compression },
+    JSON,
+    Table { max_rows: usize }, // 0 prints every row
+}
+
+impl OutputFormat {
+    pub const NAMES: [&'static str; 5] = ["CSV", "TABLE", "PARQUET", "IPC", "JSON"];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::CSV { .. } => "csv",
+            OutputFormat::Parquet { .. } => "parquet",
+            OutputFormat::IPC { .. } | OutputFormat::Table { .. } => "arrow",
+            OutputFormat::JSON => "jsonl",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compression {
+    Uncompressed,
+    Snappy,
+    Gzip,
+    Lz4,
+    Zstd,
+    Brotli,
+}
+
+impl Compression {
+    pub fn parse(name: &str) -> Option<Compression> {
+        match name.to_uppercase().as_str() {
+            "UNCOMPRESSED" => Some(Compression::Uncompressed),
+            "SNAPPY" => Some(Compression::Snappy),
+            "GZIP" => Some(Compression::Gzip),
+            "LZ4" => Some(Compression::Lz4),
+            "ZSTD" => Some(Compression::Zstd),
+            "BROTLI" => Some(Compression::Brotli),
+            _ => None,
+        }
+    }
+
+    fn parquet(self) -> CompressionOptions {
+        match self {
+            Compression::Uncompressed => CompressionOptions::Uncompressed,
+            Compression::Snappy => CompressionOptions::Snappy,
+            Compression::Gzip => CompressionOptions::Gzip(None),
+            Compression::Lz4 => CompressionOptions::Lz4Raw,
+            Compression::Zstd => CompressionOptions::Zstd(None),
+            Compression::Brotli => CompressionOptions::Brotli(None),
+        }
+    }
+
+    // Arrow IPC buffers can only be compressed with LZ4 or ZSTD
+    pub fn ipc(self) -> Result<Option<ipc_write::Compression>, KonaError> {
+        match self {
+            Compression::Uncompressed => Ok(None),
+            Compression::Lz4 => Ok(Some(ipc_write::Compression::LZ4)),
+            Compression::Zstd => Ok(Some(ipc_write::Compression::ZSTD)),
+            _ => {
+                let name = format!("{:?}", self).to_uppercase();
+                Err(KonaError::Bind(f!("{name} is not supported by Arrow IPC. Expected UNCOMPRESSED, LZ4 or ZSTD")))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum SinkFormat {
+    CSV { header: bool, separator: char },
+    Parquet,
+}
+
+// Tasks write their partitions in the table's format to a staging directory. Once every task has succeeded,
+// `commit` folds the partitions into the table's one file, so a failed flow or commit leaves the table untouched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TableSink {
+    pub format: SinkFormat,
+    pub pathname: String,
+    pub schema: Schema, // The table's column names with the query's types
+    pub is_append: bool,
+}
+
+impl TableSink {
+    pub fn new(format: SinkFormat, pathname: String, schema: Schema, is_append: bool) -> Self {
+        TableSink { format, pathname, schema, is_append }
+    }
+
+    fn staging_dir(flow: &Flow) -> String {
+        format!("{}/sink", flow.dir())
+    }
+
+    fn partition_path(&self, flow: &Flow, partition_id: PartitionId) -> String {
+        let extension = match self.format {
+            SinkFormat::CSV { .. } => "csv",
+            SinkFormat::Parquet => "parquet",
+        };
+        format!("{}/partition-{}.{}", Self::staging_dir(flow), partition_id, extension)
+    }
+
+    // Returns the number of rows written
+    pub fn commit(&self, flow: &Flow) -> Result<usize, KonaError> {
+        let staging_dir = Self::staging_dir(flow);
+        let mut partitions = vec![];
+        // No staging directory means no task produced any rows
+        if let Ok(entries) = fs::read_dir(&staging_dir) {
+            for entry in entries {
+                let entry = entry.map_err(|err| KonaError::io(&staging_dir, err))?;
+                partitions.push(entry.path().display().to_string());
+            }
+        }
+        partitions.sort();
+        self.commit_partitions(&partitions)
+    }
+
+    fn commit_partitions(&self, partitions: &[String]) -> Result<usize, KonaError> {
+        // The new contents are written next to the table and replace it once complete, so a failed commit leaves the
+        // table as it was
+        let tmp_pathname = format!("{}.tmp", self.pathname);
+        let result = match &self.format {
+            SinkFormat::CSV { header, separator } => self.write_csv(&tmp_pathname, partitions, *header, *separator),
+            SinkFormat::Parquet => self.write_parquet(&tmp_pathname, partitions),
+        };
+        let result = result.and_then(|nrows| fs::rename(&tmp_pathname, &self.pathname).map(|_| nrows).map_err(|err| KonaError::io(&self.pathname, err)));
+        if result.is_err() {
+            fs::remove_file(&tmp_pathname).unwrap_or_default();
+        }
+        result
+    }
+
+    // An INSERT copies the table and appends the partitions to the copy
+    fn write_csv(&self, tmp_pathname: &str, partitions: &[String], header: bool, separator: char) -> Result<usize, KonaError> {
+        let mut file = if self.is_append {
+            fs::copy(&self.pathname, tmp_pathname).map_err(|err| KonaError::io(&self.pathname, err))?;
+            OpenOptions::new().append(true).open(tmp_pathname).map_err(|err| KonaError::io(tmp_pathname, err))?
+        } else {
+            File::create(tmp_pathname).map_err(|err| KonaError::io(tmp_pathname, err))?
+        };
+
+        if header && !self.is_append {
+            let names = self.schema.fields.iter().map(|field| field.name.clone()).collect::<Vec<_>>();
+            let options = csv_write::SerializeOptions { delimiter: separator as u8, ..Default::default() };
+            csv_write::write_header(&mut file, &names, &options).map_err(|err| KonaError::arrow(tmp_pathname, err))?;
+        }
+
+        let mut nrows = 0;
+        for partition in partitions.iter() {
+            let contents = fs::read(partition).map_err(|err| KonaError::io(partition, err))?;
+            nrows += contents.iter().filter(|&&byte| byte == b'\n').count();
+            std::io::Write::write_all(&mut file, &contents).map_err(|err| KonaError::io(tmp_pathname, err))?;
+        }
+        Ok(nrows)
+    }
+
+    // A Parquet table is a single file, and Parquet files can't be appended to. Every partition is merged into that
+    // file, and an INSERT rewrites the whole table: its existing row groups followed by the new ones.
+    fn write_parquet(&self, tmp_pathname: &str, partitions: &[String]) -> Result<usize, KonaError> {
+        let pathname = &self.pathname;
+        let mut inputs = vec![];
+        if self.is_append {
+            inputs.push(pathname.clone());
+        }
+        inputs.extend(partitions.iter().cloned());
+
+        let file = File::create(tmp_pathname).map_err(|err| KonaError::io(tmp_pathname, err))?;
+        let mut writer = ParquetWriter::try_new(file, self.schema.clone(), tmp_pathname, Compression::Uncompressed)?;
+        let mut nrows = 0;
+        for input in inputs.iter() {
+            let mut reader = File::open(input).map_err(|err| KonaError::io(input, err))?;
+            let metadata = pq_read::read_metadata(&mut reader).map_err(|err| KonaError::arrow(input, err))?;
+            let schema = pq_read::infer_schema(&metadata).map_err(|err| KonaError::arrow(input, err))?;
+            let file_reader = pq_read::FileReader::new(reader, metadata.row_groups, schema, None, None, None);
+            for chunk in file_reader {
+                let chunk = chunk.map_err(|err| KonaError::arrow(input, err))?;
+                if input != pathname {
+                    nrows += chunk.len();
+                }
+                writer.write_chunk(chunk)?;
+            }
+        }
+        writer.finish()?;
+        Ok(nrows)
+    }
+}
+
+pub struct ParquetWriter {
+    writer: FileWriter<File>,
+    pathname: String,
+    schema: Schema,
+    options: WriteOptions,
+    encodings: Vec<Vec<Encoding>>,
+}
+
+impl ParquetWriter {
+    fn try_new(file: File, schema: Schema, pathname: &str, compression: Compression) -> Result<Self, KonaError> {
+        let options = WriteOptions { write_statistics: true, compression: compression.parquet(), version: Version::V2, data_pagesize_limit: None };
+        let encodings = schema.fields.iter().map(|field| transverse(&field.data_type, |_| Encoding::Plain)).collect();
+        let writer = FileWriter::try_new(file, schema.clone(), options).map_err(|err| KonaError::arrow(pathname, err))?;
+        Ok(ParquetWriter { writer, pathname: pathname.to_string(), schema, options, encodings })
+    }
+
+    fn write_chunk(&mut self, chunk: ChunkBox) -> Result<(), KonaError> {
+        let pathname = &self.pathname;
+        let row_groups =
+            RowGroupIterator::try_new(vec![Ok(chunk)].into_iter(), &self.schema, self.options, self.encodings.clone()).map_err(|err| KonaError::arrow(pathname, err))?;
+        for row_group in row_groups {
+            let row_group = row_group.map_err(|err| KonaError::arrow(pathname, err))?;
+            self.writer.write(row_group).map_err(|err| KonaError::arrow(pathname, err))?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), KonaError> {
+        let pathname = &self.pathname;
+        self.writer.end(None).map_err(|err| KonaError::arrow(pathname, err))?;
+        Ok(())
+    }
+}
+
+// Chunks of an Arrow IPC file, such as the partitions of a TABLE result
+pub fn read_ipc(pathname: &str) -> Result<Vec<ChunkBox>, KonaError> {
+    let mut file = File::open(pathname).map_err(|err| KonaError::io(pathname, err))?;
+    let metadata = ipc_read::read_file_metadata(&mut file).map_err(|err| KonaError::arrow(pathname, err))?;
+    ipc_read::FileReader::new(file, metadata, None, None).map(|chunk| chunk.map_err(|err| KonaError::arrow(pathname, err))).collect()
+}
+
+// Arrow's JSON writer has no decimals or intervals, so they are written as strings
+fn json_chunk(chunk: &ChunkBox) -> Result<ChunkBox, KonaError> {
+    let chunk = decimal::format_chunk(chunk);
+    let arrays = chunk
+        .arrays()
+        .iter()
+        .map(|array| match array.data_type() {
+            DataType::Interval(_) => {
+                let display = get_display(&**array, "");
+                let values = (0..array.len())
+                    .map(|ix| {
+                        let mut value = String::new();
+                        display(&mut value, ix).map_err(|err| KonaError::execution(err.to_string()))?;
+                        Ok(array.is_valid(ix).then_some(value))
+                    })
+                    .collect::<Result<Vec<_>, KonaError>>()?;
+                Ok(Box::new(Utf8Array::<i32>::from(values)) as Box<dyn Array>)
+            }
+            _ => Ok(array.clone()),
+        })
+        .collect::<Result<Vec<_>, KonaError>>()?;
+    Ok(Chunk::new(arrays))
+}
+
+// Where a top-level task writes its partition: the flow's sink if it has one, else a file in the output directory in
+// OUTPUT_FORMAT. ANALYZE TABLE flows write the statistics of their partition instead, and flows run by a Session send
+// their chunks back to the driver.
+pub enum PartitionWriter {
+    CSV { file: File, options: csv_write::SerializeOptions },
+    Parquet(Box<ParquetWriter>),
+    IPC(Box<ipc_write::FileWriter<File>>),
+    JSON { file: File, schema: Schema },
+    Stats { pathname: String, stats: PartitionStats },
+    Driver { driver: mpsc::SyncSender<SchedulerMessage>, partition_id: PartitionId },
+}
+
+impl PartitionWriter {
+    pub fn try_new(flow: &Flow, partition_id: PartitionId, driver: &mpsc::SyncSender<SchedulerMessage>) -> Result<Self, KonaError> {
+        if flow.collect_stats {
+            return Ok(PartitionWriter::Stats { pathname: PartitionStats::pathname(flow, partition_id), stats: PartitionStats::default() });
+        }
+        if flow.return_chunks {
+            return Ok(PartitionWriter::Driver { driver: driver.clone(), partition_id });
+        }
+        let (dirname, pathname) = if let Some(sink) = flow.sink.as_ref() {
+            (TableSink::staging_dir(flow), sink.partition_path(flow, partition_id))
+        } else {
+            let dirname = flow.output_dir();
+            let pathname = format!("{}/partition-{}.{}", dirname, partition_id, flow.settings.output_format.extension());
+            (dirname, pathname)
+        };
+        std::fs::create_dir_all(&dirname).map_err(|err| KonaError::io(&dirname, err))?;
+        let file = File::create(&pathname).map_err(|err| KonaError::io(&pathname, err))?;
+
+        let writer = match flow.sink.as_ref() {
+            Some(TableSink { format: SinkFormat::Parquet, schema, .. }) => {
+                PartitionWriter::Parquet(Box::new(ParquetWriter::try_new(file, schema.clone(), &pathname, Compression::Uncompressed)?))
+            }
+            Some(TableSink { format: SinkFormat::CSV { separator, .. }, .. }) => {
+                PartitionWriter::CSV { file, options: csv_write::SerializeOptions { delimiter: *separator as u8, ..Default::default() } }
+            }
+            None => Self::try_new_output(flow, file, &pathname)?,
+        };
+        Ok(writer)
+    }
+
+    // Every partition is a file of its own, so each CSV partition gets a header
+    fn try_new_output(flow: &Flow, mut file: File, pathname: &str) -> Result<Self, KonaError> {
+        let schema = flow.schema.clone();
+        let writer = match flow.settings.output_format {
+            OutputFormat::CSV { header, delimiter } => {
+                let options = csv_write::SerializeOptions { delimiter, ..Default::default() };
+                if header {
+                    let names = schema.fields.iter().map(|field| field.name.clone()).collect::<Vec<_>>();
+                    csv_write::write_header(&mut file, &names, &options).map_err(|err| KonaError::arrow(pathname, err))?;
+                }
+                PartitionWriter::CSV { file, options }
+            }
+            OutputFormat::Parquet { compression } => PartitionWriter::Parquet(Box::new(ParquetWriter::try_new(file, schema, pathname, compression)?)),
+            OutputFormat::IPC { compression } => {
+                let options = ipc_write::WriteOptions { compression: compression.ipc()? };
+                PartitionWriter::IPC(Box::new(ipc_write::FileWriter::try_new(file, schema, None, options).map_err(|err| KonaError::arrow(pathname, err))?))
+            }
+            OutputFormat::Table { .. } => {
+                let options = ipc_write::WriteOptions { compression: None };
+                PartitionWriter::IPC(Box::new(ipc_write::FileWriter::try_new(file, schema, None, options).map_err(|err| KonaError::arrow(pathname, err))?))
+            }
+            OutputFormat::JSON => PartitionWriter::JSON { file, schema },
+        };
+        Ok(writer)
+    }
+
+    pub fn write_chunk(&mut self, chunk: ChunkBox) -> Result<(), KonaError> {
+        match self {
+            PartitionWriter::CSV { file, options } => {
+                let chunk = decimal::format_chunk(&chunk);
+                csv_write::write_chunk(file, &chunk, options).map_err(|err| KonaError::arrow("Cannot write CSV output", err))
+            }
+            PartitionWriter::Parquet(writer) => writer.write_chunk(chunk),
+            PartitionWriter::IPC(writer) => writer.write(&chunk, None).map_err(|err| KonaError::arrow("Cannot write Arrow IPC output", err)),
+            PartitionWriter::JSON { file, schema } => {
+                // One object per line
+                let chunk = json_chunk(&chunk)?;
+                let mut records = json_write::RecordSerializer::new(schema.clone(), &chunk, vec![]);
+                let mut buffer = vec![];
+                while let Some(record) = records.next().map_err(|err| KonaError::arrow("Cannot write JSON output", err))? {
+                    buffer.extend_from_slice(record);
+                    buffer.push(b'\n');
+                }
+                file.write_all(&buffer).map_err(|err| KonaError::io("Cannot write JSON output", err))
+            }
+            PartitionWriter::Stats { stats, .. } => stats.add_chunk(&chunk),
+            PartitionWriter::Driver { driver, partition_id } => {
+                driver.send(SchedulerMessage::TaskOutput { partition_id: *partition_id, chunk }).map_err(|err| KonaError::execution(err.to_string()))
+            }
+        }
+    }
+
+    pub fn finish(self) -> Result<(), KonaError> {
+        match self {
+            PartitionWriter::CSV { .. } | PartitionWriter::JSON { .. } | PartitionWriter::Driver { .. } => Ok(()),
+            PartitionWriter::Parquet(mut writer) => writer.finish(),
+            PartitionWriter::IPC(mut writer) => writer.finish().map_err(|err| KonaError::arrow("Cannot write Arrow IPC output", err)),
+            PartitionWriter::Stats { pathname, stats } => stats.write(&pathname),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::testing::*;
+
+    #[test]
+    fn create_table_as_and_insert_into() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT, S STRING", &["1,a", "2,b", "3,c"]);
+        let dir = temp_dir("SINK");
+
+        for (name, typ) in [("C", "CSV"), ("P", "PARQUET")] {
+            let status =
+                query_value(&mut session, &f!("CREATE TABLE {name} WITH (TYPE = '{typ}', PATH = '{dir}/{name}.{typ}') AS SELECT ID, S AS NAME FROM T WHERE ID > 1")).unwrap();
+            assert_eq!(status, Utf8(f!("Created table {name} with 2 rows")));
+            let status = query_value(&mut session, &f!("INSERT INTO {name} SELECT ID, S FROM T WHERE ID = 1")).unwrap();
+            assert_eq!(status, Utf8(f!("Inserted 1 rows into {name}")));
+            let rows = query(&mut session, &f!("SELECT ID, NAME FROM {name}")).unwrap();
+            assert_eq!(rows, vec![vec![Int64(1), Utf8("a".into())], vec![Int64(2), Utf8("b".into())], vec![Int64(3), Utf8("c".into())]], "{}", typ);
+        }
+    }
+
+    #[test]
+    fn sink_errors() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT, S STRING", &["1,a"]);
+        let dir = temp_dir("SINK");
+        execute(&mut session, &f!("CREATE TABLE C WITH (TYPE = 'CSV', PATH = '{dir}/C.csv') AS SELECT ID FROM T")).unwrap();
+
+        for (sql, message) in [
+            (f!("CREATE TABLE C WITH (TYPE = 'CSV', PATH = '{dir}/C2.csv') AS SELECT ID FROM T"), "already exists"),
+            (f!("CREATE TABLE D WITH (TYPE = 'CSV', PATH = '{dir}/D.csv') AS SELECT ID, ID FROM T"), "needs a distinct name for every column"),
+            (String::from("INSERT INTO C SELECT S FROM T"), "expects columns of type"),
+            (String::from("INSERT INTO C SELECT ID, ID FROM T"), "expects columns of type"),
+        ] {
+            let err = query(&mut session, &sql).unwrap_err();
+            assert!(matches!(&err, KonaError::Bind(msg) if msg.contains(message)), "{}: {}", sql, err);
+        }
+        // A failed INSERT leaves the table as it was
+        assert_eq!(query(&mut session, "SELECT ID FROM C").unwrap(), vec![vec![Int64(1)]]);
+    }
+
+    #[test]
+    fn grouped_queries_name_their_columns() {
+        let mut session = session();
+        create_table(&mut session, "T", "K STRING, V INT", &["a,1", "a,2", "b,5"]);
+        let dir = temp_dir("SINK");
+        let expected = vec![vec![Int64(3)], vec![Int64(5)]];
+
+        for (name, typ) in [("C", "CSV"), ("P", "PARQUET")] {
+            execute(&mut session, &f!("CREATE TABLE {name} WITH (TYPE = '{typ}', PATH = '{dir}/{name}.{typ}') AS SELECT K, SUM(V) AS TOTAL FROM T GROUP BY K")).unwrap();
+            assert_eq!(query(&mut session, &f!("SELECT TOTAL FROM {name}")).unwrap(), expected, "{}", typ);
+            assert_eq!(query(&mut session, &f!("SELECT TOTAL FROM {name} WHERE K = 'b'")).unwrap(), vec![vec![Int64(5)]], "{}", typ);
+        }
+        execute(&mut session, "CREATE VIEW V AS SELECT K, SUM(V) AS TOTAL FROM T GROUP BY 1").unwrap();
+        assert_eq!(query(&mut session, "SELECT TOTAL FROM V").unwrap(), expected);
+    }
+
+    #[test]
+    fn parquet_tables_are_rewritten_into_one_file() {
+        let mut session = session();
+        let rows = (1..=6).map(|id| f!("{id}\n")).collect::<String>();
+        let dir = temp_dir("SINK");
+        fs::write(f!("{dir}/T.csv"), rows).unwrap();
+        execute(&mut session, &f!("CATALOG TABLE T (ID INT) WITH (TYPE = 'CSV', PATH = '{dir}/T.csv', HEADER = 'N', PARTITIONS = 3)")).unwrap();
+
+        let table_dir = temp_dir("PARQUET");
+        execute(&mut session, &f!("CREATE TABLE P WITH (TYPE = 'PARQUET', PATH = '{table_dir}/P.parquet') AS SELECT ID FROM T WHERE ID <= 3")).unwrap();
+        execute(&mut session, "INSERT INTO P SELECT ID FROM T WHERE ID > 3").unwrap();
+        execute(&mut session, "INSERT INTO P SELECT ID FROM T WHERE ID = 1").unwrap();
+
+        // Every partition of every statement ends up in the table's one file
+        assert_eq!(list_files(&table_dir).unwrap(), vec![f!("{table_dir}/P.parquet")]);
+        let rows = query(&mut session, "SELECT ID FROM P").unwrap();
+        assert_eq!(rows, [1, 1, 2, 3, 4, 5, 6].iter().map(|&id| vec![Int64(id)]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn failed_commits_leave_the_table_as_it_was() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT", &["1", "2"]);
+        let dir = temp_dir("SINK");
+        let schema = Schema::from(vec![Field::new("ID", DataType::Int64, true)]);
+
+        for (name, typ, format) in [("C", "CSV", SinkFormat::CSV { header: true, separator: ',' }), ("P", "PARQUET", SinkFormat::Parquet)] {
+            let pathname = f!("{dir}/{name}.{typ}");
+            execute(&mut session, &f!("CREATE TABLE {name} WITH (TYPE = '{typ}', PATH = '{pathname}') AS SELECT ID FROM T")).unwrap();
+            let contents = fs::read(&pathname).unwrap();
+
+            // The second partition is gone by the time the commit reaches it
+            let partition = f!("{dir}/partition-0.{typ}");
+            fs::copy(&pathname, &partition).unwrap();
+            let sink = TableSink::new(format, pathname.clone(), schema.clone(), true);
+            let err = sink.commit_partitions(&[partition, f!("{dir}/partition-1.{typ}")]).unwrap_err();
+            assert!(matches!(&err, KonaError::Io { source, .. } if source.kind() == std::io::ErrorKind::NotFound), "{}: {}", typ, err);
+
+            assert_eq!(fs::read(&pathname).unwrap(), contents, "{}", typ);
+            assert!(!Path::new(&f!("{pathname}.tmp")).exists(), "{}", typ);
+            assert_eq!(query(&mut session, &f!("SELECT ID FROM {name}")).unwrap(), vec![vec![Int64(1)], vec![Int64(2)]], "{}", typ);
+        }
+    }
+
+    // Runs a query the way the kona binary does, with OUTPUT_FORMAT and the other `settings` set, and returns the
+    // files it wrote
+    fn output_files(session: &mut TestSession, settings: &str, sql: &str) -> Vec<String> {
+        let dir = temp_dir("OUTPUT");
+        session.execute_script(&f!("SET TEMP_DIR = '{dir}'; {settings} {sql};")).unwrap();
+        let flow_dir = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let mut files = list_files(&f!("{}/output", flow_dir.display())).unwrap();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn text_output_formats() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT, S STRING", &["1,a", "2,b"]);
+
+        for (settings, extension, expected) in [
+            ("SET OUTPUT_FORMAT = 'CSV';", "csv", "ID,S\n1,a\n2,b\n"),
+            ("SET OUTPUT_FORMAT = 'csv'; SET CSV_HEADER = 'no'; SET CSV_DELIMITER = '|';", "csv", "1|a\n2|b\n"),
+            ("SET OUTPUT_FORMAT = 'JSON';", "jsonl", "{\"ID\":1,\"S\":\"a\"}\n{\"ID\":2,\"S\":\"b\"}\n"),
+        ] {
+            let files = output_files(&mut session, settings, "SELECT ID, S FROM T");
+            assert_eq!(files.len(), 1, "{}", settings);
+            assert!(files[0].ends_with(&f!("partition-0.{extension}")), "{}: {}", settings, files[0]);
+            assert_eq!(fs::read_to_string(&files[0]).unwrap(), expected, "{}", settings);
+        }
+    }
+
+    #[test]
+    fn binary_output_formats() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT, S STRING", &["1,a", "2,b"]);
+
+        for compression in ["UNCOMPRESSED", "LZ4", "ZSTD"] {
+            let files = output_files(&mut session, &f!("SET OUTPUT_FORMAT = 'IPC'; SET IPC_COMPRESSION = '{compression}';"), "SELECT ID, S FROM T");
+            let chunks = read_ipc(&files[0]).unwrap();
+            assert_eq!(chunks.iter().map(|chunk| chunk.len()).sum::<usize>(), 2, "{}", compression);
+        }
+
+        for compression in ["UNCOMPRESSED", "SNAPPY", "GZIP", "ZSTD"] {
+            let files = output_files(&mut session, &f!("SET OUTPUT_FORMAT = 'PARQUET'; SET PARQUET_COMPRESSION = '{compression}';"), "SELECT ID, S FROM T");
+            assert!(files[0].ends_with("partition-0.parquet"), "{}", files[0]);
+            // The next query clears the flow's directory, so the file is read from a copy
+            let path = f!("{}/P.parquet", temp_dir("P"));
+            fs::copy(&files[0], &path).unwrap();
+            execute(&mut session, &f!("CATALOG OR REPLACE TABLE P (TYPE = 'PARQUET', PATH = '{path}')")).unwrap();
+            let rows = query(&mut session, "SELECT ID, S FROM P").unwrap();
+            assert_eq!(rows, vec![vec![Int64(1), Utf8("a".into())], vec![Int64(2), Utf8("b".into())]], "{}", compression);
+        }
+    }
+
+    #[test]
+    fn table_output_format() {
+        let mut session = session();
+        create_table(&mut session, "T", "ID INT, S STRING", &["1,a", "22,bb", "3,c"]);
+
+        let files = output_files(&mut session, "SET OUTPUT_FORMAT = 'TABLE';", "SELECT ID, S FROM T");
+        assert!(files[0].ends_with("partition-0.arrow"), "{}", files[0]);
+        let chunks = read_ipc(&files[0]).unwrap();
+        let schema = Schema::from(vec![Field::new("ID", DataType::Int64, true), Field::new("S", DataType::Utf8, true)]);
+
+        let table = pop::chunks_to_tabularstring(&schema, &chunks, 0);
+        assert_eq!(table, "+----+----+\n| ID | S  |\n+----+----+\n|  1 | a  |\n| 22 | bb |\n|  3 | c  |\n+----+----+\n(3 rows)");
+        let table = pop::chunks_to_tabularstring(&schema, &chunks, 2);
+        assert!(table.ends_with("| 22 | bb |\n+----+----+\n(3 rows, 2 shown)"), "{}", table);
+    }
+}
@@ -32,6 +32,7 @@ pub struct StageContext {
     // Runtime details
     pub nchildren_completed: usize,
     pub npartitions_completed: usize,
+    pub is_scheduled: bool,
 }
 
 /***************************************************************************************************/
@@ -43,7 +44,7 @@ impl Stage {
         Stage { stage_id, parent_stage_id, parent_pop_key: None, root_lop_key, root_pop_key: None, nchildren: 0, npartitions: 0, pop_graph }
     }
 
-    pub fn schedule(&self, env: &Env, flow: &Flow) -> Result<(), String> {
+    pub fn schedule(&self, env: &Env, flow: &Flow) -> Result<usize, KonaError> {
         debug!("Schedule stage: {:?}", self.root_pop_key);
 
         let (_, props, ..) = self.pop_graph.get3(self.root_pop_key.unwrap());
@@ -57,9 +58,13 @@ impl Stage {
             let task_triplet = &(flow, self, task);
             let task_serialized: Vec<u8> = bincode::serialize(&task_triplet).unwrap();
 
-            env.scheduler.s2t_channels_sx[thread_id].send(SchedulerMessage::ScheduleTask(task_serialized)).map_err(stringify)?;
+            env.scheduler.s2t_channels_sx[thread_id].send(SchedulerMessage::ScheduleTask(task_serialized)).map_err(|err| KonaError::Execution {
+                stage_id: self.stage_id,
+                partition_id,
+                message: err.to_string(),
+            })?;
         }
-        Ok(())
+        Ok(npartitions)
     }
 }
 
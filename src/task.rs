@@ -1,19 +1,22 @@
 // task
 
-use std::fs::File;
-
-use arrow2::io::csv::write;
+use std::{cell::RefCell, rc::Rc, sync::mpsc};
 
 use crate::{
     flow::Flow,
     graph::POPKey,
     includes::*,
+    metrics::{MeteredContext, TaskMetrics},
     pop::{POPContext, POP},
     pop_csv::CSVContext,
     pop_hashagg::HashAggContext,
     pop_hashmatch::HashMatchContext,
     pop_parquet::ParquetContext,
+    pop_project::ProjectContext,
     pop_repartition::{RepartitionReadContext, RepartitionWriteContext},
+    pop_sort::SortContext,
+    scheduler::SchedulerMessage,
+    sink::PartitionWriter,
     stage::Stage,
 };
 
@@ -24,22 +27,24 @@ pub struct Task {
 
     #[serde(skip)]
     pub contexts: Vec<Box<dyn POPContext>>,
+
+    #[serde(skip)]
+    metrics: Rc<RefCell<TaskMetrics>>,
 }
 
 // Tasks write to flow-id / top-id / dest-part-id / source-part-id
 impl Task {
     pub fn new(partition_id: PartitionId) -> Task {
-        Task { partition_id, contexts: vec![] }
+        Task { partition_id, contexts: vec![], metrics: Rc::default() }
     }
 
-    pub fn run(&mut self, flow: &Flow, stage: &Stage) -> Result<(), String> {
-        let options = write::SerializeOptions::default();
-
+    // `driver` takes the chunks of stage-0 tasks whose flow returns them
+    pub fn run(&mut self, flow: &Flow, stage: &Stage, driver: &mpsc::SyncSender<SchedulerMessage>) -> Result<TaskMetrics, KonaError> {
         let mut writer = None;
 
         if stage.stage_id == 0 {
-            let dirname = get_output_dir(flow.id);
-            std::fs::create_dir_all(&dirname).map_err(stringify)?;
+            let dirname = flow.output_dir();
+            std::fs::create_dir_all(&dirname).map_err(|err| KonaError::io(&dirname, err))?;
         }
         /*
         debug!(
@@ -54,43 +59,46 @@ impl Task {
             let chunk = root_context.next(flow, stage)?;
             if let Some(chunk) = chunk {
                 if stage.stage_id == 0 {
+                    // Tasks in top-level stages write their outputs to disk
                     if writer.is_none() {
-                        // Tasks in top-level stages write their outputs to disk
-                        let dirname = get_output_dir(flow.id);
-                        std::fs::create_dir_all(&dirname).map_err(stringify)?;
-                        let path = format!("{}/partition-{}.csv", dirname, self.partition_id);
-                        let localwriter = File::create(path).map_err(stringify)?;
-                        writer = Some(localwriter);
+                        writer = Some(PartitionWriter::try_new(flow, self.partition_id, driver)?);
                     }
-
                     if let Some(writer) = writer.as_mut() {
-                        write::write_chunk(writer, &chunk, &options).map_err(stringify)?;
+                        writer.write_chunk(chunk)?;
                     }
                 }
             } else {
                 break;
             }
         }
-        Ok(())
+        if let Some(writer) = writer {
+            writer.finish()?;
+        }
+
+        // Operators report their metrics when their contexts are dropped
+        drop(root_context);
+        Ok(self.metrics.take())
     }
 
-    pub fn init_context(&self, flow: &Flow, stage: &Stage, popkey: POPKey) -> Result<Box<dyn POPContext>, String> {
+    pub fn init_context(&self, flow: &Flow, stage: &Stage, popkey: POPKey) -> Result<Box<dyn POPContext>, KonaError> {
         let (pop, _, children) = stage.pop_graph.get3(popkey);
         let child_contexts = if let Some(children) = children {
-            let children = children.iter().map(|&child_popkey| self.init_context(flow, stage, child_popkey).unwrap()).collect::<Vec<_>>();
+            let children = children.iter().map(|&child_popkey| self.init_context(flow, stage, child_popkey)).collect::<Result<Vec<_>, _>>()?;
             Some(children)
         } else {
             None
         };
 
         let ctxt = match &pop {
-            POP::CSV(csv) => CSVContext::try_new(popkey, csv, self.partition_id)?,
+            POP::CSV(csv) => CSVContext::try_new(popkey, csv, self.partition_id, flow.settings.chunk_size)?,
             POP::Parquet(pq) => ParquetContext::try_new(popkey, pq, self.partition_id)?,
             POP::RepartitionWrite(rpw) => RepartitionWriteContext::try_new(popkey, rpw, child_contexts.unwrap(), self.partition_id)?,
-            POP::RepartitionRead(rpr) => RepartitionReadContext::try_new(flow.id, popkey, rpr, self.partition_id)?,
+            POP::RepartitionRead(rpr) => RepartitionReadContext::try_new(flow, popkey, rpr, self.partition_id)?,
             POP::HashMatch(hj) => HashMatchContext::try_new(popkey, hj, child_contexts.unwrap(), self.partition_id)?,
             POP::HashAgg(ha) => HashAggContext::try_new(popkey, ha, child_contexts.unwrap(), self.partition_id)?,
+            POP::Project(prj) => ProjectContext::try_new(popkey, prj, child_contexts.unwrap(), self.partition_id)?,
+            POP::Sort(sort) => SortContext::try_new(popkey, sort, child_contexts.unwrap(), self.partition_id)?,
         };
-        Ok(ctxt)
+        Ok(MeteredContext::wrap(popkey, ctxt, self.metrics.clone()))
     }
 }
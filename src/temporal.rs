@@ -0,0 +1,413 @@
+// temporal: Dates, timestamps and intervals
+
+use std::{convert::TryFrom, fmt, str::FromStr};
+
+use arrow2::{
+    compute::{arithmetics::time::add_interval_scalar, arity::unary, cast, temporal},
+    datatypes::{IntervalUnit, TimeUnit},
+    scalar::PrimitiveScalar,
+    temporal_conversions::{date32_to_date, parse_offset, timestamp_us_to_datetime, EPOCH_DAYS_FROM_CE},
+    types::months_days_ns,
+};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+
+use crate::includes::*;
+
+pub const NANOS_PER_MICRO: i64 = 1_000;
+pub const MICROS_PER_SECOND: i64 = 1_000_000;
+pub const MICROS_PER_DAY: i64 = 86_400 * MICROS_PER_SECOND;
+
+/// All timestamps are stored as microseconds since the Unix epoch (UTC).
+pub fn timestamp_datatype(tz: Option<String>) -> DataType {
+    DataType::Timestamp(TimeUnit::Microsecond, tz)
+}
+
+pub fn interval_datatype() -> DataType {
+    DataType::Interval(IntervalUnit::MonthDayNano)
+}
+
+pub fn is_temporal(dt: &DataType) -> bool {
+    matches!(dt, DataType::Date32 | DataType::Timestamp(TimeUnit::Microsecond, _))
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum DateField {
+    Year,
+    Quarter,
+    Month,
+    Week,
+    Day,
+    Hour,
+    Minute,
+    Second,
+}
+
+impl FromStr for DateField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let field = match s.to_uppercase().as_str() {
+            "YEAR" | "YEARS" => DateField::Year,
+            "QUARTER" | "QUARTERS" => DateField::Quarter,
+            "MONTH" | "MONTHS" => DateField::Month,
+            "WEEK" | "WEEKS" => DateField::Week,
+            "DAY" | "DAYS" => DateField::Day,
+            "HOUR" | "HOURS" => DateField::Hour,
+            "MINUTE" | "MINUTES" => DateField::Minute,
+            "SECOND" | "SECONDS" => DateField::Second,
+            _ => return Err(f!("Invalid date/time field: {s}")),
+        };
+        Ok(field)
+    }
+}
+
+impl fmt::Display for DateField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let display_str = match self {
+            DateField::Year => "YEAR",
+            DateField::Quarter => "QUARTER",
+            DateField::Month => "MONTH",
+            DateField::Week => "WEEK",
+            DateField::Day => "DAY",
+            DateField::Hour => "HOUR",
+            DateField::Minute => "MINUTE",
+            DateField::Second => "SECOND",
+        };
+        write!(f, "{}", display_str)
+    }
+}
+
+/***************************************************************************************************/
+// Literal parsing and formatting
+
+pub fn parse_date(s: &str) -> Result<i32, String> {
+    let date = s.trim().parse::<NaiveDate>().map_err(|err| stringify1(err, s))?;
+    Ok(date.num_days_from_ce() - EPOCH_DAYS_FROM_CE)
+}
+
+pub fn format_date(days: i32) -> String {
+    date32_to_date(days).format("%Y-%m-%d").to_string()
+}
+
+// Parses `YYYY-MM-DD[ HH:MM:SS[.ffffff]][(+|-)HH:MM]`. Timestamps with an offset are normalized to UTC and remember the offset as their timezone.
+pub fn parse_timestamp(s: &str) -> Result<(i64, Option<String>), String> {
+    let s = s.trim();
+    if let Ok(dt) = DateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f%:z").or_else(|_| DateTime::parse_from_rfc3339(s)) {
+        let tz = dt.offset().to_string();
+        return Ok((dt.timestamp_micros(), Some(tz)));
+    }
+    let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S%.f"))
+        .or_else(|_| s.parse::<NaiveDate>().map(|d| d.and_time(NaiveTime::MIN)))
+        .map_err(|err| stringify1(err, s))?;
+    Ok((naive.and_utc().timestamp_micros(), None))
+}
+
+pub fn format_timestamp(micros: i64, tz: Option<&String>) -> String {
+    let naive = timestamp_us_to_datetime(micros);
+    if let Some(offset) = tz.and_then(|tz| parse_offset(tz).ok()) {
+        offset.from_utc_datetime(&naive).format("%Y-%m-%d %H:%M:%S%.f%:z").to_string()
+    } else {
+        naive.format("%Y-%m-%d %H:%M:%S%.f").to_string()
+    }
+}
+
+// Parses the body of an INTERVAL literal. Either `INTERVAL '3' DAY` (value + unit) or `INTERVAL '1 month 3 days'` (unit-less) is accepted.
+pub fn parse_interval(value: &str, unit: Option<&str>) -> Result<months_days_ns, String> {
+    let tokens = if let Some(unit) = unit { vec![value.trim(), unit] } else { value.split_whitespace().collect::<Vec<_>>() };
+    if tokens.is_empty() || tokens.len() % 2 != 0 {
+        return Err(f!("Invalid interval literal: '{value}'"));
+    }
+
+    let (mut months, mut days, mut nanos) = (0i32, 0i32, 0i64);
+    for pair in tokens.chunks(2) {
+        let n = pair[0].parse::<i64>().map_err(|_| f!("Invalid interval quantity: '{}'", pair[0]))?;
+        let (unit_months, unit_days, unit_nanos) = match DateField::from_str(pair[1])? {
+            DateField::Year => (12, 0, 0),
+            DateField::Quarter => (3, 0, 0),
+            DateField::Month => (1, 0, 0),
+            DateField::Week => (0, 7, 0),
+            DateField::Day => (0, 1, 0),
+            DateField::Hour => (0, 0, 3600 * MICROS_PER_SECOND * NANOS_PER_MICRO),
+            DateField::Minute => (0, 0, 60 * MICROS_PER_SECOND * NANOS_PER_MICRO),
+            DateField::Second => (0, 0, MICROS_PER_SECOND * NANOS_PER_MICRO),
+        };
+        // Quantities that overflow the interval's months, days or nanoseconds are rejected rather than wrapped
+        let out_of_range = || f!("Interval out of range: '{value}'");
+        months = n.checked_mul(unit_months).and_then(|n| i32::try_from(n).ok()).and_then(|n| months.checked_add(n)).ok_or_else(out_of_range)?;
+        days = n.checked_mul(unit_days).and_then(|n| i32::try_from(n).ok()).and_then(|n| days.checked_add(n)).ok_or_else(out_of_range)?;
+        nanos = n.checked_mul(unit_nanos).and_then(|n| nanos.checked_add(n)).ok_or_else(out_of_range)?;
+    }
+    Ok(months_days_ns::new(months, days, nanos))
+}
+
+pub fn format_interval(interval: &months_days_ns) -> String {
+    let mut parts = vec![];
+    if interval.months() != 0 {
+        parts.push(f!("{} MONTHS", interval.months()));
+    }
+    if interval.days() != 0 {
+        parts.push(f!("{} DAYS", interval.days()));
+    }
+    if interval.ns() != 0 || parts.is_empty() {
+        parts.push(f!("{} SECONDS", interval.ns() / (MICROS_PER_SECOND * NANOS_PER_MICRO)));
+    }
+    parts.join(" ")
+}
+
+// Literal-to-literal conversions used by the resolver to coerce operands of comparisons and arithmetic. As with Arrow casts, naive
+// timestamps and dates are taken to be UTC.
+pub fn coerce_temporal_literal(datum: &Datum, to_datatype: &DataType) -> Result<Datum, String> {
+    let datum = match (datum, to_datatype) {
+        (Utf8(s), DataType::Date32) => Date32(parse_date(s)?),
+        (Utf8(s), DataType::Timestamp(TimeUnit::Microsecond, to_tz)) => Timestamp(parse_timestamp(s)?.0, to_tz.clone()),
+        (Date32(days), DataType::Timestamp(TimeUnit::Microsecond, to_tz)) => Timestamp(*days as i64 * MICROS_PER_DAY, to_tz.clone()),
+        (Timestamp(micros, _), DataType::Timestamp(TimeUnit::Microsecond, to_tz)) => Timestamp(*micros, to_tz.clone()),
+        (Timestamp(micros, _), DataType::Date32) => Date32(micros.div_euclid(MICROS_PER_DAY) as i32),
+        _ => return Err(f!("Cannot convert {} to {:?}", datum, to_datatype)),
+    };
+    Ok(datum)
+}
+
+fn get_offset(tz: Option<&String>) -> Result<FixedOffset, String> {
+    match tz {
+        Some(tz) => parse_offset(tz).map_err(|err| stringify1(err, tz)),
+        None => Ok(FixedOffset::east_opt(0).unwrap()),
+    }
+}
+
+/***************************************************************************************************/
+// Scalar arithmetic, used to fold literal expressions at resolve time
+
+pub fn add_interval_to_date(days: i32, interval: months_days_ns) -> Result<i32, String> {
+    let date = date32_to_date(days);
+    let date = if interval.months() >= 0 {
+        date.checked_add_months(Months::new(interval.months() as u32))
+    } else {
+        date.checked_sub_months(Months::new(interval.months().unsigned_abs()))
+    };
+    let date = date.and_then(|date| date.checked_add_signed(Duration::days(interval.days() as i64)));
+    let date = date.ok_or_else(|| f!("Date out of range: DATE '{}' + INTERVAL '{}'", format_date(days), format_interval(&interval)))?;
+    Ok(date.num_days_from_ce() - EPOCH_DAYS_FROM_CE)
+}
+
+// Evaluate an array kernel over a single temporal literal
+pub fn eval_datum<F>(datum: &Datum, kernel: F) -> Result<Datum, String>
+where
+    F: Fn(&dyn Array) -> Result<Box<dyn Array>, String>,
+{
+    let array: Box<dyn Array> = match datum {
+        Date32(days) => Box::new(PrimitiveArray::<i32>::from_slice([*days]).to(DataType::Date32)),
+        Timestamp(micros, tz) => Box::new(PrimitiveArray::<i64>::from_slice([*micros]).to(timestamp_datatype(tz.clone()))),
+        _ => return Err(f!("Expected a date or timestamp, found {}", datum)),
+    };
+    let array = kernel(&*array)?;
+    let datum = match array.data_type() {
+        DataType::Date32 => Date32(array.as_any().downcast_ref::<PrimitiveArray<i32>>().unwrap().value(0)),
+        DataType::Timestamp(_, tz) => Timestamp(array.as_any().downcast_ref::<PrimitiveArray<i64>>().unwrap().value(0), tz.clone()),
+        DataType::Int64 => Int64(array.as_any().downcast_ref::<PrimitiveArray<i64>>().unwrap().value(0)),
+        typ => return Err(f!("Unexpected result type {:?}", typ)),
+    };
+    Ok(datum)
+}
+
+/***************************************************************************************************/
+// Array kernels, used by PCode
+
+pub fn add_interval(array: &dyn Array, interval: months_days_ns) -> Result<Box<dyn Array>, String> {
+    match array.data_type() {
+        DataType::Date32 if interval.ns() == 0 => {
+            // NULL slots hold arbitrary days, so only valid ones are shifted
+            let array = array.as_any().downcast_ref::<PrimitiveArray<i32>>().unwrap();
+            let days = array.iter().map(|days| days.map(|&days| add_interval_to_date(days, interval)).transpose()).collect::<Result<Vec<_>, _>>()?;
+            Ok(Box::new(PrimitiveArray::<i32>::from(days).to(DataType::Date32)))
+        }
+        DataType::Date32 => {
+            let array = date_to_timestamp(array, None);
+            add_interval(&*array, interval)
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, _) => {
+            let array = array.as_any().downcast_ref::<PrimitiveArray<i64>>().unwrap();
+            let scalar = PrimitiveScalar::new(interval_datatype(), Some(interval));
+            Ok(Box::new(add_interval_scalar(array, &scalar).map_err(stringify)?))
+        }
+        typ => Err(f!("Cannot add an interval to {:?}", typ)),
+    }
+}
+
+// Arrow has no Date32 -> Timestamp cast
+pub fn date_to_timestamp(array: &dyn Array, tz: Option<String>) -> Box<dyn Array> {
+    let array = array.as_any().downcast_ref::<PrimitiveArray<i32>>().unwrap();
+    Box::new(unary(array, |days| days as i64 * MICROS_PER_DAY, timestamp_datatype(tz)))
+}
+
+pub fn cast_temporal(array: &dyn Array, to_datatype: &DataType) -> Result<Box<dyn Array>, String> {
+    match (array.data_type(), to_datatype) {
+        (DataType::Date32, DataType::Timestamp(TimeUnit::Microsecond, tz)) => Ok(date_to_timestamp(array, tz.clone())),
+        _ => cast::cast(array, to_datatype, Default::default()).map_err(stringify),
+    }
+}
+
+pub fn extract(array: &dyn Array, field: DateField) -> Result<Box<dyn Array>, String> {
+    let values: Box<dyn Array> = match field {
+        DateField::Year => Box::new(temporal::year(array).map_err(stringify)?),
+        DateField::Quarter => {
+            let months = temporal::month(array).map_err(stringify)?;
+            Box::new(unary(&months, |m| (m - 1) / 3 + 1, DataType::UInt32))
+        }
+        DateField::Month => Box::new(temporal::month(array).map_err(stringify)?),
+        DateField::Week => Box::new(temporal::iso_week(array).map_err(stringify)?),
+        DateField::Day => Box::new(temporal::day(array).map_err(stringify)?),
+        DateField::Hour => Box::new(temporal::hour(array).map_err(stringify)?),
+        DateField::Minute => Box::new(temporal::minute(array).map_err(stringify)?),
+        DateField::Second => Box::new(temporal::second(array).map_err(stringify)?),
+    };
+    cast::cast(&*values, &DataType::Int64, Default::default()).map_err(stringify)
+}
+
+fn trunc_date(date: NaiveDate, field: DateField) -> NaiveDate {
+    match field {
+        DateField::Year => date.with_day(1).and_then(|d| d.with_month(1)),
+        DateField::Quarter => date.with_day(1).and_then(|d| d.with_month((d.month() - 1) / 3 * 3 + 1)),
+        DateField::Month => date.with_day(1),
+        DateField::Week => Some(date - Duration::days(date.weekday().num_days_from_monday() as i64)),
+        DateField::Day | DateField::Hour | DateField::Minute | DateField::Second => Some(date),
+    }
+    .unwrap()
+}
+
+fn trunc_datetime(dt: NaiveDateTime, field: DateField) -> NaiveDateTime {
+    let secs = dt.and_utc().timestamp();
+    let from_secs = |secs| DateTime::from_timestamp(secs, 0).map(|dt| dt.naive_utc()).unwrap();
+    match field {
+        DateField::Hour => from_secs(secs - secs.rem_euclid(3600)),
+        DateField::Minute => from_secs(secs - secs.rem_euclid(60)),
+        DateField::Second => from_secs(secs),
+        _ => trunc_date(dt.date(), field).and_time(NaiveTime::MIN),
+    }
+}
+
+// Timestamps are truncated in their own timezone, i.e. DATE_TRUNC('day', ts) yields local midnight.
+pub fn date_trunc(array: &dyn Array, field: DateField) -> Result<Box<dyn Array>, String> {
+    match array.data_type() {
+        DataType::Date32 => {
+            let array = array.as_any().downcast_ref::<PrimitiveArray<i32>>().unwrap();
+            let op = |days| trunc_date(date32_to_date(days), field).num_days_from_ce() - EPOCH_DAYS_FROM_CE;
+            Ok(Box::new(unary(array, op, DataType::Date32)))
+        }
+        DataType::Timestamp(TimeUnit::Microsecond, tz) => {
+            let offset = get_offset(tz.as_ref())?.local_minus_utc() as i64 * MICROS_PER_SECOND;
+            let array = array.as_any().downcast_ref::<PrimitiveArray<i64>>().unwrap();
+            let op = |micros: i64| trunc_datetime(timestamp_us_to_datetime(micros + offset), field).and_utc().timestamp_micros() - offset;
+            Ok(Box::new(unary(array, op, array.data_type().clone())))
+        }
+        typ => Err(f!("DATE_TRUNC() not supported for {:?}", typ)),
+    }
+}
+
+// DATE - DATE yields the number of days between the two
+pub fn date_diff(lhs: &dyn Array, rhs: &dyn Array) -> Result<Box<dyn Array>, String> {
+    let lhs = cast::cast(lhs, &DataType::Int64, Default::default()).map_err(stringify)?;
+    let rhs = cast::cast(rhs, &DataType::Int64, Default::default()).map_err(stringify)?;
+    Ok(arithmetics::sub(&*lhs, &*rhs))
+}
+
+pub fn date_diff_scalar(lhs: &dyn Array, rhs: i32) -> Result<Box<dyn Array>, String> {
+    let lhs = cast::cast(lhs, &DataType::Int64, Default::default()).map_err(stringify)?;
+    let lhs = lhs.as_any().downcast_ref::<PrimitiveArray<i64>>().unwrap();
+    Ok(Box::new(arithmetics::basic::sub_scalar(lhs, &(rhs as i64))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    fn date(s: &str) -> Datum {
+        Date32(parse_date(s).unwrap())
+    }
+
+    fn timestamp(s: &str) -> Datum {
+        Timestamp(parse_timestamp(s).unwrap().0, None)
+    }
+
+    #[test]
+    fn date_arithmetic_in_both_orders() {
+        let mut session = session();
+        create_table(&mut session, "T", "D DATE, TS TIMESTAMP", &["1999-01-11,1999-01-11 10:00:00"]);
+        let cases = [
+            ("D + INTERVAL '1' DAY", date("1999-01-12")),
+            ("INTERVAL '1' DAY + D", date("1999-01-12")),
+            ("D - INTERVAL '1' MONTH", date("1998-12-11")),
+            ("DATE '1999-01-01' + INTERVAL '1' DAY", date("1999-01-02")),
+            ("INTERVAL '1' DAY + DATE '1999-01-01'", date("1999-01-02")),
+            ("D + INTERVAL '1' HOUR", timestamp("1999-01-11 01:00:00")),
+            ("TS + INTERVAL '1' HOUR", timestamp("1999-01-11 11:00:00")),
+            ("INTERVAL '1' HOUR + TS", timestamp("1999-01-11 11:00:00")),
+            ("TS - INTERVAL '1' DAY", timestamp("1999-01-10 10:00:00")),
+            ("D - DATE '1999-01-01'", Int64(10)),
+            ("DATE '1999-01-01' - D", Int64(-10)),
+            ("D - D", Int64(0)),
+        ];
+        for (expr, expected) in cases {
+            // D keeps the select list from being all constants
+            let rows = query(&mut session, &f!("SELECT D, {expr} FROM T")).unwrap();
+            assert_eq!(rows[0][1], expected, "{}", expr);
+        }
+
+        execute(&mut session, "PREPARE P AS SELECT $1 - D FROM T").unwrap();
+        assert_eq!(query_value(&mut session, "EXECUTE P USING DATE '1999-01-21'").unwrap(), Int64(10));
+    }
+
+    #[test]
+    fn date_out_of_range_is_an_error() {
+        let mut session = session();
+        create_table(&mut session, "T", "D DATE", &["1999-01-11", ""]);
+        let err = query(&mut session, "SELECT D + INTERVAL '300000' YEAR FROM T").unwrap_err();
+        assert!(matches!(&err, KonaError::Execution { message, .. } if message.contains("Date out of range: DATE '1999-01-11' + INTERVAL")), "{}", err);
+
+        // Literals are folded when the query is resolved
+        let err = query(&mut session, "SELECT D, DATE '1999-01-01' - INTERVAL '300000' YEAR FROM T").unwrap_err();
+        assert!(matches!(&err, KonaError::Bind(msg) if msg.contains("Date out of range: DATE '1999-01-01' + INTERVAL")), "{}", err);
+    }
+
+    #[test]
+    fn unsupported_operand_orders_are_rejected() {
+        let mut session = session();
+        create_table(&mut session, "T", "D DATE", &["1999-01-11"]);
+        for expr in ["INTERVAL '1' DAY - D", "D + D", "D + 1"] {
+            assert!(matches!(query(&mut session, &f!("SELECT {expr} FROM T")), Err(KonaError::Bind(_))), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn interval_literals() {
+        let interval = parse_interval("1 year 2 months 3 days 4 hours", None).unwrap();
+        assert_eq!((interval.months(), interval.days(), interval.ns()), (14, 3, 4 * 3600 * MICROS_PER_SECOND * NANOS_PER_MICRO));
+        assert_eq!(parse_interval("3", Some("WEEK")).unwrap().days(), 21);
+        assert!(parse_interval("3", None).is_err());
+        assert!(parse_interval("3 fortnights", None).is_err());
+        for value in ["200000000 years", "3000000000 days", "-3000000000 months", "9223372036854775807 years", "3000000000 hours"] {
+            assert_eq!(parse_interval(value, None).unwrap_err(), f!("Interval out of range: '{value}'"));
+        }
+    }
+
+    #[test]
+    fn extract_and_date_trunc() {
+        let mut session = session();
+        create_table(&mut session, "T", "TS TIMESTAMP", &["1999-05-17 10:42:13"]);
+        assert_eq!(query_value(&mut session, "SELECT EXTRACT(QUARTER FROM TS) FROM T").unwrap(), Int64(2));
+        assert_eq!(query_value(&mut session, "SELECT EXTRACT(HOUR FROM TS) FROM T").unwrap(), Int64(10));
+        assert_eq!(query_value(&mut session, "SELECT DATE_TRUNC('month', TS) FROM T").unwrap(), timestamp("1999-05-01 00:00:00"));
+        assert_eq!(query_value(&mut session, "SELECT DATE_TRUNC('minute', TS) FROM T").unwrap(), timestamp("1999-05-17 10:42:00"));
+        assert_eq!(query_value(&mut session, "SELECT DATE_TRUNC('week', CAST(TS AS DATE)) FROM T").unwrap(), date("1999-05-17"));
+    }
+
+    #[test]
+    fn temporal_keywords_name_columns() {
+        let mut session = session();
+        create_table(&mut session, "T", "date DATE, interval INT", &["1999-01-11,3"]);
+        let rows = query(&mut session, "SELECT date, interval AS extract FROM T WHERE date > DATE '1999-01-01'").unwrap();
+        assert_eq!(rows, vec![vec![date("1999-01-11"), Int64(3)]]);
+    }
+}
@@ -0,0 +1,134 @@
+// testing: Helpers for unit tests that run SQL statements against an Env of their own
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{
+    ast::AST,
+    compile_query,
+    datum::F64,
+    includes::*,
+    parse_script, prepare, run_flow, run_script, run_statement,
+    sink::{self, Compression, OutputFormat},
+};
+
+static NTABLES: AtomicUsize = AtomicUsize::new(0);
+static NSESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+// Statements are run the way the kona binary runs them, except that query results are written as Arrow IPC and read
+// back. This leaves the tests of the engine independent of the Session API that embeds it.
+pub struct TestSession {
+    pub env: Env,
+}
+
+pub fn session() -> TestSession {
+    session_with_catalog(None)
+}
+
+pub fn session_with_catalog(catalog_dir: Option<&str>) -> TestSession {
+    let id = std::process::id() as usize * 1000 + NSESSIONS.fetch_add(1, Ordering::Relaxed);
+    let mut env = Env::new(id, 2, String::new(), String::new(), catalog_dir).unwrap();
+    env.init_output_dir().unwrap();
+    TestSession { env }
+}
+
+impl TestSession {
+    // The chunks returned by one statement, if any
+    pub fn sql(&mut self, text: &str) -> Result<Vec<ChunkBox>, KonaError> {
+        let mut astlist = parse_script("<test>", text)?;
+        assert_eq!(astlist.len(), 1, "{text} holds {} statements", astlist.len());
+        let env = &mut self.env;
+        let flow = match astlist.pop().unwrap() {
+            AST::QGM(mut qgm) => compile_query(env, &mut qgm)?,
+            AST::Execute { name, values } => prepare::execute(env, name, values)?,
+            ast => return Ok(run_statement(env, ast)?.map(|resultset| resultset.chunk).into_iter().collect()),
+        };
+        let mut flow = match flow {
+            Some(flow) => flow,
+            None => return Ok(vec![]),
+        };
+        flow.settings.output_format = OutputFormat::IPC { compression: Compression::Uncompressed };
+        run_flow(env, &flow)?;
+
+        let mut files = list_files(&flow.output_dir())?;
+        files.sort();
+        let mut chunks = vec![];
+        for file in files.iter() {
+            chunks.extend(sink::read_ipc(file)?);
+        }
+        Ok(chunks)
+    }
+
+    pub fn execute_script(&mut self, text: &str) -> Result<(), KonaError> {
+        run_script(&mut self.env, "<script>", text, false)
+    }
+}
+
+// Stops the scheduler's threads and removes the files of the flows and plans
+impl Drop for TestSession {
+    fn drop(&mut self) {
+        let scheduler = &mut self.env.scheduler;
+        if scheduler.end_all_threads().is_ok() {
+            scheduler.join().unwrap_or_default();
+        }
+        fs::remove_dir_all(format!("{}/flow-{}", self.env.settings.temp_dir(), self.env.id)).unwrap_or_default();
+        fs::remove_dir_all(&self.env.output_dir).unwrap_or_default();
+    }
+}
+
+// Runs one statement and returns its rows, NULLs as Datum::Null. Rows come back in no particular order, so they are sorted.
+pub fn query(session: &mut TestSession, sql: &str) -> Result<Vec<Vec<Datum>>, KonaError> {
+    let mut rows = query_in_order(session, sql)?;
+    rows.sort();
+    Ok(rows)
+}
+
+// The rows of a query with an ORDER BY, in the order they come back
+pub fn query_in_order(session: &mut TestSession, sql: &str) -> Result<Vec<Vec<Datum>>, KonaError> {
+    let mut rows = vec![];
+    for chunk in session.sql(sql)? {
+        for ix in 0..chunk.len() {
+            let row = chunk.arrays().iter().map(|array| Datum::from_array(&**array, ix).map(|datum| datum.unwrap_or(Null)));
+            rows.push(row.collect::<Result<Vec<_>, _>>().unwrap());
+        }
+    }
+    Ok(rows)
+}
+
+// The single value returned by a query
+pub fn query_value(session: &mut TestSession, sql: &str) -> Result<Datum, KonaError> {
+    let rows = query(session, sql)?;
+    assert_eq!(rows.len(), 1, "{sql} returned {} rows", rows.len());
+    Ok(rows[0][0].clone())
+}
+
+pub fn execute(session: &mut TestSession, sql: &str) -> Result<(), KonaError> {
+    session.sql(sql).map(|_| ())
+}
+
+// A directory of its own under the system temp directory
+pub fn temp_dir(name: &str) -> String {
+    let dir = std::env::temp_dir().join(f!("kona-test-{}-{}-{name}", std::process::id(), NTABLES.fetch_add(1, Ordering::Relaxed)));
+    fs::create_dir_all(&dir).unwrap();
+    dir.display().to_string()
+}
+
+// Writes `rows` to a CSV file without a header and catalogs it as `name` with the given column list, e.g. "ID INT, NAME STRING"
+pub fn create_table(session: &mut TestSession, name: &str, columns: &str, rows: &[&str]) {
+    let path = f!("{}/{name}.csv", temp_dir(name));
+    fs::write(&path, rows.iter().map(|row| f!("{row}\n")).collect::<String>()).unwrap();
+    execute(session, &f!("CATALOG TABLE {name} ({columns}) WITH (TYPE = 'CSV', PATH = '{path}', HEADER = 'N')")).unwrap();
+}
+
+// Each type a column can have, as its SQL name and two CSV values a < b, followed by the same values as datums
+pub fn typed_values() -> Vec<(&'static str, &'static str, &'static str, Datum, Datum)> {
+    vec![
+        ("INT32", "1", "2", Int32(1), Int32(2)),
+        ("BIGINT", "1", "2", Int64(1), Int64(2)),
+        ("DOUBLE", "-2.5", "1.5", Float64(F64::from(-2.5)), Float64(F64::from(1.5))),
+        ("STRING", "a", "b", Utf8("a".into()), Utf8("b".into())),
+        ("BOOLEAN", "false", "true", Boolean(false), Boolean(true)),
+        ("DATE", "2024-01-01", "2024-02-01", Date32(19723), Date32(19754)),
+        ("TIMESTAMP", "2024-01-01 10:00:00", "2024-01-01 11:00:00", Timestamp(1704103200000000, None), Timestamp(1704106800000000, None)),
+        ("DECIMAL(10,2)", "1.25", "2.50", Decimal(125, 10, 2), Decimal(250, 10, 2)),
+    ]
+}
@@ -1,6 +1,6 @@
 :
 
-DIR=/Users/adarshrp/Projects/kona/tests
+DIR=$(cd "$(dirname "$0")" && pwd)
 (cd $DIR/gold; rm -rf *)
 (cd $DIR/output; cp -pr * ../gold)
 